@@ -1,23 +1,34 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use bazed_input_mapper::input_event::KeyInput;
 use bazed_rpc::{
     core_proto::ToBackend,
-    core_proto::{Coordinate, ToFrontend, ViewData},
+    core_proto::{Coordinate, ToFrontend},
     server::ClientSendHandle,
 };
 use color_eyre::Result;
 use futures::StreamExt;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc::UnboundedReceiver, RwLock};
 
 use crate::{
     buffer::position::Position,
-    document::{Document, DocumentId},
-    view::{View, ViewId, Viewport},
+    document::{Document, DocumentId, FileChange},
+    ex_command::{CommandError, EditorAction},
+    file_watcher::{self, FileWatcher},
+    view::{View, ViewId, Viewport, SCROLL_OFF},
     vim_interface::VimInterface,
 };
 
-const SCROLL_OFF: usize = 3;
+/// Order in which `:marks` lists marks
+const MARK_ORDER: &str = "'abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ^.<>";
+/// Time to wait for further changes to a file before reloading it,
+/// as other programs might write a file in several steps
+const FILE_CHANGE_DELAY: Duration = Duration::from_millis(50);
 
 #[derive(Debug, thiserror::Error)]
 enum Error {
@@ -32,6 +43,8 @@ pub struct App {
     views: HashMap<ViewId, View>,
     event_send: ClientSendHandle,
     vim_interface: VimInterface,
+    /// Watches the files of all documents, once [App::watch_files] was called
+    file_watcher: Option<FileWatcher>,
 }
 
 impl App {
@@ -41,26 +54,65 @@ impl App {
             event_send,
             views: HashMap::new(),
             vim_interface: VimInterface::new(),
+            file_watcher: None,
         }
     }
 
-    async fn open_document(&mut self, document: Document) -> Result<()> {
+    /// Start watching the files of all documents for changes made by other programs,
+    /// returning a receiver for the paths of changed files, to be passed to [App::handle_file_changed]
+    pub fn watch_files(&mut self) -> Result<UnboundedReceiver<PathBuf>> {
+        let (mut watcher, changes) = FileWatcher::new()?;
+        for path in self.documents.values().filter_map(|x| x.path.as_ref()) {
+            watcher.watch(path);
+        }
+        self.file_watcher = Some(watcher);
+        Ok(changes)
+    }
+
+    fn watch_file(&mut self, path: &Path) {
+        if let Some(watcher) = &mut self.file_watcher {
+            watcher.watch(path);
+        }
+    }
+
+    fn unwatch_file(&mut self, path: &Path) {
+        if let Some(watcher) = &mut self.file_watcher {
+            watcher.unwatch(path);
+        }
+    }
+
+    /// Add a document, watching its file
+    fn add_document(&mut self, mut document: Document) -> DocumentId {
         let document_id = DocumentId::gen();
+        if self.vim_interface.options.undofile {
+            if let Err(err) = document.read_undo_file() {
+                tracing::warn!(?document, "Failed to read undo file: {err}");
+            }
+        }
+        if let Some(path) = document.path.clone() {
+            self.watch_file(&path);
+            if let Some(issue) = document.decode_issue() {
+                let message = format!("\"{}\": {issue} (writing it needs !)", path.display());
+                self.vim_interface.show_message(message);
+            }
+        }
+        self.documents.insert(document_id, document);
+        document_id
+    }
+
+    async fn open_document(&mut self, document: Document) -> Result<()> {
         let view_id = ViewId::gen();
+        let path = document.path.clone();
+        let document_id = self.add_document(document);
         let view = View::new(document_id, Viewport::new(0, 20));
         self.event_send
             .send_rpc(ToFrontend::OpenView {
                 view_id: view_id.0,
-                path: document.path.clone(),
-                view_data: ViewData {
-                    first_line: view.vp.first_line,
-                    text: document.lines_in_viewport(&view.vp),
-                    carets: document.caret_positions(),
-                    vim_mode: self.vim_interface.mode.to_string(),
-                },
+                path,
+                view_data: self.documents[&document_id]
+                    .create_view_data(&view, &self.vim_interface),
             })
             .await?;
-        self.documents.insert(document_id, document);
         self.views.insert(view_id, view);
         Ok(())
     }
@@ -99,6 +151,41 @@ impl App {
                 self.handle_viewport_changed(ViewId(view_id), height)
                     .await?;
             },
+            ToBackend::SaveDocument { view_id, force } => {
+                self.save_document(ViewId(view_id), None, force, false)
+                    .await?;
+                self.send_view_update(ViewId(view_id)).await?;
+            },
+            ToBackend::SaveAs {
+                view_id,
+                path,
+                force,
+            } => {
+                self.save_document(ViewId(view_id), Some(path), force, true)
+                    .await?;
+                self.send_view_update(ViewId(view_id)).await?;
+            },
+            ToBackend::GetUndoTree {
+                request_id,
+                view_id,
+            } => {
+                let view = self
+                    .views
+                    .get(&ViewId(view_id))
+                    .ok_or(Error::InvalidViewId(ViewId(view_id)))?;
+                let document = self
+                    .documents
+                    .get(&view.document_id)
+                    .ok_or(Error::InvalidDocumentId(view.document_id))?;
+                let tree = document.buffer.undo_tree();
+                self.event_send
+                    .send_rpc(ToFrontend::UndoTree {
+                        request_id,
+                        view_id,
+                        tree,
+                    })
+                    .await?;
+            },
         }
         Ok(())
     }
@@ -117,11 +204,7 @@ impl App {
                 .get(&view.document_id)
                 .ok_or(Error::InvalidDocumentId(view.document_id))?;
             self.event_send
-                .send_rpc(document.create_update_notification(
-                    view_id,
-                    view,
-                    self.vim_interface.mode,
-                ))
+                .send_rpc(document.create_update_notification(view_id, view, &self.vim_interface))
                 .await?;
         }
         Ok(())
@@ -144,9 +227,367 @@ impl App {
         let caret_line = document.buffer.primary_caret_position().line;
         view.vp = view.vp.with_line_in_view(caret_line, SCROLL_OFF);
 
+        for action in self.vim_interface.take_actions() {
+            self.handle_editor_action(view_id, action).await?;
+        }
+
+        // The view is gone if it was closed by one of the actions
+        if self.views.contains_key(&view_id) {
+            self.send_view_update(view_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the current state of a view to the frontend
+    async fn send_view_update(&mut self, view_id: ViewId) -> Result<()> {
+        let view = self
+            .views
+            .get(&view_id)
+            .ok_or(Error::InvalidViewId(view_id))?;
+        let document = self
+            .documents
+            .get(&view.document_id)
+            .ok_or(Error::InvalidDocumentId(view.document_id))?;
+        self.event_send
+            .send_rpc(document.create_update_notification(view_id, view, &self.vim_interface))
+            .await?;
+        Ok(())
+    }
+
+    /// Save the document of a view to the given path, or to its own path if none is given,
+    /// and tell the frontend whether that worked
+    ///
+    /// Unless `force` is set, this refuses to overwrite changes other programs made to the file.
+    /// Save the document shown in the view, to the given path or to its own one.
+    ///
+    /// If `take_path` is set, the document keeps the given path once it is written to it.
+    async fn save_document(
+        &mut self,
+        view_id: ViewId,
+        path: Option<PathBuf>,
+        force: bool,
+        take_path: bool,
+    ) -> Result<()> {
+        let document_id = self
+            .views
+            .get(&view_id)
+            .ok_or(Error::InvalidViewId(view_id))?
+            .document_id;
+        let document = self
+            .documents
+            .get_mut(&document_id)
+            .ok_or(Error::InvalidDocumentId(document_id))?;
+        let old_path = document.path.clone();
+        // Like in vim, a document without a path takes on the first path it is written to
+        let take_path = take_path || old_path.is_none();
+        let Some(path) = path.or_else(|| old_path.clone()) else {
+            self.vim_interface.show_message("No file name".to_string());
+            return Ok(());
+        };
+        let result = if take_path {
+            document.write_as(path.clone(), force).await
+        } else {
+            document.write_to(path.clone(), force).await
+        };
+        if result.is_ok() && self.vim_interface.options.undofile {
+            if let Err(err) = document.write_undo_file() {
+                tracing::warn!(?path, "Failed to write undo file: {err}");
+            }
+        }
+        if result.is_ok() && old_path.as_ref() != Some(&path) && take_path {
+            if let Some(old_path) = old_path {
+                self.unwatch_file(&old_path);
+            }
+            self.watch_file(&path);
+        }
+        let message = match &result {
+            Ok(()) => format!("\"{}\" written", path.display()),
+            Err(err) => format!("Failed to write \"{}\": {err}", path.display()),
+        };
+        self.vim_interface.show_message(message);
+        self.event_send
+            .send_rpc(ToFrontend::DocumentSaved {
+                view_id: view_id.into(),
+                path,
+                error: result.err().map(|err| err.to_string()),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Check whether closing the given view would lose unsaved changes,
+    /// as it is the only view showing a dirty document
+    fn closing_loses_changes(&self, view_id: ViewId) -> bool {
+        let Some(view) = self.views.get(&view_id) else { return false };
+        let shown_elsewhere = self
+            .views
+            .iter()
+            .any(|(id, x)| *id != view_id && x.document_id == view.document_id);
+        !shown_elsewhere
+            && matches!(self.documents.get(&view.document_id), Some(document) if document.is_dirty())
+    }
+
+    /// Carry out an action an ex command requested in the given view
+    #[tracing::instrument(skip(self))]
+    async fn handle_editor_action(&mut self, view_id: ViewId, action: EditorAction) -> Result<()> {
+        if let EditorAction::Quit { force: false } | EditorAction::Edit { force: false, .. } =
+            action
+        {
+            if self.closing_loses_changes(view_id) {
+                let message = "No write since last change (add ! to override)";
+                self.vim_interface.show_message(message.to_string());
+                return Ok(());
+            }
+        }
+        let view = self
+            .views
+            .get_mut(&view_id)
+            .ok_or(Error::InvalidViewId(view_id))?;
+        match action {
+            EditorAction::Write { path, force } => {
+                self.save_document(view_id, path, force, false).await?
+            },
+            EditorAction::Quit { .. } => {
+                let document_id = view.document_id;
+                self.views.remove(&view_id);
+                self.remove_document_if_unused(document_id);
+                self.event_send
+                    .send_rpc(ToFrontend::CloseView {
+                        view_id: view_id.into(),
+                    })
+                    .await?;
+            },
+            EditorAction::SetFileFormat(arg) => {
+                let document = self
+                    .documents
+                    .get_mut(&view.document_id)
+                    .ok_or(Error::InvalidDocumentId(view.document_id))?;
+                match document.format.apply(&arg) {
+                    Ok(Some(message)) => self.vim_interface.show_message(message),
+                    Ok(None) => {},
+                    Err(err) => self.vim_interface.show_message(err.to_string()),
+                }
+            },
+            EditorAction::Edit { path, .. } => {
+                let document = match Document::open_file(path.clone()) {
+                    Ok(document) => document,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        Document::open_new_file(path)
+                    },
+                    Err(err) => {
+                        let message = format!("Failed to open \"{}\": {err}", path.display());
+                        self.vim_interface.show_message(message);
+                        return Ok(());
+                    },
+                };
+                let document_id = self.add_document(document);
+                self.show_document(view_id, document_id).await?;
+            },
+            EditorAction::ClaimGlobalMark(mark) => {
+                for (document_id, document) in &mut self.documents {
+                    if *document_id != view.document_id {
+                        document.buffer.remove_mark(mark);
+                    }
+                }
+            },
+            EditorAction::JumpToGlobalMark { mark, linewise } => {
+                let target = self
+                    .documents
+                    .iter_mut()
+                    .find_map(|(document_id, document)| {
+                        let position = document.buffer.mark_position(mark)?;
+                        Some((*document_id, document, position))
+                    });
+                let Some((document_id, document, position)) = target else {
+                    let message = CommandError::MarkNotSet(mark).to_string();
+                    self.vim_interface.show_message(message);
+                    return Ok(());
+                };
+                let position = if linewise {
+                    Position::new(position.line, 0)
+                } else {
+                    position
+                };
+                document.buffer.jump_caret_to_position(position, true);
+                if let Some(document) = self.documents.get_mut(&view.document_id) {
+                    view.record_jump(&mut document.buffer);
+                }
+                self.show_document(view_id, document_id).await?;
+            },
+            EditorAction::ShowJump(jump) => {
+                let Some(document) = self.documents.get_mut(&jump.document_id) else {
+                    tracing::info!(?jump, "Document of jump was closed");
+                    return Ok(());
+                };
+                let position = document
+                    .buffer
+                    .anchor_offset(jump.anchor)
+                    .and_then(|x| Position::from_offset(document.buffer.head_rope(), x));
+                if let Some(position) = position {
+                    document.buffer.jump_caret_to_position(position, true);
+                }
+                self.show_document(view_id, jump.document_id).await?;
+            },
+            EditorAction::ReadUndoFiles => {
+                for document in self.documents.values_mut() {
+                    if let Err(err) = document.read_undo_file() {
+                        tracing::warn!(?document, "Failed to read undo file: {err}");
+                    }
+                }
+            },
+            EditorAction::ListMarks(filter) => {
+                let document_id = view.document_id;
+                let message = self.describe_marks(document_id, &filter);
+                self.vim_interface.show_message(message);
+            },
+        }
+        Ok(())
+    }
+
+    /// Show another document in a view, scrolled to its primary caret,
+    /// dropping the document shown before if no other view shows it
+    async fn show_document(&mut self, view_id: ViewId, document_id: DocumentId) -> Result<()> {
+        let view = self
+            .views
+            .get_mut(&view_id)
+            .ok_or(Error::InvalidViewId(view_id))?;
+        let document = self
+            .documents
+            .get(&document_id)
+            .ok_or(Error::InvalidDocumentId(document_id))?;
+        let old_document_id = std::mem::replace(&mut view.document_id, document_id);
+        let caret_line = document.buffer.primary_caret_position().line;
+        view.vp.first_line = 0;
+        view.vp = view.vp.with_line_in_view(caret_line, SCROLL_OFF);
         self.event_send
-            .send_rpc(document.create_update_notification(view_id, view, self.vim_interface.mode))
+            .send_rpc(ToFrontend::OpenView {
+                view_id: view_id.into(),
+                path: document.path.clone(),
+                view_data: document.create_view_data(view, &self.vim_interface),
+            })
             .await?;
+        self.remove_document_if_unused(old_document_id);
+        Ok(())
+    }
+
+    /// List the marks given in `filter`, or all marks if it is empty, like vim's `:marks`.
+    ///
+    /// Marks of the given document are shown together with the text of their line,
+    /// global marks of other documents with the path of their document.
+    fn describe_marks(&self, document_id: DocumentId, filter: &str) -> String {
+        let mut marks = Vec::new();
+        for (id, document) in &self.documents {
+            let buffer = &document.buffer;
+            for (mark, _) in buffer.marks() {
+                let Some(position) = buffer.mark_position(mark) else { continue };
+                let shown_with = if *id == document_id {
+                    buffer.line_contents(position.line..=position.line).concat()
+                } else if mark.is_ascii_uppercase() {
+                    document
+                        .path
+                        .as_ref()
+                        .map_or("[No Name]".to_string(), |x| x.display().to_string())
+                } else {
+                    continue;
+                };
+                if filter.is_empty() || filter.contains(mark) {
+                    marks.push((mark, position, shown_with));
+                }
+            }
+        }
+        if marks.is_empty() {
+            return format!("No marks matching \"{filter}\"");
+        }
+        marks.sort_by_key(|(mark, ..)| MARK_ORDER.find(*mark));
+        let mut lines = vec!["mark line  col file/text".to_string()];
+        for (mark, position, shown_with) in marks {
+            let line = position.line + 1;
+            let col = position.col;
+            lines.push(format!(
+                " {mark} {line:>6} {col:>4} {}",
+                shown_with.trim_end()
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// Drop a document once it isn't shown in any view anymore
+    fn remove_document_if_unused(&mut self, document_id: DocumentId) {
+        if self.views.values().any(|x| x.document_id == document_id) {
+            return;
+        }
+        let path = self
+            .documents
+            .remove(&document_id)
+            .and_then(|document| document.path);
+        if let Some(path) = path {
+            self.unwatch_file(&path);
+        }
+    }
+
+    /// Reload or merge all documents of a file after another program changed it,
+    /// and tell the frontend about changes that had to be merged with unsaved changes.
+    ///
+    /// The path is expected in the form reported by [FileWatcher].
+    pub async fn handle_file_changed(&mut self, path: PathBuf) -> Result<()> {
+        let document_ids: Vec<_> = self
+            .documents
+            .iter()
+            .filter(|(_, document)| {
+                let normalized = document
+                    .path
+                    .as_deref()
+                    .and_then(file_watcher::normalize_path);
+                normalized.as_ref() == Some(&path)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for document_id in document_ids {
+            let document = self
+                .documents
+                .get_mut(&document_id)
+                .ok_or(Error::InvalidDocumentId(document_id))?;
+            let change = match document.reload_from_disk() {
+                Ok(change) => change,
+                Err(err) => {
+                    tracing::error!(?path, "Failed to reload changed file: {err}");
+                    continue;
+                },
+            };
+            let conflict = match change {
+                FileChange::Unchanged => continue,
+                FileChange::Reloaded => None,
+                FileChange::Merged { conflict } => Some(conflict),
+            };
+            tracing::info!(?path, ?change, "File changed on disk");
+            if let Some(conflict) = conflict {
+                let message = if conflict {
+                    "changed on disk, conflicting with unsaved changes"
+                } else {
+                    "changed on disk, merged with unsaved changes"
+                };
+                self.vim_interface
+                    .show_message(format!("\"{}\" {message}", path.display()));
+            }
+            let view_ids: Vec<_> = self
+                .views
+                .iter()
+                .filter(|(_, view)| view.document_id == document_id)
+                .map(|(id, _)| *id)
+                .collect();
+            for view_id in view_ids {
+                if let Some(conflict) = conflict {
+                    self.event_send
+                        .send_rpc(ToFrontend::FileChanged {
+                            view_id: view_id.into(),
+                            path: path.clone(),
+                            conflict,
+                        })
+                        .await?;
+                }
+                self.send_view_update(view_id).await?;
+            }
+        }
         Ok(())
     }
 
@@ -159,11 +600,12 @@ impl App {
             .documents
             .get_mut(&view.document_id)
             .ok_or(Error::InvalidDocumentId(view.document_id))?;
+        view.record_jump(&mut document.buffer);
         document
             .buffer
             .jump_caret_to_position(Position::new(coords.line, coords.col), false);
         self.event_send
-            .send_rpc(document.create_update_notification(view_id, view, self.vim_interface.mode))
+            .send_rpc(document.create_update_notification(view_id, view, &self.vim_interface))
             .await?;
         Ok(())
     }
@@ -189,7 +631,7 @@ impl App {
         );
 
         self.event_send
-            .send_rpc(document.create_update_notification(view_id, view, self.vim_interface.mode))
+            .send_rpc(document.create_update_notification(view_id, view, &self.vim_interface))
             .await?;
 
         Ok(())
@@ -205,7 +647,16 @@ pub async fn start(addr: &str, path: Option<std::path::PathBuf>) -> Result<()> {
         let path = path.clone();
         let (send, mut recv) = bazed_rpc::server::wait_for_client(addr).await?;
 
-        let core = Arc::new(RwLock::new(App::new(send)));
+        let mut app = App::new(send);
+        let file_changes = app.watch_files();
+        let core = Arc::new(RwLock::new(app));
+
+        match file_changes {
+            Ok(file_changes) => {
+                tokio::spawn(handle_file_changes(core.clone(), file_changes));
+            },
+            Err(err) => tracing::error!(?err, "Failed to watch files"),
+        }
 
         tokio::spawn({
             let core = core.clone();
@@ -229,6 +680,23 @@ pub async fn start(addr: &str, path: Option<std::path::PathBuf>) -> Result<()> {
         });
     }
 }
+/// Pass changed files on to the app, once they haven't been changed for a moment
+async fn handle_file_changes(core: Arc<RwLock<App>>, mut changes: UnboundedReceiver<PathBuf>) {
+    while let Some(path) = changes.recv().await {
+        tokio::time::sleep(FILE_CHANGE_DELAY).await;
+        let mut paths = HashSet::from([path]);
+        while let Ok(path) = changes.try_recv() {
+            paths.insert(path);
+        }
+        let mut core = core.write().await;
+        for path in paths {
+            if let Err(err) = core.handle_file_changed(path).await {
+                tracing::error!("Failed to handle changed file: {err:?}");
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bazed_input_mapper::input_event::{Key, KeyInput, Modifiers, RawKey};
@@ -239,7 +707,7 @@ mod tests {
     use futures::channel::mpsc::unbounded;
 
     use super::App;
-    use crate::test_util;
+    use crate::{file_watcher, test_util, view::ViewId};
 
     macro_rules! expect_msg {
         ($s:literal, $recv:ident, $p:pat => $e:expr) => {
@@ -309,4 +777,312 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_quit_closes_view() -> color_eyre::Result<()> {
+        test_util::setup_test();
+        let (mut app, mut to_frontend_recv, view_id) = setup_view().await?;
+
+        for key in [":", "q", "Enter"] {
+            app.handle_rpc_call(ToBackend::KeyPressed {
+                view_id,
+                input: KeyInput {
+                    modifiers: Modifiers::empty(),
+                    key: Key(key.to_string()),
+                    code: RawKey::from(key),
+                },
+            })
+            .await?;
+        }
+        expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { .. } => ());
+        let command_line = expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { view_data, .. } => view_data.command_line);
+        assert_eq!(Some(":q".to_string()), command_line);
+        let closed = expect_msg!("CloseView", to_frontend_recv, ToFrontend::CloseView { view_id } => view_id);
+        assert_eq!(view_id, closed);
+        assert!(app.views().is_empty());
+        assert!(app.documents.is_empty());
+
+        Ok(())
+    }
+
+    /// Press the given keys, one key per element, and discard the resulting view updates
+    async fn press_keys(
+        app: &mut App,
+        recv: &mut futures::channel::mpsc::UnboundedReceiver<ToFrontend>,
+        view_id: uuid::Uuid,
+        keys: &[&str],
+    ) -> color_eyre::Result<()> {
+        for key in keys {
+            app.handle_rpc_call(ToBackend::KeyPressed {
+                view_id,
+                input: KeyInput {
+                    modifiers: Modifiers::empty(),
+                    key: Key(key.to_string()),
+                    code: RawKey::from(*key),
+                },
+            })
+            .await?;
+            expect_msg!("UpdateView", recv, ToFrontend::UpdateView { .. } => ());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_save_as_and_dirty_flag() -> color_eyre::Result<()> {
+        test_util::setup_test();
+        let (mut app, mut to_frontend_recv, view_id) = setup_view().await?;
+        let path = std::env::temp_dir().join(format!("bazed-test-{}.txt", uuid::Uuid::new_v4()));
+
+        press_keys(&mut app, &mut to_frontend_recv, view_id, &["i", "a"]).await?;
+        app.handle_rpc_call(ToBackend::SaveAs {
+            view_id,
+            path: path.clone(),
+            force: false,
+        })
+        .await?;
+        let error = expect_msg!("DocumentSaved", to_frontend_recv, ToFrontend::DocumentSaved { error, .. } => error);
+        assert_eq!(None, error);
+        let dirty = expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { view_data, .. } => view_data.dirty);
+        assert!(!dirty);
+        assert_eq!("a", std::fs::read_to_string(&path)?);
+
+        app.handle_rpc_call(ToBackend::KeyPressed {
+            view_id,
+            input: KeyInput {
+                modifiers: Modifiers::empty(),
+                key: Key("b".to_string()),
+                code: RawKey::from("b"),
+            },
+        })
+        .await?;
+        let dirty = expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { view_data, .. } => view_data.dirty);
+        assert!(dirty);
+
+        app.handle_rpc_call(ToBackend::SaveDocument {
+            view_id,
+            force: false,
+        })
+        .await?;
+        expect_msg!("DocumentSaved", to_frontend_recv, ToFrontend::DocumentSaved { .. } => ());
+        expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { .. } => ());
+        assert_eq!("ab", std::fs::read_to_string(&path)?);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_save_as_refuses_existing_file() -> color_eyre::Result<()> {
+        test_util::setup_test();
+        let (mut app, mut to_frontend_recv, view_id) = setup_view().await?;
+        let path = std::env::temp_dir().join(format!("bazed-test-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "old")?;
+
+        press_keys(&mut app, &mut to_frontend_recv, view_id, &["i", "a"]).await?;
+        for (force, succeeds) in [(false, false), (true, true)] {
+            app.handle_rpc_call(ToBackend::SaveAs {
+                view_id,
+                path: path.clone(),
+                force,
+            })
+            .await?;
+            let error = expect_msg!("DocumentSaved", to_frontend_recv, ToFrontend::DocumentSaved { error, .. } => error);
+            assert_eq!(succeeds, error.is_none(), "{error:?}");
+            expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { .. } => ());
+            let document = app.documents.values().next().unwrap();
+            assert_eq!(succeeds, document.path.as_ref() == Some(&path));
+            let expected = if succeeds { "a" } else { "old" };
+            assert_eq!(expected, std::fs::read_to_string(&path)?);
+        }
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quit_refuses_dirty_document() -> color_eyre::Result<()> {
+        test_util::setup_test();
+        let (mut app, mut to_frontend_recv, view_id) = setup_view().await?;
+
+        let keys = ["i", "a", "Escape", ":", "q", "Enter"];
+        press_keys(&mut app, &mut to_frontend_recv, view_id, &keys).await?;
+        assert_eq!(1, app.views().len());
+        press_keys(&mut app, &mut to_frontend_recv, view_id, &[":", "q", "!"]).await?;
+        app.handle_rpc_call(ToBackend::KeyPressed {
+            view_id,
+            input: KeyInput {
+                modifiers: Modifiers::empty(),
+                key: Key("Enter".to_string()),
+                code: RawKey::from("Enter"),
+            },
+        })
+        .await?;
+        expect_msg!("CloseView", to_frontend_recv, ToFrontend::CloseView { .. } => ());
+        assert!(app.views().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_changed_on_disk() -> color_eyre::Result<()> {
+        test_util::setup_test();
+        let path = std::env::temp_dir().join(format!("bazed-test-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "one\ntwo\n")?;
+        let (to_frontend_send, mut to_frontend_recv) = unbounded::<ToFrontend>();
+        let mut app = App::new(ClientSendHandle(to_frontend_send));
+        app.open_file(path.clone()).await?;
+        let view_id = expect_msg!("OpenView", to_frontend_recv, ToFrontend::OpenView { view_id, .. } => view_id);
+        let changed_path = file_watcher::normalize_path(&path).unwrap();
+
+        // Without unsaved changes, the document is reloaded
+        std::fs::write(&path, "one\ntwo\nthree\n")?;
+        app.handle_file_changed(changed_path.clone()).await?;
+        let view_data = expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { view_data, .. } => view_data);
+        assert_eq!(vec!["one", "two", "three"], view_data.text);
+        assert!(!view_data.dirty);
+
+        // With unsaved changes, the change is merged
+        press_keys(&mut app, &mut to_frontend_recv, view_id, &["i", "x"]).await?;
+        std::fs::write(&path, "one\nchanged\nthree\n")?;
+        app.handle_file_changed(changed_path).await?;
+        let conflict = expect_msg!("FileChanged", to_frontend_recv, ToFrontend::FileChanged { conflict, .. } => conflict);
+        assert!(!conflict);
+        let view_data = expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { view_data, .. } => view_data);
+        assert_eq!(vec!["xone", "changed", "three"], view_data.text);
+        assert!(view_data.dirty);
+
+        // Changes the app didn't notice yet are not overwritten, unless forced to
+        std::fs::write(&path, "other\n")?;
+        app.handle_rpc_call(ToBackend::SaveDocument {
+            view_id,
+            force: false,
+        })
+        .await?;
+        let error = expect_msg!("DocumentSaved", to_frontend_recv, ToFrontend::DocumentSaved { error, .. } => error);
+        assert!(error.is_some());
+        expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { .. } => ());
+        assert_eq!("other\n", std::fs::read_to_string(&path)?);
+        app.handle_rpc_call(ToBackend::SaveDocument {
+            view_id,
+            force: true,
+        })
+        .await?;
+        let error = expect_msg!("DocumentSaved", to_frontend_recv, ToFrontend::DocumentSaved { error, .. } => error);
+        assert_eq!(None, error);
+        assert_eq!("xone\nchanged\nthree\n", std::fs::read_to_string(&path)?);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lossy_file_needs_force() -> color_eyre::Result<()> {
+        test_util::setup_test();
+        let path = std::env::temp_dir().join(format!("bazed-test-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "one\r\ntwo\nthree\n")?;
+        let (to_frontend_send, mut to_frontend_recv) = unbounded::<ToFrontend>();
+        let mut app = App::new(ClientSendHandle(to_frontend_send));
+        app.open_file(path.clone()).await?;
+        let (view_id, command_line) = expect_msg!("OpenView", to_frontend_recv, ToFrontend::OpenView { view_id, view_data, .. } => (view_id, view_data.command_line));
+        assert!(command_line.unwrap().contains("mixes line endings"));
+
+        // Writing would turn the CRLF line ending into LF, so it has to be forced.
+        // Once written, the file can be written without forcing again.
+        for (force, succeeds) in [(false, false), (true, true), (false, true)] {
+            app.handle_rpc_call(ToBackend::SaveDocument { view_id, force })
+                .await?;
+            let error = expect_msg!("DocumentSaved", to_frontend_recv, ToFrontend::DocumentSaved { error, .. } => error);
+            assert_eq!(succeeds, error.is_none(), "{error:?}");
+            expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { .. } => ());
+            let expected = if succeeds {
+                "one\ntwo\nthree\n"
+            } else {
+                "one\r\ntwo\nthree\n"
+            };
+            assert_eq!(expected, std::fs::read_to_string(&path)?);
+        }
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lossy_file_save_as_new_path() -> color_eyre::Result<()> {
+        test_util::setup_test();
+        let path = std::env::temp_dir().join(format!("bazed-test-{}.txt", uuid::Uuid::new_v4()));
+        let new_path = path.with_extension("new.txt");
+        std::fs::write(&path, "one\r\ntwo\nthree\n")?;
+        let (to_frontend_send, mut to_frontend_recv) = unbounded::<ToFrontend>();
+        let mut app = App::new(ClientSendHandle(to_frontend_send));
+        app.open_file(path.clone()).await?;
+        let view_id = expect_msg!("OpenView", to_frontend_recv, ToFrontend::OpenView { view_id, .. } => view_id);
+
+        // The original file stays as it is, so there is nothing to lose by writing to a new one
+        app.handle_rpc_call(ToBackend::SaveAs {
+            view_id,
+            path: new_path.clone(),
+            force: false,
+        })
+        .await?;
+        let error = expect_msg!("DocumentSaved", to_frontend_recv, ToFrontend::DocumentSaved { error, .. } => error);
+        assert_eq!(None, error);
+        assert_eq!("one\ntwo\nthree\n", std::fs::read_to_string(&new_path)?);
+        assert_eq!("one\r\ntwo\nthree\n", std::fs::read_to_string(&path)?);
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(&new_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pending_count() -> color_eyre::Result<()> {
+        let (mut app, mut to_frontend_recv, view_id) = setup_view().await?;
+
+        for digit in ["1", "2"] {
+            app.handle_rpc_call(ToBackend::KeyPressed {
+                view_id,
+                input: KeyInput {
+                    modifiers: Modifiers::empty(),
+                    key: Key(digit.to_string()),
+                    code: RawKey::num(digit),
+                },
+            })
+            .await?;
+        }
+        expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { .. } => ());
+        let pending_count = expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { view_data, .. } => view_data.pending_count);
+        assert_eq!(Some(12), pending_count);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_global_marks() -> color_eyre::Result<()> {
+        test_util::setup_test();
+        let (mut app, mut to_frontend_recv, first_view) = setup_view().await?;
+        app.open_ephemeral().await?;
+        let second_view = expect_msg!("OpenView", to_frontend_recv, ToFrontend::OpenView { view_id, .. } => view_id);
+        let keys = ["i", "f", "o", "o", "Escape", "0", "l", "m", "A"];
+        press_keys(&mut app, &mut to_frontend_recv, first_view, &["m", "A"]).await?;
+        press_keys(&mut app, &mut to_frontend_recv, second_view, &keys).await?;
+
+        press_keys(&mut app, &mut to_frontend_recv, first_view, &["`"]).await?;
+        app.handle_rpc_call(ToBackend::KeyPressed {
+            view_id: first_view,
+            input: KeyInput {
+                modifiers: Modifiers::empty(),
+                key: Key("A".to_string()),
+                code: RawKey::from("A"),
+            },
+        })
+        .await?;
+        expect_msg!("OpenView", to_frontend_recv, ToFrontend::OpenView { .. } => ());
+        expect_msg!("UpdateView", to_frontend_recv, ToFrontend::UpdateView { .. } => ());
+        let document_id = app.views[&ViewId(first_view)].document_id;
+        assert_eq!(document_id, app.views[&ViewId(second_view)].document_id);
+        assert_eq!(1, app.documents.len());
+        assert_eq!(1, app.documents[&document_id].buffer.primary_caret().head);
+
+        let keys = [":", "m", "a", "r", "k", "s", "Enter"];
+        press_keys(&mut app, &mut to_frontend_recv, first_view, &keys).await?;
+        assert_eq!(
+            Some("mark line  col file/text\n A      1    1 foo\n ^      1    3 foo\n .      1    2 foo".to_string()),
+            app.vim_interface.command_line()
+        );
+        Ok(())
+    }
 }
@@ -8,25 +8,81 @@
 //!
 //! Terminology of `Region`s and `Carets` etc. is specified in [BufferRegions].
 
+use std::collections::HashMap;
+
+use bazed_rpc::core_proto::UndoTree;
 use nonempty::NonEmpty;
-use xi_rope::{engine::Engine, DeltaBuilder, LinesMetric, Rope, RopeDelta, RopeInfo};
+use xi_rope::{
+    delta::DeltaElement,
+    diff::{Diff, LineHashDiff},
+    engine::{Engine, RevId},
+    DeltaBuilder, LinesMetric, Rope, RopeDelta, RopeInfo,
+};
 
 use self::{
     buffer_regions::BufferRegions, movement::apply_motion_to_region, position::Position,
-    undo_history::UndoHistory,
+    regex_cursor::RegexCursor, undo_history::UndoHistory,
 };
 use crate::{
-    region::Region,
-    user_buffer_op::{BufferOp, EditType, Motion},
+    region::{Region, RegionId},
+    register::RegisterContent,
+    search,
+    user_buffer_op::{
+        BufferOp, EditType, Motion, Operator, OperatorTarget, SurroundPair, TextObjectKind,
+    },
     view::Viewport,
 };
 
 mod buffer_regions;
+mod matching_pair;
 mod movement;
+mod number;
+mod operator;
 pub mod position;
 mod regex_cursor;
+mod text_object;
+mod undo_file;
 mod undo_history;
 
+/// Maximum number of positions kept in the change list
+const MAX_CHANGES: usize = 100;
+
+/// The carets of a buffer at some point in time, which can be restored later on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CaretSnapshot {
+    carets: NonEmpty<Region>,
+    primary_index: usize,
+}
+
+/// The carets from right before and right after the edits of an undo group,
+/// which are restored when undoing and redoing the group respectively
+#[derive(Debug, Clone)]
+struct UndoCarets {
+    before: CaretSnapshot,
+    after: CaretSnapshot,
+}
+
+/// A line of the rectangle selected in visual block mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BlockLine {
+    /// Part of the line inside the block.
+    /// Empty and at the end of the line if the line is short.
+    pub(crate) range: std::ops::Range<usize>,
+    /// Set if the line ends before the left edge of the block
+    pub(crate) short: bool,
+}
+
+/// Where [Buffer::split_block] places the carets in each line of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BlockSplit {
+    /// Select the part of every line inside the block, skipping short lines
+    Selections,
+    /// Place a caret at the left edge of the block, skipping short lines
+    Starts,
+    /// Place a caret at the right edge of the block, or at the end of short lines
+    Ends,
+}
+
 #[derive(Debug)]
 pub struct Buffer {
     text: Rope,
@@ -35,6 +91,18 @@ pub struct Buffer {
     undo_history: UndoHistory,
     /// edit type of the most recently performed action, kept for grouping edits into undo-groups
     last_edit_type: EditType,
+    /// Number of currently active, nested calls to [Buffer::begin_undo_group].
+    /// While this is non-zero, all edits are grouped into a single undo group.
+    undo_group_depth: usize,
+    /// Set once an edit started the undo group opened by [Buffer::begin_undo_group]
+    undo_group_started: bool,
+    /// Carets of the undo groups created since the buffer was loaded, by undo group id
+    undo_carets: HashMap<usize, UndoCarets>,
+    /// Regions at the positions of the most recent changes, oldest first, gone through by `g;` and `g,`
+    change_list: Vec<RegionId>,
+    /// Index of the change that was gone to most recently,
+    /// or the number of changes if none was since the last change
+    change_list_index: usize,
 }
 
 impl Buffer {
@@ -46,6 +114,11 @@ impl Buffer {
             regions: BufferRegions::default(),
             undo_history: UndoHistory::default(),
             last_edit_type: EditType::Other,
+            undo_group_depth: 0,
+            undo_group_started: false,
+            undo_carets: HashMap::new(),
+            change_list: Vec::new(),
+            change_list_index: 0,
         }
     }
 
@@ -69,11 +142,38 @@ impl Buffer {
         self.text.measure::<LinesMetric>()
     }
 
+    /// Index of the last line, not counting the empty line after a final line break
+    pub(crate) fn last_line(&self) -> usize {
+        let line_count = self.line_count();
+        if line_count > 0
+            && self
+                .text
+                .slice_to_cow(self.text.len() - 1..)
+                .ends_with('\n')
+        {
+            line_count - 1
+        } else {
+            line_count
+        }
+    }
+
     /// Return a snapshot of the latest commited state of the text
     pub fn head_rope(&self) -> &Rope {
         self.engine.get_head()
     }
 
+    /// Identifier of the latest commited state of the text
+    pub fn head_revision(&self) -> RevId {
+        self.engine.get_head_rev_id()
+    }
+
+    /// Check whether the text is the same as at the given revision,
+    /// either because it wasn't changed since, or because all changes since then were undone
+    pub fn is_at_revision(&self, revision: RevId) -> bool {
+        self.engine
+            .is_equivalent_revision(revision, self.engine.get_head_rev_id())
+    }
+
     /// Return all regions corresponding to carets or selections
     pub fn all_carets(&self) -> NonEmpty<Region> {
         self.regions.carets()
@@ -109,6 +209,276 @@ impl Buffer {
             .expect("Caret stored in BufferRegions was not a valid offset into the buffer")
     }
 
+    /// Remember the current carets, such that they can be restored with [Self::restore_carets]
+    pub(crate) fn caret_snapshot(&self) -> CaretSnapshot {
+        CaretSnapshot {
+            carets: self.regions.carets(),
+            primary_index: self.regions.primary_caret_index(),
+        }
+    }
+
+    /// Replace all carets with the ones stored in a [CaretSnapshot]
+    pub(crate) fn restore_carets(&mut self, snapshot: CaretSnapshot) {
+        self.regions
+            .set_carets(snapshot.carets, snapshot.primary_index);
+        self.clamp_carets_to_text();
+    }
+
+    /// Set a mark at the given offset, which then moves along with the text around it.
+    ///
+    /// `` ` `` names the same mark as `'`, as in vim.
+    pub(crate) fn set_mark(&mut self, mark: char, offset: usize) {
+        let offset = offset.min(self.text.len());
+        self.regions
+            .set_mark(mark_name(mark), Region::sticky_cursor(offset));
+    }
+
+    pub(crate) fn remove_mark(&mut self, mark: char) {
+        self.regions.remove_mark(mark_name(mark));
+    }
+
+    /// The offset of a mark, if it is set
+    pub(crate) fn mark_offset(&self, mark: char) -> Option<usize> {
+        Some(self.regions.mark(mark_name(mark))?.head)
+    }
+
+    /// The position of a mark, if it is set
+    pub(crate) fn mark_position(&self, mark: char) -> Option<Position> {
+        Position::from_offset(&self.text, self.mark_offset(mark)?)
+    }
+
+    /// All marks that are set, together with their offsets, in no particular order
+    pub(crate) fn marks(&self) -> Vec<(char, usize)> {
+        self.regions
+            .marks()
+            .map(|(mark, region)| (mark, region.head))
+            .collect()
+    }
+
+    /// Keep track of an offset as a region that moves along with edits, until it is removed again
+    pub(crate) fn add_anchor(&mut self, offset: usize) -> RegionId {
+        let offset = offset.min(self.text.len());
+        self.regions.add_region(Region::sticky_cursor(offset))
+    }
+
+    pub(crate) fn anchor_offset(&self, id: RegionId) -> Option<usize> {
+        Some(self.regions.region(id)?.head)
+    }
+
+    pub(crate) fn remove_anchor(&mut self, id: RegionId) {
+        self.regions.remove_region(id);
+    }
+
+    /// Add the position of a change to the change list.
+    /// Like in vim, consecutive changes within the same line only keep the position of the most recent one.
+    fn record_change(&mut self, offset: usize) {
+        let line = self.text.line_of_offset(offset);
+        if let Some(last) = self.change_list.last().copied() {
+            let last_line = self
+                .anchor_offset(last)
+                .map(|x| self.text.line_of_offset(x));
+            if last_line == Some(line) {
+                self.change_list.pop();
+                self.remove_anchor(last);
+            }
+        }
+        if self.change_list.len() >= MAX_CHANGES {
+            let oldest = self.change_list.remove(0);
+            self.remove_anchor(oldest);
+        }
+        let anchor = self.add_anchor(offset);
+        self.change_list.push(anchor);
+        self.change_list_index = self.change_list.len();
+    }
+
+    /// Go back to older changes, or forth to newer ones if `newer` is set, by the given number of changes,
+    /// returning the offset of the change gone to
+    pub(crate) fn walk_change_list(&mut self, count: usize, newer: bool) -> Option<usize> {
+        let index = if newer {
+            self.change_list_index + count
+        } else {
+            self.change_list_index.checked_sub(count)?
+        };
+        let offset = self.anchor_offset(*self.change_list.get(index)?)?;
+        self.change_list_index = index;
+        Some(offset)
+    }
+
+    /// Find all non-empty matches of the regex that start within the given range
+    pub(crate) fn find_matches(
+        &self,
+        regex: &hotsauce::Regex,
+        range: std::ops::Range<usize>,
+    ) -> Vec<std::ops::Range<usize>> {
+        let mut matches = Vec::new();
+        let mut offset = range.start;
+        while offset < range.end {
+            let Some(m) = RegexCursor::new(&self.text, offset, regex).next_match() else { break };
+            if m.start >= range.end {
+                break;
+            }
+            offset = if m.is_empty() {
+                self.text
+                    .next_grapheme_offset(m.end)
+                    .unwrap_or(self.text.len())
+            } else {
+                m.end
+            };
+            if !m.is_empty() {
+                matches.push(m);
+            }
+        }
+        matches
+    }
+
+    /// Return the word under the primary caret, or the next word after it within the same line.
+    /// Words consist of alphanumeric characters and underscores, like vim's `iskeyword`.
+    pub(crate) fn word_at_primary_caret(&self) -> Option<String> {
+        let range = self.word_range_at_primary_caret()?;
+        Some(self.text.slice_to_cow(range).to_string())
+    }
+
+    /// Range of the word returned by [Self::word_at_primary_caret]
+    fn word_range_at_primary_caret(&self) -> Option<std::ops::Range<usize>> {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+        let head = self.primary_caret().head;
+        let line = self.text.line_of_offset(head);
+        let line_start = self.text.offset_of_line(line);
+        let line_text = self.lines_between(line, line + 1).next()?.to_string();
+        let col = head - line_start;
+        let start = if line_text[col..].starts_with(is_word_char) {
+            line_text[..col]
+                .char_indices()
+                .rev()
+                .take_while(|(_, c)| is_word_char(*c))
+                .last()
+                .map_or(col, |(i, _)| i)
+        } else {
+            col
+        };
+        let start = line_text[start..]
+            .char_indices()
+            .find(|(_, c)| is_word_char(*c))
+            .map(|(i, _)| start + i)?;
+        let end = line_text[start..]
+            .char_indices()
+            .find(|(_, c)| !is_word_char(*c))
+            .map_or(line_text.len(), |(i, _)| start + i);
+        Some(line_start + start..line_start + end)
+    }
+
+    /// Range of the lines shown by the viewport, up to the end of its last line
+    pub(crate) fn viewport_range(&self, vp: &Viewport) -> std::ops::Range<usize> {
+        let last_line = self.text.line_of_offset(self.text.len());
+        let start = self.text.offset_of_line(vp.first_line.min(last_line));
+        let end = if vp.last_line() < last_line {
+            self.text.offset_of_line(vp.last_line() + 1)
+        } else {
+            self.text.len()
+        };
+        start..end
+    }
+
+    /// Check if every caret has the given number of grapheme clusters under and after it within its line,
+    /// such that [OperatorTarget::Graphemes] can act on them without being clamped
+    pub(crate) fn carets_have_graphemes(&self, count: usize) -> bool {
+        self.regions
+            .carets()
+            .iter()
+            .all(|region| operator::graphemes_at(&self.text, region.head, count, false).is_some())
+    }
+
+    /// Move every caret past the given number of grapheme clusters, stopping at the end of its line
+    pub(crate) fn advance_carets_in_line(&mut self, count: usize) {
+        self.regions.update_carets(|_, region| {
+            if let Some(range) = operator::graphemes_at(&self.text, region.head, count, true) {
+                *region = Region::sticky_cursor(range.end);
+            }
+        });
+    }
+
+    /// Offsets of the bracket under the primary caret and of the bracket matching it, if both are in the viewport.
+    ///
+    /// Like vim's matchparen, the search for the match stops at the edges of the viewport,
+    /// as an unmatched bracket would otherwise make it walk the whole text on every update.
+    pub(crate) fn matching_pair(&self, vp: &Viewport) -> Option<(usize, usize)> {
+        let head = self.primary_caret().head;
+        let bounds = self.viewport_range(vp);
+        let matching = matching_pair::find_matching_bracket_within(&self.text, head, &[], bounds)?;
+        Some((head, matching))
+    }
+
+    /// Regex matching the text selected by the primary caret literally.
+    ///
+    /// If the primary caret is a cursor, the word under it gets selected first.
+    fn occurrence_regex(&mut self) -> Option<hotsauce::Regex> {
+        let mut selection = self.primary_caret().range();
+        if selection.is_empty() {
+            selection = self.word_range_at_primary_caret()?;
+            self.regions
+                .set_primary_caret(Region::sticky(selection.end, selection.start));
+        }
+        let text = self.text.slice_to_cow(selection);
+        hotsauce::Regex::new(&search::escape(&text)).ok()
+    }
+
+    /// Add a selection at the next occurrence of the text selected by the primary caret that isn't selected yet,
+    /// wrapping around at the end of the text, and make it the primary caret.
+    /// If `skip` is set, the primary caret is moved to that occurrence instead.
+    ///
+    /// If the primary caret is a cursor, this only selects the word under it.
+    fn select_next_occurrence(&mut self, skip: bool) {
+        if self.primary_caret().is_cursor() {
+            // Selecting the word is all the first step does
+            self.occurrence_regex();
+            return;
+        }
+        let Some(regex) = self.occurrence_regex() else { return };
+        let carets = self.regions.carets();
+        let selected = |m: &std::ops::Range<usize>| {
+            carets
+                .iter()
+                .any(|caret| caret.range().start < m.end && m.start < caret.range().end)
+        };
+        let range = self.primary_caret().range();
+        let mut after_selection = RegexCursor::new(&self.text, range.end, &regex);
+        let mut from_start = RegexCursor::new(&self.text, 0, &regex);
+        let next = std::iter::from_fn(|| after_selection.next_match())
+            .chain(
+                std::iter::from_fn(|| from_start.next_match())
+                    .take_while(|m| m.start < range.start),
+            )
+            .find(|m| !m.is_empty() && !selected(m));
+        let Some(next) = next else { return };
+        let selection = Region::sticky(next.end, next.start);
+        if skip && carets.len() == 1 {
+            self.regions.set_primary_caret(selection);
+        } else {
+            if skip {
+                self.regions.remove_primary_caret();
+            }
+            self.regions.add_caret(true, selection);
+        }
+    }
+
+    /// Select all occurrences of the text selected by the primary caret within the given range,
+    /// keeping the primary caret on the occurrence it selected.
+    ///
+    /// If the primary caret is a cursor, the occurrences of the word under it are selected.
+    fn select_all_occurrences(&mut self, range: std::ops::Range<usize>) {
+        let Some(regex) = self.occurrence_regex() else { return };
+        let primary = self.primary_caret().range();
+        let matches = self.matches_within(&regex, range);
+        let primary_index = matches.iter().position(|m| *m == primary).unwrap_or(0);
+        let selections = matches
+            .into_iter()
+            .map(|m| Region::sticky(m.end, m.start))
+            .collect();
+        if let Some(selections) = NonEmpty::from_vec(selections) {
+            self.regions.set_carets(selections, primary_index);
+        }
+    }
+
     pub fn start_building_delta(&self) -> DeltaBuilder<RopeInfo> {
         DeltaBuilder::new(self.text.len())
     }
@@ -129,28 +499,240 @@ impl Buffer {
         self.regions.collapse_selections();
     }
 
-    /// Snap all regions to the closest valid points in the buffer.
+    /// Remove all carets except for the primary one
+    pub(crate) fn collapse_carets_into_primary(&mut self) {
+        self.regions.collapse_carets_into_primary();
+    }
+
+    /// The rectangle spanned by the head and tail of the primary caret, as selected in visual block mode,
+    /// split into the lines it covers.
+    ///
+    /// The preferred column of the head counts as its column, such that the block keeps its width
+    /// when the head passes through shorter lines.
+    /// If `to_line_end` is set, the block extends to the end of every line instead, giving it a ragged right edge.
+    ///
+    /// The columns of the block are counted in grapheme clusters, such that it never splits a character.
+    pub(crate) fn block_lines(&self, to_line_end: bool) -> Vec<BlockLine> {
+        let caret = self.primary_caret();
+        let head = Position::from_offset(&self.text, caret.head)
+            .expect("Caret stored in BufferRegions was not a valid offset into the buffer");
+        let tail = Position::from_offset(&self.text, caret.tail)
+            .expect("Caret stored in BufferRegions was not a valid offset into the buffer");
+        // The head may lie before its preferred column, if its line is too short to reach it
+        let head_col = self.grapheme_column(caret.head)
+            + caret
+                .preferred_column
+                .map_or(0, |col| col.saturating_sub(head.col));
+        let tail_col = self.grapheme_column(caret.tail);
+        let left = head_col.min(tail_col);
+        let right = head_col.max(tail_col);
+        (head.line.min(tail.line)..=head.line.max(tail.line))
+            .map(|line| {
+                let len = self.grapheme_column(self.end_of_line(line));
+                let right = if to_line_end { len } else { right.min(len) };
+                BlockLine {
+                    range: self.offset_of_grapheme_column(line, left.min(len))
+                        ..self.offset_of_grapheme_column(line, right),
+                    short: len < left,
+                }
+            })
+            .collect()
+    }
+
+    /// Number of grapheme clusters between the start of the line of the offset and the offset
+    fn grapheme_column(&self, offset: usize) -> usize {
+        let mut current = self.text.offset_of_line(self.text.line_of_offset(offset));
+        let mut column = 0;
+        while current < offset {
+            let Some(next) = self.text.next_grapheme_offset(current) else { break };
+            current = next;
+            column += 1;
+        }
+        column
+    }
+
+    /// Offset of the grapheme cluster at the given column of a line, or of the end of the line if it is shorter
+    fn offset_of_grapheme_column(&self, line: usize, column: usize) -> usize {
+        let end = self.end_of_line(line);
+        let mut offset = self.text.offset_of_line(line);
+        for _ in 0..column {
+            match self.text.next_grapheme_offset(offset) {
+                Some(next) if next <= end => offset = next,
+                _ => break,
+            }
+        }
+        offset
+    }
+
+    /// Offset of the end of a line, right before its line break
+    fn end_of_line(&self, line: usize) -> usize {
+        operator::end_of_line(&self.text, line)
+    }
+
+    /// Replace every caret with the selections `f` makes out of its range.
     ///
-    /// This may be required if an action (such as undo, currently) changes the buffer
-    /// without moving the regions accordingly. In the future, this should not be required
-    /// as all actions _should_ move all regions properly, either through a coordinate transform
-    /// with [xi_rope::Transformer], or, in the case of undo, by remembering where the carets where before.
+    /// The first selection made out of the primary caret becomes the primary one,
+    /// or the next selection if it didn't make any.
+    /// If no selections are made at all, the carets are left as they are.
+    fn map_selections(
+        &mut self,
+        mut f: impl FnMut(&Self, std::ops::Range<usize>) -> Vec<std::ops::Range<usize>>,
+    ) {
+        let primary_index = self.regions.primary_caret_index();
+        let mut selections = Vec::new();
+        let mut new_primary_index = 0;
+        for (index, caret) in self.regions.carets().into_iter().enumerate() {
+            if index == primary_index {
+                new_primary_index = selections.len();
+            }
+            selections.extend(
+                f(self, caret.range())
+                    .into_iter()
+                    .map(|range| Region::sticky(range.end, range.start)),
+            );
+        }
+        if let Some(selections) = NonEmpty::from_vec(selections) {
+            let primary_index = new_primary_index.min(selections.len() - 1);
+            self.regions.set_carets(selections, primary_index);
+        }
+    }
+
+    /// Join every run of overlapping or touching selections into a single one
+    fn merge_selections(&mut self) {
+        let primary_index = self.regions.primary_caret_index();
+        let mut merged: Vec<std::ops::Range<usize>> = Vec::new();
+        let mut new_primary_index = 0;
+        for (index, caret) in self.regions.carets().into_iter().enumerate() {
+            let range = caret.range();
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+            if index == primary_index {
+                new_primary_index = merged.len() - 1;
+            }
+        }
+        let selections = merged
+            .into_iter()
+            .map(|range| Region::sticky(range.end, range.start))
+            .collect();
+        if let Some(selections) = NonEmpty::from_vec(selections) {
+            self.regions.set_carets(selections, new_primary_index);
+        }
+    }
+
+    /// Find all non-empty matches of the regex that lie fully within the given range
+    fn matches_within(
+        &self,
+        regex: &hotsauce::Regex,
+        range: std::ops::Range<usize>,
+    ) -> Vec<std::ops::Range<usize>> {
+        let mut matches = self.find_matches(regex, range.clone());
+        matches.retain(|m| m.end <= range.end);
+        matches
+    }
+
+    /// Split a range at the matches of the regex, returning the non-empty parts in between
+    fn split_at_matches(
+        &self,
+        regex: &hotsauce::Regex,
+        range: std::ops::Range<usize>,
+    ) -> Vec<std::ops::Range<usize>> {
+        let mut parts = Vec::new();
+        let mut start = range.start;
+        for m in self.matches_within(regex, range.clone()) {
+            parts.push(start..m.start);
+            start = m.end;
+        }
+        parts.push(start..range.end);
+        parts.retain(|part| !part.is_empty());
+        parts
+    }
+
+    /// Split a range into the non-empty parts of the lines it touches, leaving out the line breaks
+    fn split_into_lines(&self, range: std::ops::Range<usize>) -> Vec<std::ops::Range<usize>> {
+        let first_line = self.text.line_of_offset(range.start);
+        let last_line = self.text.line_of_offset(range.end);
+        (first_line..=last_line)
+            .map(|line| {
+                self.text.offset_of_line(line).max(range.start)
+                    ..self.end_of_line(line).min(range.end)
+            })
+            .filter(|part| !part.is_empty())
+            .collect()
+    }
+
+    /// Replace the carets with one caret per line of the block selected in visual block mode,
+    /// placed as given by `split`. The caret in the first line becomes the primary one.
+    pub(crate) fn split_block(&mut self, to_line_end: bool, split: BlockSplit) {
+        let carets = self
+            .block_lines(to_line_end)
+            .into_iter()
+            .filter(|line| split == BlockSplit::Ends || !line.short)
+            .map(|BlockLine { range, .. }| match split {
+                BlockSplit::Selections => Region::sticky(range.end, range.start),
+                BlockSplit::Starts => Region::sticky_cursor(range.start),
+                BlockSplit::Ends => Region::sticky_cursor(range.end),
+            })
+            .collect();
+        if let Some(carets) = NonEmpty::from_vec(carets) {
+            self.regions.set_carets(carets, 0);
+        }
+    }
+
+    /// Move carets that lie beyond the end of the text to its end.
     ///
-    /// **WARNING:** This is very much a temporary solution, as it _will_ cause inconsistent state as soon as we use
-    /// regions for more than just caret position. (see <https://github.com/bazed-editor/bazed/issues/47>)
-    fn snap_regions_to_valid_position(&mut self) {
-        self.regions.update_regions(|_, region| {
+    /// Only needed for carets that weren't moved along with the edits to the text,
+    /// i.e. ones restored from a snapshot taken before the text changed.
+    fn clamp_carets_to_text(&mut self) {
+        self.regions.update_carets(|_, region| {
             region.head = region.head.min(self.text.len());
             region.tail = region.tail.min(self.text.len());
         });
     }
 
-    #[tracing::instrument(skip(self), fields(head_rev_id = ?self.engine.get_head_rev_id()))]
+    /// Apply an edit made by the user, which sets the `.` mark and is added to the change list.
     pub fn commit_delta(&mut self, delta: RopeDelta, edit_type: EditType) -> Rope {
+        let (changed, _) = delta.summary();
+        self.remove_marks_on_deleted_lines(&delta);
+        let text = self.commit_untracked_delta(delta, edit_type);
+        self.set_mark('.', changed.start());
+        self.record_change(changed.start());
+        text
+    }
+
+    /// Remove the marks on lines the delta deletes entirely, as vim does instead of moving them to the next line.
+    /// A line is deleted along with its line break, or the one before it if it is the last line.
+    fn remove_marks_on_deleted_lines(&mut self, delta: &RopeDelta) {
+        let deleted = changed_ranges(delta);
+        let last_line = self.text.line_of_offset(self.text.len());
+        for (mark, offset) in self.marks() {
+            let line = self.text.line_of_offset(offset);
+            let lines = operator::full_lines(&self.text, line, line);
+            let line_deleted = deleted.iter().any(|range| {
+                range.start <= lines.start
+                    && lines.end <= range.end
+                    && (line < last_line || range.start < lines.start)
+            });
+            if line_deleted {
+                self.remove_mark(mark);
+            }
+        }
+    }
+
+    /// Apply an edit without treating it as a change made by the user, as done for changes merged in from disk.
+    #[tracing::instrument(skip(self), fields(head_rev_id = ?self.engine.get_head_rev_id()))]
+    fn commit_untracked_delta(&mut self, delta: RopeDelta, edit_type: EditType) -> Rope {
         tracing::debug!("Committing delta");
+        let carets_before = self.caret_snapshot();
         self.regions.apply_delta(&delta);
 
-        if self.last_edit_type != edit_type {
+        let start_new_group = if self.undo_group_depth > 0 {
+            !std::mem::replace(&mut self.undo_group_started, true)
+        } else {
+            self.last_edit_type != edit_type || edit_type == EditType::Other
+        };
+        if start_new_group {
             self.undo_history.start_new_undo_group();
         }
         let undo_group = self.undo_history.calculate_undo_id();
@@ -161,9 +743,26 @@ impl Buffer {
         self.engine.edit_rev(1, undo_group, head_rev.token(), delta);
 
         self.text = self.engine.get_head().clone();
+        self.undo_carets
+            .entry(undo_group)
+            .or_insert_with(|| UndoCarets {
+                after: carets_before.clone(),
+                before: carets_before,
+            });
+        self.record_carets_after_edit();
         self.text.clone()
     }
 
+    /// Remember the current carets as the ones to restore when redoing the current undo group.
+    ///
+    /// This happens for every edit, but has to be repeated by edits that place the carets themselves afterwards.
+    fn record_carets_after_edit(&mut self) {
+        let snapshot = self.caret_snapshot();
+        if let Some(carets) = self.undo_carets.get_mut(&self.undo_history.current()) {
+            carets.after = snapshot;
+        }
+    }
+
     fn insert_at_carets(&mut self, chars: &str) {
         let mut builder = DeltaBuilder::new(self.text.len());
         let text: Rope = chars.into();
@@ -193,16 +792,88 @@ impl Buffer {
         self.commit_delta(delta, EditType::Replace);
     }
 
-    /// Delete exactly the ranges of all carets (meaning that only selections will be affected)
-    fn delete_in_selections(&mut self) {
+    /// Return the text that is selected by each caret
+    pub fn selected_texts(&self) -> Vec<String> {
+        self.regions
+            .carets()
+            .iter()
+            .map(|region| self.text.slice_to_cow(region.range()).to_string())
+            .collect()
+    }
+
+    /// Replace each of the given, non-overlapping ranges with its text, as a single edit
+    pub(crate) fn replace_ranges(
+        &mut self,
+        edits: impl IntoIterator<Item = (std::ops::Range<usize>, String)>,
+    ) {
         let mut builder = DeltaBuilder::new(self.text.len());
-        for region in self.regions.carets() {
-            builder.delete(region.range());
+        for (range, text) in edits {
+            builder.replace(range, Rope::from(text));
         }
-        let delta = builder.build();
-        self.commit_delta(delta, EditType::Delete);
+        self.commit_delta(builder.build(), EditType::Other);
+    }
+
+    /// Offsets from the start of the first up to the end of the last of the given lines,
+    /// excluding the line break at the end of the last line
+    pub(crate) fn offsets_of_lines(
+        &self,
+        lines: std::ops::RangeInclusive<usize>,
+    ) -> std::ops::Range<usize> {
+        let range = operator::full_lines(&self.text, *lines.start(), *lines.end());
+        let end = if self.text.slice_to_cow(range.clone()).ends_with('\n') {
+            range.end - 1
+        } else {
+            range.end
+        };
+        range.start..end
+    }
+
+    /// Text of each of the given lines, without line breaks
+    pub(crate) fn line_contents(&self, lines: std::ops::RangeInclusive<usize>) -> Vec<String> {
+        let range = self.offsets_of_lines(lines);
+        self.text
+            .slice_to_cow(range)
+            .split('\n')
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Merge changes made to the text outside of the editor, i.e. by another program changing the file.
+    ///
+    /// `base` is the text both the buffer and `theirs` were derived from.
+    /// The changes from `base` to the buffer and to `theirs` are combined in a three-way merge,
+    /// by applying both as concurrent edits to the base revision of an [Engine].
+    /// The merged changes are a single undo group, and carets are moved along with the text.
+    ///
+    /// Returns true if the changes conflict, i.e. both sides changed the same part of the text.
+    /// Conflicting changes are still both kept, next to each other.
+    pub(crate) fn merge_external_change(&mut self, base: &Rope, theirs: &Rope) -> bool {
+        let ours_delta = LineHashDiff::compute_delta(base, &self.text);
+        let theirs_delta = LineHashDiff::compute_delta(base, theirs);
+        let ours_changes = changed_ranges(&ours_delta);
+        let conflict = changed_ranges(&theirs_delta).iter().any(|theirs| {
+            ours_changes
+                .iter()
+                .any(|ours| ours.start <= theirs.end && theirs.start <= ours.end)
+        });
+
+        let mut merge = Engine::new(base.clone());
+        let base_rev = merge.get_head_rev_id().token();
+        merge.edit_rev(1, 1, base_rev, ours_delta);
+        let ours_rev = merge.get_head_rev_id().token();
+        merge.edit_rev(0, 2, base_rev, theirs_delta);
+        match merge.try_delta_rev_head(ours_rev) {
+            Ok(delta) if delta.is_identity() => {},
+            Ok(delta) => {
+                self.break_undo_group();
+                self.commit_untracked_delta(delta, EditType::Other);
+            },
+            Err(err) => tracing::error!("Error generating delta while merging changes: {err}"),
+        }
+        conflict
     }
 
+    /// Delete the text between each caret and the position the motion takes it to.
     fn delete_at_carets(&mut self, vp: &Viewport, motion: Motion<'_>) {
         let mut builder = DeltaBuilder::new(self.text.len());
         for region in self.regions.carets() {
@@ -215,10 +886,343 @@ impl Buffer {
         self.commit_delta(delta, EditType::Delete);
     }
 
+    /// Apply an operator at every caret.
+    ///
+    /// Returns the text each caret's operator acted on, as it was before the operation,
+    /// such that it can be stored in a register.
+    pub(crate) fn apply_operator(
+        &mut self,
+        vp: &Viewport,
+        operator: Operator,
+        target: OperatorTarget,
+    ) -> RegisterContent {
+        let caret_ranges = self
+            .regions
+            .carets()
+            .map(|region| operator::operator_range(&self.text, vp, region, operator, target));
+        let ranges = operator::merge_ranges(caret_ranges.iter().cloned().collect());
+        let content = RegisterContent::new(
+            ranges.iter().map(|x| x.register_text(&self.text)).collect(),
+            ranges.iter().any(|x| x.linewise),
+        );
+
+        if operator == Operator::Yank {
+            // Nothing changes, so the carets just move to the start of their range,
+            // unless they are already in the first of the lines they act on.
+            let mut caret_ranges = caret_ranges.into_iter();
+            self.regions.update_carets(|_, region| {
+                let Some(range) = caret_ranges.next() else { return };
+                let stays_in_line = range.linewise
+                    && self.text.line_of_offset(region.head)
+                        == self.text.line_of_offset(range.range.start);
+                let offset = if stays_in_line {
+                    region.head
+                } else {
+                    range.range.start
+                };
+                *region = Region::sticky_cursor(offset);
+            });
+            return content;
+        }
+
+        let edits = ranges
+            .iter()
+            .filter_map(|range| operator::operator_edit(&self.text, operator, range))
+            .collect::<Vec<_>>();
+        // Carets are placed at the start of the text that replaced their range.
+        // These positions have to be computed up front, as carets in adjacent edits
+        // can't be told apart anymore once the delta has been applied.
+        let mut builder = DeltaBuilder::new(self.text.len());
+        let mut new_starts = Vec::with_capacity(edits.len());
+        let mut shift = 0isize;
+        for (range, replacement) in &edits {
+            new_starts.push(range.start.saturating_add_signed(shift));
+            shift += replacement.len() as isize - range.len() as isize;
+            builder.replace(range.clone(), Rope::from(replacement.as_str()));
+        }
+        let caret_offsets = caret_ranges.map(|caret_range| {
+            edits
+                .iter()
+                .zip(&new_starts)
+                .find(|((range, _), _)| {
+                    let start = caret_range.range.start;
+                    range.contains(&start) || range.start == start
+                        // Joining lines only replaces the text after the first line
+                        || matches!(operator, Operator::Join { .. })
+                            && caret_range.range.contains(&range.start)
+                })
+                .map_or(caret_range.range.start, |(_, start)| *start)
+        });
+
+        let edit_type = if operator == Operator::Change {
+            // Text typed after changing gets grouped together with the change itself
+            self.break_undo_group();
+            EditType::Insert
+        } else {
+            EditType::Other
+        };
+        let primary_index = self.regions.primary_caret_index();
+        self.commit_delta(builder.build(), edit_type);
+
+        let linewise = content.linewise;
+        let carets = caret_offsets.map(|offset| {
+            let offset = match operator {
+                Operator::Change | Operator::Join { .. } => offset,
+                _ if linewise => operator::first_non_blank_in_line(&self.text, offset),
+                _ => offset,
+            };
+            Region::sticky_cursor(offset)
+        });
+        self.regions.set_carets(carets, primary_index);
+        self.record_carets_after_edit();
+        content
+    }
+
+    /// Surround the range the target covers at every caret with the pair, as a single edit.
+    ///
+    /// Whitespace at the start and end of the ranges, like indentation and line breaks, is left outside of the pair.
+    /// The carets end up on the opening delimiters.
+    fn surround(&mut self, vp: &Viewport, target: OperatorTarget, pair: &SurroundPair) {
+        // Surrounding acts on the same ranges that yanking does
+        let ranges = self
+            .regions
+            .carets()
+            .map(|region| operator::operator_range(&self.text, vp, region, Operator::Yank, target));
+        let ranges = operator::merge_ranges(ranges.into_iter().collect());
+        let mut builder = DeltaBuilder::new(self.text.len());
+        let mut starts = Vec::with_capacity(ranges.len());
+        let mut shift = 0;
+        for range in ranges {
+            let mut range = range.range;
+            let content = self.text.slice_to_cow(range.clone());
+            range.end -= content.len() - content.trim_end().len();
+            range.start = (range.start + content.len() - content.trim_start().len()).min(range.end);
+            starts.push(range.start + shift);
+            shift += pair.open.len() + pair.close.len();
+            builder.replace(range.start..range.start, Rope::from(pair.open.as_str()));
+            builder.replace(range.end..range.end, Rope::from(pair.close.as_str()));
+        }
+        let Some(carets) = NonEmpty::from_vec(starts) else { return };
+        let primary_index = self.regions.primary_caret_index().min(carets.len() - 1);
+        self.commit_delta(builder.build(), EditType::Other);
+        self.regions
+            .set_carets(carets.map(Region::sticky_cursor), primary_index);
+        self.record_carets_after_edit();
+    }
+
+    /// Replace the delimiters of the bracket, quote or tag object around every caret with the pair,
+    /// or delete them if no pair is given, as a single edit.
+    ///
+    /// Carets whose objects were changed end up on the opening delimiters.
+    fn change_surround(&mut self, object: TextObjectKind, pair: Option<&SurroundPair>) {
+        let (open, close) = pair.map_or(("", ""), |x| (x.open.as_str(), x.close.as_str()));
+        let carets = self.regions.carets();
+        let delimiters = carets
+            .clone()
+            .map(|caret| text_object::find_delimiters(&self.text, caret.head, object));
+        let mut edits = delimiters
+            .iter()
+            .flatten()
+            .flat_map(|(open_range, close_range)| {
+                [(open_range.clone(), open), (close_range.clone(), close)]
+            })
+            .collect::<Vec<_>>();
+        edits.sort_by_key(|(range, _)| range.start);
+        edits.dedup_by_key(|(range, _)| range.start);
+        if edits.is_empty() {
+            return;
+        }
+
+        let mut builder = DeltaBuilder::new(self.text.len());
+        for (range, replacement) in &edits {
+            builder.replace(range.clone(), Rope::from(*replacement));
+        }
+        let new_offset = |offset: usize| {
+            let shift = edits
+                .iter()
+                .take_while(|(range, _)| range.start < offset)
+                .map(|(range, replacement)| replacement.len() as isize - range.len() as isize)
+                .sum::<isize>();
+            offset.saturating_add_signed(shift)
+        };
+        let new_carets = carets
+            .iter()
+            .zip(delimiters.iter())
+            .map(|(caret, delimiters)| {
+                let offset = match delimiters {
+                    Some((open_range, _)) => open_range.start,
+                    None => caret.head,
+                };
+                Region::sticky_cursor(new_offset(offset))
+            });
+        let new_carets = NonEmpty::collect(new_carets).expect("carets are never empty");
+        let primary_index = self.regions.primary_caret_index();
+        self.commit_delta(builder.build(), EditType::Other);
+        self.regions.set_carets(new_carets, primary_index);
+        self.record_carets_after_edit();
+    }
+
+    /// Add the amount to the number under or after each caret within its line, as done by `Ctrl-a` and `Ctrl-x` in vim.
+    /// Carets on a number end up on its last character.
+    fn add_to_numbers(&mut self, amount: i64) {
+        let numbers = self.regions.carets().map(|caret| {
+            let line = self.text.line_of_offset(caret.head);
+            let line_start = self.text.offset_of_line(line);
+            let content = self.text.slice_to_cow(line_start..self.end_of_line(line));
+            let number = number::add_to_number(&content, caret.head - line_start, amount)
+                .map(|(range, number)| (line_start + range.start..line_start + range.end, number));
+            (caret.head, number)
+        });
+        let mut edits = numbers
+            .iter()
+            .filter_map(|(_, number)| number.clone())
+            .collect::<Vec<_>>();
+        edits.sort_by_key(|(range, _)| range.start);
+        edits.dedup_by_key(|(range, _)| range.start);
+        if edits.is_empty() {
+            return;
+        }
+
+        let mut builder = DeltaBuilder::new(self.text.len());
+        for (range, number) in &edits {
+            builder.replace(range.clone(), Rope::from(number.as_str()));
+        }
+        // Offsets of all carets move by the change in length of the numbers before them
+        let shift_at = |offset: usize| {
+            edits
+                .iter()
+                .take_while(|(range, _)| range.start < offset)
+                .map(|(range, number)| number.len() as isize - range.len() as isize)
+                .sum::<isize>()
+        };
+        let new_carets = numbers.map(|(head, number)| {
+            let offset = match number {
+                Some((range, number)) => range.start + number.len() - 1,
+                None => head,
+            };
+            Region::sticky_cursor(offset.saturating_add_signed(shift_at(offset)))
+        });
+        let primary_index = self.regions.primary_caret_index();
+        self.commit_delta(builder.build(), EditType::Other);
+        self.regions.set_carets(new_carets, primary_index);
+        self.record_carets_after_edit();
+    }
+
+    /// Make sure the next edit starts a new undo group, rather than being grouped with the previous edits
+    pub(crate) fn break_undo_group(&mut self) {
+        self.last_edit_type = EditType::Other;
+    }
+
+    /// Group all edits until the matching call to [Self::end_undo_group] into a single new undo group,
+    /// regardless of their edit types. Calls may be nested, in which case the outermost group is used.
+    pub(crate) fn begin_undo_group(&mut self) {
+        if self.undo_group_depth == 0 {
+            self.undo_group_started = false;
+        }
+        self.undo_group_depth += 1;
+    }
+
+    /// Stop grouping edits started by [Self::begin_undo_group].
+    /// After the outermost group has ended, the next edit will start a new undo group.
+    pub(crate) fn end_undo_group(&mut self) {
+        self.undo_group_depth = self.undo_group_depth.saturating_sub(1);
+        if self.undo_group_depth == 0 {
+            self.break_undo_group();
+        }
+    }
+
+    /// Insert the content of a register at every caret, replacing selections.
+    ///
+    /// If there are as many slices as there are carets, every caret gets its own slice,
+    /// otherwise all slices are joined and put at every caret.
+    /// When `after` is set, the text is put after the character under the caret rather than before it.
+    /// Linewise content is put into new lines below or above the line of the caret instead.
+    ///
+    /// Afterwards, each caret is placed on the last character of the text it put,
+    /// or on the start of the first line put for linewise content.
+    pub(crate) fn put_at_carets(&mut self, content: &RegisterContent, after: bool) {
+        let carets = self.regions.carets();
+        let joined = content
+            .slices
+            .join(if content.linewise { "" } else { "\n" });
+        let texts = if content.slices.len() == carets.len() {
+            content
+                .slices
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+        } else {
+            vec![joined.as_str(); carets.len()]
+        };
+
+        let last_line = self.text.line_of_offset(self.text.len());
+        let mut builder = DeltaBuilder::new(self.text.len());
+        // Offsets of the inserted text in the resulting buffer
+        let mut inserted_ranges = Vec::new();
+        let mut shift = 0isize;
+        for (region, text) in carets.iter().zip(texts) {
+            let mut text = text.to_string();
+            // Part of the inserted text that is not part of the put content
+            let mut prefix_len = 0;
+            let range = if !region.is_cursor() {
+                region.range()
+            } else if content.linewise {
+                let line = self.text.line_of_offset(region.head);
+                if !after {
+                    let offset = self.text.offset_of_line(line);
+                    offset..offset
+                } else if line < last_line {
+                    let offset = self.text.offset_of_line(line + 1);
+                    offset..offset
+                } else {
+                    // There is no line below to put the text in front of, so we need to add one
+                    text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                    prefix_len = 1;
+                    self.text.len()..self.text.len()
+                }
+            } else if after && !self.is_at_line_end(region.head) {
+                let offset = self.text.next_grapheme_offset(region.head).unwrap();
+                offset..offset
+            } else {
+                region.head..region.head
+            };
+            let start = range.start.saturating_add_signed(shift) + prefix_len;
+            inserted_ranges.push(start..(start + text.len() - prefix_len));
+            shift += text.len() as isize - range.len() as isize;
+            builder.replace(range, Rope::from(text));
+        }
+        self.commit_delta(builder.build(), EditType::Other);
+
+        let mut inserted_ranges = inserted_ranges.into_iter();
+        self.regions.update_carets(|_, region| {
+            if let Some(range) = inserted_ranges.next() {
+                let offset = if range.is_empty() || content.linewise {
+                    range.start
+                } else {
+                    self.text
+                        .prev_grapheme_offset(range.end)
+                        .unwrap_or(range.start)
+                };
+                *region = Region::sticky_cursor(offset);
+            }
+        });
+        self.record_carets_after_edit();
+    }
+
+    /// Check if there is no character in the line after the given offset
+    fn is_at_line_end(&self, offset: usize) -> bool {
+        offset == self.text.len() || self.text.byte_at(offset) == b'\n'
+    }
+
     fn undo(&mut self) {
         tracing::trace!(history = ?self.undo_history, "before undo");
+        let undone_group = self.undo_history.current();
         if self.undo_history.undo() {
-            self.update_undo_state();
+            let carets = self
+                .undo_carets
+                .get(&undone_group)
+                .map(|x| x.before.clone());
+            self.update_undo_state(carets);
         }
         tracing::trace!(history = ?self.undo_history, "after undo");
     }
@@ -226,24 +1230,55 @@ impl Buffer {
     fn redo(&mut self) {
         tracing::trace!(history = ?self.undo_history, "before redo");
         if self.undo_history.redo() {
-            self.update_undo_state();
+            self.update_undo_state(self.carets_after_current_group());
         }
         tracing::trace!(history = ?self.undo_history, "after redo");
     }
 
-    fn update_undo_state(&mut self) {
+    /// Remember that the buffer was saved in its current state, for [BufferOp::Earlier] and [BufferOp::Later].
+    /// Further edits go into a new undo group.
+    pub(crate) fn mark_saved(&mut self) {
+        self.undo_history.mark_saved();
+        self.last_edit_type = EditType::Other;
+    }
+
+    pub(crate) fn undo_tree(&self) -> UndoTree {
+        self.undo_history.tree()
+    }
+
+    /// The carets recorded after the edits of the undo group leading to the current state, if any
+    fn carets_after_current_group(&self) -> Option<CaretSnapshot> {
+        let carets = self.undo_carets.get(&self.undo_history.current())?;
+        Some(carets.after.clone())
+    }
+
+    /// Bring the engine into the state the undo history is currently in.
+    ///
+    /// All regions are moved along with the resulting change to the text,
+    /// and then the carets are replaced with the given ones.
+    /// Without carets to restore, i.e. for undo groups of a previous session,
+    /// the carets jump into the changed area instead.
+    fn update_undo_state(&mut self, carets: Option<CaretSnapshot>) {
         self.last_edit_type = EditType::Other;
         let old_head_rev = self.engine.get_head_rev_id();
 
-        self.engine
-            .undo(self.undo_history.currently_undone().clone());
+        self.engine.undo(self.undo_history.currently_undone());
         self.text = self.engine.get_head().clone();
 
         match self.engine.try_delta_rev_head(old_head_rev.token()) {
-            Ok(delta) => self.jump_carets_into_range_of_delta(&delta),
+            Ok(delta) => {
+                self.regions.apply_delta(&delta);
+                match carets {
+                    Some(carets) => self.restore_carets(carets),
+                    None => self.jump_carets_into_range_of_delta(&delta),
+                }
+            },
             Err(err) => {
                 tracing::error!("Error generating delta while updating undo state: {err}");
-                self.snap_regions_to_valid_position();
+                match carets {
+                    Some(carets) => self.restore_carets(carets),
+                    None => self.clamp_carets_to_text(),
+                }
             },
         }
     }
@@ -251,7 +1286,6 @@ impl Buffer {
     fn jump_carets_into_range_of_delta(&mut self, delta: &RopeDelta) {
         let (before_undo_interval, replacement_length) = delta.summary();
         let affected_range_end = before_undo_interval.start() + replacement_length;
-        self.regions.apply_delta(delta);
 
         // Jump all carets into the changed area, ensuring that the user
         // sees where stuff has changed
@@ -294,10 +1328,21 @@ impl Buffer {
         // Insertion should replace, backspace should delete, etc. How do we implement that cleanly?
         match op {
             BufferOp::Insert(text) => self.insert_at_carets(&text),
-            BufferOp::Delete(motion) => self.delete_at_carets(vp, motion),
-            BufferOp::DeleteSelected => self.delete_in_selections(),
+            BufferOp::Delete(motion) => {
+                self.delete_at_carets(vp, motion);
+            },
             BufferOp::Undo => self.undo(),
             BufferOp::Redo => self.redo(),
+            BufferOp::Earlier(step) => {
+                if self.undo_history.earlier(step) {
+                    self.update_undo_state(self.carets_after_current_group());
+                }
+            },
+            BufferOp::Later(step) => {
+                if self.undo_history.later(step) {
+                    self.update_undo_state(self.carets_after_current_group());
+                }
+            },
             BufferOp::Move(motion) => {
                 // TODO is this the strat?
                 // Do we just discard selections when moving without BufferOp::Selection?
@@ -306,6 +1351,17 @@ impl Buffer {
             BufferOp::Selection(motion) => self.regions.update_carets(|_, region| {
                 *region = apply_motion_to_region(&self.text, vp, *region, true, motion);
             }),
+            BufferOp::SelectTextObject(object) => self.regions.update_carets(|_, region| {
+                if let Some(range) = text_object::text_object_range(&self.text, *region, object) {
+                    let selection = region.range();
+                    *region = Region {
+                        tail: range.start.min(selection.start),
+                        head: range.end.max(selection.end),
+                        preferred_column: None,
+                        ..*region
+                    };
+                }
+            }),
             BufferOp::NewCaret(motion) => {
                 let carets = self.regions.carets();
                 let primary_caret = carets.first();
@@ -315,6 +1371,41 @@ impl Buffer {
                     self.regions.add_caret(true, new_caret);
                 }
             },
+            BufferOp::SelectMatches(regex) => {
+                self.map_selections(|buffer, range| buffer.matches_within(regex, range))
+            },
+            BufferOp::SplitSelections(regex) => {
+                self.map_selections(|buffer, range| buffer.split_at_matches(regex, range))
+            },
+            BufferOp::SplitLines => {
+                self.map_selections(|buffer, range| buffer.split_into_lines(range))
+            },
+            BufferOp::FilterSelections { regex, keep } => self.map_selections(|buffer, range| {
+                let has_match = !buffer.matches_within(regex, range.clone()).is_empty();
+                if has_match == keep {
+                    vec![range]
+                } else {
+                    Vec::new()
+                }
+            }),
+            BufferOp::MergeSelections => self.merge_selections(),
+            BufferOp::RotatePrimaryCaret { backwards } => {
+                self.regions.rotate_primary_caret(backwards)
+            },
+            BufferOp::RemovePrimaryCaret => self.regions.remove_primary_caret(),
+            BufferOp::RemoveLastAddedCaret => self.regions.remove_last_added_caret(),
+            BufferOp::AddToNumbers(amount) => self.add_to_numbers(amount),
+            BufferOp::Surround { target, pair } => self.surround(vp, target, pair),
+            BufferOp::ChangeSurround { object, pair } => self.change_surround(object, pair),
+            BufferOp::SelectNextOccurrence { skip } => self.select_next_occurrence(skip),
+            BufferOp::SelectAllOccurrences { in_viewport } => {
+                let range = if in_viewport {
+                    self.viewport_range(vp)
+                } else {
+                    0..self.text.len()
+                };
+                self.select_all_occurrences(range);
+            },
         }
     }
 
@@ -326,11 +1417,45 @@ impl Buffer {
     }
 }
 
+/// Resolve alternative names of marks, i.e. `` ` `` for `'`
+fn mark_name(mark: char) -> char {
+    match mark {
+        '`' => '\'',
+        mark => mark,
+    }
+}
+
+/// Ranges of the base text of a delta that it deletes or replaces,
+/// including empty ranges where it only inserts text
+fn changed_ranges(delta: &RopeDelta) -> Vec<std::ops::Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    let mut inserted = false;
+    for element in &delta.els {
+        match element {
+            DeltaElement::Copy(start, end) => {
+                if *start > offset || inserted {
+                    ranges.push(offset..*start);
+                }
+                offset = *end;
+                inserted = false;
+            },
+            DeltaElement::Insert(_) => inserted = true,
+        }
+    }
+    if offset < delta.base_len || inserted {
+        ranges.push(offset..delta.base_len);
+    }
+    ranges
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::test_util;
+    use crate::user_buffer_op::{TextObject, TextObjectKind};
     use crate::view::Viewport;
+    use crate::word_boundary::WordBoundaryType;
 
     #[test]
     fn test_insert() {
@@ -402,6 +1527,443 @@ mod test {
         assert_eq!("lo", b.content_to_string());
     }
 
+    #[test]
+    fn test_operator_returns_text() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo bar".to_string());
+        b.regions.add_caret(false, Region::sticky_cursor(4));
+        let deleted = b.apply_operator(
+            &Viewport::new_ginormeous(),
+            Operator::Delete,
+            OperatorTarget::Motion(Motion::NextWordBoundary(WordBoundaryType::End)),
+        );
+        assert_eq!(vec!["foo", "bar"], deleted.slices);
+        assert!(!deleted.linewise);
+        assert_eq!(" ", b.content_to_string());
+    }
+
+    #[test]
+    fn test_yank_moves_to_start() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo bar".to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(6));
+        let yanked = b.apply_operator(
+            &Viewport::new_ginormeous(),
+            Operator::Yank,
+            OperatorTarget::Motion(Motion::PrevWordBoundary(WordBoundaryType::Start)),
+        );
+        assert_eq!(vec!["ba"], yanked.slices);
+        assert_eq!(4, b.primary_caret().head);
+        assert_eq!("foo bar", b.content_to_string());
+    }
+
+    #[test]
+    fn test_yank_lines_keeps_caret() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo\nbar".to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(2));
+        let yanked = b.apply_operator(
+            &Viewport::new_ginormeous(),
+            Operator::Yank,
+            OperatorTarget::Lines(2),
+        );
+        assert_eq!(vec!["foo\nbar\n"], yanked.slices);
+        assert!(yanked.linewise);
+        assert_eq!(2, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_operator_multiple_carets() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo\n  bar\nbaz".to_string());
+        b.regions.add_caret(false, Region::sticky_cursor(8));
+        b.apply_operator(
+            &Viewport::new_ginormeous(),
+            Operator::Uppercase,
+            OperatorTarget::Lines(1),
+        );
+        assert_eq!("FOO\n  BAR\nbaz", b.content_to_string());
+        let carets = b.all_carets().map(|x| x.head);
+        assert_eq!(vec![0, 6], carets.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_operator_overlapping_ranges() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("a\nb\nc\nd".to_string());
+        b.regions.add_caret(false, Region::sticky_cursor(2));
+        let deleted = b.apply_operator(
+            &Viewport::new_ginormeous(),
+            Operator::Delete,
+            OperatorTarget::Lines(2),
+        );
+        assert_eq!(vec!["a\nb\nc\n"], deleted.slices);
+        assert_eq!("d", b.content_to_string());
+        assert_eq!(1, b.all_carets().len());
+    }
+
+    #[test]
+    fn test_change_groups_with_insert() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo bar".to_string());
+        let vp = Viewport::new_ginormeous();
+        b.apply_operator(
+            &vp,
+            Operator::Change,
+            OperatorTarget::Motion(Motion::NextWordBoundary(WordBoundaryType::Start)),
+        );
+        b.insert_at_carets("baz");
+        assert_eq!("baz bar", b.content_to_string());
+        b.undo();
+        assert_eq!("foo bar", b.content_to_string());
+    }
+
+    #[test]
+    fn test_find_matches() {
+        test_util::setup_test();
+        let b = Buffer::new_from_string("foo bar\nbaz foo".to_string());
+        let re = hotsauce::Regex::new("foo|ba.").unwrap();
+        assert_eq!(vec![0..3, 4..7, 8..11], b.find_matches(&re, 0..9));
+        let re = hotsauce::Regex::new("x*").unwrap();
+        assert!(b.find_matches(&re, 0..15).is_empty());
+    }
+
+    #[test]
+    fn test_split_and_filter_selections() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo bar foo\nbaz foo".to_string());
+        let vp = Viewport::new_ginormeous();
+        let ranges = |b: &Buffer| {
+            b.all_carets()
+                .into_iter()
+                .map(|x| x.range())
+                .collect::<Vec<_>>()
+        };
+        b.regions.set_primary_caret(Region::sticky(19, 0));
+        b.apply_buffer_op(&vp, BufferOp::SplitLines);
+        assert_eq!(vec![0..11, 12..19], ranges(&b));
+
+        b.regions.set_primary_caret(Region::sticky(19, 0));
+        let foo = hotsauce::Regex::new("foo").unwrap();
+        b.apply_buffer_op(&vp, BufferOp::SelectMatches(&foo));
+        assert_eq!(vec![0..3, 8..11, 16..19], ranges(&b));
+        b.apply_buffer_op(&vp, BufferOp::MergeSelections);
+        assert_eq!(vec![0..3, 8..11, 16..19], ranges(&b));
+        let touching = vec![
+            Region::sticky(8, 0),
+            Region::sticky(11, 8),
+            Region::sticky(19, 11),
+        ];
+        b.regions
+            .set_carets(NonEmpty::from_vec(touching).unwrap(), 1);
+        b.apply_buffer_op(&vp, BufferOp::MergeSelections);
+        assert_eq!(vec![0..19], ranges(&b));
+
+        let space = hotsauce::Regex::new(" ").unwrap();
+        b.apply_buffer_op(&vp, BufferOp::SplitSelections(&space));
+        assert_eq!(vec![0..3, 4..7, 8..15, 16..19], ranges(&b));
+        let newline = hotsauce::Regex::new("\n").unwrap();
+        b.apply_buffer_op(&vp, BufferOp::FilterSelections {
+            regex: &newline,
+            keep: false,
+        });
+        assert_eq!(vec![0..3, 4..7, 16..19], ranges(&b));
+        // Filtering out every selection leaves them as they are
+        b.apply_buffer_op(&vp, BufferOp::FilterSelections {
+            regex: &newline,
+            keep: true,
+        });
+        assert_eq!(vec![0..3, 4..7, 16..19], ranges(&b));
+    }
+
+    #[test]
+    fn test_rotate_and_remove_carets() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo bar baz".to_string());
+        let vp = Viewport::new_ginormeous();
+        b.regions.add_caret(true, Region::sticky_cursor(8));
+        b.regions.add_caret(false, Region::sticky_cursor(4));
+        assert_eq!(8, b.primary_caret().head);
+        b.apply_buffer_op(&vp, BufferOp::RotatePrimaryCaret { backwards: false });
+        assert_eq!(0, b.primary_caret().head);
+        b.apply_buffer_op(&vp, BufferOp::RotatePrimaryCaret { backwards: true });
+        assert_eq!(8, b.primary_caret().head);
+
+        b.apply_buffer_op(&vp, BufferOp::RemoveLastAddedCaret);
+        let heads = b.all_carets().map(|x| x.head);
+        assert_eq!(vec![0, 8], heads.into_iter().collect::<Vec<_>>());
+        b.apply_buffer_op(&vp, BufferOp::RemovePrimaryCaret);
+        assert_eq!(0, b.primary_caret().head);
+        b.apply_buffer_op(&vp, BufferOp::RemovePrimaryCaret);
+        assert_eq!(1, b.all_carets().len());
+    }
+
+    #[test]
+    fn test_select_next_occurrence() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo bar foo foobar foo".to_string());
+        let vp = Viewport::new_ginormeous();
+        b.regions.set_primary_caret(Region::sticky_cursor(9));
+        b.apply_buffer_op(&vp, BufferOp::SelectNextOccurrence { skip: false });
+        assert_eq!(8..11, b.primary_caret().range());
+        b.apply_buffer_op(&vp, BufferOp::SelectNextOccurrence { skip: false });
+        assert_eq!(12..15, b.primary_caret().range());
+        b.apply_buffer_op(&vp, BufferOp::SelectNextOccurrence { skip: false });
+        b.apply_buffer_op(&vp, BufferOp::SelectNextOccurrence { skip: false });
+        assert_eq!(0..3, b.primary_caret().range());
+        assert_eq!(4, b.all_carets().len());
+
+        let mut b = Buffer::new_from_string("foo bar foo foobar foo".to_string());
+        b.regions.set_primary_caret(Region::sticky(4, 7));
+        b.apply_buffer_op(&vp, BufferOp::SelectNextOccurrence { skip: true });
+        assert_eq!(15..18, b.primary_caret().range());
+        b.regions.set_primary_caret(Region::sticky(0, 3));
+        b.apply_buffer_op(&vp, BufferOp::SelectNextOccurrence { skip: true });
+        assert_eq!(8..11, b.primary_caret().range());
+        assert_eq!(1, b.all_carets().len());
+
+        b.apply_buffer_op(&vp, BufferOp::SelectAllOccurrences { in_viewport: false });
+        let ranges = b.all_carets().map(|x| x.range());
+        assert_eq!(
+            vec![0..3, 8..11, 12..15, 19..22],
+            ranges.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(8..11, b.primary_caret().range());
+    }
+
+    #[test]
+    fn test_surround() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo bar\n  baz\n".to_string());
+        let vp = Viewport::new_ginormeous();
+        b.regions.add_caret(false, Region::sticky_cursor(5));
+        let word = OperatorTarget::TextObject(
+            TextObject {
+                kind: TextObjectKind::Word,
+                inner: true,
+            },
+            1,
+        );
+        let pair = SurroundPair::for_char('(');
+        b.apply_buffer_op(&vp, BufferOp::Surround {
+            target: word,
+            pair: &pair,
+        });
+        assert_eq!("( foo ) ( bar )\n  baz\n", b.content_to_string());
+        let heads = b.all_carets().map(|x| x.head);
+        assert_eq!(vec![0, 8], heads.into_iter().collect::<Vec<_>>());
+
+        let parens = TextObjectKind::Bracketed {
+            open: '(',
+            close: ')',
+        };
+        let tag = SurroundPair::tag("a href=\"x\"");
+        b.apply_buffer_op(&vp, BufferOp::ChangeSurround {
+            object: parens,
+            pair: Some(&tag),
+        });
+        assert_eq!(
+            "<a href=\"x\"> foo </a> <a href=\"x\"> bar </a>\n  baz\n",
+            b.content_to_string()
+        );
+        b.apply_buffer_op(&vp, BufferOp::Undo);
+        b.apply_buffer_op(&vp, BufferOp::ChangeSurround {
+            object: parens,
+            pair: None,
+        });
+        assert_eq!(" foo   bar \n  baz\n", b.content_to_string());
+
+        b.regions.collapse_carets_into_primary();
+        b.regions.set_primary_caret(Region::sticky_cursor(14));
+        let quotes = SurroundPair::for_char('"');
+        b.apply_buffer_op(&vp, BufferOp::Surround {
+            target: OperatorTarget::Lines(1),
+            pair: &quotes,
+        });
+        assert_eq!(" foo   bar \n  \"baz\"\n", b.content_to_string());
+        assert_eq!(14, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_word_at_primary_caret() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo_1 (bar)\n".to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(2));
+        assert_eq!(Some("foo_1".to_string()), b.word_at_primary_caret());
+        b.regions.set_primary_caret(Region::sticky_cursor(5));
+        assert_eq!(Some("bar".to_string()), b.word_at_primary_caret());
+        b.regions.set_primary_caret(Region::sticky_cursor(10));
+        assert_eq!(None, b.word_at_primary_caret());
+    }
+
+    #[test]
+    fn test_line_contents() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo\nbar\nbaz\n".to_string());
+        assert_eq!(4..11, b.offsets_of_lines(1..=2));
+        assert_eq!(vec!["bar", "baz"], b.line_contents(1..=2));
+        assert_eq!(vec![""], b.line_contents(3..=3));
+        b.replace_ranges([(0..3, "x".to_string()), (8..11, "yz".to_string())]);
+        assert_eq!("x\nbar\nyz\n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_explicit_undo_group() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo".to_string());
+        let vp = Viewport::new_ginormeous();
+        b.insert_at_carets("a");
+        b.begin_undo_group();
+        b.insert_at_carets("b");
+        b.begin_undo_group();
+        b.apply_buffer_op(&vp, BufferOp::Delete(Motion::Right));
+        b.end_undo_group();
+        b.apply_operator(&vp, Operator::Delete, OperatorTarget::Lines(1));
+        b.end_undo_group();
+        b.insert_at_carets("c");
+        assert_eq!("c", b.content_to_string());
+        b.undo();
+        assert_eq!("", b.content_to_string());
+        b.undo();
+        assert_eq!("afoo", b.content_to_string());
+        b.undo();
+        assert_eq!("foo", b.content_to_string());
+    }
+
+    #[test]
+    fn test_operator_text_object() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("f(a, b) g(c)".to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(2));
+        b.regions.add_caret(false, Region::sticky_cursor(10));
+        let object = TextObject {
+            kind: TextObjectKind::Bracketed {
+                open: '(',
+                close: ')',
+            },
+            inner: true,
+        };
+        let deleted = b.apply_operator(
+            &Viewport::new_ginormeous(),
+            Operator::Delete,
+            OperatorTarget::TextObject(object, 1),
+        );
+        assert_eq!(vec!["a, b", "c"], deleted.slices);
+        assert_eq!("f() g()", b.content_to_string());
+    }
+
+    #[test]
+    fn test_select_text_object() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo bar baz".to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(5));
+        let vp = Viewport::new_ginormeous();
+        let word = TextObject {
+            kind: TextObjectKind::Word,
+            inner: true,
+        };
+        b.apply_buffer_op(&vp, BufferOp::SelectTextObject(word));
+        assert_eq!(4..7, b.primary_caret().range());
+        b.apply_buffer_op(&vp, BufferOp::SelectTextObject(word));
+        assert_eq!(4..8, b.primary_caret().range());
+    }
+
+    #[test]
+    fn test_block_lines() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("abcd\na\nabcd\n".to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(2));
+        let vp = Viewport::new_ginormeous();
+        b.apply_buffer_op(&vp, BufferOp::Selection(Motion::Right));
+        b.apply_buffer_op(&vp, BufferOp::Selection(Motion::Down));
+        b.apply_buffer_op(&vp, BufferOp::Selection(Motion::Down));
+        let ranges = |b: &Buffer, to_line_end| {
+            b.block_lines(to_line_end)
+                .into_iter()
+                .map(|line| (line.range, line.short))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![(2..3, false), (6..6, true), (9..10, false)],
+            ranges(&b, false)
+        );
+        assert_eq!(
+            vec![(2..4, false), (6..6, true), (9..11, false)],
+            ranges(&b, true)
+        );
+
+        b.split_block(false, BlockSplit::Ends);
+        let heads = b.all_carets().map(|x| x.head);
+        assert_eq!(vec![3, 6, 10], heads.into_iter().collect::<Vec<_>>());
+    }
+
+    fn content(slices: &[&str], linewise: bool) -> RegisterContent {
+        RegisterContent::new(slices.iter().map(|x| x.to_string()).collect(), linewise)
+    }
+
+    #[test]
+    fn test_put() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("ac".to_string());
+        b.put_at_carets(&content(&["b"], false), true);
+        assert_eq!("abc", b.content_to_string());
+        assert_eq!(1, b.primary_caret().head);
+        b.put_at_carets(&content(&["xy"], false), false);
+        assert_eq!("axybc", b.content_to_string());
+        assert_eq!(2, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_put_at_end_of_line() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("\n".to_string());
+        b.put_at_carets(&content(&["foo"], false), true);
+        assert_eq!("foo\n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_put_slice_per_caret() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("a\nb".to_string());
+        b.regions.add_caret(false, Region::sticky_cursor(2));
+        b.put_at_carets(&content(&["1", "2"], false), true);
+        assert_eq!("a1\nb2", b.content_to_string());
+        b.put_at_carets(&content(&["x"], false), false);
+        assert_eq!("ax1\nbx2", b.content_to_string());
+    }
+
+    #[test]
+    fn test_put_replaces_selection() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("hello".to_string());
+        b.regions.set_primary_caret(Region::sticky(1, 4));
+        b.put_at_carets(&content(&["EY"], false), true);
+        assert_eq!("hEYo", b.content_to_string());
+        assert_eq!(2, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_put_lines() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo\nbar".to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(1));
+        b.put_at_carets(&content(&["x\n"], true), true);
+        assert_eq!("foo\nx\nbar", b.content_to_string());
+        assert_eq!(4, b.primary_caret().head);
+        b.put_at_carets(&content(&["y\n"], true), false);
+        assert_eq!("foo\ny\nx\nbar", b.content_to_string());
+        assert_eq!(4, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_put_lines_after_last_line() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo".to_string());
+        b.put_at_carets(&content(&["x\n"], true), true);
+        assert_eq!("foo\nx", b.content_to_string());
+        assert_eq!(4, b.primary_caret().head);
+    }
+
     #[test]
     fn test_delete_empty() {
         test_util::setup_test();
@@ -597,6 +2159,82 @@ mod test {
         assert_eq!(3, b.all_caret_positions().first().col);
     }
 
+    #[test]
+    fn test_marks_move_with_edits() {
+        test_util::setup_test();
+        let vp = Viewport::new_ginormeous();
+        let mut b = Buffer::new_from_string("foo\nbar\n".to_string());
+        b.set_mark('a', 5);
+        b.set_mark('`', 1);
+        assert_eq!(Some(1), b.mark_offset('\''));
+        b.insert_at_carets("xy\n");
+        assert_eq!(Some(Position::new(2, 1)), b.mark_position('a'));
+        assert_eq!(Some(0), b.mark_offset('.'));
+        b.undo();
+        assert_eq!(Some(5), b.mark_offset('a'));
+        b.regions.set_primary_caret(Region::sticky_cursor(2));
+        b.apply_buffer_op(&vp, BufferOp::Delete(Motion::Right));
+        assert_eq!(Some(4), b.mark_offset('a'));
+        assert_eq!(Some(2), b.mark_offset('.'));
+        b.remove_mark('a');
+        assert_eq!(None, b.mark_offset('a'));
+        assert_eq!(vec![('\'', 1), ('.', 2)], {
+            let mut marks = b.marks();
+            marks.sort();
+            marks
+        });
+    }
+
+    #[test]
+    fn test_change_list() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo\nbar\n".to_string());
+        b.insert_at_carets("x");
+        b.regions.set_primary_caret(Region::sticky_cursor(2));
+        b.insert_at_carets("y");
+        b.regions.set_primary_caret(Region::sticky_cursor(7));
+        b.insert_at_carets("z");
+        assert_eq!("xfyoo\nbzar\n", b.content_to_string());
+        assert_eq!(Some(7), b.walk_change_list(1, false));
+        assert_eq!(Some(2), b.walk_change_list(1, false));
+        assert_eq!(None, b.walk_change_list(1, false));
+        assert_eq!(Some(7), b.walk_change_list(1, true));
+        assert_eq!(None, b.walk_change_list(1, true));
+        b.insert_at_carets("w");
+        assert_eq!(Some(2), b.walk_change_list(2, false));
+    }
+
+    #[test]
+    fn test_undo_redo_restores_carets() {
+        test_util::setup_test();
+        let vp = Viewport::new_ginormeous();
+        let ranges = |b: &Buffer| {
+            b.regions
+                .carets()
+                .map(|x| x.range())
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        let mut b = Buffer::new_from_string("abc\nabc\n".to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(1));
+        b.regions.add_caret(false, Region::sticky(5, 7));
+        let before = ranges(&b);
+        b.insert_at_carets("xy");
+        assert_eq!("axybc\naxy\n", b.content_to_string());
+        let after = ranges(&b);
+        b.regions.collapse_carets_into_primary();
+        b.move_carets(&vp, Motion::Down);
+
+        b.undo();
+        assert_eq!("abc\nabc\n", b.content_to_string());
+        assert_eq!(before, ranges(&b));
+        assert_eq!(0, b.regions.primary_caret_index());
+
+        b.regions.collapse_carets_into_primary();
+        b.redo();
+        assert_eq!(after, ranges(&b));
+    }
+
     #[test]
     fn test_undo_redo() {
         test_util::setup_test();
@@ -629,6 +2267,61 @@ mod test {
         assert_eq!("hello world", b.content_to_string());
     }
 
+    #[test]
+    fn test_is_at_revision() {
+        test_util::setup_test();
+        let mut b = Buffer::new_from_string("foo".to_string());
+        let vp = Viewport::new_ginormeous();
+        let saved = b.head_revision();
+        assert!(b.is_at_revision(saved));
+        b.apply_buffer_op(&vp, BufferOp::Insert("x".to_string()));
+        assert!(!b.is_at_revision(saved));
+        b.apply_buffer_op(&vp, BufferOp::Undo);
+        assert!(b.is_at_revision(saved));
+        b.apply_buffer_op(&vp, BufferOp::Redo);
+        assert!(!b.is_at_revision(saved));
+    }
+
+    #[test]
+    fn test_merge_external_change() {
+        test_util::setup_test();
+        let base = Rope::from("one\ntwo\nthree\n");
+        let mut b = Buffer::new_from_string(base.to_string());
+        b.jump_caret_to_position(Position::new(2, 1), true);
+        b.replace_ranges([(0..3, "ONE".to_string())]);
+        let theirs = Rope::from("one\ninserted\ntwo\nthree\n");
+        assert!(!b.merge_external_change(&base, &theirs));
+        assert_eq!("ONE\ninserted\ntwo\nthree\n", b.content_to_string());
+        assert_eq!(Position::new(3, 1), b.primary_caret_position());
+        b.apply_buffer_op(&Viewport::new_ginormeous(), BufferOp::Undo);
+        assert_eq!("ONE\ntwo\nthree\n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_merge_external_change_is_not_a_user_change() {
+        test_util::setup_test();
+        let base = Rope::from("one\ntwo\nthree\n");
+        let mut b = Buffer::new_from_string(base.to_string());
+        b.replace_ranges([(4..7, "TWO".to_string())]);
+        let theirs = Rope::from("one\ntwo\nthree\nfour\n");
+        assert!(!b.merge_external_change(&base, &theirs));
+        assert_eq!("one\nTWO\nthree\nfour\n", b.content_to_string());
+        assert_eq!(Some(4), b.mark_offset('.'));
+        assert_eq!(Some(4), b.walk_change_list(1, false));
+        assert_eq!(None, b.walk_change_list(1, false));
+    }
+
+    #[test]
+    fn test_merge_external_change_conflict() {
+        test_util::setup_test();
+        let base = Rope::from("one\nabc\n");
+        let mut b = Buffer::new_from_string(base.to_string());
+        b.replace_ranges([(4..7, "xyz".to_string())]);
+        assert!(b.merge_external_change(&base, &Rope::from("one\nuvw\n")));
+        let content = b.content_to_string();
+        assert!(content.contains("xyz") && content.contains("uvw"));
+    }
+
     #[test]
     fn test_undo_edit_redo() {
         test_util::setup_test();
@@ -26,6 +26,11 @@ pub(super) struct BufferRegions {
     carets: Vec<RegionId>,
     /// The primary caret is the caret that will remain when exiting any sort of multi-caret mode.
     primary_caret_id: RegionId,
+    /// Carets added with [Self::add_caret], in the order they were added.
+    /// May contain ids of carets that have since been merged or removed.
+    added_carets: Vec<RegionId>,
+    /// Regions of the marks set in this buffer, by the name of the mark
+    marks: HashMap<char, RegionId>,
 }
 
 impl Default for BufferRegions {
@@ -37,6 +42,8 @@ impl Default for BufferRegions {
             regions,
             carets: vec![primary_caret_id],
             primary_caret_id,
+            added_carets: Vec::new(),
+            marks: HashMap::new(),
         }
     }
 }
@@ -73,16 +80,6 @@ impl BufferRegions {
         carets
     }
 
-    pub(super) fn update_regions<F>(&mut self, mut f: F)
-    where
-        F: FnMut(&RegionId, &mut Region),
-    {
-        for (id, region) in self.regions.iter_mut() {
-            f(id, region);
-        }
-        self.make_carets_consistent()
-    }
-
     pub(super) fn update_carets<F>(&mut self, mut f: F)
     where
         F: FnMut(&RegionId, &mut Region),
@@ -108,6 +105,49 @@ impl BufferRegions {
             self.primary_caret_id = id;
         }
         self.make_carets_consistent();
+        self.added_carets.retain(|id| self.carets.contains(id));
+        self.added_carets.push(id);
+    }
+
+    /// Make the caret after the primary one the new primary caret, or the one before it if `backwards` is set,
+    /// wrapping around at the ends
+    pub(super) fn rotate_primary_caret(&mut self, backwards: bool) {
+        let len = self.carets.len();
+        let index = self.primary_caret_index();
+        let index = if backwards {
+            (index + len - 1) % len
+        } else {
+            (index + 1) % len
+        };
+        self.primary_caret_id = self.carets[index];
+    }
+
+    /// Remove the primary caret, making the caret after it the new primary one.
+    /// Does nothing if it is the only caret.
+    pub(super) fn remove_primary_caret(&mut self) {
+        if self.carets.len() < 2 {
+            return;
+        }
+        let index = self.primary_caret_index();
+        self.carets.remove(index);
+        self.regions.remove(&self.primary_caret_id);
+        self.primary_caret_id = self.carets[index.min(self.carets.len() - 1)];
+    }
+
+    /// Remove the caret that was added most recently and still exists,
+    /// making the one added before it the primary caret if the removed caret was the primary one.
+    /// Does nothing if it is the only caret.
+    pub(super) fn remove_last_added_caret(&mut self) {
+        self.added_carets.retain(|id| self.carets.contains(id));
+        if self.carets.len() < 2 {
+            return;
+        }
+        let Some(id) = self.added_carets.pop() else { return };
+        self.carets.retain(|x| *x != id);
+        self.regions.remove(&id);
+        if self.primary_caret_id == id {
+            self.primary_caret_id = self.added_carets.last().copied().unwrap_or(self.carets[0]);
+        }
     }
 
     /// Directly overwrite the primary caret / selection.
@@ -117,6 +157,72 @@ impl BufferRegions {
         self.make_carets_consistent();
     }
 
+    /// Index of the primary caret in [Self::carets]
+    pub(super) fn primary_caret_index(&self) -> usize {
+        self.carets
+            .iter()
+            .position(|id| *id == self.primary_caret_id)
+            .expect("primary caret not found in carets")
+    }
+
+    /// Replace all carets with the given regions, making the one at `primary_index` the primary caret.
+    ///
+    /// Unlike [Self::update_carets], this does not depend on the current number of carets,
+    /// which may have changed if carets got merged while applying an edit.
+    pub(super) fn set_carets(&mut self, carets: NonEmpty<Region>, primary_index: usize) {
+        self.collapse_carets_into_primary();
+        for (index, region) in carets.into_iter().enumerate() {
+            if index == primary_index {
+                self.regions.insert(self.primary_caret_id, region);
+            } else {
+                let id = RegionId::gen();
+                self.carets.push(id);
+                self.regions.insert(id, region);
+            }
+        }
+        self.make_carets_consistent();
+    }
+
+    /// Add a region that isn't a caret, returning its id
+    pub(super) fn add_region(&mut self, region: Region) -> RegionId {
+        let id = RegionId::gen();
+        self.regions.insert(id, region);
+        id
+    }
+
+    pub(super) fn region(&self, id: RegionId) -> Option<&Region> {
+        self.regions.get(&id)
+    }
+
+    /// Remove a region that was added with [Self::add_region]
+    pub(super) fn remove_region(&mut self, id: RegionId) {
+        debug_assert!(!self.carets.contains(&id), "tried to remove a caret");
+        self.regions.remove(&id);
+    }
+
+    /// Set a mark to the given region, replacing the region it was set to before
+    pub(super) fn set_mark(&mut self, mark: char, region: Region) {
+        let id = *self.marks.entry(mark).or_insert_with(RegionId::gen);
+        self.regions.insert(id, region);
+    }
+
+    pub(super) fn mark(&self, mark: char) -> Option<&Region> {
+        self.regions.get(self.marks.get(&mark)?)
+    }
+
+    pub(super) fn remove_mark(&mut self, mark: char) {
+        if let Some(id) = self.marks.remove(&mark) {
+            self.regions.remove(&id);
+        }
+    }
+
+    /// All marks that are set, in no particular order
+    pub(super) fn marks(&self) -> impl Iterator<Item = (char, &Region)> {
+        self.marks
+            .iter()
+            .filter_map(|(mark, id)| Some((*mark, self.regions.get(id)?)))
+    }
+
     /// Ensure that the list of carets is ordered and carets are not overlapping.
     ///
     /// TODO For now, we just run this after any change to the regions, which is obviously suboptimal
@@ -0,0 +1,157 @@
+//! Find the bracket matching another one, as done by `%` in vim.
+//!
+//! Brackets are matched by walking the chunks of the rope from the bracket towards its match,
+//! so that the search never has to copy large parts of the text.
+
+use std::ops::Range;
+
+use xi_rope::Rope;
+
+use crate::word_boundary::iter_rope_chunks_reverse;
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Find the first bracket at or after the offset, within the line of the offset
+pub(super) fn bracket_at_or_after(text: &Rope, offset: usize) -> Option<usize> {
+    let mut current = offset;
+    for c in text.iter_chunks(offset..).flat_map(|x| x.chars()) {
+        if c == '\n' {
+            break;
+        } else if PAIRS.iter().any(|(open, close)| c == *open || c == *close) {
+            return Some(current);
+        }
+        current += c.len_utf8();
+    }
+    None
+}
+
+/// Find the bracket matching the one at the given offset, respecting nesting.
+///
+/// `skipped` are sorted, non-overlapping ranges whose brackets are ignored, such as strings and comments.
+/// If the bracket at the offset lies within one of them itself,
+/// its match is only searched for within that range instead.
+pub(super) fn find_matching_bracket(
+    text: &Rope,
+    offset: usize,
+    skipped: &[Range<usize>],
+) -> Option<usize> {
+    find_matching_bracket_within(text, offset, skipped, 0..text.len())
+}
+
+/// Like [find_matching_bracket], but only searches for the match within the given range,
+/// such that a bracket without a match doesn't make the search walk the whole text.
+pub(super) fn find_matching_bracket_within(
+    text: &Rope,
+    offset: usize,
+    skipped: &[Range<usize>],
+    bounds: Range<usize>,
+) -> Option<usize> {
+    if !bounds.contains(&offset) {
+        return None;
+    }
+    let c = text.iter_chunks(offset..).flat_map(|x| x.chars()).next()?;
+    let enclosing = skipped.iter().find(|range| range.contains(&offset));
+    let (bounds, skipped) = match enclosing {
+        Some(range) => (
+            range.start.max(bounds.start)..range.end.min(bounds.end),
+            &[][..],
+        ),
+        None => (bounds, skipped),
+    };
+    let is_skipped = |offset: usize| {
+        let index = skipped.partition_point(|range| range.end <= offset);
+        matches!(skipped.get(index), Some(range) if range.start <= offset)
+    };
+
+    if let Some((open, close)) = PAIRS.iter().find(|(open, _)| *open == c) {
+        let mut depth = 0usize;
+        let mut current = offset + c.len_utf8();
+        let start = current.min(bounds.end);
+        for c in text.iter_chunks(start..bounds.end).flat_map(|x| x.chars()) {
+            if (c == *open || c == *close) && !is_skipped(current) {
+                if c == *open {
+                    depth += 1;
+                } else if depth == 0 {
+                    return Some(current);
+                } else {
+                    depth -= 1;
+                }
+            }
+            current += c.len_utf8();
+        }
+    } else if let Some((open, close)) = PAIRS.iter().find(|(_, close)| *close == c) {
+        let mut depth = 0usize;
+        let mut current = offset;
+        for c in iter_rope_chunks_reverse(text, bounds.start..offset).flat_map(|x| x.chars().rev())
+        {
+            current -= c.len_utf8();
+            if (c == *open || c == *close) && !is_skipped(current) {
+                if c == *close {
+                    depth += 1;
+                } else if depth == 0 {
+                    return Some(current);
+                } else {
+                    depth -= 1;
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use xi_rope::Rope;
+
+    use super::{bracket_at_or_after, find_matching_bracket, find_matching_bracket_within};
+    use crate::test_util;
+
+    #[test]
+    fn test_bracket_at_or_after() {
+        test_util::setup_test();
+        let text = Rope::from("foo(bar)\nbaz[]");
+        assert_eq!(Some(3), bracket_at_or_after(&text, 0));
+        assert_eq!(Some(3), bracket_at_or_after(&text, 3));
+        assert_eq!(Some(7), bracket_at_or_after(&text, 4));
+        assert_eq!(None, bracket_at_or_after(&text, 8));
+        assert_eq!(Some(12), bracket_at_or_after(&text, 9));
+    }
+
+    #[test]
+    fn test_find_matching_bracket() {
+        test_util::setup_test();
+        let text = Rope::from("a(b[c]{\nd(e)\n})");
+        assert_eq!(Some(14), find_matching_bracket(&text, 1, &[]));
+        assert_eq!(Some(1), find_matching_bracket(&text, 14, &[]));
+        assert_eq!(Some(5), find_matching_bracket(&text, 3, &[]));
+        assert_eq!(Some(13), find_matching_bracket(&text, 6, &[]));
+        assert_eq!(Some(6), find_matching_bracket(&text, 13, &[]));
+        assert_eq!(None, find_matching_bracket(&text, 0, &[]));
+        assert_eq!(None, find_matching_bracket(&Rope::from("(()"), 0, &[]));
+        assert_eq!(None, find_matching_bracket(&Rope::from("())"), 2, &[]));
+
+        let line = 0..7;
+        assert_eq!(
+            None,
+            find_matching_bracket_within(&text, 1, &[], line.clone())
+        );
+        assert_eq!(
+            Some(5),
+            find_matching_bracket_within(&text, 3, &[], line.clone())
+        );
+        assert_eq!(None, find_matching_bracket_within(&text, 9, &[], line));
+    }
+
+    #[test]
+    fn test_find_matching_bracket_skips_ranges() {
+        test_util::setup_test();
+        let text = Rope::from(r#"f(")", g(x)) // )"#);
+        let skipped = [2..5, 13..17];
+        assert_eq!(Some(11), find_matching_bracket(&text, 1, &skipped));
+        assert_eq!(Some(1), find_matching_bracket(&text, 11, &skipped));
+        assert_eq!(None, find_matching_bracket(&text, 3, &skipped));
+        assert_eq!(Some(3), find_matching_bracket(&text, 1, &[]));
+        let text = Rope::from(r#"("(a)") // (x)"#);
+        assert_eq!(Some(4), find_matching_bracket(&text, 2, &[1..6, 7..14]));
+    }
+}
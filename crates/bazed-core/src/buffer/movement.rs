@@ -1,7 +1,12 @@
 use xi_rope::Rope;
 
-use super::{position::Position, regex_cursor::RegexCursor};
-use crate::{region::Region, user_buffer_op::Motion, view::Viewport, word_boundary};
+use super::{matching_pair, position::Position, regex_cursor::RegexCursor};
+use crate::{
+    region::Region,
+    user_buffer_op::{CharSearch, Motion},
+    view::{Viewport, SCROLL_OFF},
+    word_boundary::{self, WordBoundaryType, WordKind},
+};
 
 /// Apply a given motion to a region.
 /// if `only_move_head` is false, the tail of the region gets set to the new head,
@@ -37,34 +42,119 @@ pub(crate) fn apply_motion_to_region(
             }
         },
         Motion::NextWordBoundary(boundary_type) => {
-            word_boundary::find_word_boundaries(text, region.head)
-                .find(|(_, t)| t.matches(&boundary_type))
-                .map_or(text.len(), |(offset, _)| offset)
+            next_word_boundary(text, region.head, WordKind::Word, boundary_type)
         },
         Motion::PrevWordBoundary(boundary_type) => {
-            word_boundary::find_word_boundaries_backwards(text, region.head)
-                .find(|(_, t)| t.matches(&boundary_type))
-                .map_or(0, |(offset, _)| offset)
+            prev_word_boundary(text, region.head, WordKind::Word, boundary_type)
+        },
+        Motion::NextBigWordBoundary(boundary_type) => {
+            next_word_boundary(text, region.head, WordKind::BigWord, boundary_type)
+        },
+        Motion::PrevBigWordBoundary(boundary_type) => {
+            prev_word_boundary(text, region.head, WordKind::BigWord, boundary_type)
+        },
+        Motion::NextSubwordBoundary(boundary_type) => {
+            next_word_boundary(text, region.head, WordKind::Subword, boundary_type)
+        },
+        Motion::PrevSubwordBoundary(boundary_type) => {
+            prev_word_boundary(text, region.head, WordKind::Subword, boundary_type)
+        },
+        Motion::NextParagraph => word_boundary::find_paragraph_boundaries(text, region.head)
+            .next()
+            .unwrap_or(text.len()),
+        Motion::PrevParagraph => {
+            word_boundary::find_paragraph_boundaries_backwards(text, region.head)
+                .next()
+                .unwrap_or(0)
+        },
+        Motion::NextSentence => word_boundary::find_sentence_starts(text, region.head)
+            .next()
+            .unwrap_or(text.len()),
+        Motion::PrevSentence => word_boundary::find_sentence_starts_backwards(text, region.head)
+            .next()
+            .unwrap_or(0),
+        // we skip the character at the head to avoid matching when we're already exactly at a match.
+        // Searches wrap around the ends of the buffer, like vims `wrapscan`.
+        Motion::FindNext(re) => {
+            let start = text.next_grapheme_offset(region.head).unwrap_or(text.len());
+            RegexCursor::new(text, start, re)
+                .next_match()
+                .or_else(|| RegexCursor::new(text, 0, re).next_match())
+                .map_or(region.head, |x| x.start)
         },
-        // we add + 1 to the head to avoid matching when we're already exactly at a match
-        Motion::FindNext(re) => RegexCursor::new(text, region.head + 1, re)
-            .next_match()
-            .map_or(region.head, |x| x.start),
-
         Motion::FindPrev(re) => RegexCursor::new(text, region.head, re)
             .prev_match()
+            .or_else(|| RegexCursor::new(text, text.len(), re).prev_match())
             .map_or(region.head, |x| x.start),
+        Motion::FindInLine { search, count } => {
+            find_in_line(text, region.head, search, count).unwrap_or(region.head)
+        },
 
+        Motion::Line(line) => {
+            let current_line = text.line_of_offset(region.head);
+            let last_line = text.line_of_offset(text.len());
+            let line_delta = line.min(last_line) as isize - current_line as isize;
+            let region = move_vertically(text, region, line_delta, only_move_head);
+            return apply_motion_to_region(text, vp, region, only_move_head, Motion::StartOfLine);
+        },
+        Motion::FirstLine => {
+            return apply_motion_to_region(text, vp, region, only_move_head, Motion::Line(0));
+        },
+        Motion::LastLine => {
+            let last_line = text.line_of_offset(text.len());
+            return apply_motion_to_region(
+                text,
+                vp,
+                region,
+                only_move_head,
+                Motion::Line(last_line),
+            );
+        },
+        Motion::Percentage(percentage) => {
+            // Like vim, go to the line containing the given percentage of all lines,
+            // i.e. the percentage rounded up to whole lines, counting from 0
+            let line_count = text.line_of_offset(text.len()) + 1;
+            let line = (percentage.min(100) * line_count).saturating_sub(1) / 100;
+            let motion = Motion::Line(line);
+            return apply_motion_to_region(text, vp, region, only_move_head, motion);
+        },
+        Motion::Offset(offset) => offset.min(text.len()),
+        // The buffer doesn't know about strings and comments yet, so no brackets are skipped.
+        Motion::MatchingBracket => matching_pair::bracket_at_or_after(text, region.head)
+            .and_then(|offset| matching_pair::find_matching_bracket(text, offset, &[]))
+            .unwrap_or(region.head),
+        Motion::Repeated(count, motion) => {
+            let mut region = region;
+            for _ in 0..count {
+                let next = apply_motion_to_region(text, vp, region, only_move_head, *motion);
+                if next == region {
+                    break;
+                }
+                region = next;
+            }
+            return region;
+        },
         Motion::Up => return move_vertically(text, region, -1, only_move_head),
         Motion::Down => return move_vertically(text, region, 1, only_move_head),
+        // Viewport motions stay within the scroll-off, so that they don't scroll the viewport
         Motion::TopOfViewport => {
             let current_line = text.line_of_offset(region.head);
-            let line_delta = vp.first_line as isize - current_line as isize;
+            let target_line = *vp.caret_lines(SCROLL_OFF).start();
+            let line_delta = target_line as isize - current_line as isize;
+            return move_vertically(text, region, line_delta, only_move_head);
+        },
+        Motion::MiddleOfViewport => {
+            let current_line = text.line_of_offset(region.head);
+            let last_line = text.line_of_offset(text.len());
+            let target_line = (vp.first_line + vp.last_line().min(last_line)) / 2;
+            let line_delta = target_line as isize - current_line as isize;
             return move_vertically(text, region, line_delta, only_move_head);
         },
         Motion::BottomOfViewport => {
             let current_line = text.line_of_offset(region.head);
-            let line_delta = vp.last_line() as isize - current_line as isize;
+            let last_line = text.line_of_offset(text.len());
+            let target_line = (*vp.caret_lines(SCROLL_OFF).end()).min(last_line);
+            let line_delta = target_line as isize - current_line as isize;
             return move_vertically(text, region, line_delta, only_move_head);
         },
     };
@@ -77,6 +167,83 @@ pub(crate) fn apply_motion_to_region(
     }
 }
 
+fn next_word_boundary(
+    text: &Rope,
+    offset: usize,
+    kind: WordKind,
+    boundary_type: WordBoundaryType,
+) -> usize {
+    word_boundary::find_boundaries(text, offset, kind)
+        .find(|(_, t)| t.matches(&boundary_type))
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+fn prev_word_boundary(
+    text: &Rope,
+    offset: usize,
+    kind: WordKind,
+    boundary_type: WordBoundaryType,
+) -> usize {
+    word_boundary::find_boundaries_backwards(text, offset, kind)
+        .find(|(_, t)| t.matches(&boundary_type))
+        .map_or(0, |(offset, _)| offset)
+}
+
+/// Find the offset the caret goes to when searching for the `count`th occurrence of a grapheme cluster
+/// within the line of the offset, as done by `f`, `t`, `F` and `T` in vim.
+///
+/// Graphemes are compared as a whole, so a character doesn't match the start of a longer cluster.
+fn find_in_line(text: &Rope, offset: usize, search: &CharSearch, count: usize) -> Option<usize> {
+    let line = text.line_of_offset(offset);
+    let line_start = text.offset_of_line(line);
+    let line_end = super::operator::end_of_line(text, line);
+    let matches_at = |range: std::ops::Range<usize>| text.slice_to_cow(range) == search.grapheme;
+    let skip_adjacent = search.till && search.repeated;
+    let mut remaining = count;
+    if search.backwards {
+        let mut current = offset;
+        if skip_adjacent {
+            current = text
+                .prev_grapheme_offset(current)
+                .filter(|x| *x >= line_start)?;
+        }
+        while let Some(prev) = text
+            .prev_grapheme_offset(current)
+            .filter(|x| *x >= line_start)
+        {
+            if matches_at(prev..current) {
+                remaining -= 1;
+                if remaining == 0 {
+                    return Some(if search.till { current } else { prev });
+                }
+            }
+            current = prev;
+        }
+    } else {
+        let mut current = text.next_grapheme_offset(offset)?;
+        if skip_adjacent {
+            current = text.next_grapheme_offset(current)?;
+        }
+        while let Some(next) = text
+            .next_grapheme_offset(current)
+            .filter(|x| *x <= line_end)
+        {
+            if matches_at(current..next) {
+                remaining -= 1;
+                if remaining == 0 {
+                    return if search.till {
+                        text.prev_grapheme_offset(current)
+                    } else {
+                        Some(current)
+                    };
+                }
+            }
+            current = next;
+        }
+    }
+    None
+}
+
 /// Move a region vertically by a given number of lines. Preserves all other attributes of the Region.
 fn move_vertically(text: &Rope, region: Region, by_lines: isize, only_move_head: bool) -> Region {
     let pos = Position::from_offset(text, region.head).unwrap();
@@ -116,8 +283,12 @@ mod test {
     use xi_rope::Rope;
 
     use crate::{
-        buffer::movement::apply_motion_to_region, region::Region, test_util,
-        user_buffer_op::Motion, view::Viewport, word_boundary::WordBoundaryType,
+        buffer::movement::apply_motion_to_region,
+        region::Region,
+        test_util,
+        user_buffer_op::{CharSearch, Motion},
+        view::Viewport,
+        word_boundary::WordBoundaryType,
     };
 
     #[test]
@@ -182,6 +353,18 @@ mod test {
             .head,
             "FindNext does nothing when there is no match"
         );
+        assert_eq!(
+            4,
+            apply_motion_to_region(
+                &t,
+                &vp,
+                Region::sticky_cursor(8),
+                false,
+                Motion::FindNext(&Regex::new("b.r").unwrap())
+            )
+            .head,
+            "FindNext wraps around the end of the buffer"
+        );
     }
 
     #[test]
@@ -223,6 +406,89 @@ mod test {
             .head,
             "FindPrev does nothing when there is no match"
         );
+        assert_eq!(
+            8,
+            apply_motion_to_region(
+                &t,
+                &vp,
+                Region::sticky_cursor(4),
+                false,
+                Motion::FindPrev(&Regex::new("b.r").unwrap())
+            )
+            .head,
+            "FindPrev wraps around the start of the buffer"
+        );
+    }
+
+    #[test]
+    fn test_repeated() {
+        test_util::setup_test();
+        let t = Rope::from("foo bar baz\nqux");
+        let vp = Viewport::new_ginormeous();
+        let word = Motion::NextWordBoundary(WordBoundaryType::Start);
+        assert_eq!(
+            8,
+            apply_motion_to_region(
+                &t,
+                &vp,
+                Region::sticky_cursor(0),
+                false,
+                Motion::Repeated(2, &word)
+            )
+            .head
+        );
+        let selection = apply_motion_to_region(
+            &t,
+            &vp,
+            Region::sticky_cursor(1),
+            true,
+            Motion::Repeated(100, &Motion::Right),
+        );
+        assert_eq!(
+            (1..15),
+            selection.range(),
+            "Repeating a motion stops at the end of the text"
+        );
+    }
+
+    #[test]
+    fn test_line() {
+        test_util::setup_test();
+        let t = Rope::from("foo\nbar\nbaz");
+        let vp = Viewport::new_ginormeous();
+        let apply = |start, motion| {
+            apply_motion_to_region(&t, &vp, Region::sticky_cursor(start), false, motion).head
+        };
+        assert_eq!(4, apply(9, Motion::Line(1)));
+        assert_eq!(0, apply(6, Motion::Line(0)));
+        assert_eq!(8, apply(1, Motion::Line(10)));
+        assert_eq!(8, apply(1, Motion::LastLine));
+        assert_eq!(4, apply(1, Motion::LastLine.with_count(Some(2))));
+        assert_eq!(0, apply(9, Motion::FirstLine));
+        assert_eq!(8, apply(1, Motion::FirstLine.with_count(Some(3))));
+        assert_eq!(4, apply(0, Motion::MatchingBracket.with_count(Some(50))));
+        assert_eq!(0, apply(9, Motion::Percentage(1)));
+        assert_eq!(8, apply(0, Motion::Percentage(200)));
+    }
+
+    #[test]
+    fn test_viewport_motions() {
+        test_util::setup_test();
+        let t = Rope::from("0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14");
+        let apply = |vp: Viewport, motion| {
+            let region = Region::sticky_cursor(t.offset_of_line(9));
+            t.line_of_offset(apply_motion_to_region(&t, &vp, region, false, motion).head)
+        };
+        let vp = Viewport::new(5, 10);
+        assert_eq!(8, apply(vp, Motion::TopOfViewport));
+        assert_eq!(9, apply(vp, Motion::MiddleOfViewport));
+        assert_eq!(11, apply(vp, Motion::BottomOfViewport));
+        // At the start of the text, the caret may go all the way up
+        assert_eq!(0, apply(Viewport::new(0, 10), Motion::TopOfViewport));
+        // Lines past the end of the text don't count for the middle
+        let vp = Viewport::new(10, 20);
+        assert_eq!(12, apply(vp, Motion::MiddleOfViewport));
+        assert_eq!(14, apply(vp, Motion::BottomOfViewport));
     }
 
     #[test]
@@ -282,4 +548,41 @@ mod test {
             apply_motion_to_region(&t, &vp, Region::sticky_cursor(6), false, motion_start).head
         );
     }
+
+    #[test]
+    fn test_find_in_line() {
+        test_util::setup_test();
+        let find = |t: &str, start: usize, grapheme: &str, backwards: bool, till: bool| {
+            let search = CharSearch {
+                grapheme: grapheme.to_string(),
+                backwards,
+                till,
+                repeated: false,
+            };
+            apply_motion_to_region(
+                &Rope::from(t),
+                &Viewport::new_ginormeous(),
+                Region::sticky_cursor(start),
+                false,
+                Motion::FindInLine {
+                    search: &search,
+                    count: 1,
+                },
+            )
+            .head
+        };
+        assert_eq!(3, find("a,b;c,d", 0, ";", false, false));
+        assert_eq!(2, find("a,b;c,d", 0, ";", false, true));
+        assert_eq!(1, find("a,b;c,d", 4, ",", true, false));
+        assert_eq!(2, find("a,b;c,d", 4, ",", true, true));
+        assert_eq!(
+            0,
+            find("a,b\n;", 0, ";", false, false),
+            "Searches stay within the line"
+        );
+        // `é` as `e` followed by a combining accent
+        assert_eq!(4, find("e\u{301} e", 0, "e", false, false));
+        assert_eq!(0, find("e\u{301} e", 4, "e\u{301}", true, false));
+        assert_eq!(11, find("a 👍🏽 👍", 0, "👍", false, false));
+    }
 }
@@ -0,0 +1,147 @@
+//! Find numbers within a line and add to them, as done by `Ctrl-a` and `Ctrl-x` in vim.
+//!
+//! Decimal numbers may be negative, hexadecimal and binary numbers are recognized by their
+//! `0x` and `0b` prefixes and are treated as unsigned, wrapping around when they overflow.
+
+use std::ops::Range;
+
+/// A number literal within a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Literal {
+    /// Range of the literal, including its prefix and sign
+    range: Range<usize>,
+    radix: u32,
+    /// Offset of the first digit
+    digits_start: usize,
+}
+
+/// Find the number under or after the given column of the line,
+/// and add the amount to it.
+///
+/// Returns the range of the number within the line, together with the text of the new number.
+/// The new number keeps the width of numbers with leading zeros,
+/// and the letter case of hexadecimal digits.
+pub(super) fn add_to_number(
+    line: &str,
+    column: usize,
+    amount: i64,
+) -> Option<(Range<usize>, String)> {
+    let literal = literal_at(line, column)?;
+    let digits = &line[literal.digits_start..literal.range.end];
+    let width = digits.len();
+    let number = if literal.radix == 10 {
+        let negative = literal.range.start < literal.digits_start;
+        let value = digits.parse::<i128>().ok()?;
+        let value = if negative { -value } else { value }.checked_add(amount as i128)?;
+        let sign = if value < 0 { "-" } else { "" };
+        let width = if digits.starts_with('0') { width } else { 0 };
+        format!("{sign}{:0width$}", value.unsigned_abs())
+    } else {
+        let value = u64::from_str_radix(digits, literal.radix)
+            .ok()?
+            .wrapping_add(amount as u64);
+        let prefix = &line[literal.range.start..literal.digits_start];
+        match literal.radix {
+            2 => format!("{prefix}{value:0width$b}"),
+            _ if digits.chars().any(|c| c.is_ascii_uppercase()) => {
+                format!("{prefix}{value:0width$X}")
+            },
+            _ => format!("{prefix}{value:0width$x}"),
+        }
+    };
+    Some((literal.range, number))
+}
+
+/// Find the first number literal in the line that ends after the given column
+fn literal_at(line: &str, column: usize) -> Option<Literal> {
+    let bytes = line.as_bytes();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let Some(literal) = literal_starting_at(bytes, offset) else {
+            offset += 1;
+            continue;
+        };
+        if literal.range.end > column {
+            return Some(literal);
+        }
+        offset = literal.range.end;
+    }
+    None
+}
+
+/// Parse the number literal starting at the given offset, if there is one
+fn literal_starting_at(bytes: &[u8], start: usize) -> Option<Literal> {
+    let digits_end = |from: usize, radix: u32| {
+        bytes[from..]
+            .iter()
+            .position(|b| !(*b as char).is_digit(radix))
+            .map_or(bytes.len(), |len| from + len)
+    };
+    let prefix = bytes.get(start..start + 2).map(|x| x.to_ascii_lowercase());
+    let radix = match prefix.as_deref() {
+        Some(b"0x") => 16,
+        Some(b"0b") => 2,
+        _ => 10,
+    };
+    if radix != 10 && digits_end(start + 2, radix) > start + 2 {
+        return Some(Literal {
+            range: start..digits_end(start + 2, radix),
+            radix,
+            digits_start: start + 2,
+        });
+    }
+    if bytes[start] == b'-' && matches!(bytes.get(start + 1), Some(b) if b.is_ascii_digit()) {
+        return Some(Literal {
+            range: start..digits_end(start + 1, 10),
+            radix: 10,
+            digits_start: start + 1,
+        });
+    }
+    if bytes[start].is_ascii_digit() {
+        return Some(Literal {
+            range: start..digits_end(start, 10),
+            radix: 10,
+            digits_start: start,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::add_to_number;
+    use crate::test_util;
+
+    fn add(line: &str, column: usize, amount: i64) -> Option<String> {
+        let (range, number) = add_to_number(line, column, amount)?;
+        Some(format!(
+            "{}{number}{}",
+            &line[..range.start],
+            &line[range.end..]
+        ))
+    }
+
+    #[test]
+    fn test_add_to_number() {
+        test_util::setup_test();
+        assert_eq!(Some("x = 43;".to_string()), add("x = 42;", 0, 1));
+        assert_eq!(Some("x = 32;".to_string()), add("x = 42;", 5, -10));
+        assert_eq!(Some("1 3".to_string()), add("1 2", 2, 1));
+        assert_eq!(None, add("1 2;", 3, 1));
+        assert_eq!(Some("x-1".to_string()), add("x1", 1, -2));
+        assert_eq!(Some("-1".to_string()), add("1", 0, -2));
+        assert_eq!(Some("3".to_string()), add("-2", 1, 5));
+        assert_eq!(Some("010".to_string()), add("009", 0, 1));
+        assert_eq!(None, add("170141183460469231731687303715884105727", 0, 1));
+    }
+
+    #[test]
+    fn test_add_to_prefixed_number() {
+        test_util::setup_test();
+        assert_eq!(Some("0x10".to_string()), add("0x0f", 0, 1));
+        assert_eq!(Some("0xFF".to_string()), add("0xFe", 3, 1));
+        assert_eq!(Some("0XB0".to_string()), add("0XAF", 1, 1));
+        assert_eq!(Some("0b0100".to_string()), add("0b0011", 0, 1));
+        assert_eq!(Some("0xffffffffffffffff".to_string()), add("0x0", 0, -1));
+    }
+}
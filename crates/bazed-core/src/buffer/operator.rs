@@ -0,0 +1,781 @@
+//! Determine the ranges [Operator]s act on, and the text they replace these ranges with.
+
+use std::ops::Range;
+
+use xi_rope::Rope;
+
+use super::{movement::apply_motion_to_region, text_object::counted_text_object_range};
+use crate::{
+    region::Region,
+    user_buffer_op::{Motion, MotionKind, Operator, OperatorTarget},
+    view::Viewport,
+    word_boundary::WordBoundaryType,
+};
+
+/// Number of spaces that make up one level of indentation in space-indented text
+pub(super) const INDENT_WIDTH: usize = 4;
+/// Width that [Operator::Format] wraps lines at
+pub(super) const TEXT_WIDTH: usize = 79;
+
+/// The range of text an operator acts on for a single caret
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct OperatorRange {
+    pub(super) range: Range<usize>,
+    /// If set, the range spans full lines, including the newline of its last line (if there is one)
+    pub(super) linewise: bool,
+}
+
+impl OperatorRange {
+    /// Text of this range, as it should be stored in a register.
+    /// Linewise text always ends in a newline.
+    pub(super) fn register_text(&self, text: &Rope) -> String {
+        let mut content = text.slice_to_cow(self.range.clone()).to_string();
+        if self.linewise && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content
+    }
+}
+
+/// Determine the range the operator acts on for a given caret.
+pub(super) fn operator_range(
+    text: &Rope,
+    vp: &Viewport,
+    region: Region,
+    operator: Operator,
+    target: OperatorTarget,
+) -> OperatorRange {
+    let (range, linewise) = match target {
+        OperatorTarget::Selection => {
+            let range = region.range();
+            if operator.is_linewise() {
+                (lines_of_range(text, &range), true)
+            } else {
+                (range, false)
+            }
+        },
+        OperatorTarget::SelectedLines => {
+            let range = region.range();
+            let first_line = text.line_of_offset(range.start);
+            let last_line = text.line_of_offset(range.end);
+            (full_lines(text, first_line, last_line), true)
+        },
+        OperatorTarget::TextObject(object, count) => {
+            let cursor = Region {
+                tail: region.head,
+                ..region
+            };
+            match counted_text_object_range(text, cursor, object, count) {
+                Some(range) if object.is_linewise() || operator.is_linewise() => {
+                    (lines_of_range(text, &range), true)
+                },
+                Some(range) => (range, false),
+                None => (region.head..region.head, false),
+            }
+        },
+        OperatorTarget::Graphemes { count, clamp } => {
+            let range = graphemes_at(text, region.head, count, clamp);
+            (range.unwrap_or(region.head..region.head), false)
+        },
+        OperatorTarget::Lines(count) => {
+            let first_line = text.line_of_offset(region.head);
+            let last_line = first_line + count.saturating_sub(1);
+            (full_lines(text, first_line, last_line), true)
+        },
+        OperatorTarget::Motion(motion) => {
+            let (range, kind) = motion_range(text, vp, region, operator, motion);
+            if kind == MotionKind::Linewise || operator.is_linewise() {
+                let first_line = text.line_of_offset(range.start);
+                let last_line = text.line_of_offset(range.end);
+                (full_lines(text, first_line, last_line), true)
+            } else {
+                (range, false)
+            }
+        },
+    };
+    OperatorRange { range, linewise }
+}
+
+/// Merge overlapping operator ranges, returning them ordered by their start.
+pub(super) fn merge_ranges(mut ranges: Vec<OperatorRange>) -> Vec<OperatorRange> {
+    ranges.sort_by_key(|x| x.range.start);
+    let mut merged: Vec<OperatorRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.range.start < last.range.end => {
+                last.range.end = last.range.end.max(range.range.end);
+                last.linewise |= range.linewise;
+            },
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Determine which part of the text the operator replaces, and what it replaces it with.
+/// Returns `None` for operators that don't change the text.
+pub(super) fn operator_edit(
+    text: &Rope,
+    operator: Operator,
+    target: &OperatorRange,
+) -> Option<(Range<usize>, String)> {
+    let range = target.range.clone();
+    let content = text.slice_to_cow(range.clone());
+    let replacement = match operator {
+        Operator::Yank => return None,
+        Operator::Delete => {
+            // When deleting the last lines of the text, the newline before them needs to go as well,
+            // as there is no newline after them that could be deleted.
+            if target.linewise && range.start > 0 && !content.ends_with('\n') {
+                return Some((range.start - 1..range.end, String::new()));
+            }
+            String::new()
+        },
+        Operator::Change => {
+            // Changing lines keeps an empty line to insert into
+            if target.linewise && content.ends_with('\n') {
+                return Some((range.start..range.end - 1, String::new()));
+            }
+            String::new()
+        },
+        Operator::Lowercase => content.chars().flat_map(char::to_lowercase).collect(),
+        Operator::Uppercase => content.chars().flat_map(char::to_uppercase).collect(),
+        Operator::ToggleCase => content.chars().flat_map(toggle_case).collect(),
+        Operator::IndentRight => {
+            let unit = indent_unit(text);
+            map_lines(&content, |line| {
+                if line.trim().is_empty() {
+                    line.to_string()
+                } else {
+                    format!("{unit}{line}")
+                }
+            })
+        },
+        Operator::IndentLeft => map_lines(&content, |line| dedent(line).to_string()),
+        Operator::Reindent => reindent(text, range.start, &content),
+        Operator::Format => format_paragraphs(&content),
+        Operator::Join { keep_whitespace } => return join_lines(text, &range, keep_whitespace),
+        Operator::ReplaceChars(c) => replace_chars(text, &range, c),
+    };
+    Some((range, replacement))
+}
+
+/// Determine the range between the head of the region and the target of the motion.
+fn motion_range(
+    text: &Rope,
+    vp: &Viewport,
+    region: Region,
+    operator: Operator,
+    motion: Motion,
+) -> (Range<usize>, MotionKind) {
+    let start = region.head;
+    let motion = if operator == Operator::Change {
+        change_word_motion(text, start, motion)
+    } else {
+        motion
+    };
+    let region = Region {
+        tail: start,
+        ..region
+    };
+    let target = apply_motion_to_region(text, vp, region, false, motion).head;
+    let kind = motion.kind();
+    let mut range = start.min(target)..start.max(target);
+    match kind {
+        MotionKind::Exclusive if target > start => {
+            // Like in vim, an exclusive motion that ends at the start of a line
+            // doesn't include the newline before it (i.e. `dw` on the last word of a line)
+            let end_line = text.line_of_offset(range.end);
+            let end_line_start = text.offset_of_line(end_line);
+            if end_line > text.line_of_offset(range.start)
+                && text
+                    .slice_to_cow(end_line_start..range.end)
+                    .chars()
+                    .all(char::is_whitespace)
+            {
+                range.end = end_line_start - 1;
+            }
+        },
+        // An inclusive motion that didn't move found no target, so it doesn't cover any text
+        MotionKind::Inclusive if target != start => {
+            range.end = text.next_grapheme_offset(range.end).unwrap_or(range.end);
+        },
+        MotionKind::Exclusive | MotionKind::Inclusive | MotionKind::Linewise => {},
+    }
+    (range, kind)
+}
+
+/// As in vim, `cw` on a word changes up to the end of the word, rather than up to the start of the next one.
+/// The same goes for whitespace-delimited words and subwords.
+fn change_word_motion<'a>(text: &Rope, offset: usize, motion: Motion<'a>) -> Motion<'a> {
+    const END_OF_WORD: Motion<'static> = Motion::NextWordBoundary(WordBoundaryType::End);
+    const END_OF_BIG_WORD: Motion<'static> = Motion::NextBigWordBoundary(WordBoundaryType::End);
+    const END_OF_SUBWORD: Motion<'static> = Motion::NextSubwordBoundary(WordBoundaryType::End);
+    let on_word = matches!(
+        text.next_grapheme_offset(offset),
+        Some(next) if !text.slice_to_cow(offset..next).chars().all(char::is_whitespace)
+    );
+    let end_of_word = |motion: &Motion| match motion {
+        Motion::NextWordBoundary(WordBoundaryType::Start) => Some(&END_OF_WORD),
+        Motion::NextBigWordBoundary(WordBoundaryType::Start) => Some(&END_OF_BIG_WORD),
+        Motion::NextSubwordBoundary(WordBoundaryType::Start) => Some(&END_OF_SUBWORD),
+        _ => None,
+    };
+    match motion {
+        _ if !on_word => motion,
+        Motion::Repeated(count, inner) => {
+            end_of_word(inner).map_or(motion, |end| Motion::Repeated(count, end))
+        },
+        motion => end_of_word(&motion).map_or(motion, |end| *end),
+    }
+}
+
+/// Offset of the first non-whitespace character in the line containing the given offset
+pub(super) fn first_non_blank_in_line(text: &Rope, offset: usize) -> usize {
+    let line_start = text.offset_of_line(text.line_of_offset(offset));
+    let indent = text
+        .iter_chunks(line_start..)
+        .flat_map(|x| x.chars())
+        .take_while(|c| *c != '\n' && c.is_whitespace())
+        .map(char::len_utf8)
+        .sum::<usize>();
+    line_start + indent
+}
+
+/// Range of the full lines the given range touches.
+/// A range ending right after a newline does not include the following line.
+fn lines_of_range(text: &Rope, range: &Range<usize>) -> Range<usize> {
+    let last_offset = if range.is_empty() {
+        range.end
+    } else {
+        range.end - 1
+    };
+    full_lines(
+        text,
+        text.line_of_offset(range.start),
+        text.line_of_offset(last_offset),
+    )
+}
+
+/// Range from the start of the first line up to the start of the line after the last line
+pub(super) fn full_lines(text: &Rope, first_line: usize, last_line: usize) -> Range<usize> {
+    let max_line = text.line_of_offset(text.len());
+    let end = if last_line < max_line {
+        text.offset_of_line(last_line + 1)
+    } else {
+        text.len()
+    };
+    text.offset_of_line(first_line.min(max_line))..end
+}
+
+/// Offset of the end of a line, right before its line break
+pub(super) fn end_of_line(text: &Rope, line: usize) -> usize {
+    if line < text.line_of_offset(text.len()) {
+        let next_line_offset = text.offset_of_line(line + 1);
+        text.prev_grapheme_offset(next_line_offset)
+            .unwrap_or(next_line_offset)
+    } else {
+        text.len()
+    }
+}
+
+/// The range of `count` grapheme clusters starting at the one under the caret.
+/// If the line ends before that, the range stops at the end of the line if `clamp` is set,
+/// and `None` is returned otherwise.
+/// Like the caret of vim, a caret at the end of a line is on the last grapheme of it.
+pub(super) fn graphemes_at(
+    text: &Rope,
+    offset: usize,
+    count: usize,
+    clamp: bool,
+) -> Option<Range<usize>> {
+    let line = text.line_of_offset(offset);
+    let line_end = end_of_line(text, line);
+    let start = if offset == line_end && offset > text.offset_of_line(line) {
+        text.prev_grapheme_offset(offset)?
+    } else {
+        offset
+    };
+    let mut end = start;
+    for _ in 0..count {
+        match text.next_grapheme_offset(end).filter(|x| *x <= line_end) {
+            Some(next) => end = next,
+            None if clamp => break,
+            None => return None,
+        }
+    }
+    Some(start..end)
+}
+
+/// Join the lines touched by the range, joining at least two lines.
+///
+/// Only the text after the end of the first line is replaced,
+/// such that the start of the edit is where the first two lines were joined.
+/// Like in vim, no space is added after a line that is empty or ends in whitespace,
+/// or before a line that is empty or starts with `)`.
+fn join_lines(
+    text: &Rope,
+    range: &Range<usize>,
+    keep_whitespace: bool,
+) -> Option<(Range<usize>, String)> {
+    let first_line = text.line_of_offset(range.start);
+    let last_line = text
+        .line_of_offset(range.end.saturating_sub(1).max(range.start))
+        .max(first_line + 1)
+        .min(last_joinable_line(text));
+    if last_line == first_line {
+        return None;
+    }
+    let start = end_of_line(text, first_line);
+    let first_content = text.slice_to_cow(text.offset_of_line(first_line)..start);
+    let mut ends_blank =
+        first_content.trim_end().len() < first_content.len() || first_content.is_empty();
+    let mut joined = String::new();
+    for line in first_line + 1..=last_line {
+        let content = text.slice_to_cow(text.offset_of_line(line)..end_of_line(text, line));
+        if keep_whitespace {
+            joined.push_str(&content);
+            continue;
+        }
+        let content = content.trim_start();
+        if !ends_blank && !content.is_empty() && !content.starts_with(')') {
+            joined.push(' ');
+        }
+        joined.push_str(content);
+        if !content.is_empty() {
+            ends_blank = content.trim_end().len() < content.len();
+        }
+    }
+    Some((start..end_of_line(text, last_line), joined))
+}
+
+/// The last line, not counting the empty line after a trailing line break
+fn last_joinable_line(text: &Rope) -> usize {
+    let last_line = text.line_of_offset(text.len());
+    if last_line > 0 && text.offset_of_line(last_line) == text.len() {
+        last_line - 1
+    } else {
+        last_line
+    }
+}
+
+/// Replace every grapheme in the range with the given character, except for line breaks
+fn replace_chars(text: &Rope, range: &Range<usize>, c: char) -> String {
+    let mut replaced = String::new();
+    let mut offset = range.start;
+    while offset < range.end {
+        let next = text
+            .next_grapheme_offset(offset)
+            .map_or(range.end, |x| x.min(range.end));
+        let grapheme = text.slice_to_cow(offset..next);
+        if grapheme.ends_with('\n') {
+            replaced.push_str(&grapheme);
+        } else {
+            replaced.push(c);
+        }
+        offset = next;
+    }
+    replaced
+}
+
+fn toggle_case(c: char) -> Vec<char> {
+    if c.is_lowercase() {
+        c.to_uppercase().collect()
+    } else if c.is_uppercase() {
+        c.to_lowercase().collect()
+    } else {
+        vec![c]
+    }
+}
+
+/// Apply a function to every line in a string, keeping the newlines intact
+fn map_lines(s: &str, mut f: impl FnMut(&str) -> String) -> String {
+    s.split_inclusive('\n')
+        .map(|line| match line.strip_suffix('\n') {
+            Some(line) => f(line) + "\n",
+            None => f(line),
+        })
+        .collect()
+}
+
+/// The string used for one level of indentation, guessed from the first indented line in the text
+fn indent_unit(text: &Rope) -> String {
+    let first_indent = text
+        .lines(..)
+        .find_map(|line| line.chars().next().filter(|c| *c == ' ' || *c == '\t'));
+    match first_indent {
+        Some(' ') => " ".repeat(INDENT_WIDTH),
+        _ => "\t".to_string(),
+    }
+}
+
+/// Remove one level of indentation from a line
+fn dedent(line: &str) -> &str {
+    if let Some(rest) = line.strip_prefix('\t') {
+        return rest;
+    }
+    let spaces = line
+        .chars()
+        .take(INDENT_WIDTH)
+        .take_while(|c| *c == ' ')
+        .count();
+    &line[spaces..]
+}
+
+/// Indentation level of a line, counting tabs and groups of [INDENT_WIDTH] spaces
+fn indent_level(line: &str) -> usize {
+    let mut tabs = 0;
+    let mut spaces = 0;
+    for c in line.chars() {
+        match c {
+            '\t' => tabs += 1,
+            ' ' => spaces += 1,
+            _ => break,
+        }
+    }
+    tabs + spaces / INDENT_WIDTH
+}
+
+fn is_opening_bracket(c: char) -> bool {
+    matches!(c, '(' | '[' | '{')
+}
+
+fn is_closing_bracket(c: char) -> bool {
+    matches!(c, ')' | ']' | '}')
+}
+
+/// Given the indentation level of a line, determine the level of the line following it,
+/// based on the brackets opened and closed in the line.
+fn level_after_line(level: usize, content: &str) -> usize {
+    let leading_closers = content
+        .chars()
+        .take_while(|c| is_closing_bracket(*c))
+        .count();
+    let opened = content.chars().filter(|c| is_opening_bracket(*c)).count();
+    let closed = content.chars().filter(|c| is_closing_bracket(*c)).count();
+    (level + opened + leading_closers).saturating_sub(closed)
+}
+
+/// Indent the given lines based on the brackets surrounding them,
+/// using the closest non-blank line before `start` as a reference.
+fn reindent(text: &Rope, start: usize, lines: &str) -> String {
+    let unit = indent_unit(text);
+    let first_line = text.line_of_offset(start);
+    let mut level = (0..first_line)
+        .rev()
+        .map(|line| text.slice_to_cow(text.offset_of_line(line)..text.offset_of_line(line + 1)))
+        .find(|line| !line.trim().is_empty())
+        .map_or(0, |line| {
+            level_after_line(indent_level(&line), line.trim_start())
+        });
+    map_lines(lines, |line| {
+        let content = line.trim();
+        if content.is_empty() {
+            return String::new();
+        }
+        let leading_closers = content
+            .chars()
+            .take_while(|c| is_closing_bracket(*c))
+            .count();
+        let line_level = level.saturating_sub(leading_closers);
+        level = level_after_line(line_level, content);
+        format!("{}{content}", unit.repeat(line_level))
+    })
+}
+
+/// Rewrap the paragraphs in the given lines to fit within [TEXT_WIDTH].
+/// Paragraphs are separated by blank lines, and keep the indentation of their first line.
+fn format_paragraphs(lines: &str) -> String {
+    let mut result = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    for line in lines.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            wrap_paragraph(&paragraph, &mut result);
+            paragraph.clear();
+            result.push_str(line);
+        } else {
+            paragraph.push(line);
+        }
+    }
+    wrap_paragraph(&paragraph, &mut result);
+    result
+}
+
+fn wrap_paragraph(lines: &[&str], out: &mut String) {
+    let Some(first_line) = lines.first() else {
+        return;
+    };
+    let indent = &first_line[..first_line.len() - first_line.trim_start().len()];
+    let mut line = indent.to_string();
+    let mut line_width = indent.chars().count();
+    let mut line_empty = true;
+    for word in lines.iter().flat_map(|x| x.split_whitespace()) {
+        let word_width = word.chars().count();
+        if !line_empty && line_width + 1 + word_width > TEXT_WIDTH {
+            out.push_str(&line);
+            out.push('\n');
+            line = indent.to_string();
+            line_width = indent.chars().count();
+            line_empty = true;
+        }
+        if !line_empty {
+            line.push(' ');
+            line_width += 1;
+        }
+        line.push_str(word);
+        line_width += word_width;
+        line_empty = false;
+    }
+    out.push_str(&line);
+    if matches!(lines.last(), Some(line) if line.ends_with('\n')) {
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use xi_rope::Rope;
+
+    use super::{format_paragraphs, operator_edit, operator_range, OperatorRange};
+    use crate::{
+        region::Region,
+        test_util,
+        user_buffer_op::{Motion, Operator, OperatorTarget},
+        view::Viewport,
+        word_boundary::WordBoundaryType,
+    };
+
+    fn range_of(t: &str, head: usize, operator: Operator, target: OperatorTarget) -> OperatorRange {
+        operator_range(
+            &Rope::from(t),
+            &Viewport::new_ginormeous(),
+            Region::sticky_cursor(head),
+            operator,
+            target,
+        )
+    }
+
+    fn apply(t: &str, head: usize, operator: Operator, target: OperatorTarget) -> String {
+        let text = Rope::from(t);
+        let range = range_of(t, head, operator, target);
+        let (range, replacement) = operator_edit(&text, operator, &range).unwrap();
+        let mut result = t.to_string();
+        result.replace_range(range, &replacement);
+        result
+    }
+
+    #[test]
+    fn test_exclusive_motion_range() {
+        test_util::setup_test();
+        let word = OperatorTarget::Motion(Motion::NextWordBoundary(WordBoundaryType::Start));
+        assert_eq!(0..4, range_of("foo bar", 0, Operator::Delete, word).range);
+        assert_eq!(
+            4..7,
+            range_of("foo bar\nbaz", 4, Operator::Delete, word).range,
+            "Exclusive motions to the start of a line stop at the end of the previous line"
+        );
+        assert_eq!(
+            0..3,
+            range_of(
+                "foo bar",
+                3,
+                Operator::Delete,
+                OperatorTarget::Motion(Motion::StartOfLine)
+            )
+            .range,
+            "Backwards motions end right before the caret"
+        );
+    }
+
+    #[test]
+    fn test_change_word() {
+        test_util::setup_test();
+        let word = Motion::NextWordBoundary(WordBoundaryType::Start);
+        assert_eq!(
+            0..3,
+            range_of("foo bar", 0, Operator::Change, OperatorTarget::Motion(word)).range
+        );
+        assert_eq!(
+            0..7,
+            range_of(
+                "foo bar baz",
+                0,
+                Operator::Change,
+                OperatorTarget::Motion(Motion::Repeated(2, &word))
+            )
+            .range
+        );
+        assert_eq!(
+            3..4,
+            range_of("foo bar", 3, Operator::Change, OperatorTarget::Motion(word)).range,
+            "On whitespace, cw behaves like dw"
+        );
+    }
+
+    #[test]
+    fn test_linewise_range() {
+        test_util::setup_test();
+        let t = "foo\nbar\nbaz";
+        assert_eq!(
+            OperatorRange {
+                range: 4..11,
+                linewise: true
+            },
+            range_of(t, 5, Operator::Delete, OperatorTarget::Motion(Motion::Down))
+        );
+        assert_eq!(
+            0..8,
+            range_of(t, 1, Operator::Yank, OperatorTarget::Lines(2)).range
+        );
+        assert_eq!(
+            0..4,
+            range_of(
+                t,
+                1,
+                Operator::IndentRight,
+                OperatorTarget::Motion(Motion::Right)
+            )
+            .range,
+            "Linewise operators always act on full lines"
+        );
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        test_util::setup_test();
+        assert_eq!(
+            "bar\n",
+            apply("foo\nbar\n", 1, Operator::Delete, OperatorTarget::Lines(1))
+        );
+        assert_eq!(
+            "foo",
+            apply("foo\nbar", 5, Operator::Delete, OperatorTarget::Lines(1))
+        );
+        assert_eq!(
+            "",
+            apply("foo\nbar", 1, Operator::Delete, OperatorTarget::Lines(5))
+        );
+        assert_eq!(
+            "\nbar",
+            apply("foo\nbar", 1, Operator::Change, OperatorTarget::Lines(1))
+        );
+    }
+
+    #[test]
+    fn test_change_case() {
+        test_util::setup_test();
+        let line = OperatorTarget::Lines(1);
+        assert_eq!(
+            "STRASSE ÄÖ",
+            apply("straße äÖ", 0, Operator::Uppercase, line)
+        );
+        assert_eq!(
+            "straße äö",
+            apply("straße äÖ", 0, Operator::Lowercase, line)
+        );
+        assert_eq!(
+            "STRASSE Äö",
+            apply("straße äÖ", 0, Operator::ToggleCase, line)
+        );
+    }
+
+    #[test]
+    fn test_join_lines() {
+        test_util::setup_test();
+        let join = Operator::Join {
+            keep_whitespace: false,
+        };
+        let lines = OperatorTarget::Lines;
+        assert_eq!("foo bar\n", apply("foo\n    bar\n", 0, join, lines(1)));
+        assert_eq!("foo bar baz", apply("foo\nbar\n  baz", 0, join, lines(3)));
+        assert_eq!("foo(x)\n", apply("foo(x\n)\n", 0, join, lines(2)));
+        assert_eq!("foo  bar", apply("foo  \n\n bar", 0, join, lines(3)));
+        assert_eq!(
+            "foo  bar",
+            apply(
+                "foo\n  bar",
+                0,
+                Operator::Join {
+                    keep_whitespace: true
+                },
+                lines(1)
+            )
+        );
+        let range = range_of("foo\n", 0, join, lines(1));
+        assert_eq!(None, operator_edit(&Rope::from("foo\n"), join, &range));
+    }
+
+    #[test]
+    fn test_replace_chars() {
+        test_util::setup_test();
+        let right = OperatorTarget::Motion(Motion::Repeated(3, &Motion::Right));
+        assert_eq!(
+            "xxx",
+            apply("äe\u{301}b", 0, Operator::ReplaceChars('x'), right)
+        );
+        assert_eq!("x\nx", apply("a\nb", 0, Operator::ReplaceChars('x'), right));
+
+        let replace = Operator::ReplaceChars('x');
+        let graphemes = |count| OperatorTarget::Graphemes {
+            count,
+            clamp: false,
+        };
+        assert_eq!("xxb", apply("äe\u{301}b", 0, replace, graphemes(2)));
+        assert_eq!("abx\ndef", apply("abc\ndef", 3, replace, graphemes(1)));
+        assert_eq!(3..3, range_of("abc\ndef", 3, replace, graphemes(2)).range);
+        assert_eq!(4..4, range_of("abc\n\n", 4, replace, graphemes(1)).range);
+        let clamped = OperatorTarget::Graphemes {
+            count: 5,
+            clamp: true,
+        };
+        assert_eq!(1..3, range_of("abc\ndef", 1, replace, clamped).range);
+    }
+
+    #[test]
+    fn test_indent() {
+        test_util::setup_test();
+        let lines = OperatorTarget::Lines(3);
+        assert_eq!(
+            "\tfoo\n\n\tbar",
+            apply("foo\n\nbar", 0, Operator::IndentRight, lines)
+        );
+        assert_eq!(
+            "    a\n        b",
+            apply("a\n    b", 0, Operator::IndentRight, lines),
+            "Indentation style is taken from the text"
+        );
+        assert_eq!(
+            "a\n  b\nc",
+            apply("\ta\n      b\n  c", 0, Operator::IndentLeft, lines)
+        );
+    }
+
+    #[test]
+    fn test_reindent() {
+        test_util::setup_test();
+        assert_eq!(
+            "fn x() {\n    foo(\n        1,\n    );\n}\n",
+            apply(
+                "fn x() {\n    foo(\n1,\n  );\n      }\n",
+                14,
+                Operator::Reindent,
+                OperatorTarget::Lines(4)
+            )
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        test_util::setup_test();
+        let words = "word ".repeat(20);
+        let expected = format!(
+            "  {}word\n  {}\n\nfoo bar\n",
+            "word ".repeat(14),
+            "word ".repeat(5).trim_end()
+        );
+        assert_eq!(
+            expected,
+            format_paragraphs(&format!("  {words}\n\nfoo\nbar\n"))
+        );
+    }
+}
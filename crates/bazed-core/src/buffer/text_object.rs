@@ -0,0 +1,759 @@
+//! Find the ranges of [TextObject]s around a position in the text.
+
+use std::ops::Range;
+
+use xi_rope::Rope;
+
+use super::operator::full_lines;
+use crate::{
+    region::Region,
+    user_buffer_op::{TextObject, TextObjectKind},
+    word_boundary::{
+        find_word_boundaries, find_word_boundaries_backwards, is_blank_line,
+        iter_rope_chunks_reverse, split_sentences,
+    },
+};
+
+/// Find the range of a text object around a region.
+///
+/// For cursors, this is the object around the head of the region.
+/// For selections of brackets, the innermost pair of brackets that extends the selection is chosen,
+/// such that repeatedly selecting the same object grows the selection outwards.
+///
+/// Returns `None` if there is no such object, i.e. when looking for brackets outside of any brackets.
+pub(super) fn text_object_range(
+    text: &Rope,
+    region: Region,
+    object: TextObject,
+) -> Option<Range<usize>> {
+    let inner = object.inner;
+    match object.kind {
+        TextObjectKind::Word => word_object_range(text, region.head, inner, word_run),
+        TextObjectKind::BigWord => word_object_range(text, region.head, inner, big_word_run),
+        TextObjectKind::Quoted(quote) => quoted_range(text, region.head, quote, inner),
+        TextObjectKind::Bracketed { open, close } => {
+            bracketed_range(text, region, open, close, inner)
+        },
+        TextObjectKind::Tag => tag_range(text, region.head, inner, 1),
+        TextObjectKind::Paragraph => paragraph_range(text, region.head, inner),
+        TextObjectKind::Sentence => sentence_range(text, region.head, inner),
+    }
+}
+
+/// Find the range of `count` text objects around a region, like `d2aw` or `c2i(`.
+///
+/// Bracket and tag objects count outwards, such that the count selects the level of nesting,
+/// while quotes ignore the count. Other objects count forwards, joining the following objects
+/// as long as there are any within the line or paragraph.
+pub(super) fn counted_text_object_range(
+    text: &Rope,
+    region: Region,
+    object: TextObject,
+    count: usize,
+) -> Option<Range<usize>> {
+    if object.kind == TextObjectKind::Tag {
+        return tag_range(text, region.head, object.inner, count);
+    }
+    let mut range = text_object_range(text, region, object)?;
+    for _ in 1..count {
+        match object.kind {
+            TextObjectKind::Bracketed { .. } => {
+                let selection = Region::sticky(range.end, range.start);
+                let outer = text_object_range(text, selection, object)?;
+                if outer == range {
+                    return None;
+                }
+                range = outer;
+            },
+            TextObjectKind::Quoted(_) => break,
+            _ => match text_object_range(text, Region::sticky_cursor(range.end), object) {
+                Some(next) if next.end > range.end => range.end = next.end,
+                _ => break,
+            },
+        }
+    }
+    Some(range)
+}
+
+/// Range of the `count`th pair of tags around the offset, counting outwards from the innermost pair
+fn tag_range(text: &Rope, offset: usize, inner: bool, count: usize) -> Option<Range<usize>> {
+    let (mut open, mut close) = find_tag_pair(text, offset)?;
+    for _ in 1..count {
+        let mut offset = open.start;
+        // The pair around the offset before the opening tag may be a sibling rather than the parent
+        (open, close) = loop {
+            let (outer_open, outer_close) = find_tag_pair(text, offset.checked_sub(1)?)?;
+            if outer_open.start < open.start && close.end <= outer_close.start {
+                break (outer_open, outer_close);
+            }
+            offset = outer_open.start;
+        };
+    }
+    Some(if inner {
+        open.end..close.start
+    } else {
+        open.start..close.end
+    })
+}
+
+/// Find the offsets of the innermost pair of brackets that encloses the given offset.
+/// If the character at the offset is a bracket, it is considered to be part of the pair.
+pub(crate) fn find_enclosing_pair(
+    text: &Rope,
+    offset: usize,
+    open: char,
+    close: char,
+) -> Option<(usize, usize)> {
+    let open_offset = if char_at(text, offset) == Some(open) {
+        offset
+    } else {
+        find_unmatched_open(text, offset, open, close)?
+    };
+    let close_offset = find_unmatched_close(text, open_offset + open.len_utf8(), open, close)?;
+    Some((open_offset, close_offset))
+}
+
+/// Find the offsets of the quotes surrounding the given offset, within its line.
+///
+/// Quotes are paired up from the start of the line, ignoring quotes escaped with a backslash.
+/// If the offset is not inside of a quoted string, the next quoted string in the line is used.
+pub(crate) fn find_quote_pair(text: &Rope, offset: usize, quote: char) -> Option<(usize, usize)> {
+    let line = line_content(text, offset);
+    let line_text = text.slice_to_cow(line.clone());
+    let mut prev = None;
+    let quotes = line_text
+        .char_indices()
+        .filter(|(_, c)| {
+            let is_quote = *c == quote && prev != Some('\\');
+            prev = Some(*c);
+            is_quote
+        })
+        .map(|(i, _)| line.start + i)
+        .collect::<Vec<_>>();
+    quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|(_, close)| *close >= offset)
+}
+
+/// An opening or closing tag, like `<a href="x">` or `</a>`
+struct Tag {
+    range: Range<usize>,
+    name: String,
+    closing: bool,
+}
+
+fn is_tag_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')
+}
+
+/// Parse the opening or closing tag starting at the `<` at the given offset.
+/// Self-closing tags like `<br/>` are skipped, as they never form a pair.
+fn tag_at(text: &Rope, start: usize) -> Option<Tag> {
+    let mut content = String::new();
+    let mut chars = text.iter_chunks(start + 1..).flat_map(|x| x.chars());
+    loop {
+        match chars.next()? {
+            '<' => return None,
+            '>' => break,
+            c => content.push(c),
+        }
+    }
+    let closing = content.starts_with('/');
+    let name = content[usize::from(closing)..]
+        .chars()
+        .take_while(|c| is_tag_name_char(*c))
+        .collect::<String>();
+    if name.is_empty() || content.ends_with('/') {
+        return None;
+    }
+    Some(Tag {
+        range: start..start + content.len() + 2,
+        name,
+        closing,
+    })
+}
+
+/// Search forwards from an opening tag for the closing tag matching it.
+///
+/// Gives up at a closing tag of an element opened before the opening tag,
+/// as the opening tag is never closed within that element.
+fn find_closing_tag(text: &Rope, open: &Tag) -> Option<Range<usize>> {
+    let mut open_names: Vec<String> = Vec::new();
+    let mut current = open.range.end;
+    for c in text.iter_chunks(open.range.end..).flat_map(|x| x.chars()) {
+        let start = current;
+        current += c.len_utf8();
+        if c != '<' {
+            continue;
+        }
+        let Some(tag) = tag_at(text, start) else { continue };
+        if !tag.closing {
+            open_names.push(tag.name);
+        } else if let Some(index) = open_names.iter().rposition(|name| *name == tag.name) {
+            open_names.truncate(index);
+        } else if tag.name == open.name {
+            return Some(tag.range);
+        } else {
+            return None;
+        }
+    }
+    None
+}
+
+/// Find the ranges of the innermost pair of matching opening and closing tags around the given offset,
+/// such as `<a href="x">` and `</a>`. The tags themselves are considered to be part of the pair.
+///
+/// Searches backwards from the offset for an opening tag that is closed after the offset,
+/// skipping opening tags that are closed before the offset or never closed at all.
+pub(crate) fn find_tag_pair(text: &Rope, offset: usize) -> Option<(Range<usize>, Range<usize>)> {
+    // A tag starting at the offset is part of the search
+    let search_end = text.next_grapheme_offset(offset).unwrap_or(text.len());
+    // Names of the tags closed before the offset that haven't been matched with their opening tag yet
+    let mut closed_names: Vec<String> = Vec::new();
+    let mut current = search_end;
+    for c in iter_rope_chunks_reverse(text, ..search_end).flat_map(|x| x.chars().rev()) {
+        current -= c.len_utf8();
+        if c != '<' {
+            continue;
+        }
+        let Some(tag) = tag_at(text, current) else { continue };
+        if tag.closing {
+            if tag.range.end <= offset {
+                closed_names.push(tag.name);
+            }
+        } else if let Some(index) = closed_names.iter().rposition(|name| *name == tag.name) {
+            closed_names.remove(index);
+        } else if let Some(close) = find_closing_tag(text, &tag) {
+            if offset < close.end {
+                return Some((tag.range, close));
+            }
+        }
+    }
+    None
+}
+
+/// Find the delimiters of the bracket, quote or tag object around the offset,
+/// i.e. the ranges of its opening and closing brackets.
+///
+/// Returns `None` for other kinds of objects, which have no delimiters,
+/// or if there is no such object around the offset.
+pub(super) fn find_delimiters(
+    text: &Rope,
+    offset: usize,
+    kind: TextObjectKind,
+) -> Option<(Range<usize>, Range<usize>)> {
+    match kind {
+        TextObjectKind::Bracketed { open, close } => {
+            let (open_offset, close_offset) = find_enclosing_pair(text, offset, open, close)?;
+            Some((
+                open_offset..open_offset + open.len_utf8(),
+                close_offset..close_offset + close.len_utf8(),
+            ))
+        },
+        TextObjectKind::Quoted(quote) => {
+            let (open_offset, close_offset) = find_quote_pair(text, offset, quote)?;
+            Some((
+                open_offset..open_offset + quote.len_utf8(),
+                close_offset..close_offset + quote.len_utf8(),
+            ))
+        },
+        TextObjectKind::Tag => find_tag_pair(text, offset),
+        _ => None,
+    }
+}
+
+fn char_at(text: &Rope, offset: usize) -> Option<char> {
+    text.slice_to_cow(offset..text.next_grapheme_offset(offset)?)
+        .chars()
+        .next()
+}
+
+fn is_whitespace_at(text: &Rope, offset: usize) -> bool {
+    matches!(char_at(text, offset), Some(c) if c.is_whitespace())
+}
+
+/// Range of the line containing the offset, excluding its newline
+fn line_content(text: &Rope, offset: usize) -> Range<usize> {
+    let line = text.line_of_offset(offset);
+    let start = text.offset_of_line(line);
+    let end = text.offset_of_line(line + 1);
+    let content = text.slice_to_cow(start..end);
+    start..(start + content.trim_end_matches(['\n', '\r']).len())
+}
+
+/// Search backwards from the offset (exclusive) for an opening bracket that isn't closed before the offset
+fn find_unmatched_open(text: &Rope, offset: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut current = offset;
+    for c in iter_rope_chunks_reverse(text, ..offset).flat_map(|x| x.chars().rev()) {
+        current -= c.len_utf8();
+        if c == close {
+            depth += 1;
+        } else if c == open {
+            if depth == 0 {
+                return Some(current);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Search forwards from the offset (inclusive) for a closing bracket that wasn't opened after the offset
+fn find_unmatched_close(text: &Rope, offset: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut current = offset;
+    for c in text.iter_chunks(offset..).flat_map(|x| x.chars()) {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return Some(current);
+            }
+            depth -= 1;
+        }
+        current += c.len_utf8();
+    }
+    None
+}
+
+/// Range of the run of characters of the same category as the character at the offset,
+/// determined via word boundaries.
+fn word_run(text: &Rope, offset: usize, line: &Range<usize>) -> Range<usize> {
+    let next = text.next_grapheme_offset(offset).unwrap_or(offset);
+    let start = find_word_boundaries_backwards(text, next)
+        .next()
+        .map_or(0, |(offset, _)| offset);
+    let end = find_word_boundaries(text, offset)
+        .next()
+        .map_or(text.len(), |(offset, _)| offset);
+    start.max(line.start)..end.min(line.end)
+}
+
+/// Range of the run of whitespace or non-whitespace characters around the offset
+fn big_word_run(text: &Rope, offset: usize, line: &Range<usize>) -> Range<usize> {
+    let is_whitespace = is_whitespace_at(text, offset);
+    let before = text.slice_to_cow(line.start..offset);
+    let after = text.slice_to_cow(offset..line.end);
+    let start = offset
+        - before
+            .chars()
+            .rev()
+            .take_while(|c| c.is_whitespace() == is_whitespace)
+            .map(char::len_utf8)
+            .sum::<usize>();
+    let end = offset
+        + after
+            .chars()
+            .take_while(|c| c.is_whitespace() == is_whitespace)
+            .map(char::len_utf8)
+            .sum::<usize>();
+    start..end
+}
+
+/// Range of a word, given a function that finds the run of similar characters around an offset.
+///
+/// Around a word, the trailing whitespace is included, or the leading whitespace if there is none.
+/// Around whitespace, the following word is included.
+fn word_object_range(
+    text: &Rope,
+    offset: usize,
+    inner: bool,
+    run: fn(&Rope, usize, &Range<usize>) -> Range<usize>,
+) -> Option<Range<usize>> {
+    let line = line_content(text, offset);
+    if !line.contains(&offset) {
+        return None;
+    }
+    let range = run(text, offset, &line);
+    if inner {
+        return Some(range);
+    }
+    if is_whitespace_at(text, offset) {
+        if range.end < line.end {
+            return Some(range.start..run(text, range.end, &line).end);
+        }
+    } else if range.end < line.end && is_whitespace_at(text, range.end) {
+        return Some(range.start..run(text, range.end, &line).end);
+    } else if range.start > line.start {
+        let before = text
+            .prev_grapheme_offset(range.start)
+            .unwrap_or(range.start);
+        if is_whitespace_at(text, before) {
+            return Some(run(text, before, &line).start..range.end);
+        }
+    }
+    Some(range)
+}
+
+/// Range of a quoted string. Around a string, trailing whitespace is included,
+/// or leading whitespace if there is none.
+fn quoted_range(text: &Rope, offset: usize, quote: char, inner: bool) -> Option<Range<usize>> {
+    let (open, close) = find_quote_pair(text, offset, quote)?;
+    if inner {
+        return Some(open + quote.len_utf8()..close);
+    }
+    let line = line_content(text, offset);
+    let end = close + quote.len_utf8();
+    let after = text.slice_to_cow(end..line.end);
+    let trailing = after.len() - after.trim_start().len();
+    if trailing > 0 {
+        return Some(open..end + trailing);
+    }
+    let before = text.slice_to_cow(line.start..open);
+    let leading = before.len() - before.trim_end().len();
+    Some(open - leading..end)
+}
+
+fn bracketed_range(
+    text: &Rope,
+    region: Region,
+    open: char,
+    close: char,
+    inner: bool,
+) -> Option<Range<usize>> {
+    let selection = region.range();
+    let offset = if region.is_cursor() {
+        region.head
+    } else {
+        selection.start
+    };
+    let mut pair = find_enclosing_pair(text, offset, open, close)?;
+    loop {
+        let range = pair_range(text, pair, open, close, inner);
+        let extends_selection =
+            range.start <= selection.start && selection.end <= range.end && range != selection;
+        if region.is_cursor() || extends_selection {
+            return Some(range);
+        }
+        let outer_open = find_unmatched_open(text, pair.0, open, close)?;
+        let outer_close = find_unmatched_close(text, pair.1 + close.len_utf8(), open, close)?;
+        pair = (outer_open, outer_close);
+    }
+}
+
+/// Range of the text between (or including) a pair of brackets.
+///
+/// If the inside of the brackets spans multiple lines, the newline after the opening bracket
+/// and the indentation before the closing bracket are not part of the inner range,
+/// such that only the full lines in between are included.
+fn pair_range(
+    text: &Rope,
+    (open_offset, close_offset): (usize, usize),
+    open: char,
+    close: char,
+    inner: bool,
+) -> Range<usize> {
+    if !inner {
+        return open_offset..close_offset + close.len_utf8();
+    }
+    let mut start = open_offset + open.len_utf8();
+    if char_at(text, start) == Some('\n') {
+        start += 1;
+    }
+    let close_line_start = text.offset_of_line(text.line_of_offset(close_offset));
+    let before_close = text.slice_to_cow(close_line_start..close_offset);
+    let end = if close_line_start > start && before_close.trim().is_empty() {
+        close_line_start
+    } else {
+        close_offset
+    };
+    start..end.max(start)
+}
+
+/// Range of the lines around the offset that are all either blank or not.
+/// Around a paragraph, the following blank lines are included, or the preceding ones if there are none.
+fn paragraph_range(text: &Rope, offset: usize, inner: bool) -> Option<Range<usize>> {
+    let last_line = text.line_of_offset(text.len());
+    let line = text.line_of_offset(offset);
+    let blank = is_blank_line(text, line);
+    let mut first = line;
+    while first > 0 && is_blank_line(text, first - 1) == blank {
+        first -= 1;
+    }
+    let mut last = line;
+    while last < last_line && is_blank_line(text, last + 1) == blank {
+        last += 1;
+    }
+    if !inner {
+        if last < last_line {
+            last += 1;
+            while last < last_line && is_blank_line(text, last + 1) != blank {
+                last += 1;
+            }
+        } else if first > 0 {
+            first -= 1;
+            while first > 0 && is_blank_line(text, first - 1) != blank {
+                first -= 1;
+            }
+        }
+    }
+    Some(full_lines(text, first, last))
+}
+
+/// Range of the sentence around the offset, within its paragraph.
+/// Around a sentence, the trailing whitespace is included, or the leading whitespace if there is none.
+fn sentence_range(text: &Rope, offset: usize, inner: bool) -> Option<Range<usize>> {
+    let paragraph = paragraph_range(text, offset, true)?;
+    let content = text.slice_to_cow(paragraph.clone());
+    let content_len = content.trim_end().len();
+    if content_len == 0 {
+        return None;
+    }
+    let sentences = split_sentences(&content[..content_len]);
+    let relative = offset - paragraph.start;
+    let (index, sentence) = sentences
+        .iter()
+        .enumerate()
+        .find(|(i, _)| sentences.get(i + 1).map_or(content_len, |next| next.start) > relative)
+        .or_else(|| sentences.iter().enumerate().next_back())?;
+    let range = if inner {
+        sentence.clone()
+    } else if let Some(next) = sentences.get(index + 1) {
+        sentence.start..next.start
+    } else if index > 0 {
+        sentences[index - 1].end..sentence.end
+    } else {
+        sentence.clone()
+    };
+    Some(paragraph.start + range.start..paragraph.start + range.end)
+}
+
+#[cfg(test)]
+mod test {
+    use xi_rope::Rope;
+
+    use super::{counted_text_object_range, find_delimiters, text_object_range};
+    use crate::{
+        region::Region,
+        test_util,
+        user_buffer_op::{TextObject, TextObjectKind},
+    };
+
+    fn object_at(t: &str, offset: usize, kind: TextObjectKind, inner: bool) -> Option<&str> {
+        let range = text_object_range(&Rope::from(t), Region::sticky_cursor(offset), TextObject {
+            kind,
+            inner,
+        })?;
+        Some(&t[range])
+    }
+
+    #[test]
+    fn test_word() {
+        test_util::setup_test();
+        let t = "foo bar.baz  qux";
+        assert_eq!(Some("bar"), object_at(t, 5, TextObjectKind::Word, true));
+        assert_eq!(
+            Some("bar "),
+            object_at("foo bar baz", 5, TextObjectKind::Word, false)
+        );
+        assert_eq!(Some("."), object_at(t, 7, TextObjectKind::Word, true));
+        assert_eq!(Some("  "), object_at(t, 11, TextObjectKind::Word, true));
+        assert_eq!(Some("  qux"), object_at(t, 11, TextObjectKind::Word, false));
+        assert_eq!(
+            Some(" qux"),
+            object_at("foo qux\nbar", 5, TextObjectKind::Word, false),
+            "Without trailing whitespace, leading whitespace is included"
+        );
+        assert_eq!(
+            Some("äöü"),
+            object_at("a äöü", 4, TextObjectKind::Word, true)
+        );
+        assert_eq!(None, object_at("foo\n\nbar", 4, TextObjectKind::Word, true));
+    }
+
+    #[test]
+    fn test_big_word() {
+        test_util::setup_test();
+        let t = "foo bar.baz  qux";
+        assert_eq!(
+            Some("bar.baz"),
+            object_at(t, 5, TextObjectKind::BigWord, true)
+        );
+        assert_eq!(
+            Some("bar.baz  "),
+            object_at(t, 5, TextObjectKind::BigWord, false)
+        );
+    }
+
+    #[test]
+    fn test_quoted() {
+        test_util::setup_test();
+        let t = r#"x = "foo \" bar" + "baz""#;
+        let quoted = TextObjectKind::Quoted('"');
+        assert_eq!(Some(r#"foo \" bar"#), object_at(t, 6, quoted, true));
+        assert_eq!(Some(r#""foo \" bar" "#), object_at(t, 6, quoted, false));
+        assert_eq!(
+            Some("foo \\\" bar"),
+            object_at(t, 0, quoted, true),
+            "Before any quotes, the next quoted string is used"
+        );
+        assert_eq!(Some(r#" "baz""#), object_at(t, 20, quoted, false));
+        assert_eq!(None, object_at("\"foo\n\"", 1, quoted, true));
+    }
+
+    #[test]
+    fn test_bracketed() {
+        test_util::setup_test();
+        let parens = TextObjectKind::Bracketed {
+            open: '(',
+            close: ')',
+        };
+        let t = "f(a, (b), c)";
+        assert_eq!(Some("a, (b), c"), object_at(t, 3, parens, true));
+        assert_eq!(Some("(a, (b), c)"), object_at(t, 10, parens, false));
+        assert_eq!(Some("b"), object_at(t, 6, parens, true));
+        assert_eq!(Some("(b)"), object_at(t, 5, parens, false));
+        assert_eq!(Some("(b)"), object_at(t, 7, parens, false));
+        assert_eq!(None, object_at(t, 0, parens, true));
+    }
+
+    #[test]
+    fn test_tag() {
+        test_util::setup_test();
+        let t = "<div class=\"x\"><p>a<br/>b</p> <i>c</div>";
+        assert_eq!(Some("a<br/>b"), object_at(t, 19, TextObjectKind::Tag, true));
+        assert_eq!(
+            Some("<p>a<br/>b</p>"),
+            object_at(t, 16, TextObjectKind::Tag, false)
+        );
+        assert_eq!(
+            Some("<p>a<br/>b</p> <i>c"),
+            object_at(t, 34, TextObjectKind::Tag, true)
+        );
+        assert_eq!(None, object_at("a < b", 3, TextObjectKind::Tag, true));
+        let t = "<b>x<b>y</b>z</b>";
+        assert_eq!(Some("y"), object_at(t, 10, TextObjectKind::Tag, true));
+        assert_eq!(
+            Some("x<b>y</b>z"),
+            object_at(t, 12, TextObjectKind::Tag, true)
+        );
+    }
+
+    #[test]
+    fn test_find_delimiters() {
+        test_util::setup_test();
+        let t = Rope::from("f(\"a\", <b>c</b>)");
+        let parens = TextObjectKind::Bracketed {
+            open: '(',
+            close: ')',
+        };
+        assert_eq!(Some((1..2, 15..16)), find_delimiters(&t, 3, parens));
+        assert_eq!(
+            Some((2..3, 4..5)),
+            find_delimiters(&t, 3, TextObjectKind::Quoted('"'))
+        );
+        assert_eq!(
+            Some((7..10, 11..15)),
+            find_delimiters(&t, 10, TextObjectKind::Tag)
+        );
+        assert_eq!(None, find_delimiters(&t, 3, TextObjectKind::Word));
+    }
+
+    #[test]
+    fn test_bracketed_multiline() {
+        test_util::setup_test();
+        let braces = TextObjectKind::Bracketed {
+            open: '{',
+            close: '}',
+        };
+        let t = "fn x() {\n    foo\n}";
+        assert_eq!(Some("    foo\n"), object_at(t, 12, braces, true));
+        assert_eq!(Some("{\n    foo\n}"), object_at(t, 12, braces, false));
+    }
+
+    #[test]
+    fn test_bracketed_selection_grows() {
+        test_util::setup_test();
+        let t = "((a) b)";
+        let object = TextObject {
+            kind: TextObjectKind::Bracketed {
+                open: '(',
+                close: ')',
+            },
+            inner: true,
+        };
+        let rope = Rope::from(t);
+        let first = text_object_range(&rope, Region::sticky_cursor(2), object).unwrap();
+        assert_eq!(2..3, first);
+        let second = text_object_range(&rope, Region::sticky(3, 2), object).unwrap();
+        assert_eq!(1..6, second);
+    }
+
+    #[test]
+    fn test_counted() {
+        test_util::setup_test();
+        let counted = |t: &'static str, offset, kind, inner, count| {
+            let object = TextObject { kind, inner };
+            let region = Region::sticky_cursor(offset);
+            let range = counted_text_object_range(&Rope::from(t), region, object, count)?;
+            Some(&t[range])
+        };
+        let t = "foo bar baz qux";
+        assert_eq!(
+            Some("bar baz "),
+            counted(t, 5, TextObjectKind::Word, false, 2)
+        );
+        assert_eq!(Some("bar "), counted(t, 5, TextObjectKind::Word, true, 2));
+        assert_eq!(
+            Some("bar baz qux"),
+            counted(t, 5, TextObjectKind::Word, false, 9)
+        );
+        let parens = TextObjectKind::Bracketed {
+            open: '(',
+            close: ')',
+        };
+        assert_eq!(Some("a, (b)"), counted("f(a, (b))", 6, parens, true, 2));
+        assert_eq!(None, counted("f(a, (b))", 6, parens, true, 3));
+        let t = "<a><b>x</b><c>y</c></a>";
+        assert_eq!(
+            Some("<b>x</b><c>y</c>"),
+            counted(t, 15, TextObjectKind::Tag, true, 2)
+        );
+        assert_eq!(None, counted(t, 15, TextObjectKind::Tag, true, 3));
+        let quoted = TextObjectKind::Quoted('"');
+        assert_eq!(Some("x"), counted("\"x\" \"y\"", 1, quoted, true, 2));
+    }
+
+    #[test]
+    fn test_paragraph() {
+        test_util::setup_test();
+        let t = "a\nb\n\n\nc\n";
+        assert_eq!(
+            Some("a\nb\n"),
+            object_at(t, 2, TextObjectKind::Paragraph, true)
+        );
+        assert_eq!(
+            Some("a\nb\n\n\n"),
+            object_at(t, 2, TextObjectKind::Paragraph, false)
+        );
+        assert_eq!(
+            Some("\n\n"),
+            object_at(t, 4, TextObjectKind::Paragraph, true)
+        );
+        assert_eq!(
+            Some("\n\nc\n"),
+            object_at(t, 4, TextObjectKind::Paragraph, false)
+        );
+    }
+
+    #[test]
+    fn test_sentence() {
+        test_util::setup_test();
+        let t = "Hello there. How are\nyou?  Fine!\n\nNext.";
+        assert_eq!(
+            Some("Hello there."),
+            object_at(t, 3, TextObjectKind::Sentence, true)
+        );
+        assert_eq!(
+            Some("How are\nyou?  "),
+            object_at(t, 16, TextObjectKind::Sentence, false)
+        );
+        assert_eq!(
+            Some("  Fine!"),
+            object_at(t, 28, TextObjectKind::Sentence, false)
+        );
+        assert_eq!(None, object_at(t, 33, TextObjectKind::Sentence, true));
+    }
+}
@@ -0,0 +1,128 @@
+//! Undo files, which keep the undo history of a buffer across sessions.
+//!
+//! An undo file stores the revisions of the engine together with the [UndoHistory],
+//! as well as a hash of the text the history leads up to.
+//! The history is only restored if that hash matches the text that is being loaded,
+//! such that changes made to a file outside of the editor invalidate its undo file.
+
+use std::{io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use xi_rope::engine::Engine;
+
+use super::{undo_history::UndoHistory, Buffer};
+
+/// Version of the format of undo files. Files in any other version are ignored.
+const VERSION: u32 = 2;
+
+#[derive(Serialize)]
+struct UndoFileRef<'a> {
+    version: u32,
+    content_hash: u64,
+    history: &'a UndoHistory,
+    engine: &'a Engine,
+}
+
+#[derive(Deserialize)]
+struct UndoFile {
+    content_hash: u64,
+    history: UndoHistory,
+    /// Kept as a value to fix up the session of the engine before deserializing it, see [read_engine]
+    engine: serde_json::Value,
+}
+
+impl Buffer {
+    /// Store the undo history of the buffer in an undo file,
+    /// along with the hash of the text it was written for.
+    pub(crate) fn write_undo_file(&self, path: &Path, content_hash: u64) -> io::Result<()> {
+        let undo_file = UndoFileRef {
+            version: VERSION,
+            content_hash,
+            history: &self.undo_history,
+            engine: &self.engine,
+        };
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        std::fs::write(path, serde_json::to_vec(&undo_file)?)
+    }
+
+    /// Restore a buffer, including its undo history, from an undo file.
+    ///
+    /// Returns `None` if there is no undo file at the given path,
+    /// or if it was written for a text with a different hash or in an older format.
+    pub(crate) fn read_undo_file(path: &Path, content_hash: u64) -> io::Result<Option<Buffer>> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        // The version is checked first, as files in other versions might not even deserialize
+        let value: serde_json::Value = serde_json::from_slice(&bytes)?;
+        if value.get("version").and_then(|x| x.as_u64()) != Some(VERSION.into()) {
+            tracing::info!(?path, "Ignoring undo file of a different version");
+            return Ok(None);
+        }
+        let undo_file: UndoFile = serde_json::from_value(value)?;
+        if undo_file.content_hash != content_hash {
+            tracing::info!(?path, "Ignoring undo file of a file that was changed");
+            return Ok(None);
+        }
+        let engine = read_engine(undo_file.engine)?;
+        let mut buffer = Buffer::new_empty();
+        buffer.text = engine.get_head().clone();
+        buffer.engine = engine;
+        buffer.undo_history = undo_file.history;
+        Ok(Some(buffer))
+    }
+}
+
+/// Deserialize an engine, giving it a new session.
+///
+/// The session and revision counter of an engine aren't serialized,
+/// so without this, new revisions would reuse the ids of revisions from the previous session.
+fn read_engine(mut engine: serde_json::Value) -> serde_json::Result<Engine> {
+    let (session1, session2) = uuid::Uuid::new_v4().as_u64_pair();
+    engine["session"] = serde_json::json!([session1, session2 as u32]);
+    serde_json::from_value(engine)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Buffer;
+    use crate::{region::Region, test_util, user_buffer_op::BufferOp, view::Viewport};
+
+    #[test]
+    fn test_undo_file_round_trip() {
+        test_util::setup_test();
+        let dir = std::env::temp_dir().join(format!("bazed-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("undo");
+        let vp = Viewport::new_ginormeous();
+        let mut b = Buffer::new_from_string("hello".to_string());
+        b.insert_at_carets("a");
+        b.break_undo_group();
+        b.insert_at_carets("b");
+        b.write_undo_file(&path, 42).unwrap();
+
+        assert!(Buffer::read_undo_file(&path, 1).unwrap().is_none());
+        assert!(Buffer::read_undo_file(&dir.join("missing"), 42)
+            .unwrap()
+            .is_none());
+
+        let mut b = Buffer::read_undo_file(&path, 42).unwrap().unwrap();
+        assert_eq!("abhello", b.content_to_string());
+        b.apply_buffer_op(&vp, BufferOp::Undo);
+        assert_eq!("ahello", b.content_to_string());
+        b.regions.set_primary_caret(Region::sticky_cursor(0));
+        b.insert_at_carets("c");
+        b.break_undo_group();
+        assert_eq!("cahello", b.content_to_string());
+        b.apply_buffer_op(&vp, BufferOp::Undo);
+        assert_eq!("ahello", b.content_to_string());
+        b.apply_buffer_op(&vp, BufferOp::Undo);
+        assert_eq!("hello", b.content_to_string());
+        b.apply_buffer_op(&vp, BufferOp::Redo);
+        assert_eq!("ahello", b.content_to_string());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
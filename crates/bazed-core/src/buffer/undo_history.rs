@@ -1,117 +1,232 @@
-use std::collections::BTreeSet;
+use std::{
+    collections::BTreeSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bazed_rpc::core_proto::{UndoTree, UndoTreeState};
+use serde::{Deserialize, Serialize};
+
+use crate::user_buffer_op::UndoStep;
 
 /// Manages an undo history, including undo groupings and redo.
 ///
+/// The history is a tree of states of the buffer. Every undo group leads from one state to a new one,
+/// so states are identified by the id of the undo group leading to them, with state 0 being the initial one.
+/// Editing after undoing starts a new branch, and the old branch stays reachable
+/// by moving through the states in the order they were created in.
+/// In any state, exactly the undo groups on the path from the initial state to it are applied.
+///
 /// # Behavior in a sample editing history
 /// ```ignore
-/// initial           => id = 1, history = [0            ], idx = 0, undone = {}
-/// edit[new_group=t] => id = 1, history = [0, 1         ], idx = 1, undone = {}
-/// edit[new_group=f] => id = 1, history = [0, 1         ], idx = 1, undone = {}
-/// edit[new_group=t] => id = 2, history = [0, 1, 2      ], idx = 2, undone = {}
-/// edit[new_group=f] => id = 2, history = [0, 1, 2      ], idx = 2, undone = {}
-/// edit[new_group=t] => id = 3, history = [0, 1, 2, 3   ], idx = 3, undone = {}
-/// undo              => id = 3, history = [0, 1, 2, 3   ], idx = 2, undone = { 3 }
-/// undo              => id = 3, history = [0, 1, 2, 3   ], idx = 1, undone = { 3, 2 }
-/// undo              => id = 3, history = [0, 1, 2, 3   ], idx = 1, undone = { 3, 2 }
-/// redo              => id = 3, history = [0, 1, 2, 3   ], idx = 2, undone = { 3 }
-/// edit[new_group=t] => id = 4, history = [0, 1, 2,    4], idx = 3, undone = { 3 }
+/// initial           => current = 0, states = [0                      ], undone = {}
+/// edit[new_group=t] => current = 1, states = [0, 1<-0                ], undone = {}
+/// edit[new_group=f] => current = 1, states = [0, 1<-0                ], undone = {}
+/// edit[new_group=t] => current = 2, states = [0, 1<-0, 2<-1          ], undone = {}
+/// undo              => current = 1, states = [0, 1<-0, 2<-1          ], undone = { 2 }
+/// undo              => current = 0, states = [0, 1<-0, 2<-1          ], undone = { 1, 2 }
+/// undo              => current = 0, states = [0, 1<-0, 2<-1          ], undone = { 1, 2 }
+/// redo              => current = 1, states = [0, 1<-0, 2<-1          ], undone = { 2 }
+/// edit[new_group=t] => current = 3, states = [0, 1<-0, 2<-1, 3<-1    ], undone = { 2 }
+/// earlier 1         => current = 2, states = [0, 1<-0, 2<-1, 3<-1    ], undone = { 3 }
 /// ```
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub(super) struct UndoHistory {
-    /// The undo group id that current undos will be grouped under.
+    /// All states of the history, indexed by the id of the undo group leading to them.
     ///
-    /// This ID always just increments and will never reuse previous IDs, even if we undo and then do other edits.
-    undo_gid: usize,
-    /// The current position in the history. This is *not* an undo-group-id.
-    /// Think of this as a cursor into time:
-    /// - every id `history[n]` where `n > current_history_index` is in the future and may be redone to.
-    /// - every id `history[n]` where `n < current_history_index` is in the past and may be undone to
-    /// - when adding further edits, history gets truncated to end at the current index, and a new step gets added.
+    /// Undo group ids always just increment and are never reused, even if we undo and then do other edits,
+    /// so the states are in the order they were created in.
+    states: Vec<UndoState>,
+    /// The state the buffer is currently in.
     ///
-    /// **Invariant**: always < history.len().
-    cur_history_idx: usize,
-    /// List of undo groups in the history. See documentation of `cur_history_idx` for more details
-    history: Vec<usize>,
-    /// Set of undo groups that are currently undone.
-    /// This may contain undo groups that are no longer part of the history due do
-    /// us undoing and then performing edits.
-    currently_undone: BTreeSet<usize>,
+    /// **Invariant**: always < states.len().
+    current: usize,
+    /// The states the buffer was saved in, in the order of the saves
+    saves: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UndoState {
+    /// The state this one was created from, `None` for the initial state
+    parent: Option<usize>,
+    /// The child state that redo moves to, which is the one most recently visited
+    redo_child: Option<usize>,
+    /// Time the state was created at
+    time: SystemTime,
 }
 
 impl Default for UndoHistory {
     fn default() -> Self {
         Self {
-            undo_gid: 0,
-            cur_history_idx: 0,
-            history: vec![0],
-            currently_undone: Default::default(),
+            states: vec![UndoState {
+                parent: None,
+                redo_child: None,
+                time: SystemTime::now(),
+            }],
+            current: 0,
+            saves: Vec::new(),
         }
     }
 }
 
 impl UndoHistory {
-    /// If a new undo group should be created, creates a new undo group id,
-    /// truncates any history-elements that are in the future and adds the new group to the history.
-    /// Otherwise, just returns the current undo group id.
+    /// Creates a new undo group id, adding a new state as a child of the current one, and moves to it.
     pub(super) fn start_new_undo_group(&mut self) -> usize {
-        self.undo_gid += 1;
-        self.cur_history_idx += 1;
-        self.history.truncate(self.cur_history_idx);
-        self.history.push(self.undo_gid);
+        self.add_state(SystemTime::now());
         tracing::trace!(undo_history = ?self, "Started a new undo group");
-        self.undo_gid
+        self.current
+    }
+
+    fn add_state(&mut self, time: SystemTime) {
+        let id = self.states.len();
+        self.states.push(UndoState {
+            parent: Some(self.current),
+            redo_child: None,
+            time,
+        });
+        self.states[self.current].redo_child = Some(id);
+        self.current = id;
+    }
+
+    /// The state the buffer is currently in, which is the id of the last undo group applied to it
+    pub(super) fn current(&self) -> usize {
+        self.current
     }
 
     /// Returns the current undo group id,
-    /// or starts a new group if we're not currently at the end of the history
+    /// or starts a new group if we're not currently in the most recently created state
     pub(super) fn calculate_undo_id(&mut self) -> usize {
-        if !self.at_end_of_history() {
+        if !self.at_newest_state() {
             self.start_new_undo_group();
         }
-        self.undo_gid
-    }
-
-    /// Check whether we're currently at the end of the history
-    pub(super) fn at_end_of_history(&self) -> bool {
-        self.cur_history_idx == self.history.len() - 1
+        self.current
     }
 
-    pub(super) fn currently_undone(&self) -> &BTreeSet<usize> {
-        &self.currently_undone
+    /// Check whether we're currently in the most recently created state,
+    /// as only the undo group leading to it may still be extended
+    fn at_newest_state(&self) -> bool {
+        self.current == self.newest_state()
     }
 
-    pub(super) fn can_undo(&self) -> bool {
-        self.cur_history_idx > 0
+    fn newest_state(&self) -> usize {
+        self.states.len() - 1
     }
 
-    /// Get the id of the point in history that is currently undone to.
-    /// I.e. if history is [0, 1, 2], we have undone once, then this will yield 1.
-    pub(super) fn get_active_undo_id(&self) -> usize {
-        self.history[self.cur_history_idx]
+    /// All undo groups that are not on the path to the current state
+    pub(super) fn currently_undone(&self) -> BTreeSet<usize> {
+        let mut undone: BTreeSet<usize> = (0..self.states.len()).collect();
+        let mut state = Some(self.current);
+        while let Some(id) = state {
+            undone.remove(&id);
+            state = self.states[id].parent;
+        }
+        undone
     }
 
     pub(super) fn undo(&mut self) -> bool {
-        if !self.can_undo() {
-            return false;
-        }
-        debug_assert!(self.currently_undone.insert(self.get_active_undo_id()));
-        self.cur_history_idx -= 1;
+        let Some(parent) = self.states[self.current].parent else { return false };
+        self.states[parent].redo_child = Some(self.current);
+        self.current = parent;
         true
     }
 
     pub(super) fn redo(&mut self) -> bool {
-        if self.at_end_of_history() {
-            // If there are no further history-elements to redo to, we cannot redo.
+        // If there is no child state to redo to, we cannot redo.
+        let Some(child) = self.states[self.current].redo_child else { return false };
+        self.current = child;
+        true
+    }
+
+    /// Move to an older state in chronological order, regardless of the branch it is on
+    pub(super) fn earlier(&mut self, step: UndoStep) -> bool {
+        let target = match step {
+            UndoStep::Count(count) => self.current.saturating_sub(count),
+            UndoStep::Time(duration) => {
+                let time = self.states[self.current].time.checked_sub(duration);
+                self.states[..=self.current]
+                    .iter()
+                    .rposition(|state| Some(state.time) <= time)
+                    .unwrap_or(0)
+            },
+            UndoStep::Saves(count) => self
+                .saved_states()
+                .rev()
+                .filter(|id| *id < self.current)
+                .nth(count.saturating_sub(1))
+                .unwrap_or(0),
+        };
+        self.goto(target)
+    }
+
+    /// Move to a newer state in chronological order, regardless of the branch it is on
+    pub(super) fn later(&mut self, step: UndoStep) -> bool {
+        let target = match step {
+            UndoStep::Count(count) => (self.current + count).min(self.newest_state()),
+            UndoStep::Time(duration) => {
+                let time = self.states[self.current].time.checked_add(duration);
+                let newer = self.states[self.current..]
+                    .iter()
+                    .rposition(|state| time.map_or(true, |time| state.time <= time));
+                self.current + newer.unwrap_or(0)
+            },
+            UndoStep::Saves(count) => self
+                .saved_states()
+                .filter(|id| *id > self.current)
+                .nth(count.saturating_sub(1))
+                .unwrap_or_else(|| self.newest_state()),
+        };
+        self.goto(target)
+    }
+
+    /// All states the buffer was saved in, in chronological order
+    fn saved_states(&self) -> impl DoubleEndedIterator<Item = usize> {
+        let saved: BTreeSet<usize> = self.saves.iter().copied().collect();
+        saved.into_iter()
+    }
+
+    /// Move to the given state, such that redo from any state on the path to it leads towards it
+    fn goto(&mut self, target: usize) -> bool {
+        if target == self.current {
             return false;
         }
-        self.cur_history_idx += 1;
-        debug_assert!(self.currently_undone.remove(&self.get_active_undo_id()));
+        let mut state = target;
+        while let Some(parent) = self.states[state].parent {
+            self.states[parent].redo_child = Some(state);
+            state = parent;
+        }
+        self.current = target;
         true
     }
+
+    /// Remember that the buffer was saved in its current state
+    pub(super) fn mark_saved(&mut self) {
+        self.saves.push(self.current);
+    }
+
+    pub(super) fn tree(&self) -> UndoTree {
+        let states = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(id, state)| UndoTreeState {
+                parent: state.parent,
+                timestamp: state
+                    .time
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |x| x.as_millis() as u64),
+                saved: self.saves.contains(&id),
+            })
+            .collect();
+        UndoTree {
+            states,
+            current: self.current,
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
+
     use super::*;
     use crate::test_util;
 
@@ -123,21 +238,9 @@ mod test {
 
     /// assert history state with a defined syntax to make tests prettier
     macro_rules! assert_hist {
-        ($h:expr,
-            gid = $gid:expr,
-            idx = $idx:expr,
-            history = [$($hist:expr),*],
-            undone = [$($undone:expr),*]
-        ) => {
-            assert_eq!(
-                UndoHistory {
-                    undo_gid: $gid,
-                    history: vec![$($hist),*],
-                    cur_history_idx: $idx,
-                    currently_undone: set![$($undone),*],
-                },
-                $h
-            )
+        ($h:expr, current = $current:expr, undone = [$($undone:expr),*]) => {
+            assert_eq!($current, $h.current);
+            assert_eq!(set![$($undone),*], $h.currently_undone());
         };
     }
 
@@ -145,14 +248,14 @@ mod test {
     fn test_update_history() {
         test_util::setup_test();
         let mut h = UndoHistory::default();
-        assert_hist!(h, gid = 0, idx = 0, history = [0], undone = []);
+        assert_hist!(h, current = 0, undone = []);
         h.start_new_undo_group();
-        assert_hist!(h, gid = 1, idx = 1, history = [0, 1], undone = []);
-        // calculating a new undo group id should not do anything as long as idx is history.len() - 1
+        assert_hist!(h, current = 1, undone = []);
+        // calculating a new undo group id should not do anything as long as we are in the newest state
         h.calculate_undo_id();
-        assert_hist!(h, gid = 1, idx = 1, history = [0, 1], undone = []);
+        assert_hist!(h, current = 1, undone = []);
         h.start_new_undo_group();
-        assert_hist!(h, gid = 2, idx = 2, history = [0, 1, 2], undone = []);
+        assert_hist!(h, current = 2, undone = []);
     }
 
     #[test]
@@ -161,11 +264,11 @@ mod test {
         let mut h = UndoHistory::default();
         h.start_new_undo_group();
         h.start_new_undo_group();
-        assert_hist!(h, gid = 2, idx = 2, history = [0, 1, 2], undone = []);
+        assert_hist!(h, current = 2, undone = []);
         assert!(h.undo());
-        assert_hist!(h, gid = 2, idx = 1, history = [0, 1, 2], undone = [2]);
+        assert_hist!(h, current = 1, undone = [2]);
         assert!(h.undo());
-        assert_hist!(h, gid = 2, idx = 0, history = [0, 1, 2], undone = [1, 2]);
+        assert_hist!(h, current = 0, undone = [1, 2]);
     }
 
     #[test]
@@ -173,7 +276,7 @@ mod test {
         test_util::setup_test();
         let mut h = UndoHistory::default();
         assert!(!h.undo());
-        assert_hist!(h, gid = 0, idx = 0, history = [0], undone = []);
+        assert_hist!(h, current = 0, undone = []);
     }
 
     #[test]
@@ -184,7 +287,8 @@ mod test {
         h.undo();
         // This _should_ start a new undo group, as we're working off of an undone state
         h.calculate_undo_id();
-        assert_hist!(h, gid = 2, idx = 1, history = [0, 2], undone = [1]);
+        assert_hist!(h, current = 2, undone = [1]);
+        assert_eq!(Some(0), h.states[2].parent);
     }
 
     #[test]
@@ -193,7 +297,7 @@ mod test {
         let mut h = UndoHistory::default();
         h.start_new_undo_group();
         assert!(!h.redo());
-        assert_hist!(h, gid = 1, idx = 1, history = [0, 1], undone = []);
+        assert_hist!(h, current = 1, undone = []);
     }
 
     #[test]
@@ -202,8 +306,73 @@ mod test {
         let mut h = UndoHistory::default();
         h.start_new_undo_group();
         h.undo();
-        assert_hist!(h, gid = 1, idx = 0, history = [0, 1], undone = [1]);
+        assert_hist!(h, current = 0, undone = [1]);
         assert!(h.redo());
-        assert_hist!(h, gid = 1, idx = 1, history = [0, 1], undone = []);
+        assert_hist!(h, current = 1, undone = []);
+    }
+
+    #[test]
+    fn test_earlier_later_across_branches() {
+        test_util::setup_test();
+        let mut h = UndoHistory::default();
+        h.start_new_undo_group();
+        h.start_new_undo_group();
+        h.undo();
+        h.calculate_undo_id();
+        assert_hist!(h, current = 3, undone = [2]);
+        assert!(h.earlier(UndoStep::Count(1)));
+        assert_hist!(h, current = 2, undone = [3]);
+        // redo leads back towards the state we moved to most recently
+        h.undo();
+        assert!(h.redo());
+        assert_hist!(h, current = 2, undone = [3]);
+        assert!(h.earlier(UndoStep::Count(5)));
+        assert_hist!(h, current = 0, undone = [1, 2, 3]);
+        assert!(!h.earlier(UndoStep::Count(1)));
+        assert!(h.later(UndoStep::Count(10)));
+        assert_hist!(h, current = 3, undone = [2]);
+    }
+
+    #[test]
+    fn test_earlier_later_by_time() {
+        test_util::setup_test();
+        let start = UNIX_EPOCH + Duration::from_secs(1000);
+        let mut h = UndoHistory::default();
+        h.states[0].time = start;
+        for secs in [10, 20, 60, 70] {
+            h.add_state(start + Duration::from_secs(secs));
+        }
+        assert!(h.earlier(UndoStep::Time(Duration::from_secs(30))));
+        assert_eq!(2, h.current);
+        assert!(h.later(UndoStep::Time(Duration::from_secs(40))));
+        assert_eq!(3, h.current);
+        assert!(h.earlier(UndoStep::Time(Duration::from_secs(3600))));
+        assert_eq!(0, h.current);
+        assert!(h.later(UndoStep::Time(Duration::from_secs(3600))));
+        assert_eq!(4, h.current);
+    }
+
+    #[test]
+    fn test_earlier_later_by_saves() {
+        test_util::setup_test();
+        let mut h = UndoHistory::default();
+        h.start_new_undo_group();
+        h.mark_saved();
+        h.start_new_undo_group();
+        h.start_new_undo_group();
+        h.mark_saved();
+        h.start_new_undo_group();
+        // with changes since the last save, the first step goes back to that save
+        assert!(h.earlier(UndoStep::Saves(1)));
+        assert_eq!(3, h.current);
+        assert!(h.earlier(UndoStep::Saves(1)));
+        assert_eq!(1, h.current);
+        assert!(h.earlier(UndoStep::Saves(1)));
+        assert_eq!(0, h.current);
+        assert!(h.later(UndoStep::Saves(2)));
+        assert_eq!(3, h.current);
+        assert!(h.later(UndoStep::Saves(1)));
+        assert_eq!(4, h.current);
+        assert!(h.tree().states[3].saved);
     }
 }
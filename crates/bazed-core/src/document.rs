@@ -2,17 +2,20 @@ use std::{
     ffi::OsString,
     fs::File,
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 use bazed_rpc::core_proto::{Coordinate, CoordinateRegion, ToFrontend, ViewData};
 use uuid::Uuid;
-use xi_rope::Rope;
+use xi_rope::{engine::RevId, Rope};
+use xxhash_rust::xxh3::Xxh3;
 
 use crate::{
-    buffer::Buffer,
+    buffer::{position::Position, Buffer},
+    file_format::{DecodeIssue, FileFormat},
     view::{View, ViewId, Viewport},
-    vim_interface::VimMode,
+    vim_interface::{VimInterface, VimMode},
 };
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, derive_more::Display, derive_more::Into)]
@@ -27,38 +30,289 @@ impl DocumentId {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum SaveError {
+    #[error("The file has been changed since reading it (add ! to override)")]
+    ChangedOnDisk,
+    #[error("The file already exists (add ! to override)")]
+    FileExists,
+    #[error("{0} (add ! to write anyway)")]
+    Lossy(DecodeIssue),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// How the document was affected by a change to its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileChange {
+    /// The content of the file is the same as the one last loaded or saved
+    Unchanged,
+    /// The document had no unsaved changes, and was reloaded
+    Reloaded,
+    /// The change was merged with the unsaved changes of the document
+    Merged { conflict: bool },
+}
+
+/// The state of the file of a document when it was last loaded or saved
+#[derive(Debug, Clone)]
+struct DiskState {
+    path: PathBuf,
+    format: FileFormat,
+    /// The text of the file, as decoded from its format
+    text: Rope,
+    modified: Option<SystemTime>,
+    hash: u64,
+    /// What the text doesn't preserve about the file, such that writing it back would change more than was edited
+    issue: Option<DecodeIssue>,
+}
+
+impl DiskState {
+    fn new(path: PathBuf, format: FileFormat, text: Rope, modified: Option<SystemTime>) -> Self {
+        Self {
+            path,
+            format,
+            hash: hash_rope(&text),
+            text,
+            modified,
+            issue: None,
+        }
+    }
+
+    /// Read the current state of a file
+    fn read(path: PathBuf) -> io::Result<Self> {
+        let bytes = std::fs::read(&path)?;
+        let modified = std::fs::metadata(&path)?.modified().ok();
+        let (format, text, issue) = FileFormat::decode(&bytes);
+        Ok(Self {
+            issue,
+            ..Self::new(path, format, Rope::from(text), modified)
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct Document {
     pub path: Option<PathBuf>,
     pub buffer: Buffer,
+    /// Format the document is saved in, which is detected when loading a file
+    pub format: FileFormat,
+    /// Revision of the buffer that was last loaded from or saved to the path of the document
+    clean_revision: RevId,
+    /// State of the file the document was last loaded from or saved to,
+    /// used to notice changes made to it by other programs
+    disk_state: Option<DiskState>,
 }
 
 impl Document {
-    pub fn open_ephemeral() -> Document {
+    fn new(path: Option<PathBuf>, buffer: Buffer) -> Self {
         Self {
-            path: None,
-            buffer: Buffer::new_empty(),
+            path,
+            clean_revision: buffer.head_revision(),
+            buffer,
+            format: FileFormat::default(),
+            disk_state: None,
         }
     }
 
+    pub fn open_ephemeral() -> Document {
+        Self::new(None, Buffer::new_empty())
+    }
+
+    /// Open a file, detecting its encoding and line ending
     pub fn open_file(path: PathBuf) -> std::io::Result<Document> {
-        let content = std::fs::read_to_string(&path)?;
-        Ok(Self {
-            path: Some(path),
-            buffer: Buffer::new_from_string(content),
-        })
+        let disk_state = DiskState::read(path.clone())?;
+        let mut document = Self::new(
+            Some(path),
+            Buffer::new_from_string(disk_state.text.to_string()),
+        );
+        document.format = disk_state.format;
+        document.disk_state = Some(disk_state);
+        Ok(document)
+    }
+
+    /// Open an empty document for a file that doesn't exist yet, and will be created once the document is saved
+    pub fn open_new_file(path: PathBuf) -> Document {
+        Self::new(Some(path), Buffer::new_empty())
+    }
+
+    /// Check whether the buffer or its format differ from the last saved or loaded state of the file
+    pub fn is_dirty(&self) -> bool {
+        !self.buffer.is_at_revision(self.clean_revision)
+            || matches!(&self.disk_state, Some(disk_state) if disk_state.format != self.format)
     }
 
     /// Asynchronously save the current buffer state to its path. Does nothing when no path is set.
-    pub async fn write_to_file(&self) -> std::io::Result<()> {
-        tracing::info!(document = ?self, "Saving document");
+    ///
+    /// See [Self::write_to] for when `force` is needed.
+    pub async fn write_to_file(&mut self, force: bool) -> Result<(), SaveError> {
         if let Some(path) = self.path.clone() {
-            let rope = self.buffer.head_rope().clone();
-            tokio::task::spawn_blocking(move || write_rope_to_file(&path, &rope)).await??;
+            self.write_to(path, force).await?;
         }
         Ok(())
     }
 
+    /// Asynchronously save the current buffer state to the given path.
+    ///
+    /// The path of the document stays the same,
+    /// and the document is only marked as clean if the path is the document's own.
+    /// Unless `force` is set, this refuses to overwrite the file
+    /// if another program changed it since the document last loaded or saved it,
+    /// or if the file has a [DecodeIssue], so writing it would change parts that weren't edited.
+    pub async fn write_to(&mut self, path: PathBuf, force: bool) -> Result<(), SaveError> {
+        self.write(path, force, false).await
+    }
+
+    /// Asynchronously save the current buffer state to the given path, which becomes the document's path once written.
+    ///
+    /// Unless `force` is set, this refuses to overwrite an existing file other than the document's own one.
+    /// See [Self::write_to] for the other cases `force` is needed for.
+    pub async fn write_as(&mut self, path: PathBuf, force: bool) -> Result<(), SaveError> {
+        if !force && self.path.as_ref() != Some(&path) && path.exists() {
+            return Err(SaveError::FileExists);
+        }
+        self.write(path, force, true).await
+    }
+
+    async fn write(
+        &mut self,
+        path: PathBuf,
+        force: bool,
+        take_path: bool,
+    ) -> Result<(), SaveError> {
+        tracing::info!(document = ?self, ?path, "Saving document");
+        if !force {
+            if let Some(issue) = self
+                .decode_issue()
+                .filter(|_| self.path.as_ref() == Some(&path))
+            {
+                return Err(SaveError::Lossy(issue));
+            }
+            if self.changed_on_disk(&path)? {
+                return Err(SaveError::ChangedOnDisk);
+            }
+        }
+        let revision = self.buffer.head_revision();
+        let rope = self.buffer.head_rope().clone();
+        let format = self.format;
+        let is_own_path = self.path.as_ref() == Some(&path);
+        let modified = tokio::task::spawn_blocking({
+            let path = path.clone();
+            let rope = rope.clone();
+            move || {
+                write_rope_to_file(&path, &rope, &format)?;
+                Ok::<_, io::Error>(std::fs::metadata(&path)?.modified().ok())
+            }
+        })
+        .await
+        .map_err(io::Error::from)??;
+        if take_path {
+            self.path = Some(path.clone());
+        }
+        if is_own_path || take_path {
+            self.buffer.mark_saved();
+            self.clean_revision = revision;
+            self.disk_state = Some(DiskState::new(path, format, rope, modified));
+        }
+        Ok(())
+    }
+
+    /// What the document can't preserve about its file when writing it, as of the last time it was loaded
+    pub(crate) fn decode_issue(&self) -> Option<DecodeIssue> {
+        self.disk_state.as_ref().and_then(|x| x.issue)
+    }
+
+    /// Check whether the file at the given path was changed since the document last loaded or saved it.
+    ///
+    /// Files the document never loaded or saved don't count as changed,
+    /// and neither do files that were only touched, without changing their content.
+    fn changed_on_disk(&self, path: &Path) -> io::Result<bool> {
+        let Some(disk_state) = self.disk_state.as_ref().filter(|x| x.path == path) else {
+            return Ok(false);
+        };
+        let modified = match std::fs::metadata(path) {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        if modified.is_some() && modified == disk_state.modified {
+            return Ok(false);
+        }
+        let (_, content, _) = FileFormat::decode(&std::fs::read(path)?);
+        Ok(hash_rope(&Rope::from(content)) != disk_state.hash)
+    }
+
+    /// Load the file of the document again after another program changed it.
+    ///
+    /// Without unsaved changes, the document is simply replaced with the new content of the file.
+    /// Otherwise, the changes to the file are merged with the ones in the document,
+    /// using the content of the file when it was last loaded or saved as the common base.
+    pub(crate) fn reload_from_disk(&mut self) -> io::Result<FileChange> {
+        let Some(path) = self.path.clone() else { return Ok(FileChange::Unchanged) };
+        let disk_state = match DiskState::read(path) {
+            Ok(disk_state) => disk_state,
+            // The file might be recreated later on, until then the document keeps its content
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(FileChange::Unchanged),
+            Err(err) => return Err(err),
+        };
+        let dirty = self.is_dirty();
+        let old_disk_state = self.disk_state.take().filter(|x| x.path == disk_state.path);
+        if matches!(&old_disk_state, Some(old) if old.hash == disk_state.hash) {
+            if !dirty {
+                self.format = disk_state.format;
+            }
+            self.disk_state = Some(disk_state);
+            return Ok(FileChange::Unchanged);
+        }
+
+        let base = if dirty {
+            old_disk_state.map(|x| x.text).unwrap_or_default()
+        } else {
+            self.buffer.head_rope().clone()
+        };
+        let conflict = self.buffer.merge_external_change(&base, &disk_state.text);
+        if !dirty {
+            self.format = disk_state.format;
+        }
+        if hash_rope(self.buffer.head_rope()) == disk_state.hash {
+            self.clean_revision = self.buffer.head_revision();
+        }
+        self.disk_state = Some(disk_state);
+        Ok(if dirty {
+            FileChange::Merged { conflict }
+        } else {
+            FileChange::Reloaded
+        })
+    }
+
+    /// Restore the undo history of the document from its undo file,
+    /// if there is one that was written for the current content of the file.
+    /// Does nothing for documents with unsaved changes.
+    /// As the content stays the same, the carets are kept where they are.
+    pub(crate) fn read_undo_file(&mut self) -> io::Result<()> {
+        let Some(disk_state) = self.disk_state.as_ref().filter(|_| !self.is_dirty()) else {
+            return Ok(());
+        };
+        let Some(undo_path) = undo_file_path(&disk_state.path) else { return Ok(()) };
+        if let Some(buffer) = Buffer::read_undo_file(&undo_path, disk_state.hash)? {
+            tracing::info!(?undo_path, "Restored undo history");
+            let carets = self.buffer.caret_snapshot();
+            self.buffer = buffer;
+            self.buffer.restore_carets(carets);
+            self.clean_revision = self.buffer.head_revision();
+        }
+        Ok(())
+    }
+
+    /// Store the undo history of the document in its undo file.
+    /// Does nothing for documents with unsaved changes, as the undo file has to match the content of the file.
+    pub(crate) fn write_undo_file(&self) -> io::Result<()> {
+        let Some(disk_state) = self.disk_state.as_ref().filter(|_| !self.is_dirty()) else {
+            return Ok(());
+        };
+        let Some(undo_path) = undo_file_path(&disk_state.path) else { return Ok(()) };
+        self.buffer.write_undo_file(&undo_path, disk_state.hash)
+    }
+
     pub fn lines_in_viewport(&self, vp: &Viewport) -> Vec<String> {
         self.buffer
             .lines_between(vp.first_line, vp.last_line())
@@ -67,6 +321,48 @@ impl Document {
             .collect::<Vec<_>>()
     }
 
+    /// Find the matches of a regex in the lines shown by the viewport
+    pub(crate) fn matches_in_viewport(
+        &self,
+        vp: &Viewport,
+        regex: &hotsauce::Regex,
+    ) -> Vec<CoordinateRegion> {
+        let text = self.buffer.head_rope();
+        let coordinate = |offset| {
+            let position = Position::from_offset(text, offset)
+                .expect("Search match was not a valid offset into the buffer");
+            Coordinate {
+                line: position.line,
+                col: position.col,
+            }
+        };
+        self.buffer
+            .find_matches(regex, self.buffer.viewport_range(vp))
+            .into_iter()
+            .map(|range| CoordinateRegion {
+                head: coordinate(range.end),
+                tail: coordinate(range.start),
+            })
+            .collect()
+    }
+
+    /// Positions of the bracket under the primary caret and of its matching bracket, if both are in the viewport
+    fn matching_pair_positions(&self, vp: &Viewport) -> Vec<Coordinate> {
+        let text = self.buffer.head_rope();
+        let coordinate = |offset| {
+            let position = Position::from_offset(text, offset)
+                .expect("Matching bracket was not a valid offset into the buffer");
+            Coordinate {
+                line: position.line,
+                col: position.col,
+            }
+        };
+        self.buffer
+            .matching_pair(vp)
+            .map(|(bracket, matching)| vec![coordinate(bracket), coordinate(matching)])
+            .unwrap_or_default()
+    }
+
     pub fn caret_positions(&self) -> Vec<CoordinateRegion> {
         self.buffer
             .all_caret_region_positions()
@@ -83,32 +379,100 @@ impl Document {
             .into()
     }
 
+    /// The lines of the block selected in visual block mode, as one selection per line,
+    /// leaving out lines that end before the block starts
+    fn block_positions(&self, to_line_end: bool) -> Vec<CoordinateRegion> {
+        let coordinate = |offset| {
+            let position = Position::from_offset(self.buffer.head_rope(), offset)
+                .expect("Block selection was not a valid offset into the buffer");
+            Coordinate {
+                line: position.line,
+                col: position.col,
+            }
+        };
+        self.buffer
+            .block_lines(to_line_end)
+            .into_iter()
+            .filter(|line| !line.short)
+            .map(|line| CoordinateRegion {
+                head: coordinate(line.range.end),
+                tail: coordinate(line.range.start),
+            })
+            .collect()
+    }
+
     /// Create a notification for the frontend, that contains all relevant state of this document.
     ///
     /// *Note:* This will later be replaced with a proper
     /// damage tracking-style system that sends patches to the frontend.
     /// Additionally, this will later only send updates concerning
     /// the parts of the document that are currently visible / relevant in the frontend.
-    pub fn create_update_notification(
+    pub(crate) fn create_update_notification(
         &self,
         view_id: ViewId,
         view: &View,
-        vim_mode: VimMode,
+        vim: &VimInterface,
     ) -> ToFrontend {
         ToFrontend::UpdateView {
             view_id: view_id.into(),
-            view_data: ViewData {
-                first_line: view.vp.first_line,
-                text: self.lines_in_viewport(&view.vp),
-                vim_mode: vim_mode.to_string(),
-                carets: self.caret_positions(),
+            view_data: self.create_view_data(view, vim),
+        }
+    }
+
+    /// Collect the state of a view into this document that is relevant to the frontend.
+    pub(crate) fn create_view_data(&self, view: &View, vim: &VimInterface) -> ViewData {
+        ViewData {
+            first_line: view.vp.first_line,
+            text: self.lines_in_viewport(&view.vp),
+            vim_mode: vim.mode.to_string(),
+            carets: if vim.mode == VimMode::VisualBlock {
+                self.block_positions(vim.block_to_line_end)
+            } else {
+                self.caret_positions()
             },
+            pending_count: vim.pending_count(),
+            search_matches: vim
+                .highlighted_search()
+                .map(|regex| self.matches_in_viewport(&view.vp, regex))
+                .unwrap_or_default(),
+            matching_pair: self.matching_pair_positions(&view.vp),
+            command_line: vim.command_line(),
+            dirty: self.is_dirty(),
         }
     }
 }
 
-/// write a rope to a file by first writing to a .swp file and then renaming
-fn write_rope_to_file(path: &std::path::Path, rope: &Rope) -> io::Result<()> {
+/// Path of the undo file of a file, in the state directory of bazed.
+///
+/// Like in vim, undo files are named after the absolute path of their file, with every separator replaced by `%`.
+fn undo_file_path(path: &Path) -> Option<PathBuf> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(state_dir) if !state_dir.is_empty() => PathBuf::from(state_dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    let path = crate::file_watcher::normalize_path(path)?;
+    let name = path
+        .to_string_lossy()
+        .replace(std::path::MAIN_SEPARATOR, "%");
+    Some(state_dir.join("bazed/undo").join(name))
+}
+
+/// Hash the contents of a rope.
+/// This uses xxh3, as opposed to std's `DefaultHasher`, as the hash is persisted in undo files
+/// and thus needs to stay the same across builds.
+fn hash_rope(rope: &Rope) -> u64 {
+    let mut hasher = Xxh3::new();
+    for chunk in rope.iter_chunks(..rope.len()) {
+        hasher.update(chunk.as_bytes());
+    }
+    hasher.digest()
+}
+
+/// write a rope to a file in the given format, by first writing to a .swp file and then renaming
+fn write_rope_to_file(path: &std::path::Path, rope: &Rope, format: &FileFormat) -> io::Result<()> {
+    let bytes = format
+        .encode(rope)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
     // we first write the text to a tmp file with the same name, but ending in .swp
     let tmp_extension = path.extension().map_or_else(
         || OsString::from("swp"),
@@ -120,9 +484,7 @@ fn write_rope_to_file(path: &std::path::Path, rope: &Rope) -> io::Result<()> {
     );
     let tmp_path = &path.with_extension(tmp_extension);
     let mut file = File::create(tmp_path)?;
-    for chunk in rope.iter_chunks(..rope.len()) {
-        file.write_all(chunk.as_bytes())?;
-    }
+    file.write_all(&bytes)?;
 
     // remember the files permissions, if it already exists
     let permissions = std::fs::metadata(path).ok().map(|x| x.permissions());
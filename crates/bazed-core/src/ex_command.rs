@@ -0,0 +1,367 @@
+//! Ex commands, as typed into the command line after `:`, i.e. `:w`, `:%s/foo/bar/g` or `:set ic`.
+//!
+//! Commands are looked up by their, possibly abbreviated, name in a [CommandRegistry].
+//! Besides the built-in commands, any part of the editor may register its own commands there.
+
+use std::{ops::RangeInclusive, path::PathBuf, sync::Arc};
+
+use self::range::{parse_range, RangeContext};
+use crate::{
+    buffer::{position::Position, Buffer},
+    view::{Jump, View},
+    vim_interface::VimInterface,
+};
+
+mod builtin;
+mod range;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub(crate) enum CommandError {
+    #[error("Not an editor command: {0}")]
+    UnknownCommand(String),
+    #[error("Ambiguous command: {0}")]
+    AmbiguousCommand(String),
+    #[error("Invalid range")]
+    InvalidRange,
+    #[error("No range allowed")]
+    RangeNotAllowed,
+    #[error("Mark not set: {0}")]
+    MarkNotSet(char),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+    #[error("Pattern not found: {0}")]
+    PatternNotFound(String),
+    #[error("No previous regular expression")]
+    NoPreviousPattern,
+    #[error("Unknown option: {0}")]
+    UnknownOption(String),
+}
+
+/// Something a command asks the application to do, as it concerns more than the buffer of the current view
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum EditorAction {
+    /// Save the document, to the given path or to its own one,
+    /// even if that overwrites changes another program made to the file when `force` is set
+    Write { path: Option<PathBuf>, force: bool },
+    /// Close the current view, even if that loses unsaved changes when `force` is set
+    Quit { force: bool },
+    /// Open a file in the current view, in place of its current document,
+    /// even if that loses unsaved changes when `force` is set
+    Edit { path: PathBuf, force: bool },
+    /// Apply an argument of `:set` concerning the format of the document, see [crate::file_format::FileFormat::apply]
+    SetFileFormat(String),
+    /// Remove a global mark from all documents but the current one, as it was just set in the current one
+    ClaimGlobalMark(char),
+    /// Show the document containing a global mark in the current view, moving the caret to the mark,
+    /// or to the start of its line if `linewise` is set
+    JumpToGlobalMark { mark: char, linewise: bool },
+    /// List the given marks, or all marks if none are given, as done by `:marks`
+    ListMarks(String),
+    /// Show the document of a jump from the jump list in the current view, moving the caret to the jump
+    ShowJump(Jump),
+    /// Restore the undo history of all documents from their undo files, as `undofile` was just turned on
+    ReadUndoFiles,
+}
+
+/// The lines a command acts on when it is given no range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DefaultRange {
+    CurrentLine,
+    All,
+}
+
+/// Everything a command may act on
+pub(crate) struct CommandContext<'a> {
+    pub(crate) view: &'a mut View,
+    pub(crate) buffer: &'a mut Buffer,
+    pub(crate) vim: &'a mut VimInterface,
+}
+
+/// How a command was invoked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Invocation {
+    /// Lines the command acts on, counting from 0.
+    /// For commands that don't accept a range, this is the line of the primary caret.
+    pub(crate) lines: RangeInclusive<usize>,
+    /// Set if the name was followed by a `!`, i.e. in `:q!`
+    pub(crate) bang: bool,
+    /// Everything after the name, with surrounding whitespace removed
+    pub(crate) args: String,
+}
+
+type RunFn =
+    Arc<dyn Fn(&mut CommandContext, &Invocation) -> Result<(), CommandError> + Send + Sync>;
+type CompleteFn = Arc<dyn Fn(&str) -> Vec<String> + Send + Sync>;
+
+/// A named command that can be run from the command line
+#[derive(Clone)]
+pub(crate) struct ExCommand {
+    name: String,
+    /// Length the name may be abbreviated to, i.e. 1 for `substitute`, which may be run as `:s`
+    min_len: usize,
+    /// Lines the command acts on if no range is given, or `None` if it doesn't accept a range
+    default_range: Option<DefaultRange>,
+    run: RunFn,
+    /// Completion of the arguments, returning all candidates for the partially typed arguments
+    complete: Option<CompleteFn>,
+}
+
+impl ExCommand {
+    /// Create a command that doesn't accept a range,
+    /// and whose name may be abbreviated down to its first `min_len` characters
+    pub(crate) fn new<F>(name: &str, min_len: usize, run: F) -> Self
+    where
+        F: Fn(&mut CommandContext, &Invocation) -> Result<(), CommandError> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            min_len,
+            default_range: None,
+            run: Arc::new(run),
+            complete: None,
+        }
+    }
+
+    /// Accept a range, acting on the given default lines when no range is given
+    pub(crate) fn with_range(mut self, default_range: DefaultRange) -> Self {
+        self.default_range = Some(default_range);
+        self
+    }
+
+    /// Complete the arguments of the command with the given function
+    pub(crate) fn with_completion<F>(mut self, complete: F) -> Self
+    where
+        F: Fn(&str) -> Vec<String> + Send + Sync + 'static,
+    {
+        self.complete = Some(Arc::new(complete));
+        self
+    }
+
+    fn accepts_name(&self, name: &str) -> bool {
+        name.len() >= self.min_len && self.name.starts_with(name)
+    }
+}
+
+/// All commands that can be run from the command line
+#[derive(Clone, Default)]
+pub(crate) struct CommandRegistry {
+    commands: Vec<ExCommand>,
+}
+
+impl CommandRegistry {
+    /// Create a registry containing all built-in commands
+    pub(crate) fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        builtin::register_builtins(&mut registry);
+        registry
+    }
+
+    /// Add a command, replacing any command of the same name
+    pub(crate) fn register(&mut self, command: ExCommand) {
+        self.commands.retain(|x| x.name != command.name);
+        self.commands.push(command);
+    }
+
+    /// Look up a command by its full or abbreviated name
+    pub(crate) fn find(&self, name: &str) -> Result<&ExCommand, CommandError> {
+        if let Some(command) = self.commands.iter().find(|x| x.name == name) {
+            return Ok(command);
+        }
+        let mut candidates = self.commands.iter().filter(|x| x.accepts_name(name));
+        match (candidates.next(), candidates.next()) {
+            (Some(command), None) => Ok(command),
+            (Some(_), Some(_)) => Err(CommandError::AmbiguousCommand(name.to_string())),
+            (None, _) => Err(CommandError::UnknownCommand(name.to_string())),
+        }
+    }
+
+    /// Complete a partially typed command line, returning the full command line for each candidate.
+    ///
+    /// Completes the command name while it is being typed, and its arguments afterwards.
+    pub(crate) fn complete(&self, line: &str) -> Vec<String> {
+        let Ok((_, rest)) = parse_range(line) else { return Vec::new() };
+        let (name, after_name) = split_name(rest);
+        if after_name.is_empty() {
+            let prefix = &line[..line.len() - rest.len()];
+            let mut names: Vec<_> = self
+                .commands
+                .iter()
+                .filter(|x| x.name.starts_with(name))
+                .map(|x| format!("{prefix}{}", x.name))
+                .collect();
+            names.sort();
+            return names;
+        }
+        let Ok(command) = self.find(name) else { return Vec::new() };
+        let Some(complete) = &command.complete else { return Vec::new() };
+        let args = after_name.strip_prefix('!').unwrap_or(after_name);
+        if !args.starts_with(char::is_whitespace) {
+            return Vec::new();
+        }
+        let args = args.trim_start();
+        let prefix = &line[..line.len() - args.len()];
+        complete(args)
+            .into_iter()
+            .map(|candidate| format!("{prefix}{candidate}"))
+            .collect()
+    }
+}
+
+/// Split a command line, with its range removed, into the name of the command and everything after it
+fn split_name(s: &str) -> (&str, &str) {
+    let end = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// Parse and run a command line, with or without its leading `:`.
+///
+/// A range without a command moves the primary caret to the last line of the range.
+/// All edits made by the command form a single undo group.
+pub(crate) fn execute(ctx: &mut CommandContext, line: &str) -> Result<(), CommandError> {
+    let line = line.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let (range, rest) = parse_range(line)?;
+    let (name, rest) = split_name(rest.trim_start());
+    let (bang, args) = match rest.strip_prefix('!') {
+        Some(args) => (true, args),
+        None => (false, rest),
+    };
+
+    let last_line = ctx.buffer.last_line();
+    let current_line = ctx.buffer.primary_caret_position().line.min(last_line);
+    let lines = match &range {
+        Some(range) => {
+            let buffer = &*ctx.buffer;
+            range.resolve(&RangeContext {
+                current_line,
+                last_line,
+                mark_line: &|mark| Some(buffer.mark_position(mark)?.line),
+            })?
+        },
+        None => current_line..=current_line,
+    };
+
+    if name.is_empty() {
+        if bang || !args.trim().is_empty() {
+            return Err(CommandError::UnknownCommand(line.to_string()));
+        }
+        if range.is_some() {
+            ctx.view.record_jump(ctx.buffer);
+            ctx.buffer
+                .jump_caret_to_position(Position::new(*lines.end(), 0), true);
+        }
+        return Ok(());
+    }
+
+    let command = ctx.vim.commands.find(name)?.clone();
+    let lines = match (command.default_range, range) {
+        (None, Some(_)) => return Err(CommandError::RangeNotAllowed),
+        (Some(DefaultRange::All), None) => 0..=last_line,
+        _ => lines,
+    };
+    let invocation = Invocation {
+        lines,
+        bang,
+        args: args.trim().to_string(),
+    };
+    ctx.buffer.begin_undo_group();
+    let result = (command.run)(ctx, &invocation);
+    ctx.buffer.end_undo_group();
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{execute, CommandContext, CommandError, CommandRegistry, ExCommand};
+    use crate::{
+        buffer::Buffer,
+        document::DocumentId,
+        test_util,
+        view::{View, Viewport},
+        vim_interface::VimInterface,
+    };
+
+    fn run(text: &str, line: &str) -> (Result<(), CommandError>, Buffer) {
+        let mut vim = VimInterface::new();
+        let mut view = View::new(DocumentId::gen(), Viewport::new_ginormeous());
+        let mut buffer = Buffer::new_from_string(text.to_string());
+        let mut ctx = CommandContext {
+            view: &mut view,
+            buffer: &mut buffer,
+            vim: &mut vim,
+        };
+        let result = execute(&mut ctx, line);
+        (result, buffer)
+    }
+
+    #[test]
+    fn test_find_command() {
+        test_util::setup_test();
+        let mut registry = CommandRegistry::default();
+        registry.register(ExCommand::new("write", 1, |_, _| Ok(())));
+        registry.register(ExCommand::new("wall", 2, |_, _| Ok(())));
+        assert_eq!("write", registry.find("w").unwrap().name);
+        assert_eq!("write", registry.find("wri").unwrap().name);
+        assert_eq!("wall", registry.find("wa").unwrap().name);
+        assert!(matches!(
+            registry.find("writex"),
+            Err(CommandError::UnknownCommand(_))
+        ));
+        registry.register(ExCommand::new("wq", 2, |_, _| Ok(())));
+        registry.register(ExCommand::new("wqall", 3, |_, _| Ok(())));
+        assert_eq!("wq", registry.find("wq").unwrap().name);
+    }
+
+    #[test]
+    fn test_ambiguous_command() {
+        test_util::setup_test();
+        let mut registry = CommandRegistry::default();
+        registry.register(ExCommand::new("foo", 1, |_, _| Ok(())));
+        registry.register(ExCommand::new("fizz", 1, |_, _| Ok(())));
+        assert_eq!(
+            Err(CommandError::AmbiguousCommand("f".to_string())),
+            registry.find("f").map(|x| x.name.clone())
+        );
+    }
+
+    #[test]
+    fn test_complete() {
+        test_util::setup_test();
+        let mut registry = CommandRegistry::default();
+        registry.register(ExCommand::new("sort", 3, |_, _| Ok(())));
+        registry.register(
+            ExCommand::new("set", 2, |_, _| Ok(()))
+                .with_completion(|arg| vec![format!("{arg}case"), format!("{arg}search")]),
+        );
+        assert_eq!(vec!["%set", "%sort"], registry.complete("%s"));
+        assert_eq!(
+            vec!["set nocase", "set nosearch"],
+            registry.complete("set no")
+        );
+        assert!(registry.complete("sort x").is_empty());
+    }
+
+    #[test]
+    fn test_execute_errors() {
+        test_util::setup_test();
+        assert!(matches!(
+            run("foo", "frobnicate").0,
+            Err(CommandError::UnknownCommand(_))
+        ));
+        assert_eq!(Err(CommandError::RangeNotAllowed), run("foo", "%q").0);
+        assert_eq!(Err(CommandError::InvalidRange), run("foo", "3").0);
+    }
+
+    #[test]
+    fn test_range_jumps_to_line() {
+        test_util::setup_test();
+        let (result, buffer) = run("a\nb\nc\nd\n", ":2,3");
+        assert_eq!(Ok(()), result);
+        assert_eq!(2, buffer.primary_caret_position().line);
+        let (_, buffer) = run("a\nb\nc\nd\n", "$");
+        assert_eq!(3, buffer.primary_caret_position().line);
+    }
+}
@@ -0,0 +1,630 @@
+//! The commands every command registry starts out with.
+
+use std::{cmp::Ordering, path::PathBuf, time::Duration};
+
+use super::{
+    execute, CommandContext, CommandError, CommandRegistry, DefaultRange, EditorAction, ExCommand,
+    Invocation,
+};
+use crate::{
+    buffer::position::Position,
+    file_format::FileFormat,
+    options::Options,
+    register::RegisterName,
+    search::Search,
+    user_buffer_op::{BufferOp, Operator, OperatorTarget, UndoStep},
+};
+
+pub(super) fn register_builtins(registry: &mut CommandRegistry) {
+    registry.register(ExCommand::new("write", 1, write).with_completion(complete_path));
+    registry.register(ExCommand::new("quit", 1, quit));
+    registry.register(ExCommand::new("wq", 2, write_quit).with_completion(complete_path));
+    registry.register(ExCommand::new("xit", 1, write_quit).with_completion(complete_path));
+    registry.register(ExCommand::new("edit", 1, edit).with_completion(complete_path));
+    registry.register(
+        ExCommand::new("substitute", 1, substitute).with_range(DefaultRange::CurrentLine),
+    );
+    registry.register(
+        ExCommand::new("global", 1, |ctx, inv| global(ctx, inv, false))
+            .with_range(DefaultRange::All),
+    );
+    registry.register(
+        ExCommand::new("vglobal", 1, |ctx, inv| global(ctx, inv, true))
+            .with_range(DefaultRange::All),
+    );
+    registry.register(ExCommand::new("delete", 1, delete).with_range(DefaultRange::CurrentLine));
+    registry.register(ExCommand::new("sort", 3, sort).with_range(DefaultRange::All));
+    registry.register(ExCommand::new("set", 2, set).with_completion(complete_option));
+    registry.register(ExCommand::new("earlier", 2, |ctx, inv| {
+        let step = parse_undo_step(&inv.args)?;
+        ctx.buffer
+            .apply_buffer_op(&ctx.view.vp, BufferOp::Earlier(step));
+        Ok(())
+    }));
+    registry.register(ExCommand::new("later", 3, |ctx, inv| {
+        let step = parse_undo_step(&inv.args)?;
+        ctx.buffer
+            .apply_buffer_op(&ctx.view.vp, BufferOp::Later(step));
+        Ok(())
+    }));
+    registry.register(ExCommand::new("mark", 2, mark).with_range(DefaultRange::CurrentLine));
+    registry.register(ExCommand::new("marks", 5, |ctx, inv| {
+        ctx.vim
+            .push_action(EditorAction::ListMarks(inv.args.clone()));
+        Ok(())
+    }));
+    registry.register(ExCommand::new("nohlsearch", 3, |ctx, _| {
+        ctx.vim.hide_search_highlight();
+        Ok(())
+    }));
+}
+
+/// `:w[!] [path]`, save the document
+fn write(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    let path = (!inv.args.is_empty()).then(|| PathBuf::from(&inv.args));
+    ctx.vim.push_action(EditorAction::Write {
+        path,
+        force: inv.bang,
+    });
+    Ok(())
+}
+
+/// `:q[!]`, close the current view
+fn quit(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    ctx.vim.push_action(EditorAction::Quit { force: inv.bang });
+    Ok(())
+}
+
+/// `:wq [path]` or `:x [path]`, save the document and close the current view
+fn write_quit(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    write(ctx, inv)?;
+    quit(ctx, inv)
+}
+
+/// `:e[!] path`, open a file in the current view
+fn edit(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    if inv.args.is_empty() {
+        return Err(CommandError::InvalidArgument(
+            "missing file name".to_string(),
+        ));
+    }
+    ctx.vim.push_action(EditorAction::Edit {
+        path: PathBuf::from(&inv.args),
+        force: inv.bang,
+    });
+    Ok(())
+}
+
+/// `:[range]ma[rk] {a-zA-Z'}`, set a mark at the start of the last line of the range
+fn mark(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    let mut chars = inv.args.chars();
+    let (Some(mark), None) = (chars.next(), chars.next()) else {
+        return Err(CommandError::InvalidArgument(inv.args.clone()));
+    };
+    let offset = ctx.buffer.head_rope().offset_of_line(*inv.lines.end());
+    if !ctx.vim.set_mark(ctx.buffer, mark, offset) {
+        return Err(CommandError::InvalidArgument(inv.args.clone()));
+    }
+    Ok(())
+}
+
+/// `:s/pattern/replacement/flags`, replace matches of the pattern within each line.
+///
+/// Only the first match of each line is replaced, unless the `g` flag is given.
+/// The flags `i` and `I` make the pattern ignore or respect case.
+/// In the replacement, `&` stands for the matched text, and `\r` or `\n` for a line break.
+fn substitute(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    let (delimiter, pattern, rest) = split_pattern(&inv.args)?;
+    let (replacement, flags) = take_until_delimiter(rest, delimiter);
+    let pattern = resolve_pattern(ctx, pattern)?;
+
+    let mut global = false;
+    let mut ignore_case = ctx.vim.options.ignores_case(&pattern);
+    for flag in flags.trim().chars() {
+        match flag {
+            'g' => global = true,
+            'i' => ignore_case = true,
+            'I' => ignore_case = false,
+            _ => return Err(CommandError::InvalidArgument(flags.to_string())),
+        }
+    }
+    let search = Search::new(pattern.clone(), false, ignore_case)
+        .ok_or_else(|| CommandError::InvalidPattern(pattern.clone()))?;
+
+    let first_line = *inv.lines.start();
+    let mut edits = Vec::new();
+    let mut last_changed_line = None;
+    for (i, text) in ctx
+        .buffer
+        .line_contents(inv.lines.clone())
+        .iter()
+        .enumerate()
+    {
+        let line_start = ctx.buffer.head_rope().offset_of_line(first_line + i);
+        let matches = search
+            .regex
+            .matches(text.bytes())
+            .take(if global { usize::MAX } else { 1 });
+        for m in matches {
+            let expanded = expand_replacement(&replacement, &text[m.start..m.end])?;
+            edits.push((line_start + m.start..line_start + m.end, expanded));
+            last_changed_line = Some(first_line + i);
+        }
+    }
+    let Some(last_changed_line) = last_changed_line else {
+        return Err(CommandError::PatternNotFound(pattern));
+    };
+    ctx.buffer.replace_ranges(edits);
+    ctx.buffer
+        .jump_caret_to_position(Position::new(last_changed_line, 0), true);
+    ctx.vim.set_last_search(search);
+    Ok(())
+}
+
+/// Expand the special characters of a `:s` replacement for the given match
+fn expand_replacement(replacement: &str, matched: &str) -> Result<String, CommandError> {
+    let mut expanded = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => expanded.push_str(matched),
+            '\\' => match chars.next() {
+                Some('r' | 'n') => expanded.push('\n'),
+                Some('t') => expanded.push('\t'),
+                Some('0') => expanded.push_str(matched),
+                Some(c) if c.is_ascii_digit() => {
+                    return Err(CommandError::InvalidArgument(
+                        "capture groups are not supported".to_string(),
+                    ))
+                },
+                Some(c) => expanded.push(c),
+                None => expanded.push('\\'),
+            },
+            c => expanded.push(c),
+        }
+    }
+    Ok(expanded)
+}
+
+/// `:g/pattern/command`, run a command on every line matching the pattern,
+/// or on every line not matching it for `:g!` and `:v`.
+///
+/// The command is run with the primary caret on the respective line, going from the last line upwards,
+/// such that a command changing the number of lines doesn't affect the lines that remain.
+fn global(ctx: &mut CommandContext, inv: &Invocation, invert: bool) -> Result<(), CommandError> {
+    let invert = invert != inv.bang;
+    let (_, pattern, command) = split_pattern(&inv.args)?;
+    if command.trim().is_empty() {
+        return Err(CommandError::InvalidArgument("missing command".to_string()));
+    }
+    let pattern = resolve_pattern(ctx, pattern)?;
+    let ignore_case = ctx.vim.options.ignores_case(&pattern);
+    let search = Search::new(pattern.clone(), false, ignore_case)
+        .ok_or_else(|| CommandError::InvalidPattern(pattern.clone()))?;
+
+    let first_line = *inv.lines.start();
+    let lines: Vec<_> = ctx
+        .buffer
+        .line_contents(inv.lines.clone())
+        .iter()
+        .enumerate()
+        .filter(|(_, text)| search.regex.matches(text.bytes()).next().is_some() != invert)
+        .map(|(i, _)| first_line + i)
+        .collect();
+    if lines.is_empty() {
+        return Err(CommandError::PatternNotFound(pattern));
+    }
+    ctx.vim.set_last_search(search);
+    for line in lines.into_iter().rev() {
+        ctx.buffer
+            .jump_caret_to_position(Position::new(line, 0), true);
+        execute(ctx, command)?;
+    }
+    Ok(())
+}
+
+/// `:d [register]`, delete whole lines
+fn delete(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    let register = match inv.args.chars().collect::<Vec<_>>().as_slice() {
+        [] => None,
+        [c] => Some(
+            RegisterName::from_char(*c)
+                .ok_or_else(|| CommandError::InvalidArgument(inv.args.clone()))?,
+        ),
+        _ => return Err(CommandError::InvalidArgument(inv.args.clone())),
+    };
+    let line_count = inv.lines.end() - inv.lines.start() + 1;
+    ctx.buffer
+        .jump_caret_to_position(Position::new(*inv.lines.start(), 0), true);
+    let content = ctx.buffer.apply_operator(
+        &ctx.view.vp,
+        Operator::Delete,
+        OperatorTarget::Lines(line_count),
+    );
+    ctx.vim.registers.record_delete(register, content);
+    Ok(())
+}
+
+/// `:sort [i] [n] [u]`, sort lines.
+///
+/// `i` ignores case, `n` sorts by the first number in each line, with lines without a number first,
+/// and `u` removes all but the first of each run of equal lines. `:sort!` sorts in reverse.
+fn sort(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    let (mut ignore_case, mut numeric, mut unique) = (false, false, false);
+    for flag in inv.args.chars().filter(|c| !c.is_whitespace()) {
+        match flag {
+            'i' => ignore_case = true,
+            'n' => numeric = true,
+            'u' => unique = true,
+            _ => return Err(CommandError::InvalidArgument(inv.args.clone())),
+        }
+    }
+    let compare = |a: &String, b: &String| -> Ordering {
+        if numeric {
+            first_number(a).cmp(&first_number(b))
+        } else if ignore_case {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        } else {
+            a.cmp(b)
+        }
+    };
+
+    let mut lines = ctx.buffer.line_contents(inv.lines.clone());
+    lines.sort_by(compare);
+    if inv.bang {
+        lines.reverse();
+    }
+    if unique {
+        lines.dedup_by(|a, b| compare(a, b) == Ordering::Equal);
+    }
+    let range = ctx.buffer.offsets_of_lines(inv.lines.clone());
+    ctx.buffer.replace_ranges([(range, lines.join("\n"))]);
+    Ok(())
+}
+
+/// The first decimal number in the text, including its sign
+fn first_number(text: &str) -> Option<i64> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let end = text[start..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(text.len(), |x| start + x);
+    let start = if text[..start].ends_with('-') {
+        start - 1
+    } else {
+        start
+    };
+    text[start..end].parse().ok()
+}
+
+/// Parse the argument of `:earlier` and `:later`, which is a count of states,
+/// or a count with the unit `s`, `m`, `h` or `d` for a duration, or with `f` for a number of saves.
+/// Without an argument, the step is a single state.
+fn parse_undo_step(arg: &str) -> Result<UndoStep, CommandError> {
+    if arg.is_empty() {
+        return Ok(UndoStep::Count(1));
+    }
+    let invalid = || CommandError::InvalidArgument(arg.to_string());
+    let digits_end = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let count: u64 = arg[..digits_end].parse().map_err(|_| invalid())?;
+    let seconds = |factor: u64| UndoStep::Time(Duration::from_secs(count.saturating_mul(factor)));
+    Ok(match &arg[digits_end..] {
+        "" => UndoStep::Count(count as usize),
+        "s" => seconds(1),
+        "m" => seconds(60),
+        "h" => seconds(60 * 60),
+        "d" => seconds(24 * 60 * 60),
+        "f" => UndoStep::Saves(count as usize),
+        _ => return Err(invalid()),
+    })
+}
+
+/// `:set option...`, change or query options. Without arguments, or with `all`, shows all options.
+fn set(ctx: &mut CommandContext, inv: &Invocation) -> Result<(), CommandError> {
+    if inv.args.is_empty() || inv.args == "all" {
+        let description = ctx.vim.options.describe();
+        ctx.vim.show_message(description);
+        return Ok(());
+    }
+    let mut messages = Vec::new();
+    for arg in inv.args.split_whitespace() {
+        if FileFormat::is_option(arg) {
+            // The format belongs to the document, which only the application has access to
+            ctx.vim
+                .push_action(EditorAction::SetFileFormat(arg.to_string()));
+        } else {
+            let undofile = ctx.vim.options.undofile;
+            messages.extend(ctx.vim.options.apply(arg)?);
+            if !undofile && ctx.vim.options.undofile {
+                // Documents opened so far were read without their undo history
+                ctx.vim.push_action(EditorAction::ReadUndoFiles);
+            }
+        }
+    }
+    if !messages.is_empty() {
+        ctx.vim.show_message(messages.join("  "));
+    }
+    Ok(())
+}
+
+/// Complete the option name in the last argument of `:set`
+fn complete_option(args: &str) -> Vec<String> {
+    let (before, word) = match args.rfind(' ') {
+        Some(i) => args.split_at(i + 1),
+        None => ("", args),
+    };
+    let (prefix, name) = ["no", "inv"]
+        .iter()
+        .find_map(|prefix| Some((*prefix, word.strip_prefix(prefix)?)))
+        .unwrap_or(("", word));
+    let mut names: Vec<_> = Options::NAMES
+        .iter()
+        .chain(FileFormat::NAMES)
+        .filter(|x| x.starts_with(name))
+        .map(|x| format!("{before}{prefix}{x}"))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Complete a path to a file or directory, relative to the working directory
+fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => partial.split_at(i + 1),
+        None => ("", partial),
+    };
+    let Ok(entries) = std::fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<_> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            // Hidden files are only completed when explicitly asked for
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let suffix = if entry.file_type().ok()?.is_dir() {
+                "/"
+            } else {
+                ""
+            };
+            Some(format!("{dir}{name}{suffix}"))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+/// Split arguments of the form `/pattern/rest`, where the first character is used as delimiter.
+///
+/// Returns the delimiter, the pattern and everything after the delimiter following the pattern.
+fn split_pattern(args: &str) -> Result<(char, String, &str), CommandError> {
+    let mut chars = args.chars();
+    let delimiter = chars
+        .next()
+        .ok_or_else(|| CommandError::InvalidArgument("missing pattern".to_string()))?;
+    if delimiter.is_alphanumeric() || delimiter.is_whitespace() || "\\\"|".contains(delimiter) {
+        return Err(CommandError::InvalidArgument(args.to_string()));
+    }
+    let (pattern, rest) = take_until_delimiter(chars.as_str(), delimiter);
+    Ok((delimiter, pattern, rest))
+}
+
+/// Take text up to the first unescaped delimiter, removing the escaping from escaped delimiters.
+///
+/// Returns the text and everything after the delimiter, which is empty if there is no delimiter.
+fn take_until_delimiter(s: &str, delimiter: char) -> (String, &str) {
+    let mut text = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return (text, &s[i + c.len_utf8()..]);
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => text.push(next),
+                Some((_, next)) => {
+                    text.push('\\');
+                    text.push(next);
+                },
+                None => text.push('\\'),
+            }
+        } else {
+            text.push(c);
+        }
+    }
+    (text, "")
+}
+
+/// Use the pattern of the most recent search in place of an empty pattern, like vim does
+fn resolve_pattern(ctx: &CommandContext, pattern: String) -> Result<String, CommandError> {
+    if !pattern.is_empty() {
+        return Ok(pattern);
+    }
+    ctx.vim
+        .last_search_pattern()
+        .map(str::to_string)
+        .ok_or(CommandError::NoPreviousPattern)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{complete_option, complete_path, first_number, parse_undo_step};
+    use crate::{
+        buffer::Buffer,
+        document::DocumentId,
+        ex_command::{execute, CommandContext, CommandError, EditorAction},
+        register::RegisterName,
+        test_util,
+        user_buffer_op::UndoStep,
+        view::{View, Viewport},
+        vim_interface::VimInterface,
+    };
+
+    fn run_all(vim: &mut VimInterface, text: &str, lines: &[&str]) -> Result<String, CommandError> {
+        let mut view = View::new(DocumentId::gen(), Viewport::new_ginormeous());
+        let mut buffer = Buffer::new_from_string(text.to_string());
+        let mut ctx = CommandContext {
+            view: &mut view,
+            buffer: &mut buffer,
+            vim,
+        };
+        for line in lines {
+            execute(&mut ctx, line)?;
+        }
+        Ok(buffer.content_to_string())
+    }
+
+    fn run(text: &str, line: &str) -> Result<String, CommandError> {
+        run_all(&mut VimInterface::new(), text, &[line])
+    }
+
+    #[test]
+    fn test_substitute() {
+        test_util::setup_test();
+        assert_eq!(
+            Ok("xoo foo\nfoo\n".to_string()),
+            run("foo foo\nfoo\n", "s/f/x/")
+        );
+        assert_eq!(
+            Ok("[foo] [foo]\n[foo]\n".to_string()),
+            run("foo foo\nfoo\n", "%s/foo/[&]/g")
+        );
+        assert_eq!(Ok("a/b\nc\n".to_string()), run("a b\nc\n", r"s# #\/#"));
+        assert_eq!(Ok("a\nb\n".to_string()), run("a b\n", r"s/ /\r/"));
+        assert_eq!(Ok("# a\n# b\nc".to_string()), run("a\nb\nc", "1,2s/^/# /"));
+        assert_eq!(
+            Err(CommandError::PatternNotFound("x".to_string())),
+            run("foo", "s/x/y/")
+        );
+    }
+
+    #[test]
+    fn test_substitute_uses_last_search() {
+        test_util::setup_test();
+        let mut vim = VimInterface::new();
+        assert_eq!(
+            Err(CommandError::NoPreviousPattern),
+            run_all(&mut vim, "foo", &["s//x/"])
+        );
+        assert_eq!(
+            Ok("x x\n".to_string()),
+            run_all(&mut vim, "foo foo\n", &["s/foo/x/", "s//x/"])
+        );
+    }
+
+    #[test]
+    fn test_global() {
+        test_util::setup_test();
+        let text = "keep 1\ndrop\nkeep 2\ndrop\n";
+        assert_eq!(Ok("keep 1\nkeep 2\n".to_string()), run(text, "g/drop/d"));
+        assert_eq!(Ok("keep 1\nkeep 2\n".to_string()), run(text, "v/keep/d"));
+        assert_eq!(Ok("drop\ndrop\n".to_string()), run(text, "g!/drop/d"));
+        assert_eq!(
+            Ok("keep 1\nx\nkeep 2\nx\n".to_string()),
+            run(text, "g/drop/s/.*/x/")
+        );
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        test_util::setup_test();
+        let mut vim = VimInterface::new();
+        assert_eq!(
+            Ok("a\nd\n".to_string()),
+            run_all(&mut vim, "a\nb\nc\nd\n", &["2,3d x"])
+        );
+        let deleted = vim.registers.get(RegisterName::from_char('x').unwrap());
+        assert_eq!(vec!["b\nc\n"], deleted.unwrap().slices);
+    }
+
+    #[test]
+    fn test_sort() {
+        test_util::setup_test();
+        let text = "b\nC\na\nb\n";
+        assert_eq!(Ok("C\na\nb\nb\n".to_string()), run(text, "sort"));
+        assert_eq!(Ok("a\nb\nC\n".to_string()), run(text, "sort iu"));
+        assert_eq!(Ok("b\nb\na\nC\n".to_string()), run(text, "sort!"));
+        assert_eq!(Ok("x\n2\n10\n".to_string()), run("10\nx\n2\n", "%sort n"));
+        assert_eq!(Ok("c\na\nb".to_string()), run("c\nb\na", "2,3sor"));
+        assert_eq!(Some(-12), first_number("a-12b3"));
+    }
+
+    #[test]
+    fn test_earlier_later() {
+        test_util::setup_test();
+        let mut vim = VimInterface::new();
+        let edits = ["s/a/b/", "s/b/c/"];
+        let run_after_edits = |vim: &mut VimInterface, commands: &[&str]| {
+            run_all(vim, "a", &[&edits[..], commands].concat())
+        };
+        assert_eq!(Ok("b".to_string()), run_after_edits(&mut vim, &["earlier"]));
+        assert_eq!(Ok("a".to_string()), run_after_edits(&mut vim, &["ea 5"]));
+        assert_eq!(
+            Ok("b".to_string()),
+            run_after_edits(&mut vim, &["earlier 2", "later"])
+        );
+        assert_eq!(
+            Ok("c".to_string()),
+            run_after_edits(&mut vim, &["earlier 2", "lat 5"])
+        );
+        assert_eq!(
+            Ok("a".to_string()),
+            run_after_edits(&mut vim, &["earlier 1h"])
+        );
+        assert_eq!(
+            Ok("a".to_string()),
+            run_after_edits(&mut vim, &["earlier 1f"])
+        );
+        assert_eq!(
+            Err(CommandError::InvalidArgument("2x".to_string())),
+            run_after_edits(&mut vim, &["earlier 2x"])
+        );
+        assert_eq!(
+            Ok(UndoStep::Time(Duration::from_secs(600))),
+            parse_undo_step("10m")
+        );
+    }
+
+    #[test]
+    fn test_set_and_actions() {
+        test_util::setup_test();
+        let mut vim = VimInterface::new();
+        run_all(&mut vim, "", &[
+            "set noic ff=dos udf",
+            "set udf",
+            "w foo.txt",
+            "q",
+        ])
+        .unwrap();
+        assert!(!vim.options.ignorecase);
+        assert_eq!(
+            vec![
+                EditorAction::SetFileFormat("ff=dos".to_string()),
+                EditorAction::ReadUndoFiles,
+                EditorAction::Write {
+                    path: Some("foo.txt".into()),
+                    force: false,
+                },
+                EditorAction::Quit { force: false }
+            ],
+            vim.take_actions()
+        );
+        assert_eq!(
+            Err(CommandError::UnknownOption("foo".to_string())),
+            run("", "set foo")
+        );
+    }
+
+    #[test]
+    fn test_complete_arguments() {
+        test_util::setup_test();
+        assert_eq!(vec!["hls noignorecase"], complete_option("hls noig"));
+        assert_eq!(vec!["incsearch"], complete_option("inc"));
+        assert_eq!(vec!["fileencoding", "fileformat"], complete_option("fi"));
+        assert_eq!(vec!["Cargo.toml"], complete_path("Cargo.t"));
+        assert_eq!(vec!["src/lib.rs"], complete_path("src/li"));
+    }
+}
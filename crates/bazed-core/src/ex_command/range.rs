@@ -0,0 +1,222 @@
+//! Line ranges of ex commands, i.e. the `%` in `:%s/a/b/` or the `.,$` in `:.,$d`.
+
+use std::ops::RangeInclusive;
+
+use super::CommandError;
+
+/// The line an [Address] is relative to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressBase {
+    /// `.`, the line of the primary caret
+    Current,
+    /// `$`, the last line of the buffer
+    Last,
+    /// A line number, counting from 1
+    Number(usize),
+    /// `'x`, the line of a mark
+    Mark(char),
+}
+
+/// A single line, i.e. `.`, `$`, `12` or `'a`, optionally followed by offsets such as `+2` or `-`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Address {
+    base: AddressBase,
+    offset: isize,
+}
+
+/// The lines an ex command was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RangeSpec {
+    /// `%`, all lines of the buffer
+    All,
+    Single(Address),
+    /// Two addresses, separated by `,`
+    Between(Address, Address),
+    /// Two addresses, separated by `;`.
+    /// Like in vim, the current line is set to the first address before resolving the second one,
+    /// such that `5;+2` covers lines 5 to 7.
+    Chained(Address, Address),
+}
+
+/// Information needed to turn a [RangeSpec] into actual lines
+pub(crate) struct RangeContext<'a> {
+    /// Line of the primary caret
+    pub(crate) current_line: usize,
+    /// The last line of the buffer
+    pub(crate) last_line: usize,
+    /// Look up the line of a mark, returning `None` if it isn't set
+    pub(crate) mark_line: &'a dyn Fn(char) -> Option<usize>,
+}
+
+/// Parse a range at the start of a command line, returning the range, if any, and the rest of the line
+pub(crate) fn parse_range(s: &str) -> Result<(Option<RangeSpec>, &str), CommandError> {
+    if let Some(rest) = s.strip_prefix('%') {
+        return Ok((Some(RangeSpec::All), rest));
+    }
+    let (first, rest) = parse_address(s)?;
+    let (chained, rest) = match rest.chars().next() {
+        Some(',') => (false, &rest[1..]),
+        Some(';') => (true, &rest[1..]),
+        _ => return Ok((first.map(RangeSpec::Single), rest)),
+    };
+    let (second, rest) = parse_address(rest)?;
+    let current = Address {
+        base: AddressBase::Current,
+        offset: 0,
+    };
+    let (first, second) = (first.unwrap_or(current), second.unwrap_or(current));
+    let range = if chained {
+        RangeSpec::Chained(first, second)
+    } else {
+        RangeSpec::Between(first, second)
+    };
+    Ok((Some(range), rest))
+}
+
+/// Parse a single address, returning `None` if the string doesn't start with one
+fn parse_address(s: &str) -> Result<(Option<Address>, &str), CommandError> {
+    let mut chars = s.chars();
+    let (base, mut rest) = match chars.next() {
+        Some('.') => (Some(AddressBase::Current), chars.as_str()),
+        Some('$') => (Some(AddressBase::Last), chars.as_str()),
+        Some('\'') => {
+            let mark = chars.next().ok_or(CommandError::InvalidRange)?;
+            (Some(AddressBase::Mark(mark)), chars.as_str())
+        },
+        Some(c) if c.is_ascii_digit() => {
+            let (number, rest) = parse_number(s);
+            (Some(AddressBase::Number(number)), rest)
+        },
+        _ => (None, s),
+    };
+
+    let mut offset = 0isize;
+    let mut has_offset = false;
+    loop {
+        let sign = match rest.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => break,
+        };
+        has_offset = true;
+        let (number, after_number) = match parse_number(&rest[1..]) {
+            (_, after) if after.len() == rest.len() - 1 => (1, after),
+            (number, after) => (number, after),
+        };
+        offset = offset.saturating_add(sign * number as isize);
+        rest = after_number;
+    }
+
+    let address = match base {
+        Some(base) => Some(Address { base, offset }),
+        // An offset on its own is relative to the current line
+        None if has_offset => Some(Address {
+            base: AddressBase::Current,
+            offset,
+        }),
+        None => None,
+    };
+    Ok((address, rest))
+}
+
+/// Parse a decimal number at the start of the string, returning 0 if there is none
+fn parse_number(s: &str) -> (usize, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    (s[..end].parse().unwrap_or(0), &s[end..])
+}
+
+impl Address {
+    fn resolve(&self, ctx: &RangeContext) -> Result<usize, CommandError> {
+        let base = match self.base {
+            AddressBase::Current => ctx.current_line,
+            AddressBase::Last => ctx.last_line,
+            // Line 0 is treated as the first line
+            AddressBase::Number(n) => n.saturating_sub(1),
+            AddressBase::Mark(mark) => {
+                (ctx.mark_line)(mark).ok_or(CommandError::MarkNotSet(mark))?
+            },
+        };
+        base.checked_add_signed(self.offset)
+            .filter(|line| *line <= ctx.last_line)
+            .ok_or(CommandError::InvalidRange)
+    }
+}
+
+impl RangeSpec {
+    /// Resolve the range into lines, counting from 0.
+    /// Backwards ranges such as `5,3` are turned around.
+    pub(crate) fn resolve(
+        &self,
+        ctx: &RangeContext,
+    ) -> Result<RangeInclusive<usize>, CommandError> {
+        match self {
+            RangeSpec::All => Ok(0..=ctx.last_line),
+            RangeSpec::Single(address) => {
+                let line = address.resolve(ctx)?;
+                Ok(line..=line)
+            },
+            RangeSpec::Between(first, second) => {
+                let (first, second) = (first.resolve(ctx)?, second.resolve(ctx)?);
+                Ok(first.min(second)..=first.max(second))
+            },
+            RangeSpec::Chained(first, second) => {
+                let first = first.resolve(ctx)?;
+                let second = second.resolve(&RangeContext {
+                    current_line: first,
+                    ..*ctx
+                })?;
+                Ok(first.min(second)..=first.max(second))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_range, RangeContext};
+    use crate::{ex_command::CommandError, test_util};
+
+    /// First and last line of the parsed range, if any, and the rest of the command line
+    type Resolved<'a> = (Option<(usize, usize)>, &'a str);
+
+    fn resolve(s: &str) -> Result<Resolved, CommandError> {
+        let mark_line = |mark| (mark == 'a').then_some(7);
+        let ctx = RangeContext {
+            current_line: 4,
+            last_line: 9,
+            mark_line: &mark_line,
+        };
+        let (range, rest) = parse_range(s)?;
+        let lines = match range {
+            Some(range) => {
+                let lines = range.resolve(&ctx)?;
+                Some((*lines.start(), *lines.end()))
+            },
+            None => None,
+        };
+        Ok((lines, rest))
+    }
+
+    #[test]
+    fn test_parse_range() {
+        test_util::setup_test();
+        assert_eq!((None, "s/a/b/"), resolve("s/a/b/").unwrap());
+        assert_eq!((Some((0, 9)), "d"), resolve("%d").unwrap());
+        assert_eq!((Some((4, 9)), "d"), resolve(".,$d").unwrap());
+        assert_eq!((Some((2, 2)), ""), resolve("3").unwrap());
+        assert_eq!((Some((1, 7)), "y"), resolve("'a,2y").unwrap());
+        assert_eq!((Some((6, 8)), ""), resolve("+2;$-").unwrap());
+        assert_eq!((Some((3, 4)), "s"), resolve(".-1,s").unwrap());
+        assert_eq!((Some((1, 5)), ""), resolve("2,+1").unwrap());
+        assert_eq!((Some((1, 2)), ""), resolve("2;+1").unwrap());
+        assert_eq!((Some((7, 7)), "d"), resolve("'a;.d").unwrap());
+    }
+
+    #[test]
+    fn test_invalid_range() {
+        test_util::setup_test();
+        assert_eq!(Err(CommandError::InvalidRange), resolve("11"));
+        assert_eq!(Err(CommandError::InvalidRange), resolve(".-5"));
+        assert_eq!(Err(CommandError::MarkNotSet('b')), resolve("'b"));
+    }
+}
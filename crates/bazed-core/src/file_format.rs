@@ -0,0 +1,289 @@
+//! Encodings and line endings of files.
+//!
+//! Buffers always hold UTF-8 text with `\n` line endings.
+//! The format a file was in when loading it is remembered in a [FileFormat],
+//! such that the file can be written back out in that same format.
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use xi_rope::Rope;
+
+use crate::ex_command::CommandError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    /// Name of the line ending as used by the `fileformat` option
+    fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "unix",
+            LineEnding::CrLf => "dos",
+        }
+    }
+}
+
+/// Something about a file that decoding it couldn't preserve,
+/// such that writing the text back to the file would also change parts of it that weren't edited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeIssue {
+    #[error("Malformed {0} sequences were replaced")]
+    Malformed(&'static str),
+    #[error("The file mixes line endings, which would all be written as {0}")]
+    MixedLineEndings(&'static str),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("The text can't be encoded as {encoding}")]
+pub struct EncodeError {
+    encoding: &'static str,
+}
+
+/// How the text of a document is stored in its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFormat {
+    pub encoding: &'static Encoding,
+    /// Whether the file starts with a byte order mark. Only used for UTF-8 and UTF-16.
+    pub bom: bool,
+    pub line_ending: LineEnding,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+            line_ending: LineEnding::default(),
+        }
+    }
+}
+
+impl FileFormat {
+    /// Full names of all options concerning the file format, which are set per document
+    pub(crate) const NAMES: &'static [&'static str] = &["bomb", "fileencoding", "fileformat"];
+
+    /// Detect the format of the content of a file and decode it,
+    /// returning the text with all CRLF line endings turned into `\n`,
+    /// along with what the text can't represent about the file, if anything.
+    ///
+    /// Files starting with a byte order mark are decoded with the encoding it indicates.
+    /// Otherwise, files that are valid UTF-8 are taken as such, and the encoding of any other file is guessed.
+    /// The line ending of the format is the one that is used by most lines.
+    pub fn decode(bytes: &[u8]) -> (Self, String, Option<DecodeIssue>) {
+        let (encoding, bom_len) = match Encoding::for_bom(bytes) {
+            Some((encoding, bom_len)) => (encoding, bom_len),
+            None if std::str::from_utf8(bytes).is_ok() => (UTF_8, 0),
+            None => {
+                let mut detector = EncodingDetector::new();
+                detector.feed(bytes, true);
+                (detector.guess(None, true), 0)
+            },
+        };
+        let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        let crlf_count = text.matches("\r\n").count();
+        let lf_count = text.matches('\n').count() - crlf_count;
+        let line_ending = if crlf_count > lf_count {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        };
+        let issue = if had_errors {
+            tracing::warn!(
+                encoding = encoding.name(),
+                "Replaced malformed sequences while decoding file"
+            );
+            Some(DecodeIssue::Malformed(encoding.name()))
+        } else if crlf_count > 0 && lf_count > 0 {
+            Some(DecodeIssue::MixedLineEndings(line_ending.name()))
+        } else {
+            None
+        };
+        let text = if crlf_count > 0 {
+            text.replace("\r\n", "\n")
+        } else {
+            text.into_owned()
+        };
+        let format = Self {
+            encoding,
+            bom: bom_len > 0,
+            line_ending,
+        };
+        (format, text, issue)
+    }
+
+    /// Encode text with `\n` line endings in this format
+    pub fn encode(&self, text: &Rope) -> Result<Vec<u8>, EncodeError> {
+        let mut text = text.to_string();
+        if self.line_ending != LineEnding::Lf {
+            text = text.replace('\n', self.line_ending.as_str());
+        }
+        let mut bytes = Vec::new();
+        // encoding_rs only decodes UTF-16, encoding it has to be done by hand
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            let units = self.bom.then_some(0xFEFF).into_iter();
+            for unit in units.chain(text.encode_utf16()) {
+                if self.encoding == UTF_16LE {
+                    bytes.extend(unit.to_le_bytes());
+                } else {
+                    bytes.extend(unit.to_be_bytes());
+                }
+            }
+            return Ok(bytes);
+        }
+        if self.bom && self.encoding == UTF_8 {
+            bytes.extend(b"\xEF\xBB\xBF");
+        }
+        let (encoded, _, unmappable) = self.encoding.encode(&text);
+        if unmappable {
+            return Err(EncodeError {
+                encoding: self.encoding.name(),
+            });
+        }
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
+    }
+
+    /// Check whether an argument of `:set` concerns the file format rather than the options of the editor
+    pub(crate) fn is_option(arg: &str) -> bool {
+        let name = arg.split(['=', '?', '!']).next().unwrap_or_default();
+        let name = name
+            .strip_prefix("no")
+            .or_else(|| name.strip_prefix("inv"))
+            .filter(|x| *x == "bomb")
+            .unwrap_or(name);
+        matches!(name, "bomb" | "fileencoding" | "fenc" | "fileformat" | "ff")
+    }
+
+    /// Apply a single argument of `:set` concerning the file format,
+    /// i.e. `ff=dos`, `fenc=latin1`, `nobomb` or `ff?`.
+    ///
+    /// Returns a message showing the value of the option, if it was queried.
+    pub(crate) fn apply(&mut self, arg: &str) -> Result<Option<String>, CommandError> {
+        let invalid = || CommandError::InvalidArgument(arg.to_string());
+        if let Some((name, value)) = arg.split_once('=') {
+            match name {
+                "fileformat" | "ff" => {
+                    self.line_ending = match value {
+                        "unix" => LineEnding::Lf,
+                        "dos" => LineEnding::CrLf,
+                        _ => return Err(invalid()),
+                    }
+                },
+                "fileencoding" | "fenc" => {
+                    self.encoding = Encoding::for_label(value.as_bytes()).ok_or_else(invalid)?;
+                },
+                _ => return Err(CommandError::UnknownOption(arg.to_string())),
+            }
+            return Ok(None);
+        }
+        match arg {
+            "fileformat?" | "ff?" => return Ok(Some(self.describe_line_ending())),
+            "fileencoding?" | "fenc?" => return Ok(Some(self.describe_encoding())),
+            "bomb?" => return Ok(Some(self.describe_bom().to_string())),
+            "bomb" => self.bom = true,
+            "nobomb" => self.bom = false,
+            "invbomb" | "bomb!" => self.bom = !self.bom,
+            _ => return Err(CommandError::UnknownOption(arg.to_string())),
+        }
+        Ok(None)
+    }
+
+    fn describe_bom(&self) -> &'static str {
+        if self.bom {
+            "bomb"
+        } else {
+            "nobomb"
+        }
+    }
+
+    fn describe_encoding(&self) -> String {
+        format!("fileencoding={}", self.encoding.name().to_lowercase())
+    }
+
+    fn describe_line_ending(&self) -> String {
+        format!("fileformat={}", self.line_ending.name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use encoding_rs::{UTF_16LE, UTF_8, WINDOWS_1252};
+    use xi_rope::Rope;
+
+    use super::{DecodeIssue, FileFormat, LineEnding};
+    use crate::test_util;
+
+    #[test]
+    fn test_decode() {
+        test_util::setup_test();
+        let (format, text, issue) = FileFormat::decode(b"\xEF\xBB\xBFa\r\nb\r\nc\n");
+        assert_eq!(UTF_8, format.encoding);
+        assert!(format.bom);
+        assert_eq!(LineEnding::CrLf, format.line_ending);
+        assert_eq!("a\nb\nc\n", text);
+        assert_eq!(Some(DecodeIssue::MixedLineEndings("dos")), issue);
+
+        let (format, text, issue) = FileFormat::decode(b"\xFF\xFEa\x00\n\x00");
+        assert_eq!(UTF_16LE, format.encoding);
+        assert_eq!("a\n", text);
+        assert_eq!(None, issue);
+
+        let (format, text, issue) = FileFormat::decode(b"caf\xE9 cr\xE8me br\xFBl\xE9e\n");
+        assert_eq!(WINDOWS_1252, format.encoding);
+        assert!(!format.bom);
+        assert_eq!(LineEnding::Lf, format.line_ending);
+        assert_eq!("café crème brûlée\n", text);
+        assert_eq!(None, issue);
+
+        let (_, _, issue) = FileFormat::decode(b"\xFF\xFEa\x00\x00\xD8");
+        assert_eq!(Some(DecodeIssue::Malformed("UTF-16LE")), issue);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        test_util::setup_test();
+        for bytes in [
+            &b"\xEF\xBB\xBFa\r\nb\r\n"[..],
+            b"\xFE\xFF\x00a\x00\r\x00\n",
+            b"caf\xE9 cr\xE8me br\xFBl\xE9e\n",
+        ] {
+            let (format, text, _) = FileFormat::decode(bytes);
+            assert_eq!(bytes, format.encode(&Rope::from(text)).unwrap());
+        }
+        let format = FileFormat {
+            encoding: WINDOWS_1252,
+            ..FileFormat::default()
+        };
+        assert!(format.encode(&Rope::from("→")).is_err());
+    }
+
+    #[test]
+    fn test_set_options() {
+        test_util::setup_test();
+        let mut format = FileFormat::default();
+        assert!(FileFormat::is_option("ff=dos"));
+        assert!(FileFormat::is_option("nobomb"));
+        assert!(!FileFormat::is_option("noic"));
+        assert_eq!(Ok(None), format.apply("ff=dos"));
+        assert_eq!(Ok(None), format.apply("fenc=latin1"));
+        assert_eq!(Ok(None), format.apply("invbomb"));
+        assert_eq!(Ok(Some("bomb".to_string())), format.apply("bomb?"));
+        assert_eq!(
+            Ok(Some("fileencoding=windows-1252".to_string())),
+            format.apply("fenc?")
+        );
+        assert_eq!(Ok(Some("fileformat=dos".to_string())), format.apply("ff?"));
+        assert!(format.apply("ff=mac").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Watching the files of open documents for changes made by other programs.
+//!
+//! Rather than the files themselves, their directories are watched,
+//! such that files that are replaced by renaming another file over them,
+//! as done by many programs (including bazed itself) when saving, are still noticed.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+pub(crate) struct FileWatcher {
+    watcher: RecommendedWatcher,
+    /// Number of watched files in each watched directory
+    directories: HashMap<PathBuf, usize>,
+}
+
+impl FileWatcher {
+    /// Start watching for changes, returning the watcher and a receiver
+    /// for the normalized paths (see [normalize_path]) of changed files.
+    ///
+    /// Changes to files in the watched directories that aren't watched themselves are reported as well.
+    pub(crate) fn new() -> notify::Result<(Self, UnboundedReceiver<PathBuf>)> {
+        let (send, recv) = unbounded_channel();
+        let watcher = RecommendedWatcher::new(
+            move |event: notify::Result<Event>| match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    for path in event.paths {
+                        // The receiver is only gone once the app is shutting down
+                        let _ = send.send(path);
+                    }
+                },
+                Ok(_) => {},
+                Err(err) => tracing::warn!("Error while watching files: {err}"),
+            },
+            Config::default(),
+        )?;
+        let watcher = Self {
+            watcher,
+            directories: HashMap::new(),
+        };
+        Ok((watcher, recv))
+    }
+
+    /// Start watching a file for changes.
+    /// Watching the same file multiple times requires unwatching it just as often.
+    pub(crate) fn watch(&mut self, path: &Path) {
+        let Some(directory) = watched_directory(path) else {
+            tracing::warn!(?path, "Can't watch file without a directory");
+            return;
+        };
+        let count = self.directories.entry(directory.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            if let Err(err) = self.watcher.watch(&directory, RecursiveMode::NonRecursive) {
+                tracing::warn!(?directory, "Failed to watch directory: {err}");
+            }
+        }
+    }
+
+    /// Stop watching a file that was watched with [Self::watch]
+    pub(crate) fn unwatch(&mut self, path: &Path) {
+        let Some(directory) = watched_directory(path) else { return };
+        let Some(count) = self.directories.get_mut(&directory) else { return };
+        *count -= 1;
+        if *count == 0 {
+            self.directories.remove(&directory);
+            if let Err(err) = self.watcher.unwatch(&directory) {
+                tracing::warn!(?directory, "Failed to stop watching directory: {err}");
+            }
+        }
+    }
+}
+
+/// Turn a path into the form changed files are reported in,
+/// which is absolute and free of symlinks in its directory.
+///
+/// The file itself doesn't need to exist, only its directory does.
+pub(crate) fn normalize_path(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?;
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    Some(directory.canonicalize().ok()?.join(file_name))
+}
+
+/// The directory that is watched to notice changes to a file
+fn watched_directory(path: &Path) -> Option<PathBuf> {
+    Some(normalize_path(path)?.parent()?.to_path_buf())
+}
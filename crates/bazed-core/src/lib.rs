@@ -5,7 +5,13 @@
 pub mod app;
 pub mod buffer;
 pub mod document;
+mod ex_command;
+pub mod file_format;
+mod file_watcher;
+mod options;
 pub mod region;
+mod register;
+mod search;
 mod user_buffer_op;
 pub mod view;
 mod vim_interface;
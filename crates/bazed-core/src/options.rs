@@ -0,0 +1,146 @@
+//! Editor options, which can be changed at runtime via `:set`.
+
+use crate::ex_command::CommandError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Options {
+    /// Highlight all matches of the most recent search
+    pub(crate) hlsearch: bool,
+    /// Move to and highlight matches while the search pattern is being typed
+    pub(crate) incsearch: bool,
+    /// Ignore case in search patterns
+    pub(crate) ignorecase: bool,
+    /// Don't ignore case if the pattern contains an uppercase character, only used with `ignorecase`
+    pub(crate) smartcase: bool,
+    /// Keep the undo history of files in undo files, such that it survives closing and reopening them
+    pub(crate) undofile: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            hlsearch: true,
+            incsearch: true,
+            ignorecase: true,
+            smartcase: true,
+            undofile: false,
+        }
+    }
+}
+
+impl Options {
+    /// Full names of all options
+    pub(crate) const NAMES: &'static [&'static str] = &[
+        "hlsearch",
+        "ignorecase",
+        "incsearch",
+        "smartcase",
+        "undofile",
+    ];
+
+    /// Look up a boolean option by its full or its short name
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "hlsearch" | "hls" => Some(&mut self.hlsearch),
+            "incsearch" | "is" => Some(&mut self.incsearch),
+            "ignorecase" | "ic" => Some(&mut self.ignorecase),
+            "smartcase" | "scs" => Some(&mut self.smartcase),
+            "undofile" | "udf" => Some(&mut self.undofile),
+            _ => None,
+        }
+    }
+
+    /// Apply a single argument of `:set`, i.e. `ic`, `noic`, `invic`, `ic!` or `ic?`.
+    ///
+    /// Returns a message showing the value of the option, if it was queried.
+    pub(crate) fn apply(&mut self, arg: &str) -> Result<Option<String>, CommandError> {
+        let unknown = || CommandError::UnknownOption(arg.to_string());
+        if let Some(name) = arg.strip_suffix('?') {
+            let value = *self.flag_mut(name).ok_or_else(unknown)?;
+            return Ok(Some(format_flag(name, value)));
+        }
+        if let Some(name) = arg.strip_suffix('!').or_else(|| arg.strip_prefix("inv")) {
+            if let Some(flag) = self.flag_mut(name) {
+                *flag = !*flag;
+                return Ok(None);
+            }
+        }
+        if let Some(flag) = self.flag_mut(arg) {
+            *flag = true;
+            return Ok(None);
+        }
+        let flag = arg
+            .strip_prefix("no")
+            .and_then(|name| self.flag_mut(name))
+            .ok_or_else(unknown)?;
+        *flag = false;
+        Ok(None)
+    }
+
+    /// Describe the value of every option, as shown by `:set all`
+    pub(crate) fn describe(&self) -> String {
+        let flags = [
+            ("hlsearch", self.hlsearch),
+            ("ignorecase", self.ignorecase),
+            ("incsearch", self.incsearch),
+            ("smartcase", self.smartcase),
+            ("undofile", self.undofile),
+        ];
+        flags
+            .iter()
+            .map(|(name, value)| format_flag(name, *value))
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    /// Whether a search for the given pattern should ignore case
+    pub(crate) fn ignores_case(&self, pattern: &str) -> bool {
+        self.ignorecase && !(self.smartcase && pattern.chars().any(char::is_uppercase))
+    }
+}
+
+fn format_flag(name: &str, value: bool) -> String {
+    if value {
+        name.to_string()
+    } else {
+        format!("no{name}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Options;
+    use crate::{ex_command::CommandError, test_util};
+
+    #[test]
+    fn test_set_options() {
+        test_util::setup_test();
+        let mut options = Options::default();
+        assert_eq!(Ok(None), options.apply("noic"));
+        assert!(!options.ignorecase);
+        assert_eq!(
+            Ok(Some("noignorecase".to_string())),
+            options.apply("ignorecase?")
+        );
+        assert_eq!(Ok(None), options.apply("invhls"));
+        assert!(!options.hlsearch);
+        assert_eq!(Ok(None), options.apply("hls!"));
+        assert!(options.hlsearch);
+        assert_eq!(
+            Err(CommandError::UnknownOption("nofoo".to_string())),
+            options.apply("nofoo")
+        );
+    }
+
+    #[test]
+    fn test_ignores_case() {
+        test_util::setup_test();
+        let mut options = Options::default();
+        assert!(options.ignores_case("foo"));
+        assert!(!options.ignores_case("Foo"));
+        options.smartcase = false;
+        assert!(options.ignores_case("Foo"));
+        options.ignorecase = false;
+        assert!(!options.ignores_case("foo"));
+    }
+}
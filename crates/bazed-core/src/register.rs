@@ -0,0 +1,236 @@
+//! Vim-style registers, storing yanked and deleted text.
+//!
+//! Every register stores one slice of text per caret that was involved in the operation
+//! that filled it, such that yanking and putting with multiple carets keeps the text of every caret separate.
+
+use std::collections::HashMap;
+
+/// Name of a register, as selected by the user via `"{name}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum RegisterName {
+    /// `""`, which always contains the most recently written text.
+    Unnamed,
+    /// `"a` to `"z`. When specified in upper case (`"A` to `"Z`), `append` is set,
+    /// and writes will append to the register instead of overwriting it.
+    Named { name: char, append: bool },
+    /// `"0` to `"9`. `"0` contains the most recent yank,
+    /// `"1` to `"9` form a ring of the most recent deletions.
+    Numbered(u8),
+    /// `"_`, which discards anything written to it.
+    BlackHole,
+}
+
+impl RegisterName {
+    pub(crate) fn from_char(c: char) -> Option<Self> {
+        match c {
+            '"' => Some(Self::Unnamed),
+            'a'..='z' => Some(Self::Named {
+                name: c,
+                append: false,
+            }),
+            'A'..='Z' => Some(Self::Named {
+                name: c.to_ascii_lowercase(),
+                append: true,
+            }),
+            '0'..='9' => Some(Self::Numbered(c.to_digit(10)? as u8)),
+            '_' => Some(Self::BlackHole),
+            _ => None,
+        }
+    }
+}
+
+/// Content of a register, consisting of one slice of text per caret.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RegisterContent {
+    pub(crate) slices: Vec<String>,
+    /// Set if the slices consist of full lines, in which case they are put into lines of their own.
+    pub(crate) linewise: bool,
+}
+
+impl RegisterContent {
+    pub(crate) fn new(slices: Vec<String>, linewise: bool) -> Self {
+        Self { slices, linewise }
+    }
+
+    /// Append the content of another register to this one.
+    /// If both contain the same number of slices, they are appended slice by slice.
+    fn append(&mut self, other: RegisterContent) {
+        self.linewise |= other.linewise;
+        if self.slices.len() == other.slices.len() {
+            for (slice, other_slice) in self.slices.iter_mut().zip(other.slices) {
+                slice.push_str(&other_slice);
+            }
+        } else {
+            self.slices.extend(other.slices);
+        }
+    }
+}
+
+/// The set of all registers.
+#[derive(Debug, Default)]
+pub(crate) struct Registers {
+    unnamed: Option<RegisterContent>,
+    named: HashMap<char, RegisterContent>,
+    /// `"0` to `"9`, indexed by their number
+    numbered: [Option<RegisterContent>; 10],
+}
+
+impl Registers {
+    pub(crate) fn get(&self, name: RegisterName) -> Option<&RegisterContent> {
+        match name {
+            RegisterName::Unnamed => self.unnamed.as_ref(),
+            RegisterName::Named { name, .. } => self.named.get(&name),
+            RegisterName::Numbered(n) => self.numbered.get(n as usize)?.as_ref(),
+            RegisterName::BlackHole => None,
+        }
+    }
+
+    /// Store yanked text. If no register is given, the text is stored in `"0`.
+    pub(crate) fn record_yank(&mut self, name: Option<RegisterName>, content: RegisterContent) {
+        match name.unwrap_or(RegisterName::Unnamed) {
+            RegisterName::Unnamed => {
+                self.numbered[0] = Some(content.clone());
+                self.unnamed = Some(content);
+            },
+            name => self.write(name, content),
+        }
+    }
+
+    /// Store deleted text. If no register is given, the text is pushed onto the delete ring (`"1` to `"9`).
+    pub(crate) fn record_delete(&mut self, name: Option<RegisterName>, content: RegisterContent) {
+        match name.unwrap_or(RegisterName::Unnamed) {
+            RegisterName::Unnamed => {
+                self.numbered[1..].rotate_right(1);
+                self.numbered[1] = Some(content.clone());
+                self.unnamed = Some(content);
+            },
+            name => self.write(name, content),
+        }
+    }
+
+    /// Store a recorded macro in the given register.
+    /// Unlike yanking and deleting, this leaves the unnamed register untouched.
+    pub(crate) fn record_macro(&mut self, name: RegisterName, content: RegisterContent) {
+        let unnamed = self.unnamed.take();
+        self.write(name, content);
+        if name != RegisterName::Unnamed {
+            self.unnamed = unnamed;
+        }
+    }
+
+    /// Write to the given register, pointing the unnamed register to the resulting content.
+    fn write(&mut self, name: RegisterName, content: RegisterContent) {
+        let content = match name {
+            RegisterName::BlackHole => return,
+            RegisterName::Unnamed => content,
+            RegisterName::Named { name, append } => {
+                let register = self.named.entry(name).or_default();
+                if append {
+                    register.append(content);
+                } else {
+                    *register = content;
+                }
+                register.clone()
+            },
+            RegisterName::Numbered(n) => {
+                self.numbered[n as usize] = Some(content.clone());
+                content
+            },
+        };
+        self.unnamed = Some(content);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RegisterContent, RegisterName, Registers};
+    use crate::test_util;
+
+    fn content(slices: &[&str]) -> RegisterContent {
+        RegisterContent::new(slices.iter().map(|x| x.to_string()).collect(), false)
+    }
+
+    fn reg(c: char) -> RegisterName {
+        RegisterName::from_char(c).unwrap()
+    }
+
+    #[test]
+    fn test_parse_register_name() {
+        test_util::setup_test();
+        assert_eq!(Some(RegisterName::Unnamed), RegisterName::from_char('"'));
+        assert_eq!(
+            Some(RegisterName::Named {
+                name: 'a',
+                append: true
+            }),
+            RegisterName::from_char('A')
+        );
+        assert_eq!(
+            Some(RegisterName::Numbered(3)),
+            RegisterName::from_char('3')
+        );
+        assert_eq!(Some(RegisterName::BlackHole), RegisterName::from_char('_'));
+        assert_eq!(None, RegisterName::from_char('%'));
+    }
+
+    #[test]
+    fn test_yank_into_unnamed() {
+        test_util::setup_test();
+        let mut r = Registers::default();
+        r.record_yank(None, content(&["foo"]));
+        assert_eq!(Some(&content(&["foo"])), r.get(reg('"')));
+        assert_eq!(Some(&content(&["foo"])), r.get(reg('0')));
+        assert_eq!(None, r.get(reg('1')));
+    }
+
+    #[test]
+    fn test_delete_ring() {
+        test_util::setup_test();
+        let mut r = Registers::default();
+        r.record_yank(None, content(&["yanked"]));
+        for i in 0..10 {
+            r.record_delete(None, content(&[&i.to_string()]));
+        }
+        assert_eq!(Some(&content(&["9"])), r.get(reg('"')));
+        assert_eq!(Some(&content(&["9"])), r.get(reg('1')));
+        assert_eq!(Some(&content(&["1"])), r.get(reg('9')));
+        assert_eq!(
+            Some(&content(&["yanked"])),
+            r.get(reg('0')),
+            "Deleting does not touch the yank register"
+        );
+    }
+
+    #[test]
+    fn test_named_register_append() {
+        test_util::setup_test();
+        let mut r = Registers::default();
+        r.record_yank(Some(reg('a')), content(&["foo", "bar"]));
+        r.record_delete(Some(reg('A')), content(&["1", "2"]));
+        assert_eq!(Some(&content(&["foo1", "bar2"])), r.get(reg('a')));
+        assert_eq!(Some(&content(&["foo1", "bar2"])), r.get(reg('"')));
+        assert_eq!(None, r.get(reg('0')));
+        assert_eq!(None, r.get(reg('1')));
+    }
+
+    #[test]
+    fn test_macro_keeps_unnamed() {
+        test_util::setup_test();
+        let mut r = Registers::default();
+        r.record_yank(None, content(&["foo"]));
+        r.record_macro(reg('q'), content(&["dw"]));
+        r.record_macro(reg('Q'), content(&["j"]));
+        assert_eq!(Some(&content(&["dwj"])), r.get(reg('q')));
+        assert_eq!(Some(&content(&["foo"])), r.get(reg('"')));
+    }
+
+    #[test]
+    fn test_black_hole() {
+        test_util::setup_test();
+        let mut r = Registers::default();
+        r.record_yank(None, content(&["foo"]));
+        r.record_delete(Some(reg('_')), content(&["bar"]));
+        assert_eq!(Some(&content(&["foo"])), r.get(reg('"')));
+        assert_eq!(None, r.get(reg('_')));
+    }
+}
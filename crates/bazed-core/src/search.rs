@@ -0,0 +1,115 @@
+//! Regex search, as used by vim's `/`, `?`, `*` and `#` commands.
+
+use hotsauce::Regex;
+
+use crate::user_buffer_op::Motion;
+
+/// A compiled search pattern, together with the direction it was started in.
+pub(crate) struct Search {
+    /// The pattern as it was typed by the user
+    pub(crate) pattern: String,
+    pub(crate) regex: Regex,
+    /// Set if the search goes towards the start of the buffer, i.e. when started with `?` or `#`
+    pub(crate) backwards: bool,
+}
+
+impl Search {
+    /// Compile a search pattern, returning `None` if it is not a valid regex.
+    pub(crate) fn new(pattern: String, backwards: bool, ignore_case: bool) -> Option<Self> {
+        let regex = if ignore_case {
+            Regex::new(&format!("(?i){pattern}"))
+        } else {
+            Regex::new(&pattern)
+        };
+        match regex {
+            Ok(regex) => Some(Self {
+                pattern,
+                regex,
+                backwards,
+            }),
+            Err(_) => {
+                tracing::info!("Invalid search pattern {pattern}");
+                None
+            },
+        }
+    }
+
+    /// Search for occurrences of the given word that are not part of a longer word, like `*` and `#` do.
+    pub(crate) fn for_word(word: &str, backwards: bool, ignore_case: bool) -> Option<Self> {
+        // Only ascii word boundaries are supported by the regex engine,
+        // which never match next to other characters, so they are left out for words starting or ending in one.
+        let is_ascii_word_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let boundary = |c: Option<char>| match c {
+            Some(c) if is_ascii_word_char(c) => r"(?-u:\b)",
+            _ => "",
+        };
+        let start = boundary(word.chars().next());
+        let end = boundary(word.chars().last());
+        let pattern = format!("{start}{}{end}", escape(word));
+        Self::new(pattern, backwards, ignore_case)
+    }
+
+    /// Motion to the next match in the direction of the search,
+    /// or in the opposite direction if `reverse` is set (i.e. for `N`)
+    pub(crate) fn motion(&self, reverse: bool) -> Motion<'_> {
+        if self.backwards != reverse {
+            Motion::FindPrev(&self.regex)
+        } else {
+            Motion::FindNext(&self.regex)
+        }
+    }
+}
+
+/// Escape all characters that have a special meaning in regex patterns
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if r"\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::{escape, Search};
+    use crate::{buffer::Buffer, test_util, user_buffer_op::BufferOp, view::Viewport};
+
+    fn find_next(text: &str, search: &Search) -> usize {
+        let mut b = Buffer::new_from_string(text.to_string());
+        b.apply_buffer_op(
+            &Viewport::new_ginormeous(),
+            BufferOp::Move(search.motion(false)),
+        );
+        b.primary_caret().head
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        test_util::setup_test();
+        let search = Search::new("foo".to_string(), false, true).unwrap();
+        assert_eq!(4, find_next("xxx FOO", &search));
+        let search = Search::new("Foo".to_string(), false, false).unwrap();
+        assert_eq!(8, find_next("xxx FOO Foo", &search));
+    }
+
+    #[test]
+    fn test_search_word() {
+        test_util::setup_test();
+        let search = Search::for_word("foo", false, false).unwrap();
+        assert_eq!(11, find_next("foo foobar foo", &search));
+        let search = Search::for_word("a.b", false, false).unwrap();
+        assert_eq!(8, find_next("a.b axb a.b", &search));
+        let search = Search::for_word("über", false, false).unwrap();
+        assert_eq!(8, find_next("über x über\n", &search));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        test_util::setup_test();
+        assert!(Search::new("foo(".to_string(), false, false).is_none());
+        assert_eq!(r"a\.b\(", escape("a.b("));
+    }
+}
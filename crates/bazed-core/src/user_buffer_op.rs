@@ -20,12 +20,73 @@ pub(crate) enum BufferOp<'a> {
     Delete(Motion<'a>),
     Undo,
     Redo,
-    DeleteSelected,
+    /// Move to an older state of the undo history, in chronological order rather than along the current branch
+    Earlier(UndoStep),
+    /// Move to a newer state of the undo history, in chronological order rather than along the current branch
+    Later(UndoStep),
     Move(Motion<'a>),
     /// Expand or change the selection
     Selection(Motion<'a>),
     /// Create a new cursor at the location the motion targets
     NewCaret(Motion<'a>),
+    /// Expand the selection to include the text object
+    SelectTextObject(TextObject),
+    /// Replace every selection with the matches of the regex within it
+    SelectMatches(&'a hotsauce::Regex),
+    /// Split every selection at the matches of the regex, selecting the text in between
+    SplitSelections(&'a hotsauce::Regex),
+    /// Split every selection into one selection per line, leaving out the line breaks
+    SplitLines,
+    /// Keep only the selections containing a match of the regex, or only the ones without a match if `keep` is false
+    FilterSelections {
+        regex: &'a hotsauce::Regex,
+        keep: bool,
+    },
+    /// Merge selections that overlap or touch each other into one, spanning all of them
+    MergeSelections,
+    /// Make the next caret the primary one, or the previous one if `backwards` is set
+    RotatePrimaryCaret {
+        backwards: bool,
+    },
+    /// Remove the primary caret, unless it is the only one
+    RemovePrimaryCaret,
+    /// Remove the caret that was added most recently, unless it is the only one
+    RemoveLastAddedCaret,
+    /// Surround the range the target covers at every caret with the pair
+    Surround {
+        target: OperatorTarget<'a>,
+        pair: &'a SurroundPair,
+    },
+    /// Replace the delimiters of the bracket, quote or tag object around every caret with the pair,
+    /// or delete them if no pair is given
+    ChangeSurround {
+        object: TextObjectKind,
+        pair: Option<&'a SurroundPair>,
+    },
+    /// Add the amount to the number under or after each caret, within the line of the caret
+    AddToNumbers(i64),
+    /// Add a selection at the next occurrence of the text selected by the primary caret, making it the primary one,
+    /// or move the primary selection there if `skip` is set.
+    /// Selects the word under the primary caret if it isn't a selection.
+    SelectNextOccurrence {
+        skip: bool,
+    },
+    /// Select all occurrences of the text selected by the primary caret, or of the word under it,
+    /// only within the viewport if `in_viewport` is set
+    SelectAllOccurrences {
+        in_viewport: bool,
+    },
+}
+
+/// How far to move through the undo history with [BufferOp::Earlier] and [BufferOp::Later]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum UndoStep {
+    /// Move by the given number of states
+    Count(usize),
+    /// Move to the state the buffer was in the given duration before or after the current one
+    Time(std::time::Duration),
+    /// Move by the given number of saves
+    Saves(usize),
 }
 
 /// A motion, either character-wise or defined by some higher-level semantic target.
@@ -40,9 +101,297 @@ pub(crate) enum Motion<'a> {
     StartOfLine,
     EndOfLine,
     TopOfViewport,
+    /// Line in the middle of the viewport, or of the lines of text shown when they don't fill it
+    MiddleOfViewport,
     BottomOfViewport,
     NextWordBoundary(WordBoundaryType),
     PrevWordBoundary(WordBoundaryType),
+    /// Boundary of a whitespace-delimited word, like `W` and `B` in vim
+    NextBigWordBoundary(WordBoundaryType),
+    PrevBigWordBoundary(WordBoundaryType),
+    /// Boundary of a part of a camelCase or snake_case identifier
+    NextSubwordBoundary(WordBoundaryType),
+    PrevSubwordBoundary(WordBoundaryType),
+    /// The blank line after the current paragraph, or the end of the text if there is none
+    NextParagraph,
+    /// The blank line before the current paragraph, or the start of the text if there is none
+    PrevParagraph,
+    /// Start of the next sentence
+    NextSentence,
+    /// Start of the current sentence, or of the previous one when already at the start
+    PrevSentence,
     FindNext(&'a hotsauce::Regex),
     FindPrev(&'a hotsauce::Regex),
+    /// Start of the given line, counting from 0
+    Line(usize),
+    /// Start of the first line in the buffer
+    FirstLine,
+    /// Start of the last line in the buffer
+    LastLine,
+    /// Start of the line at the given percentage of the buffer, like `50%` in vim
+    Percentage(usize),
+    /// The given offset, i.e. the position of a mark
+    Offset(usize),
+    /// The bracket matching the first `()`, `[]` or `{}` bracket at or after the caret within its line
+    MatchingBracket,
+    /// The `count`th occurrence of the searched grapheme cluster within the line of the caret
+    FindInLine {
+        search: &'a CharSearch,
+        count: usize,
+    },
+    /// Apply the inner motion the given number of times
+    Repeated(usize, &'a Motion<'a>),
+}
+
+/// An in-line search for a grapheme cluster, as done by `f`, `t`, `F` and `T` in vim
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CharSearch {
+    pub(crate) grapheme: String,
+    pub(crate) backwards: bool,
+    /// Stop right before the grapheme rather than on it, or right after it when searching backwards
+    pub(crate) till: bool,
+    /// Set when the search is repeated with `;` or `,`.
+    /// Like in vim, a repeated till search skips over a match right next to the caret, instead of staying in front of it.
+    pub(crate) repeated: bool,
+}
+
+impl CharSearch {
+    /// The search that repeats this one, as done by `;`, or in the opposite direction if `reverse` is set, as done by `,`
+    pub(crate) fn repeat(&self, reverse: bool) -> CharSearch {
+        CharSearch {
+            grapheme: self.grapheme.clone(),
+            backwards: self.backwards != reverse,
+            till: self.till,
+            repeated: true,
+        }
+    }
+
+    /// The motion to the `count`th match of this search, or the first match if no count is given
+    pub(crate) fn motion(&self, count: Option<usize>) -> Motion<'_> {
+        Motion::FindInLine {
+            search: self,
+            count: count.unwrap_or(1),
+        }
+    }
+}
+
+/// A text object, describing a range of text around the caret.
+/// Conceptually similar to text objects in vim (`iw`, `a(`)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct TextObject {
+    pub(crate) kind: TextObjectKind,
+    /// If set, only the inside of the object is included (`i`), rather than including its surroundings (`a`)
+    pub(crate) inner: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum TextObjectKind {
+    /// A word, as defined by [crate::word_boundary]
+    Word,
+    /// A sequence of non-whitespace characters
+    BigWord,
+    /// Text between two of the given quote characters, within a single line
+    Quoted(char),
+    /// Text between a matching pair of brackets, respecting nesting
+    Bracketed { open: char, close: char },
+    /// Text between a matching pair of XML-like opening and closing tags, respecting nesting
+    Tag,
+    /// Lines up to the next blank line
+    Paragraph,
+    /// Text up to the next `.`, `!` or `?` that is followed by whitespace
+    Sentence,
+}
+
+impl TextObjectKind {
+    /// The bracket, quote or tag object whose delimiters are named by the character in surround commands,
+    /// like in vim-surround: `b`, `B`, `r` and `a` name brackets, `t` names tags,
+    /// and any other punctuation is treated like a quote.
+    pub(crate) fn for_surround_char(c: char) -> Option<TextObjectKind> {
+        let bracketed = |open, close| TextObjectKind::Bracketed { open, close };
+        Some(match c {
+            '(' | ')' | 'b' => bracketed('(', ')'),
+            '[' | ']' | 'r' => bracketed('[', ']'),
+            '{' | '}' | 'B' => bracketed('{', '}'),
+            '<' | '>' | 'a' => bracketed('<', '>'),
+            't' => TextObjectKind::Tag,
+            c if c.is_ascii_punctuation() => TextObjectKind::Quoted(c),
+            _ => return None,
+        })
+    }
+}
+
+impl TextObject {
+    pub(crate) fn is_linewise(&self) -> bool {
+        self.kind == TextObjectKind::Paragraph
+    }
+}
+
+/// The text added around a range by a surround operation, like a pair of brackets or tags
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SurroundPair {
+    pub(crate) open: String,
+    pub(crate) close: String,
+}
+
+impl SurroundPair {
+    /// The pair typed as the given character, like in vim-surround:
+    /// Opening brackets add a space on their inside and closing brackets don't,
+    /// `b`, `B`, `r` and `a` are aliases for closing brackets,
+    /// and any other character is put on both sides.
+    pub(crate) fn for_char(c: char) -> SurroundPair {
+        let pair = |open: &str, close: &str| SurroundPair {
+            open: open.to_string(),
+            close: close.to_string(),
+        };
+        match c {
+            '(' => pair("( ", " )"),
+            '[' => pair("[ ", " ]"),
+            '{' => pair("{ ", " }"),
+            ')' | 'b' => pair("(", ")"),
+            ']' | 'r' => pair("[", "]"),
+            '}' | 'B' => pair("{", "}"),
+            '>' | 'a' => pair("<", ">"),
+            c => pair(&c.to_string(), &c.to_string()),
+        }
+    }
+
+    /// An opening and closing tag. The tag may include attributes, which are left out of the closing tag.
+    pub(crate) fn tag(tag: &str) -> SurroundPair {
+        let name = tag.split_whitespace().next().unwrap_or_default();
+        SurroundPair {
+            open: format!("<{tag}>"),
+            close: format!("</{name}>"),
+        }
+    }
+}
+
+/// How an operator treats the range between the start and the target of a motion.
+/// Equivalent to the distinction between linewise, inclusive and exclusive motions in vim.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum MotionKind {
+    /// The range ends right before the target of the motion
+    Exclusive,
+    /// The range includes the character at the target of the motion
+    Inclusive,
+    /// The range covers all lines between the start and the target of the motion
+    Linewise,
+}
+
+impl<'a> Motion<'a> {
+    pub(crate) fn kind(&self) -> MotionKind {
+        match self {
+            Motion::Up
+            | Motion::Down
+            | Motion::TopOfViewport
+            | Motion::MiddleOfViewport
+            | Motion::BottomOfViewport
+            | Motion::Line(_)
+            | Motion::FirstLine
+            | Motion::LastLine
+            | Motion::Percentage(_) => MotionKind::Linewise,
+            Motion::MatchingBracket => MotionKind::Inclusive,
+            // Like in vim, `f` and `t` include the character they stop at, while `F` and `T` don't.
+            Motion::FindInLine { search, .. } if search.backwards => MotionKind::Exclusive,
+            Motion::FindInLine { .. } => MotionKind::Inclusive,
+            Motion::Repeated(_, motion) => motion.kind(),
+            // Word boundaries and line ends lie in between characters,
+            // so motions towards them are exclusive.
+            Motion::Left
+            | Motion::Right
+            | Motion::StartOfLine
+            | Motion::EndOfLine
+            | Motion::NextWordBoundary(_)
+            | Motion::PrevWordBoundary(_)
+            | Motion::NextBigWordBoundary(_)
+            | Motion::PrevBigWordBoundary(_)
+            | Motion::NextSubwordBoundary(_)
+            | Motion::PrevSubwordBoundary(_)
+            | Motion::NextParagraph
+            | Motion::PrevParagraph
+            | Motion::NextSentence
+            | Motion::PrevSentence
+            | Motion::FindNext(_)
+            | Motion::FindPrev(_)
+            | Motion::Offset(_) => MotionKind::Exclusive,
+        }
+    }
+
+    /// Apply a count, as typed before a command, to this motion.
+    ///
+    /// Most motions simply get repeated, but some motions interpret counts themselves,
+    /// such as [Motion::LastLine], which will go to the line given by the count instead.
+    /// Repeating [Motion::MatchingBracket] would only jump back and forth,
+    /// so like in vim, it goes to the percentage of the buffer given by the count.
+    pub(crate) fn with_count(&'a self, count: Option<usize>) -> Motion<'a> {
+        match (self, count) {
+            (Motion::MatchingBracket, Some(count)) => Motion::Percentage(count),
+            (Motion::FirstLine | Motion::LastLine, Some(count)) => {
+                Motion::Line(count.saturating_sub(1))
+            },
+            (_, None | Some(1)) => *self,
+            (Motion::FindInLine { search, .. }, Some(count)) => {
+                Motion::FindInLine { search, count }
+            },
+            (_, Some(count)) => Motion::Repeated(count, self),
+        }
+    }
+}
+
+/// An operator, acting on a range of text defined by a [OperatorTarget].
+/// Conceptually similar to operators in vim (`d`, `y`, `gU`)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Operator {
+    Delete,
+    /// Delete the text, keeping the line structure intact for linewise targets
+    Change,
+    Yank,
+    IndentRight,
+    IndentLeft,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+    /// Indent lines based on the surrounding brackets
+    Reindent,
+    /// Rewrap paragraphs to fit within the text width
+    Format,
+    /// Join the lines into one, joining at least two lines.
+    /// The indentation of the joined lines is replaced by a single space, unless `keep_whitespace` is set.
+    Join {
+        keep_whitespace: bool,
+    },
+    /// Replace every character with the given one, keeping line breaks intact
+    ReplaceChars(char),
+}
+
+impl Operator {
+    /// Check if this operator always acts on full lines, regardless of its target
+    pub(crate) fn is_linewise(&self) -> bool {
+        matches!(
+            self,
+            Operator::IndentRight
+                | Operator::IndentLeft
+                | Operator::Reindent
+                | Operator::Format
+                | Operator::Join { .. }
+        )
+    }
+}
+
+/// The range an [Operator] acts on, relative to each caret
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum OperatorTarget<'a> {
+    /// The range between the caret and the target of the motion
+    Motion(Motion<'a>),
+    /// The given number of lines, starting at the line of the caret (i.e. `dd` or `3yy`)
+    Lines(usize),
+    /// The given number of grapheme clusters under and after the caret, within its line.
+    /// If the line ends before that, the target stops at the end of the line when `clamp` is set (i.e. `3~`),
+    /// and is empty otherwise (i.e. `3rx`).
+    Graphemes { count: usize, clamp: bool },
+    /// The given number of text objects around the caret (i.e. `daw` or `d2i(`)
+    TextObject(TextObject, usize),
+    /// The selection of the caret
+    Selection,
+    /// The full lines touched by the selection of the caret, as selected in visual line mode
+    SelectedLines,
 }
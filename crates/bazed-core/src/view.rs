@@ -1,6 +1,13 @@
+use std::ops::RangeInclusive;
+
 use uuid::Uuid;
 
-use crate::document::DocumentId;
+use crate::{buffer::Buffer, document::DocumentId, region::RegionId};
+
+/// Maximum number of positions kept in a [JumpList]
+const MAX_JUMPS: usize = 100;
+/// Number of lines kept between the primary caret and the edges of the viewport
+pub(crate) const SCROLL_OFF: usize = 3;
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, derive_more::Display, derive_more::Into)]
 pub struct ViewId(pub Uuid);
@@ -19,6 +26,8 @@ pub struct View {
     pub document_id: DocumentId,
     /// Viewport of this view
     pub vp: Viewport,
+    /// Positions this view jumped away from
+    pub(crate) jump_list: JumpList,
 }
 
 impl View {
@@ -26,7 +35,91 @@ impl View {
         Self {
             document_id,
             vp: viewport,
+            jump_list: JumpList::default(),
+        }
+    }
+
+    /// Remember the position of the primary caret before jumping away from it,
+    /// both in the jump list and as the mark `''`
+    pub(crate) fn record_jump(&mut self, buffer: &mut Buffer) {
+        buffer.set_mark('\'', buffer.primary_caret().head);
+        self.jump_list.record(self.document_id, buffer);
+    }
+}
+
+/// A position in the jump list, anchored in the buffer of its document such that it moves along with edits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Jump {
+    pub(crate) document_id: DocumentId,
+    pub(crate) anchor: RegionId,
+}
+
+/// Positions a view jumped away from, i.e. by searching or going to a mark,
+/// which `Ctrl-o` and `Ctrl-i` go back and forth through
+#[derive(Debug, Default)]
+pub(crate) struct JumpList {
+    /// Oldest jump first
+    jumps: Vec<Jump>,
+    /// Index of the jump that was gone back to most recently,
+    /// or the number of jumps if none was since the last jump was recorded
+    current: usize,
+}
+
+impl JumpList {
+    /// Add the position of the primary caret to the end of the list,
+    /// replacing any older position within the same line
+    pub(crate) fn record(&mut self, document_id: DocumentId, buffer: &mut Buffer) {
+        let text = buffer.head_rope();
+        let line = text.line_of_offset(buffer.primary_caret().head);
+        let same_line = |jump: &Jump| {
+            jump.document_id == document_id
+                && !matches!(
+                    buffer.anchor_offset(jump.anchor),
+                    Some(offset) if text.line_of_offset(offset) != line
+                )
+        };
+        let (replaced, kept): (Vec<_>, Vec<_>) = self.jumps.drain(..).partition(same_line);
+        self.jumps = kept;
+        for jump in replaced {
+            buffer.remove_anchor(jump.anchor);
+        }
+        if self.jumps.len() >= MAX_JUMPS {
+            let oldest = self.jumps.remove(0);
+            if oldest.document_id == document_id {
+                buffer.remove_anchor(oldest.anchor);
+            }
+        }
+        let anchor = buffer.add_anchor(buffer.primary_caret().head);
+        self.jumps.push(Jump {
+            document_id,
+            anchor,
+        });
+        self.current = self.jumps.len();
+    }
+
+    /// Go back by the given number of jumps, returning the jump to go to.
+    ///
+    /// When going back from the end of the list, the position of the primary caret is added to the list first,
+    /// such that [Self::forward] can return to it.
+    pub(crate) fn back(
+        &mut self,
+        document_id: DocumentId,
+        buffer: &mut Buffer,
+        count: usize,
+    ) -> Option<Jump> {
+        if self.current == self.jumps.len() {
+            self.record(document_id, buffer);
+            self.current -= 1;
         }
+        self.current = self.current.checked_sub(count)?;
+        Some(self.jumps[self.current])
+    }
+
+    /// Go forward by the given number of jumps, returning the jump to go to
+    pub(crate) fn forward(&mut self, count: usize) -> Option<Jump> {
+        let jump = *self.jumps.get(self.current + count)?;
+        self.current += count;
+        Some(jump)
     }
 }
 
@@ -62,23 +155,87 @@ impl Viewport {
     /// attempt to keep it a minimum of `scroll_off` lines from the viewport edges
     /// as long as we're not at the start of the file
     pub fn with_line_in_view(&self, line_nr: usize, scroll_off: usize) -> Self {
+        let scroll_off = self.effective_scroll_off(scroll_off);
         let mut vp = *self;
         vp.first_line = if line_nr < vp.first_line + scroll_off {
             line_nr.saturating_sub(scroll_off)
         } else if line_nr > vp.last_line().saturating_sub(scroll_off) {
             let new_last_line = line_nr + scroll_off;
-            new_last_line.saturating_sub(vp.height - 1)
+            new_last_line.saturating_sub(vp.height.saturating_sub(1))
         } else {
             vp.first_line
         };
         vp
     }
+
+    /// Lines the caret can be on without [Self::with_line_in_view] having to move the viewport
+    pub fn caret_lines(&self, scroll_off: usize) -> RangeInclusive<usize> {
+        let scroll_off = self.effective_scroll_off(scroll_off);
+        let first = if self.first_line == 0 {
+            0
+        } else {
+            self.first_line + scroll_off
+        };
+        first..=self.last_line().saturating_sub(scroll_off).max(first)
+    }
+
+    /// Scroll down by the given number of lines, or up if negative.
+    ///
+    /// The viewport never scrolls further down than it could while keeping the `last_line` of the text
+    /// `scroll_off` lines away from its top.
+    pub fn scrolled_by(&self, line_delta: isize, last_line: usize, scroll_off: usize) -> Self {
+        let max_first_line = last_line.saturating_sub(self.effective_scroll_off(scroll_off));
+        let first_line = self.first_line.saturating_add_signed(line_delta);
+        Self {
+            first_line: first_line.min(max_first_line.max(self.first_line)),
+            ..*self
+        }
+    }
+
+    /// Move the viewport such that the given line is shown at the given position,
+    /// keeping `scroll_off` lines to the top or bottom edge
+    pub fn with_line_at(
+        &self,
+        line_nr: usize,
+        position: ScrollPosition,
+        scroll_off: usize,
+    ) -> Self {
+        let scroll_off = self.effective_scroll_off(scroll_off);
+        let first_line = match position {
+            ScrollPosition::Top => line_nr.saturating_sub(scroll_off),
+            ScrollPosition::Center => line_nr.saturating_sub(self.height / 2),
+            ScrollPosition::Bottom => (line_nr + scroll_off + 1).saturating_sub(self.height.max(1)),
+        };
+        Self {
+            first_line,
+            ..*self
+        }
+    }
+
+    /// The scroll-off actually kept, which is smaller than the requested one in viewports
+    /// too low to keep it on both edges
+    fn effective_scroll_off(&self, scroll_off: usize) -> usize {
+        scroll_off.min(self.height.saturating_sub(1) / 2)
+    }
+}
+
+/// Where [Viewport::with_line_at] shows a line, like vim's `zt`, `zz` and `zb`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScrollPosition {
+    Top,
+    Center,
+    Bottom,
 }
 
 #[cfg(test)]
 mod test {
-    use super::Viewport;
-    use crate::test_util;
+    use super::{JumpList, ScrollPosition, Viewport};
+    use crate::{
+        buffer::Buffer,
+        document::DocumentId,
+        test_util,
+        user_buffer_op::{BufferOp, Motion},
+    };
 
     #[test]
     fn test_scroll_line_into_view() {
@@ -96,4 +253,72 @@ mod test {
             Viewport::new(2, 10).with_line_in_view(12, 2)
         );
     }
+
+    #[test]
+    fn test_scroll_viewport() {
+        test_util::setup_test();
+        assert_eq!(0..=6, Viewport::new(0, 10).caret_lines(3));
+        assert_eq!(8..=11, Viewport::new(5, 10).caret_lines(3));
+        // Viewports too low to keep the whole scroll-off on both edges keep as much as they can
+        assert_eq!(6..=7, Viewport::new(5, 4).caret_lines(3));
+        assert_eq!(
+            Viewport::new(8, 10),
+            Viewport::new(5, 10).scrolled_by(3, 50, 3)
+        );
+        assert_eq!(
+            Viewport::new(0, 10),
+            Viewport::new(5, 10).scrolled_by(-8, 50, 3)
+        );
+        assert_eq!(
+            Viewport::new(47, 10),
+            Viewport::new(45, 10).scrolled_by(5, 50, 3)
+        );
+        let vp = Viewport::new(0, 10);
+        assert_eq!(
+            Viewport::new(17, 10),
+            vp.with_line_at(20, ScrollPosition::Top, 3)
+        );
+        assert_eq!(
+            Viewport::new(15, 10),
+            vp.with_line_at(20, ScrollPosition::Center, 3)
+        );
+        assert_eq!(
+            Viewport::new(14, 10),
+            vp.with_line_at(20, ScrollPosition::Bottom, 3)
+        );
+        assert_eq!(
+            Viewport::new(0, 10),
+            vp.with_line_at(2, ScrollPosition::Bottom, 3)
+        );
+    }
+
+    #[test]
+    fn test_jump_list() {
+        test_util::setup_test();
+        let vp = Viewport::new_ginormeous();
+        let document_id = DocumentId::gen();
+        let mut b = Buffer::new_from_string("a\nbc\nd\n".to_string());
+        let mut jumps = JumpList::default();
+        for offset in [0, 2, 3, 5] {
+            b.move_carets(&vp, Motion::Offset(offset));
+            jumps.record(document_id, &mut b);
+        }
+        let offset = |b: &Buffer, jump: Option<super::Jump>| b.anchor_offset(jump?.anchor);
+        b.move_carets(&vp, Motion::Offset(7));
+        let jump = jumps.back(document_id, &mut b, 1);
+        assert_eq!(Some(5), offset(&b, jump));
+        let jump = jumps.back(document_id, &mut b, 1);
+        assert_eq!(Some(3), offset(&b, jump));
+        let jump = jumps.back(document_id, &mut b, 1);
+        assert_eq!(Some(0), offset(&b, jump));
+        assert_eq!(None, jumps.back(document_id, &mut b, 1));
+
+        b.move_carets(&vp, Motion::Offset(0));
+        b.apply_buffer_op(&vp, BufferOp::Insert("xy".to_string()));
+        let jump = jumps.forward(2);
+        assert_eq!(Some(7), offset(&b, jump));
+        let jump = jumps.forward(1);
+        assert_eq!(Some(9), offset(&b, jump));
+        assert_eq!(None, jumps.forward(1));
+    }
 }
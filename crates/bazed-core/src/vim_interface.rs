@@ -1,29 +1,49 @@
 use std::{collections::HashMap, sync::Arc};
 
 use bazed_input_mapper::{
-    input_event::{Key, KeyInput, Modifiers},
+    input_event::{format_key_sequence, parse_key_sequence, Key, KeyInput, Modifiers},
     key_combo::{Combo, KeySpec},
     keymap::{Keymap, KeymapNode},
     InputMapper, KeymapId,
 };
 
 use crate::{
-    buffer::Buffer,
-    user_buffer_op::{BufferOp, Motion},
-    view::View,
+    buffer::{BlockSplit, Buffer, CaretSnapshot},
+    ex_command::{self, CommandContext, CommandRegistry, EditorAction},
+    options::Options,
+    register::{RegisterContent, RegisterName, Registers},
+    search::Search,
+    user_buffer_op::{
+        BufferOp, CharSearch, Motion, Operator, OperatorTarget, SurroundPair, TextObject,
+        TextObjectKind, UndoStep,
+    },
+    view::{ScrollPosition, View, SCROLL_OFF},
     word_boundary::WordBoundaryType,
 };
 
+/// Maximum number of macros that may be played within each other, to stop infinitely recursive macros
+const MAX_MACRO_DEPTH: usize = 100;
+
 type MappedFn =
-    Arc<Box<dyn Fn(&View, &mut Buffer, &mut VimInterface, KeyInput) + Send + Sync + 'static>>;
+    Arc<Box<dyn Fn(&mut View, &mut Buffer, &mut VimInterface, KeyInput) + Send + Sync + 'static>>;
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, derive_more::Display)]
-pub enum VimMode {
+pub(crate) enum VimMode {
     #[default]
     Normal,
     Insert,
     Visual,
+    /// Visual mode selecting whole lines, entered with `V`
+    VisualLine,
+    /// Visual mode selecting a rectangle, entered with `Ctrl-v`
+    VisualBlock,
     Replace,
+    /// Typing a search pattern after `/` or `?`
+    Search,
+    /// Typing an ex command after `:`
+    Command,
+    /// Typing the name of a tag to surround text with, after `t` or `<` in a surround command
+    SurroundTag,
 }
 
 impl VimMode {
@@ -32,7 +52,12 @@ impl VimMode {
             VimMode::Normal => "vim/normal",
             VimMode::Insert => "vim/insert",
             VimMode::Visual => "vim/visual",
+            VimMode::VisualLine => "vim/visual-line",
+            VimMode::VisualBlock => "vim/visual-block",
             VimMode::Replace => "vim/replace",
+            VimMode::Search => "vim/search",
+            VimMode::Command => "vim/command",
+            VimMode::SurroundTag => "vim/surround-tag",
         };
         KeymapId(s.to_string())
     }
@@ -41,15 +66,171 @@ impl VimMode {
         match self {
             VimMode::Normal => normal_mode_keymap(),
             VimMode::Insert => insert_mode_keymap(),
-            VimMode::Visual => visual_mode_keymap(),
+            VimMode::Visual | VimMode::VisualLine => visual_mode_keymap(),
+            VimMode::VisualBlock => visual_block_mode_keymap(),
             VimMode::Replace => replace_mode_keymap(),
+            VimMode::Search => search_mode_keymap(),
+            VimMode::Command => command_mode_keymap(),
+            VimMode::SurroundTag => surround_tag_mode_keymap(),
+        }
+    }
+
+    /// Check if this is one of the visual modes
+    pub(crate) fn is_visual(&self) -> bool {
+        matches!(
+            self,
+            VimMode::Visual | VimMode::VisualLine | VimMode::VisualBlock
+        )
+    }
+}
+
+/// The target of an operator as typed by the user, before a count has been applied to it
+#[derive(Debug, Clone)]
+enum TargetSpec {
+    Motion(Motion<'static>),
+    /// An in-line search, done by `f`, `t`, `F`, `T`, `;` and `,`
+    FindInLine(CharSearch),
+    /// The grapheme clusters starting at the one under the caret, one unless a count is given.
+    /// See [OperatorTarget::Graphemes] for `clamp`.
+    Graphemes {
+        clamp: bool,
+    },
+    /// The lines starting at the caret, one line unless a count is given
+    Lines,
+    TextObject(TextObject),
+    Selection,
+}
+
+/// A change to the buffer that can be repeated with `.`
+#[derive(Debug, Clone)]
+struct RecordedChange {
+    /// The operator that made the change, together with its target and count, if any
+    operation: Option<(Operator, TargetSpec, Option<usize>)>,
+    /// Inputs typed in insert mode as part of the change, up to and including the one that ended insert mode
+    inserted: Vec<KeyInput>,
+    /// Whether the carets moved past the changed characters afterwards, as they do for `~`
+    advanced: bool,
+}
+
+/// A change to the selections made with a pattern typed into the search prompt, like in kakoune
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegexSelectionOp {
+    /// Select the matches within the selections, done by `s`
+    Select,
+    /// Split the selections at the matches, done by `Alt-S`
+    Split,
+    /// Keep the selections that contain a match, or drop them if `keep` is false, done by `Alt-k` and `Alt-K`
+    Filter { keep: bool },
+}
+
+impl RegexSelectionOp {
+    fn buffer_op(self, regex: &hotsauce::Regex) -> BufferOp<'_> {
+        match self {
+            RegexSelectionOp::Select => BufferOp::SelectMatches(regex),
+            RegexSelectionOp::Split => BufferOp::SplitSelections(regex),
+            RegexSelectionOp::Filter { keep } => BufferOp::FilterSelections { regex, keep },
+        }
+    }
+
+    /// Text shown in front of the pattern while it is being typed
+    fn prompt(self) -> &'static str {
+        match self {
+            RegexSelectionOp::Select => "select:",
+            RegexSelectionOp::Split => "split:",
+            RegexSelectionOp::Filter { keep: true } => "keep matching:",
+            RegexSelectionOp::Filter { keep: false } => "keep not matching:",
         }
     }
 }
 
+/// State of the search prompt, while typing a search pattern
+struct SearchPrompt {
+    pattern: String,
+    backwards: bool,
+    /// Set if the pattern is used to change the selections rather than to search
+    selection_op: Option<RegexSelectionOp>,
+    /// Count given to the search, applied once the search is confirmed
+    count: Option<usize>,
+    /// Carets from before the search started, to which the search is relative
+    origin: CaretSnapshot,
+    /// The search for the current pattern, if it is valid, used to preview its matches while typing
+    preview: Option<Search>,
+}
+
+/// What to do with a surrounding pair once it has been typed
+#[derive(Debug, Clone)]
+enum SurroundAction {
+    /// Surround the target with the pair, done by `ys` and by `S` in visual mode
+    Add(TargetSpec, Option<usize>),
+    /// Replace the delimiters of the object around the carets with the pair, done by `cs`
+    Change(TextObjectKind),
+}
+
+/// State of the prompt for the name of a tag to surround text with
+struct TagPrompt {
+    tag: String,
+    action: SurroundAction,
+    /// The mode the prompt was opened from, i.e. visual mode to surround the selections
+    mode: VimMode,
+}
+
+/// State of the command line, while typing an ex command
+struct CommandPrompt {
+    line: String,
+    /// Candidates of the current completion, cycled through by repeatedly pressing tab,
+    /// together with the index of the candidate that is currently shown
+    completion: Option<(Vec<String>, usize)>,
+}
+
 pub(crate) struct VimInterface {
     pub(crate) input_mapper: InputMapper<MappedFn>,
     pub(crate) mode: VimMode,
+    /// Set by `$` in visual block mode, such that the block extends to the end of every line
+    pub(crate) block_to_line_end: bool,
+    pub(crate) registers: Registers,
+    /// Register selected via `"{register}` for use by the next command.
+    pending_register: Option<RegisterName>,
+    /// Set when the current input selected a register, such that it is kept for the next input.
+    register_selected: bool,
+    /// Count typed before a command, i.e. the `3` in `3w` or `3d2w`
+    count: Option<usize>,
+    /// Count typed after an operator, before its motion, i.e. the `2` in `3d2w`
+    motion_count: Option<usize>,
+    /// Set when the previous input was a digit of a count, such that further digits continue it
+    typing_count: bool,
+    /// The most recent complete change, which is repeated by `.`
+    last_change: Option<RecordedChange>,
+    /// The change that is currently being made, i.e. while typing in insert mode
+    recording_change: Option<RecordedChange>,
+    /// Set while a change is being repeated, such that it doesn't get recorded again
+    repeating_change: bool,
+    /// Register and inputs of the macro currently being recorded via `q{register}`
+    recording_macro: Option<(RegisterName, Vec<KeyInput>)>,
+    /// Register of the most recently played macro, which `@@` plays again
+    last_macro: Option<RegisterName>,
+    /// Number of macros currently being played within each other
+    macro_depth: usize,
+    /// Set by a command that could not be executed, i.e. a motion that couldn't move any caret
+    command_failed: bool,
+    /// The search pattern currently being typed
+    search_prompt: Option<SearchPrompt>,
+    /// The most recent search, repeated by `n` and `N`
+    last_search: Option<Search>,
+    /// The most recent in-line search, repeated by `;` and `,`
+    last_char_search: Option<CharSearch>,
+    /// Set by `:nohlsearch` to stop highlighting the most recent search, until the next search
+    search_highlight_hidden: bool,
+    /// The ex command currently being typed
+    command_prompt: Option<CommandPrompt>,
+    /// The tag currently being typed for a surround command
+    tag_prompt: Option<TagPrompt>,
+    /// Commands that can be run from the command line
+    pub(crate) commands: CommandRegistry,
+    pub(crate) options: Options,
+    /// Message shown in the command line until the next input, i.e. the error of a failed command
+    message: Option<String>,
+    /// Actions requested by commands, to be carried out by the application
+    actions: Vec<EditorAction>,
 }
 
 impl VimInterface {
@@ -76,24 +257,898 @@ impl VimInterface {
             VimMode::Visual.keymap_id(),
             VimMode::Visual.corresponding_keymap(),
         );
+        input_mapper.register_keymap(
+            VimMode::VisualLine.keymap_id(),
+            VimMode::VisualLine.corresponding_keymap(),
+        );
+        input_mapper.register_keymap(
+            VimMode::VisualBlock.keymap_id(),
+            VimMode::VisualBlock.corresponding_keymap(),
+        );
         input_mapper.register_keymap(
             VimMode::Replace.keymap_id(),
             VimMode::Replace.corresponding_keymap(),
         );
+        input_mapper.register_keymap(
+            VimMode::Search.keymap_id(),
+            VimMode::Search.corresponding_keymap(),
+        );
+        input_mapper.register_keymap(
+            VimMode::Command.keymap_id(),
+            VimMode::Command.corresponding_keymap(),
+        );
+        input_mapper.register_keymap(
+            VimMode::SurroundTag.keymap_id(),
+            VimMode::SurroundTag.corresponding_keymap(),
+        );
         _ = input_mapper.push_keymap(VimMode::Normal.keymap_id());
         Self {
             input_mapper,
             mode: VimMode::Normal,
+            block_to_line_end: false,
+            registers: Registers::default(),
+            pending_register: None,
+            register_selected: false,
+            count: None,
+            motion_count: None,
+            typing_count: false,
+            last_change: None,
+            recording_change: None,
+            repeating_change: false,
+            recording_macro: None,
+            last_macro: None,
+            macro_depth: 0,
+            command_failed: false,
+            search_prompt: None,
+            last_search: None,
+            last_char_search: None,
+            search_highlight_hidden: false,
+            command_prompt: None,
+            tag_prompt: None,
+            commands: CommandRegistry::with_builtins(),
+            options: Options::default(),
+            message: None,
+            actions: Vec::new(),
         }
     }
 
+    /// Handle a single key input.
+    ///
+    /// Returns `false` if the input could not be handled,
+    /// either because it isn't mapped to anything or because the command it triggered failed.
     #[tracing::instrument(skip_all, fields(mode = %self.mode))]
-    pub(crate) fn on_input(&mut self, view: &View, buffer: &mut Buffer, input: KeyInput) {
+    pub(crate) fn on_input(
+        &mut self,
+        view: &mut View,
+        buffer: &mut Buffer,
+        input: KeyInput,
+    ) -> bool {
+        self.message = None;
+        if self.recording_macro.is_some()
+            && self.mode == VimMode::Normal
+            && !self.input_mapper.has_buffered_inputs()
+            && key("q").matches(&input)
+        {
+            self.finish_macro_recording();
+            return true;
+        }
+        // Inputs that are replayed, rather than typed by the user, are not part of the macro
+        if self.macro_depth == 0 && !self.repeating_change {
+            if let Some((_, inputs)) = &mut self.recording_macro {
+                inputs.push(input.clone());
+            }
+        }
+        if self.mode == VimMode::Insert {
+            if let Some(change) = &mut self.recording_change {
+                change.inserted.push(input.clone());
+            }
+        }
+        if self.push_count_digit(&input) {
+            return true;
+        }
         match self.input_mapper.on_input(input.clone()) {
-            Some(KeymapNode::Leaf(_, f)) => f.clone()(view, buffer, self, input),
-            Some(KeymapNode::Submap(x, _)) => tracing::info!("In submap {x}"),
-            None => tracing::info!("No mapping for {input}"),
+            Some(KeymapNode::Leaf(_, f)) => {
+                self.register_selected = false;
+                self.command_failed = false;
+                // Whatever ends visual mode, the marks `'<` and `'>` are left on the selection
+                if self.mode.is_visual() {
+                    let selection = buffer.primary_caret().range();
+                    buffer.set_mark('<', selection.start);
+                    buffer.set_mark('>', selection.end);
+                }
+                f.clone()(view, buffer, self, input);
+                // A selected register only applies to the command directly following it,
+                // as does a count typed in front of the register (i.e. the `2` in `2"ap`)
+                if !self.register_selected {
+                    self.pending_register = None;
+                    self.count = None;
+                    self.motion_count = None;
+                }
+                !self.command_failed
+            },
+            Some(KeymapNode::Submap(x, _)) => {
+                tracing::info!("In submap {x}");
+                true
+            },
+            None => {
+                tracing::info!("No mapping for {input}");
+                self.pending_register = None;
+                self.count = None;
+                self.motion_count = None;
+                false
+            },
+        }
+    }
+
+    /// Interpret the input as a digit of a count, if possible.
+    /// Returns `true` if the input was consumed as part of a count.
+    ///
+    /// Digits are only part of a count if they don't have any mapping of their own,
+    /// with the exception of digits directly following another digit of the count, like the `0` in `10j`.
+    /// That way, the `1` in `d2f1` is the character searched for rather than part of the count.
+    /// Counts typed inside of a submap (i.e. after an operator) are collected separately,
+    /// and multiplied with the count typed before.
+    fn push_count_digit(&mut self, input: &KeyInput) -> bool {
+        let continues_count = std::mem::take(&mut self.typing_count);
+        if !input.modifiers.is_empty() {
+            return false;
+        }
+        let mut chars = input.key.as_str().chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            return false;
+        };
+        let Some(digit) = c.to_digit(10) else {
+            return false;
+        };
+        let digit = digit as usize;
+        let is_mapped = self.input_mapper.peek(input).is_some();
+        let count = if self.input_mapper.has_buffered_inputs() {
+            &mut self.motion_count
+        } else {
+            &mut self.count
+        };
+        match count {
+            Some(count) if continues_count || !is_mapped => {
+                *count = count.saturating_mul(10).saturating_add(digit)
+            },
+            None if digit != 0 && !is_mapped => *count = Some(digit),
+            _ => return false,
+        }
+        self.typing_count = true;
+        true
+    }
+
+    /// The count that has been typed so far, if any
+    pub(crate) fn pending_count(&self) -> Option<usize> {
+        match (self.count, self.motion_count) {
+            (None, None) => None,
+            (count, motion_count) => {
+                Some(count.unwrap_or(1).saturating_mul(motion_count.unwrap_or(1)))
+            },
+        }
+    }
+
+    /// Take the count for the current command, resetting it
+    fn take_count(&mut self) -> Option<usize> {
+        let count = self.pending_count();
+        self.count = None;
+        self.motion_count = None;
+        count
+    }
+
+    /// Select the register the next command should use
+    fn select_register(&mut self, register: RegisterName) {
+        self.pending_register = Some(register);
+        self.register_selected = true;
+    }
+
+    /// Store text that was copied by the user in the selected register
+    fn record_yank(&mut self, content: RegisterContent) {
+        let register = self.pending_register.take();
+        self.registers.record_yank(register, content);
+    }
+
+    /// Store text that was deleted by the user in the selected register
+    fn record_delete(&mut self, content: RegisterContent) {
+        let register = self.pending_register.take();
+        self.registers.record_delete(register, content);
+    }
+
+    /// Apply an operator at every caret, storing the affected text in the selected register if necessary.
+    /// In visual block mode, the block is split into one selection per line for the operator to act on.
+    ///
+    /// Any operator other than yank is recorded as a change, such that it can be repeated with `.`.
+    /// Fails without changing anything if a caret lacks the graphemes an unclamped [TargetSpec::Graphemes] target needs.
+    fn apply_operator(
+        &mut self,
+        view: &View,
+        buffer: &mut Buffer,
+        operator: Operator,
+        target: TargetSpec,
+        count: Option<usize>,
+    ) {
+        if let TargetSpec::Graphemes { clamp: false } = target {
+            if !buffer.carets_have_graphemes(count.unwrap_or(1)) {
+                self.command_failed = true;
+                return;
+            }
+        }
+        let linewise_selection = self.mode == VimMode::VisualLine;
+        if let (TargetSpec::Selection, VimMode::VisualBlock) = (&target, self.mode) {
+            buffer.split_block(self.block_to_line_end, BlockSplit::Selections);
+        }
+        if operator != Operator::Yank {
+            let (target, count) = match target {
+                TargetSpec::Selection => {
+                    selection_repeat_target(buffer, linewise_selection || operator.is_linewise())
+                },
+                ref target => (target.clone(), count),
+            };
+            self.start_change(Some((operator, target, count)));
+        }
+
+        let operator_target = operator_target(&target, count, linewise_selection);
+        let content = buffer.apply_operator(&view.vp, operator, operator_target);
+        // Like in vim, replacing characters leaves the caret on the last replaced one
+        if let (Operator::ReplaceChars(_), TargetSpec::Graphemes { .. }) = (operator, &target) {
+            let motion = Motion::Right.with_count(Some(count.unwrap_or(1) - 1));
+            buffer.apply_buffer_op(&view.vp, BufferOp::Move(motion));
+        }
+        match operator {
+            Operator::Delete | Operator::Change => self.record_delete(content),
+            Operator::Yank => self.record_yank(content),
+            _ => {},
+        }
+        if operator == Operator::Change {
+            self.switch_mode(VimMode::Insert);
+        } else {
+            // Like in vim, the caret ends up in the top left corner of a block
+            if self.mode == VimMode::VisualBlock {
+                buffer.collapse_carets_into_primary();
+            }
+            if self.mode.is_visual() {
+                self.switch_mode(VimMode::Normal);
+            }
+            self.finish_change();
+        }
+    }
+
+    /// Add the amount to the number under or after every caret, as done by `Ctrl-a` and `Ctrl-x`,
+    /// failing if there is no number to change
+    fn add_to_numbers(&mut self, view: &View, buffer: &mut Buffer, amount: i64) {
+        let before = buffer.head_revision();
+        buffer.apply_buffer_op(&view.vp, BufferOp::AddToNumbers(amount));
+        if buffer.head_revision() == before {
+            self.command_failed = true;
+        }
+    }
+
+    /// Toggle the case of the characters under the carets and move past them, as done by `~`.
+    /// Like in vim, a count that reaches past the end of the line stops there.
+    fn toggle_case_forward(&mut self, view: &View, buffer: &mut Buffer, count: Option<usize>) {
+        let target = TargetSpec::Graphemes { clamp: true };
+        self.apply_operator(view, buffer, Operator::ToggleCase, target, count);
+        buffer.advance_carets_in_line(count.unwrap_or(1));
+        if let (false, Some(change)) = (self.repeating_change, &mut self.last_change) {
+            change.advanced = true;
+        }
+    }
+
+    /// Enter the given visual mode, or go back to normal mode if it is already active,
+    /// as done by `v`, `V` and `Ctrl-v`
+    fn toggle_visual_mode(&mut self, buffer: &mut Buffer, mode: VimMode) {
+        if self.mode == mode {
+            buffer.collapse_selections();
+            self.switch_mode(VimMode::Normal);
+        } else {
+            self.block_to_line_end = false;
+            self.switch_mode(mode);
+        }
+    }
+
+    /// Split the block selected in visual block mode into one caret per line,
+    /// and enter insert mode at all of them, as done by `I` and `A`
+    fn insert_at_block(&mut self, buffer: &mut Buffer, split: BlockSplit) {
+        buffer.split_block(self.block_to_line_end, split);
+        self.start_insert();
+    }
+
+    /// Enter insert mode, recording the text typed as a change
+    fn start_insert(&mut self) {
+        self.start_change(None);
+        self.switch_mode(VimMode::Insert);
+    }
+
+    /// Start recording a change, unless the change is being made by repeating a previous one
+    fn start_change(&mut self, operation: Option<(Operator, TargetSpec, Option<usize>)>) {
+        if !self.repeating_change {
+            self.recording_change = Some(RecordedChange {
+                operation,
+                inserted: Vec::new(),
+                advanced: false,
+            });
+        }
+    }
+
+    /// Finish recording the current change, making it the one repeated by `.`
+    fn finish_change(&mut self) {
+        if let Some(change) = self.recording_change.take() {
+            self.last_change = Some(change);
+        }
+    }
+
+    /// Repeat the most recent change at every caret, as a single undo group.
+    ///
+    /// A count given to `.` replaces the count of the original change.
+    /// If the change was only inserting text, the text is inserted count times instead.
+    fn repeat_last_change(&mut self, view: &mut View, buffer: &mut Buffer) {
+        let Some(change) = self.last_change.clone() else { return };
+        let count = self.take_count();
+        self.repeating_change = true;
+        buffer.begin_undo_group();
+
+        let repetitions = match change.operation {
+            Some((Operator::ToggleCase, _, original_count)) if change.advanced => {
+                self.toggle_case_forward(view, buffer, count.or(original_count));
+                1
+            },
+            Some((operator, target, original_count)) => {
+                self.apply_operator(view, buffer, operator, target, count.or(original_count));
+                1
+            },
+            None => {
+                self.start_insert();
+                count.unwrap_or(1)
+            },
+        };
+        // The last input ended insert mode, so it must only be replayed once
+        if let Some((leave_insert, typed)) = change.inserted.split_last() {
+            for _ in 0..repetitions {
+                for input in typed {
+                    self.on_input(view, buffer, input.clone());
+                }
+            }
+            self.on_input(view, buffer, leave_insert.clone());
+        }
+
+        buffer.end_undo_group();
+        self.repeating_change = false;
+    }
+
+    /// Get the content of the selected register, or the unnamed register if none was selected
+    fn take_register_content(&mut self) -> Option<RegisterContent> {
+        let register = self.pending_register.take();
+        self.registers
+            .get(register.unwrap_or(RegisterName::Unnamed))
+            .cloned()
+    }
+
+    /// Get the content of the selected register like [Self::take_register_content],
+    /// repeating each slice as often as the current count specifies
+    fn take_register_content_counted(&mut self) -> Option<RegisterContent> {
+        let count = self.take_count().unwrap_or(1);
+        let content = self.take_register_content()?;
+        Some(RegisterContent::new(
+            content.slices.iter().map(|x| x.repeat(count)).collect(),
+            content.linewise,
+        ))
+    }
+
+    /// Start recording all inputs into the given register, until `q` is pressed in normal mode
+    fn start_macro_recording(&mut self, register: RegisterName) {
+        self.recording_macro = Some((register, Vec::new()));
+    }
+
+    /// Stop recording a macro, storing the recorded inputs as text in its register
+    fn finish_macro_recording(&mut self) {
+        if let Some((register, inputs)) = self.recording_macro.take() {
+            let content = RegisterContent::new(vec![format_key_sequence(&inputs)], false);
+            self.registers.record_macro(register, content);
+        }
+    }
+
+    /// Play the inputs stored in a register as if they were typed, as often as the count specifies.
+    ///
+    /// Playback stops at the first input that could not be handled.
+    /// All edits made by the macro form a single undo group.
+    fn play_macro(&mut self, view: &mut View, buffer: &mut Buffer, register: RegisterName) {
+        let count = self.take_count().unwrap_or(1);
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            tracing::warn!("Stopping macro playback, maximum depth reached");
+            self.command_failed = true;
+            return;
+        }
+        let Some(content) = self.registers.get(register) else {
+            self.command_failed = true;
+            return;
+        };
+        let inputs = match parse_key_sequence(&content.slices.concat()) {
+            Ok(inputs) => inputs,
+            Err(err) => {
+                tracing::warn!("Register does not contain a valid macro: {err}");
+                self.command_failed = true;
+                return;
+            },
+        };
+        self.last_macro = Some(register);
+
+        self.macro_depth += 1;
+        buffer.begin_undo_group();
+        let completed = (0..count).all(|_| {
+            inputs
+                .iter()
+                .all(|input| self.on_input(view, buffer, input.clone()))
+        });
+        buffer.end_undo_group();
+        self.macro_depth -= 1;
+        // Make sure that macros playing this one stop as well
+        self.command_failed = !completed;
+    }
+
+    /// Open the search prompt, searching towards the start of the buffer if `backwards` is set
+    fn start_search(&mut self, buffer: &Buffer, backwards: bool) {
+        self.search_prompt = Some(SearchPrompt {
+            pattern: String::new(),
+            backwards,
+            selection_op: None,
+            count: self.take_count(),
+            origin: buffer.caret_snapshot(),
+            preview: None,
+        });
+        self.switch_mode(VimMode::Search);
+    }
+
+    /// Open the search prompt to type the pattern of a change to the selections.
+    /// A block selected in visual block mode is split into its lines first.
+    fn start_selection_prompt(&mut self, buffer: &mut Buffer, op: RegexSelectionOp) {
+        if self.mode == VimMode::VisualBlock {
+            buffer.split_block(self.block_to_line_end, BlockSplit::Selections);
+        }
+        self.search_prompt = Some(SearchPrompt {
+            pattern: String::new(),
+            backwards: false,
+            selection_op: Some(op),
+            count: None,
+            origin: buffer.caret_snapshot(),
+            preview: None,
+        });
+        self.switch_mode(VimMode::Search);
+    }
+
+    /// The mode to go back to once the search prompt is closed
+    fn mode_after_search(&self) -> VimMode {
+        match &self.search_prompt {
+            Some(prompt) if prompt.selection_op.is_some() => VimMode::Visual,
+            _ => VimMode::Normal,
+        }
+    }
+
+    /// Change the pattern in the search prompt, moving the carets to the first match of the new pattern
+    /// unless the pattern is used to change the selections
+    fn edit_search_pattern(
+        &mut self,
+        view: &View,
+        buffer: &mut Buffer,
+        f: impl FnOnce(&mut String),
+    ) {
+        let Some(prompt) = &mut self.search_prompt else { return };
+        f(&mut prompt.pattern);
+        if !self.options.incsearch {
+            return;
+        }
+        buffer.restore_carets(prompt.origin.clone());
+        let ignore_case = self.options.ignores_case(&prompt.pattern);
+        prompt.preview = Search::new(prompt.pattern.clone(), prompt.backwards, ignore_case);
+        if let (Some(search), None) = (&prompt.preview, prompt.selection_op) {
+            buffer.apply_buffer_op(&view.vp, BufferOp::Move(search.motion(false)));
+        }
+    }
+
+    /// Close the search prompt, searching for the typed pattern or changing the selections with it.
+    /// An empty pattern searches for the pattern of the previous search again.
+    fn confirm_search(&mut self, view: &mut View, buffer: &mut Buffer) {
+        self.switch_mode(self.mode_after_search());
+        let Some(prompt) = self.search_prompt.take() else { return };
+        buffer.restore_carets(prompt.origin);
+        let pattern = match self.last_search_pattern() {
+            Some(last) if prompt.pattern.is_empty() => last.to_string(),
+            _ => prompt.pattern,
+        };
+        let ignore_case = self.options.ignores_case(&pattern);
+        let Some(search) = Search::new(pattern, prompt.backwards, ignore_case) else {
+            self.command_failed = true;
+            return;
+        };
+        if let Some(op) = prompt.selection_op {
+            self.change_carets_failable(view, buffer, op.buffer_op(&search.regex));
+            self.set_last_search(search);
+            return;
+        }
+        let motion = search.motion(false);
+        view.record_jump(buffer);
+        self.move_carets_failable(view, buffer, motion.with_count(prompt.count));
+        self.set_last_search(search);
+    }
+
+    /// Close the search prompt, moving the carets back to where they were before the search
+    fn cancel_search(&mut self, buffer: &mut Buffer) {
+        self.switch_mode(self.mode_after_search());
+        if let Some(prompt) = self.search_prompt.take() {
+            buffer.restore_carets(prompt.origin);
+        }
+    }
+
+    /// Search for the next match of the most recent search,
+    /// in the opposite direction of the original search if `reverse` is set
+    fn repeat_search(&mut self, view: &mut View, buffer: &mut Buffer, reverse: bool) {
+        let count = self.take_count();
+        let Some(search) = self.last_search.take() else {
+            self.command_failed = true;
+            return;
+        };
+        let motion = search.motion(reverse);
+        view.record_jump(buffer);
+        self.move_carets_failable(view, buffer, motion.with_count(count));
+        self.set_last_search(search);
+    }
+
+    /// Search for the word under the primary caret, as a whole word
+    fn search_word_at_caret(&mut self, view: &mut View, buffer: &mut Buffer, backwards: bool) {
+        let search = buffer
+            .word_at_primary_caret()
+            .and_then(|word| Search::for_word(&word, backwards, self.options.ignorecase));
+        let Some(search) = search else {
+            self.command_failed = true;
+            return;
+        };
+        self.set_last_search(search);
+        self.repeat_search(view, buffer, false);
+    }
+
+    /// Move all carets with the given motion, failing the current command if no caret moved
+    fn move_carets_failable(&mut self, view: &View, buffer: &mut Buffer, motion: Motion) {
+        let heads_before = buffer.all_carets().map(|x| x.head);
+        buffer.apply_buffer_op(&view.vp, BufferOp::Move(motion));
+        if buffer.all_carets().map(|x| x.head) == heads_before {
+            self.command_failed = true;
+        }
+    }
+
+    /// Scroll the view down by the given number of lines, or up if negative,
+    /// moving the carets along by as many lines if `move_carets` is set, like `Ctrl-d` does in vim.
+    ///
+    /// Carets that would end up outside of the scroll-off are moved back within it,
+    /// such that the viewport doesn't scroll back to them afterwards.
+    fn scroll(
+        &mut self,
+        view: &mut View,
+        buffer: &mut Buffer,
+        line_delta: isize,
+        move_carets: bool,
+    ) {
+        let vp = view
+            .vp
+            .scrolled_by(line_delta, buffer.last_line(), SCROLL_OFF);
+        let caret_line = buffer.primary_caret_position().line;
+        let caret_lines = vp.caret_lines(SCROLL_OFF);
+        let target_line = if move_carets {
+            caret_line.saturating_add_signed(line_delta)
+        } else {
+            caret_line
+        };
+        let target_line = target_line
+            .clamp(*caret_lines.start(), *caret_lines.end())
+            .min(buffer.last_line());
+        if vp == view.vp && target_line == caret_line {
+            self.command_failed = true;
+            return;
+        }
+        view.vp = vp;
+        let line_delta = target_line as isize - caret_line as isize;
+        let direction = if line_delta < 0 {
+            &Motion::Up
+        } else {
+            &Motion::Down
+        };
+        let motion = Motion::Repeated(line_delta.unsigned_abs(), direction);
+        let op = if self.mode.is_visual() {
+            BufferOp::Selection(motion)
+        } else {
+            BufferOp::Move(motion)
+        };
+        buffer.apply_buffer_op(&view.vp, op);
+    }
+
+    /// Select occurrences of the selected text or the word under the caret,
+    /// entering visual mode to act on the selections
+    fn select_occurrences(&mut self, view: &View, buffer: &mut Buffer, op: BufferOp) {
+        if self.mode == VimMode::VisualBlock {
+            buffer.split_block(self.block_to_line_end, BlockSplit::Selections);
+        }
+        self.change_carets_failable(view, buffer, op);
+        if !self.command_failed && self.mode != VimMode::Visual {
+            self.switch_mode(VimMode::Visual);
+        }
+    }
+
+    /// Apply an operation that changes the carets, failing the current command if it didn't change anything
+    fn change_carets_failable(&mut self, view: &View, buffer: &mut Buffer, op: BufferOp) {
+        let before = buffer.caret_snapshot();
+        buffer.apply_buffer_op(&view.vp, op);
+        if buffer.caret_snapshot() == before {
+            self.command_failed = true;
+        }
+    }
+
+    /// The regex whose matches should be highlighted,
+    /// either the pattern currently being typed or the most recent search
+    pub(crate) fn highlighted_search(&self) -> Option<&hotsauce::Regex> {
+        match &self.search_prompt {
+            Some(prompt) => prompt.preview.as_ref(),
+            None if self.options.hlsearch && !self.search_highlight_hidden => {
+                self.last_search.as_ref()
+            },
+            None => None,
+        }
+        .map(|search| &search.regex)
+    }
+
+    /// Make a search the most recent one, which `n` and `N` repeat and whose matches are highlighted
+    pub(crate) fn set_last_search(&mut self, search: Search) {
+        self.last_search = Some(search);
+        self.search_highlight_hidden = false;
+    }
+
+    /// The pattern of the most recent search, if any
+    pub(crate) fn last_search_pattern(&self) -> Option<&str> {
+        self.last_search
+            .as_ref()
+            .map(|search| search.pattern.as_str())
+    }
+
+    /// Stop highlighting the matches of the most recent search, until the next search
+    pub(crate) fn hide_search_highlight(&mut self) {
+        self.search_highlight_hidden = true;
+    }
+
+    /// Open the command line.
+    /// When a count is given, the command line starts out with a range covering as many lines,
+    /// and when started in visual mode, with the range of the selected lines.
+    fn start_command(&mut self, buffer: &mut Buffer) {
+        let line = if self.mode.is_visual() {
+            buffer.collapse_selections();
+            "'<,'>".to_string()
+        } else {
+            match self.take_count() {
+                Some(1) => ".".to_string(),
+                Some(count) => format!(".,.+{}", count - 1),
+                None => String::new(),
+            }
+        };
+        self.command_prompt = Some(CommandPrompt {
+            line,
+            completion: None,
+        });
+        self.switch_mode(VimMode::Command);
+    }
+
+    /// Change the text in the command line, ending any completion
+    fn edit_command_line(&mut self, f: impl FnOnce(&mut String)) {
+        if let Some(prompt) = &mut self.command_prompt {
+            f(&mut prompt.line);
+            prompt.completion = None;
+        }
+    }
+
+    /// Complete the command line, or show the next candidate if a completion is in progress
+    fn complete_command_line(&mut self) {
+        let Some(prompt) = &mut self.command_prompt else { return };
+        let (candidates, index) = match prompt.completion.take() {
+            Some((candidates, index)) => {
+                let index = (index + 1) % candidates.len();
+                (candidates, index)
+            },
+            None => (self.commands.complete(&prompt.line), 0),
+        };
+        let Some(candidate) = candidates.get(index) else {
+            self.command_failed = true;
+            return;
+        };
+        prompt.line = candidate.clone();
+        prompt.completion = Some((candidates, index));
+    }
+
+    /// Close the command line and run the typed command, showing its error if it fails
+    fn execute_command_line(&mut self, view: &mut View, buffer: &mut Buffer) {
+        let Some(prompt) = self.command_prompt.take() else { return };
+        self.switch_mode(VimMode::Normal);
+        let mut ctx = CommandContext {
+            view,
+            buffer,
+            vim: self,
+        };
+        if let Err(err) = ex_command::execute(&mut ctx, &prompt.line) {
+            tracing::info!("Command {} failed: {err}", prompt.line);
+            self.message = Some(err.to_string());
+            self.command_failed = true;
+        }
+    }
+
+    /// Close the command line without running the command
+    fn cancel_command(&mut self) {
+        self.command_prompt = None;
+        self.switch_mode(VimMode::Normal);
+    }
+
+    /// Surround the target with the pair, or replace the delimiters of an object with it,
+    /// leaving visual mode. Fails if the text didn't change.
+    fn surround(
+        &mut self,
+        view: &View,
+        buffer: &mut Buffer,
+        action: SurroundAction,
+        pair: &SurroundPair,
+    ) {
+        let before = buffer.head_revision();
+        match action {
+            SurroundAction::Add(target, count) => {
+                if let (TargetSpec::Selection, VimMode::VisualBlock) = (&target, self.mode) {
+                    buffer.split_block(self.block_to_line_end, BlockSplit::Selections);
+                }
+                let linewise_selection = self.mode == VimMode::VisualLine;
+                let target = operator_target(&target, count, linewise_selection);
+                buffer.apply_buffer_op(&view.vp, BufferOp::Surround { target, pair });
+            },
+            SurroundAction::Change(object) => {
+                let op = BufferOp::ChangeSurround {
+                    object,
+                    pair: Some(pair),
+                };
+                buffer.apply_buffer_op(&view.vp, op);
+            },
+        }
+        if self.mode.is_visual() {
+            buffer.collapse_selections();
+            self.switch_mode(VimMode::Normal);
+        }
+        self.command_failed = buffer.head_revision() == before;
+    }
+
+    /// Delete the delimiters of the object around the carets, as done by `ds`
+    fn delete_surround(&mut self, view: &View, buffer: &mut Buffer, object: TextObjectKind) {
+        let before = buffer.head_revision();
+        let op = BufferOp::ChangeSurround { object, pair: None };
+        buffer.apply_buffer_op(&view.vp, op);
+        self.command_failed = buffer.head_revision() == before;
+    }
+
+    /// Open the prompt for the name of a tag to surround text with
+    fn start_tag_prompt(&mut self, action: SurroundAction) {
+        self.tag_prompt = Some(TagPrompt {
+            tag: String::new(),
+            action,
+            mode: self.mode,
+        });
+        self.switch_mode(VimMode::SurroundTag);
+    }
+
+    /// Close the tag prompt, surrounding the text with the typed tag unless `confirm` is false
+    fn close_tag_prompt(&mut self, view: &View, buffer: &mut Buffer, confirm: bool) {
+        let Some(prompt) = self.tag_prompt.take() else { return };
+        self.switch_mode(prompt.mode);
+        if confirm && !prompt.tag.is_empty() {
+            self.surround(view, buffer, prompt.action, &SurroundPair::tag(&prompt.tag));
+        }
+    }
+
+    /// Show a message in the command line, until the next input
+    pub(crate) fn show_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    /// Request an action from the application
+    pub(crate) fn push_action(&mut self, action: EditorAction) {
+        self.actions.push(action);
+    }
+
+    /// Take all actions requested since the last call, in the order they were requested
+    pub(crate) fn take_actions(&mut self) -> Vec<EditorAction> {
+        std::mem::take(&mut self.actions)
+    }
+
+    /// Set a mark at the given offset, as done by `m`.
+    /// Uppercase marks are global, so setting one removes it from all other documents.
+    ///
+    /// Returns false if the mark can't be set by the user, i.e. because it is set automatically.
+    pub(crate) fn set_mark(&mut self, buffer: &mut Buffer, mark: char, offset: usize) -> bool {
+        if !(mark.is_ascii_alphabetic() || mark == '\'' || mark == '`') {
+            return false;
+        }
+        buffer.set_mark(mark, offset);
+        if mark.is_ascii_uppercase() {
+            self.push_action(EditorAction::ClaimGlobalMark(mark));
+        }
+        true
+    }
+
+    /// The motion to a mark, to the start of its line if `linewise` is set, as done by `'` and `` ` ``
+    fn mark_motion(buffer: &Buffer, mark: char, linewise: bool) -> Option<Motion<'static>> {
+        let offset = buffer.mark_offset(mark)?;
+        Some(if linewise {
+            Motion::Line(buffer.head_rope().line_of_offset(offset))
+        } else {
+            Motion::Offset(offset)
+        })
+    }
+
+    /// Jump to a mark, to the start of its line if `linewise` is set.
+    /// Global marks set in other documents are left to the application to jump to.
+    fn jump_to_mark(&mut self, view: &mut View, buffer: &mut Buffer, mark: char, linewise: bool) {
+        match Self::mark_motion(buffer, mark, linewise) {
+            Some(motion) => {
+                view.record_jump(buffer);
+                buffer.apply_buffer_op(&view.vp, BufferOp::Move(motion));
+            },
+            None if mark.is_ascii_uppercase() => {
+                self.push_action(EditorAction::JumpToGlobalMark { mark, linewise });
+            },
+            None => self.command_failed = true,
+        }
+    }
+
+    /// Go back through the jump list of the view, or forth if `forward` is set, by as many jumps as the count.
+    /// Jumps into other documents are left to the application.
+    fn walk_jump_list(&mut self, view: &mut View, buffer: &mut Buffer, forward: bool) {
+        let count = self.take_count().unwrap_or(1);
+        let jump = if forward {
+            view.jump_list.forward(count)
+        } else {
+            view.jump_list.back(view.document_id, buffer, count)
+        };
+        let Some(jump) = jump else {
+            self.command_failed = true;
+            return;
+        };
+        if jump.document_id != view.document_id {
+            self.push_action(EditorAction::ShowJump(jump));
+            return;
+        }
+        match buffer.anchor_offset(jump.anchor) {
+            Some(offset) => {
+                buffer.apply_buffer_op(&view.vp, BufferOp::Move(Motion::Offset(offset)))
+            },
+            None => self.command_failed = true,
+        }
+    }
+
+    /// Go to an older change in the change list, or a newer one if `newer` is set, by as many changes as the count
+    fn walk_change_list(&mut self, view: &View, buffer: &mut Buffer, newer: bool) {
+        let count = self.take_count().unwrap_or(1);
+        match buffer.walk_change_list(count, newer) {
+            Some(offset) => {
+                buffer.apply_buffer_op(&view.vp, BufferOp::Move(Motion::Offset(offset)))
+            },
+            None => self.command_failed = true,
+        }
+    }
+
+    /// Text shown in the command line, if any
+    pub(crate) fn command_line(&self) -> Option<String> {
+        if let Some(prompt) = &self.command_prompt {
+            return Some(format!(":{}", prompt.line));
         }
+        if let Some(prompt) = &self.tag_prompt {
+            return Some(format!("<{}", prompt.tag));
+        }
+        if let Some(prompt) = &self.search_prompt {
+            let prefix = match prompt.selection_op {
+                Some(op) => op.prompt(),
+                None if prompt.backwards => "?",
+                None => "/",
+            };
+            return Some(format!("{prefix}{}", prompt.pattern));
+        }
+        self.message.clone()
     }
 
     fn switch_mode(&mut self, mode: VimMode) {
@@ -105,6 +1160,52 @@ impl VimInterface {
     }
 }
 
+/// Apply a count to the target of an operator.
+/// Selections are acted on as full lines if `linewise_selection` is set, as in visual line mode.
+fn operator_target(
+    target: &TargetSpec,
+    count: Option<usize>,
+    linewise_selection: bool,
+) -> OperatorTarget<'_> {
+    match target {
+        TargetSpec::Motion(motion) => OperatorTarget::Motion(motion.with_count(count)),
+        TargetSpec::FindInLine(search) => OperatorTarget::Motion(search.motion(count)),
+        TargetSpec::Graphemes { clamp } => OperatorTarget::Graphemes {
+            count: count.unwrap_or(1),
+            clamp: *clamp,
+        },
+        TargetSpec::Lines => OperatorTarget::Lines(count.unwrap_or(1)),
+        TargetSpec::TextObject(object) => OperatorTarget::TextObject(*object, count.unwrap_or(1)),
+        TargetSpec::Selection if linewise_selection => OperatorTarget::SelectedLines,
+        TargetSpec::Selection => OperatorTarget::Selection,
+    }
+}
+
+/// Determine what a change made to the current selections acts on when it is repeated.
+///
+/// Like in vim, the repeated change acts on as many lines or characters,
+/// starting at the caret, as the primary selection covered originally.
+fn selection_repeat_target(buffer: &Buffer, linewise: bool) -> (TargetSpec, Option<usize>) {
+    let range = buffer.primary_caret().range();
+    let text = buffer.head_rope();
+    if linewise {
+        let first_line = text.line_of_offset(range.start);
+        let last_line = text.line_of_offset(range.end);
+        (TargetSpec::Lines, Some(last_line - first_line + 1))
+    } else {
+        let mut graphemes = 0;
+        let mut offset = range.start;
+        while let Some(next) = text
+            .next_grapheme_offset(offset)
+            .filter(|x| *x <= range.end)
+        {
+            graphemes += 1;
+            offset = next;
+        }
+        (TargetSpec::Motion(Motion::Right), Some(graphemes))
+    }
+}
+
 pub(crate) fn replace_mode_keymap() -> Keymap<MappedFn> {
     let on_printable: MappedFn = Arc::new(Box::new(|_, b, _, k| {
         b.replace_at_carets(&k.to_string());
@@ -121,12 +1222,13 @@ pub(crate) fn replace_mode_keymap() -> Keymap<MappedFn> {
 }
 
 pub(crate) fn normal_mode_keymap() -> Keymap<MappedFn> {
-    normal_mode_movement_key_keymap().merge(Keymap::new_from_map(HashMap::from_iter([
+    let keymap = normal_mode_movement_key_keymap()
+        .merge(caret_keymap())
+        .merge(scroll_keymap());
+    keymap.merge(Keymap::new_from_map(HashMap::from_iter([
         (
             key("i"),
-            leaf("insert mode", |_, _, vim, _| {
-                vim.switch_mode(VimMode::Insert)
-            }),
+            leaf("insert mode", |_, _, vim, _| vim.start_insert()),
         ),
         (
             key("n").with_mods(Modifiers::ALT),
@@ -134,8 +1236,20 @@ pub(crate) fn normal_mode_keymap() -> Keymap<MappedFn> {
         ),
         (
             key("v"),
-            leaf("visual mode", |_, _, vim, _| {
-                vim.switch_mode(VimMode::Visual)
+            leaf("visual mode", |_, b, vim, _| {
+                vim.toggle_visual_mode(b, VimMode::Visual)
+            }),
+        ),
+        (
+            key("v").with_mods(Modifiers::SHIFT),
+            leaf("visual line mode", |_, b, vim, _| {
+                vim.toggle_visual_mode(b, VimMode::VisualLine)
+            }),
+        ),
+        (
+            key("v").with_mods(Modifiers::CTRL),
+            leaf("visual block mode", |_, b, vim, _| {
+                vim.toggle_visual_mode(b, VimMode::VisualBlock)
             }),
         ),
         (
@@ -146,54 +1260,412 @@ pub(crate) fn normal_mode_keymap() -> Keymap<MappedFn> {
         ),
         (
             key("x"),
-            leaf("", |v, b, _, _| {
-                b.apply_buffer_op(&v.vp, BufferOp::Delete(Motion::Right))
+            leaf("", |v, b, vim, _| {
+                let count = vim.take_count();
+                vim.apply_operator(
+                    v,
+                    b,
+                    Operator::Delete,
+                    TargetSpec::Motion(Motion::Right),
+                    count,
+                );
             }),
         ),
         (
-            key("u"),
-            leaf("", |v, b, _, _| b.apply_buffer_op(&v.vp, BufferOp::Undo)),
+            translated_key("~"),
+            leaf("toggle case", |v, b, vim, _| {
+                let count = vim.take_count();
+                vim.toggle_case_forward(v, b, count);
+            }),
         ),
         (
-            key("r").with_mods(Modifiers::CTRL),
-            leaf("", |v, b, _, _| b.apply_buffer_op(&v.vp, BufferOp::Redo)),
+            key("j").with_mods(Modifiers::SHIFT),
+            leaf("join lines", |v, b, vim, _| {
+                let count = vim.take_count();
+                let join = Operator::Join {
+                    keep_whitespace: false,
+                };
+                vim.apply_operator(v, b, join, TargetSpec::Lines, count);
+            }),
         ),
         (
-            key("0"),
-            leaf("", |v, b, _, _| {
-                b.apply_buffer_op(&v.vp, BufferOp::Move(Motion::StartOfLine))
+            key("r"),
+            KeymapNode::Submap(
+                "replace character".to_string(),
+                Box::new(replace_char_keymap(TargetSpec::Graphemes { clamp: false })),
+            ),
+        ),
+        (
+            key("a").with_mods(Modifiers::CTRL),
+            leaf("increment number", |v, b, vim, _| {
+                let count = vim.take_count().unwrap_or(1);
+                vim.add_to_numbers(v, b, count as i64);
             }),
         ),
         (
-            translated_key("$"),
-            leaf("", |v, b, _, _| {
-                b.apply_buffer_op(&v.vp, BufferOp::Move(Motion::EndOfLine))
+            key("x").with_mods(Modifiers::CTRL),
+            leaf("decrement number", |v, b, vim, _| {
+                let count = vim.take_count().unwrap_or(1);
+                vim.add_to_numbers(v, b, -(count as i64));
             }),
         ),
         (
-            key("d"),
-            KeymapNode::Submap(
-                "delete".to_string(),
-                Box::new(normal_mode_movement_key_motion_keymap().map(&|motion| {
-                    mapping(move |v, b, _, _| b.apply_buffer_op(&v.vp, BufferOp::Delete(motion)))
-                })),
-            ),
+            translated_key("."),
+            leaf("repeat last change", |v, b, vim, _| {
+                vim.repeat_last_change(v, b)
+            }),
         ),
         (
-            key("c"),
-            KeymapNode::Submap(
-                "change".to_string(),
-                Box::new(normal_mode_movement_key_motion_keymap().map(&|motion| {
-                    mapping(move |v, b, vim, _| {
-                        b.apply_buffer_op(&v.vp, BufferOp::Delete(motion));
-                        vim.switch_mode(VimMode::Insert);
-                    })
-                })),
-            ),
+            key("p"),
+            leaf("put after caret", |_, b, vim, _| {
+                if let Some(content) = vim.take_register_content_counted() {
+                    b.put_at_carets(&content, true);
+                }
+            }),
         ),
-    ])))
-}
-
+        (
+            key("p").with_mods(Modifiers::SHIFT),
+            leaf("put before caret", |_, b, vim, _| {
+                if let Some(content) = vim.take_register_content_counted() {
+                    b.put_at_carets(&content, false);
+                }
+            }),
+        ),
+        (
+            translated_key("\""),
+            KeymapNode::Submap("register".to_string(), Box::new(register_keymap())),
+        ),
+        (
+            key("q"),
+            KeymapNode::Submap("record macro".to_string(), Box::new(record_macro_keymap())),
+        ),
+        (
+            key("m"),
+            KeymapNode::Submap("set mark".to_string(), Box::new(set_mark_keymap())),
+        ),
+        (
+            key("o").with_mods(Modifiers::CTRL),
+            leaf("older jump", |v, b, vim, _| vim.walk_jump_list(v, b, false)),
+        ),
+        (
+            key("i").with_mods(Modifiers::CTRL),
+            leaf("newer jump", |v, b, vim, _| vim.walk_jump_list(v, b, true)),
+        ),
+        (
+            key("Tab"),
+            leaf("newer jump", |v, b, vim, _| vim.walk_jump_list(v, b, true)),
+        ),
+        (
+            translated_key("'"),
+            KeymapNode::Submap(
+                "to line of mark".to_string(),
+                Box::new(jump_to_mark_keymap(true)),
+            ),
+        ),
+        (
+            translated_key("`"),
+            KeymapNode::Submap("to mark".to_string(), Box::new(jump_to_mark_keymap(false))),
+        ),
+        (
+            translated_key(":"),
+            leaf("command line", |_, b, vim, _| vim.start_command(b)),
+        ),
+        (key("s").with_mods(Modifiers::CTRL), save_leaf()),
+        (
+            translated_key("/"),
+            leaf("search forwards", |_, b, vim, _| vim.start_search(b, false)),
+        ),
+        (
+            translated_key("?"),
+            leaf("search backwards", |_, b, vim, _| vim.start_search(b, true)),
+        ),
+        (
+            key("n"),
+            leaf("next match", |v, b, vim, _| vim.repeat_search(v, b, false)),
+        ),
+        (
+            key("n").with_mods(Modifiers::SHIFT),
+            leaf("previous match", |v, b, vim, _| {
+                vim.repeat_search(v, b, true)
+            }),
+        ),
+        (
+            translated_key("*"),
+            leaf("search word forwards", |v, b, vim, _| {
+                vim.search_word_at_caret(v, b, false)
+            }),
+        ),
+        (
+            translated_key("#"),
+            leaf("search word backwards", |v, b, vim, _| {
+                vim.search_word_at_caret(v, b, true)
+            }),
+        ),
+        (
+            translated_key("@"),
+            KeymapNode::Submap("play macro".to_string(), Box::new(play_macro_keymap())),
+        ),
+        (
+            key("u"),
+            leaf("", |v, b, vim, _| {
+                for _ in 0..vim.take_count().unwrap_or(1) {
+                    b.apply_buffer_op(&v.vp, BufferOp::Undo);
+                }
+            }),
+        ),
+        (
+            key("r").with_mods(Modifiers::CTRL),
+            leaf("", |v, b, vim, _| {
+                for _ in 0..vim.take_count().unwrap_or(1) {
+                    b.apply_buffer_op(&v.vp, BufferOp::Redo);
+                }
+            }),
+        ),
+        (
+            key("0"),
+            leaf("", |v, b, _, _| {
+                b.apply_buffer_op(&v.vp, BufferOp::Move(Motion::StartOfLine))
+            }),
+        ),
+        (
+            translated_key("$"),
+            leaf("", |v, b, _, _| {
+                b.apply_buffer_op(&v.vp, BufferOp::Move(Motion::EndOfLine))
+            }),
+        ),
+        (
+            key("d"),
+            KeymapNode::Submap(
+                "delete".to_string(),
+                Box::new(
+                    operator_keymap(Operator::Delete, key("d"))
+                        .merge(surround_keymap(Operator::Delete)),
+                ),
+            ),
+        ),
+        (
+            key("c"),
+            KeymapNode::Submap(
+                "change".to_string(),
+                Box::new(
+                    operator_keymap(Operator::Change, key("c"))
+                        .merge(surround_keymap(Operator::Change)),
+                ),
+            ),
+        ),
+        (
+            key("y"),
+            KeymapNode::Submap(
+                "yank".to_string(),
+                Box::new(
+                    operator_keymap(Operator::Yank, key("y"))
+                        .merge(surround_keymap(Operator::Yank)),
+                ),
+            ),
+        ),
+        (
+            translated_key(">"),
+            KeymapNode::Submap(
+                "indent".to_string(),
+                Box::new(operator_keymap(Operator::IndentRight, translated_key(">"))),
+            ),
+        ),
+        (
+            translated_key("<"),
+            KeymapNode::Submap(
+                "dedent".to_string(),
+                Box::new(operator_keymap(Operator::IndentLeft, translated_key("<"))),
+            ),
+        ),
+        (
+            translated_key("="),
+            KeymapNode::Submap(
+                "reindent".to_string(),
+                Box::new(operator_keymap(Operator::Reindent, translated_key("="))),
+            ),
+        ),
+        (
+            key("g"),
+            KeymapNode::Submap(
+                "goto / operators".to_string(),
+                Box::new(Keymap::new_from_map(HashMap::from_iter([
+                    (
+                        translated_key(";"),
+                        leaf("older change", |v, b, vim, _| {
+                            vim.walk_change_list(v, b, false)
+                        }),
+                    ),
+                    (
+                        translated_key(","),
+                        leaf("newer change", |v, b, vim, _| {
+                            vim.walk_change_list(v, b, true)
+                        }),
+                    ),
+                    (
+                        translated_key("-"),
+                        leaf("older state", |v, b, vim, _| {
+                            let count = vim.take_count().unwrap_or(1);
+                            b.apply_buffer_op(&v.vp, BufferOp::Earlier(UndoStep::Count(count)));
+                        }),
+                    ),
+                    (
+                        translated_key("+"),
+                        leaf("newer state", |v, b, vim, _| {
+                            let count = vim.take_count().unwrap_or(1);
+                            b.apply_buffer_op(&v.vp, BufferOp::Later(UndoStep::Count(count)));
+                        }),
+                    ),
+                    (
+                        key("u"),
+                        KeymapNode::Submap(
+                            "lowercase".to_string(),
+                            Box::new(operator_keymap(Operator::Lowercase, key("u"))),
+                        ),
+                    ),
+                    (
+                        key("u").with_mods(Modifiers::SHIFT),
+                        KeymapNode::Submap(
+                            "uppercase".to_string(),
+                            Box::new(operator_keymap(
+                                Operator::Uppercase,
+                                key("u").with_mods(Modifiers::SHIFT),
+                            )),
+                        ),
+                    ),
+                    (
+                        translated_key("~"),
+                        KeymapNode::Submap(
+                            "toggle case".to_string(),
+                            Box::new(operator_keymap(Operator::ToggleCase, translated_key("~"))),
+                        ),
+                    ),
+                    (
+                        key("q"),
+                        KeymapNode::Submap(
+                            "format".to_string(),
+                            Box::new(operator_keymap(Operator::Format, key("q"))),
+                        ),
+                    ),
+                    (
+                        key("j").with_mods(Modifiers::SHIFT),
+                        leaf("join lines without spaces", |v, b, vim, _| {
+                            let count = vim.take_count();
+                            let join = Operator::Join {
+                                keep_whitespace: true,
+                            };
+                            vim.apply_operator(v, b, join, TargetSpec::Lines, count);
+                        }),
+                    ),
+                ]))),
+            ),
+        ),
+    ])))
+}
+
+fn search_mode_keymap() -> Keymap<MappedFn> {
+    let on_printable: MappedFn = mapping(|v, b, vim, k: KeyInput| {
+        if let Some(text) = k.key.as_key_string() {
+            vim.edit_search_pattern(v, b, |pattern| pattern.push_str(text));
+        }
+    });
+    Keymap::new(
+        HashMap::from_iter([
+            (
+                key("Backspace"),
+                leaf("delete character", |v, b, vim, _| {
+                    if matches!(&vim.search_prompt, Some(prompt) if prompt.pattern.is_empty()) {
+                        vim.cancel_search(b);
+                    } else {
+                        vim.edit_search_pattern(v, b, |pattern| _ = pattern.pop());
+                    }
+                }),
+            ),
+            (
+                key("Enter"),
+                leaf("search", |v, b, vim, _| vim.confirm_search(v, b)),
+            ),
+            (
+                key("Escape"),
+                leaf("cancel search", |_, b, vim, _| vim.cancel_search(b)),
+            ),
+        ]),
+        Some(KeymapNode::Leaf("type".to_string(), on_printable)),
+    )
+}
+
+fn command_mode_keymap() -> Keymap<MappedFn> {
+    let on_printable: MappedFn = mapping(|_, _, vim, k: KeyInput| {
+        if let Some(text) = k.key.as_key_string() {
+            vim.edit_command_line(|line| line.push_str(text));
+        }
+    });
+    Keymap::new(
+        HashMap::from_iter([
+            (
+                key("Backspace"),
+                leaf("delete character", |_, _, vim, _| {
+                    if matches!(&vim.command_prompt, Some(prompt) if prompt.line.is_empty()) {
+                        vim.cancel_command();
+                    } else {
+                        vim.edit_command_line(|line| _ = line.pop());
+                    }
+                }),
+            ),
+            (
+                key("Tab"),
+                leaf("complete", |_, _, vim, _| vim.complete_command_line()),
+            ),
+            (
+                key("Enter"),
+                leaf("run command", |v, b, vim, _| vim.execute_command_line(v, b)),
+            ),
+            (
+                key("Escape"),
+                leaf("cancel command", |_, _, vim, _| vim.cancel_command()),
+            ),
+        ]),
+        Some(KeymapNode::Leaf("type".to_string(), on_printable)),
+    )
+}
+
+fn surround_tag_mode_keymap() -> Keymap<MappedFn> {
+    let on_printable: MappedFn = mapping(|_, _, vim, k: KeyInput| {
+        if let (Some(text), Some(prompt)) = (k.key.as_key_string(), &mut vim.tag_prompt) {
+            prompt.tag.push_str(text);
+        }
+    });
+    Keymap::new(
+        HashMap::from_iter([
+            (
+                key("Backspace"),
+                leaf("delete character", |v, b, vim, _| {
+                    match &mut vim.tag_prompt {
+                        Some(prompt) if !prompt.tag.is_empty() => _ = prompt.tag.pop(),
+                        _ => vim.close_tag_prompt(v, b, false),
+                    }
+                }),
+            ),
+            (
+                key("Enter"),
+                leaf("surround with tag", |v, b, vim, _| {
+                    vim.close_tag_prompt(v, b, true)
+                }),
+            ),
+            (
+                translated_key(">"),
+                leaf("surround with tag", |v, b, vim, _| {
+                    vim.close_tag_prompt(v, b, true)
+                }),
+            ),
+            (
+                key("Escape"),
+                leaf("cancel", |v, b, vim, _| vim.close_tag_prompt(v, b, false)),
+            ),
+        ]),
+        Some(KeymapNode::Leaf("type".to_string(), on_printable)),
+    )
+}
+
 fn insert_mode_keymap() -> Keymap<MappedFn> {
     let on_printable: MappedFn = mapping(|v, b: &mut Buffer, _, k: KeyInput| {
         b.apply_buffer_op(&v.vp, BufferOp::Insert(k.key.to_string()));
@@ -224,10 +1696,14 @@ fn insert_mode_keymap() -> Keymap<MappedFn> {
                     b.apply_buffer_op(&v.vp, BufferOp::Insert("\t".to_string()))
                 }),
             ),
+            (key("s").with_mods(Modifiers::CTRL), save_leaf()),
             (
                 key("Escape"),
-                leaf("normal mode", |_, _, vim, _| {
-                    vim.switch_mode(VimMode::Normal)
+                leaf("normal mode", |_, b, vim, _| {
+                    b.break_undo_group();
+                    b.set_mark('^', b.primary_caret().head);
+                    vim.switch_mode(VimMode::Normal);
+                    vim.finish_change();
                 }),
             ),
         ]),
@@ -235,44 +1711,647 @@ fn insert_mode_keymap() -> Keymap<MappedFn> {
     ))
 }
 
-fn visual_mode_keymap() -> Keymap<MappedFn> {
-    let visual_mode_movement = normal_mode_movement_key_motion_keymap().map(&|motion| {
-        mapping(move |v, b, _, _| b.apply_buffer_op(&v.vp, BufferOp::Selection(motion)))
+fn visual_mode_keymap() -> Keymap<MappedFn> {
+    let visual_mode_movement = normal_mode_movement_key_motion_keymap()
+        .map(&|motion| {
+            mapping(move |v, b, vim, _| {
+                let count = vim.take_count();
+                b.apply_buffer_op(&v.vp, BufferOp::Selection(motion.with_count(count)))
+            })
+        })
+        .merge(find_in_line_keymap(|v, b, vim, search| {
+            let count = vim.take_count();
+            b.apply_buffer_op(&v.vp, BufferOp::Selection(search.motion(count)))
+        }));
+    let keymap = Keymap::new_from_map(HashMap::from_iter([
+        (
+            key("Escape"),
+            leaf("normal mode", |_, b, vim, _| {
+                vim.switch_mode(VimMode::Normal);
+                b.collapse_selections();
+            }),
+        ),
+        (
+            key("v"),
+            leaf("visual mode", |_, b, vim, _| {
+                vim.toggle_visual_mode(b, VimMode::Visual)
+            }),
+        ),
+        (
+            key("v").with_mods(Modifiers::SHIFT),
+            leaf("visual line mode", |_, b, vim, _| {
+                vim.toggle_visual_mode(b, VimMode::VisualLine)
+            }),
+        ),
+        (
+            key("v").with_mods(Modifiers::CTRL),
+            leaf("visual block mode", |_, b, vim, _| {
+                vim.toggle_visual_mode(b, VimMode::VisualBlock)
+            }),
+        ),
+        (
+            translated_key(":"),
+            leaf("command line on selected lines", |_, b, vim, _| {
+                vim.start_command(b)
+            }),
+        ),
+        (key("d"), visual_operator_leaf("delete", Operator::Delete)),
+        (key("x"), visual_operator_leaf("delete", Operator::Delete)),
+        (key("c"), visual_operator_leaf("change", Operator::Change)),
+        (key("y"), visual_operator_leaf("yank", Operator::Yank)),
+        (
+            translated_key(">"),
+            visual_operator_leaf("indent", Operator::IndentRight),
+        ),
+        (
+            translated_key("<"),
+            visual_operator_leaf("dedent", Operator::IndentLeft),
+        ),
+        (
+            translated_key("="),
+            visual_operator_leaf("reindent", Operator::Reindent),
+        ),
+        (
+            key("u"),
+            visual_operator_leaf("lowercase", Operator::Lowercase),
+        ),
+        (
+            key("u").with_mods(Modifiers::SHIFT),
+            visual_operator_leaf("uppercase", Operator::Uppercase),
+        ),
+        (
+            translated_key("~"),
+            visual_operator_leaf("toggle case", Operator::ToggleCase),
+        ),
+        (
+            key("j").with_mods(Modifiers::SHIFT),
+            visual_operator_leaf("join lines", Operator::Join {
+                keep_whitespace: false,
+            }),
+        ),
+        (
+            key("r"),
+            KeymapNode::Submap(
+                "replace characters".to_string(),
+                Box::new(replace_char_keymap(TargetSpec::Selection)),
+            ),
+        ),
+        (
+            key("g"),
+            KeymapNode::Submap(
+                "goto / operators".to_string(),
+                Box::new(Keymap::new_from_map(HashMap::from_iter([
+                    (
+                        key("u"),
+                        visual_operator_leaf("lowercase", Operator::Lowercase),
+                    ),
+                    (
+                        key("u").with_mods(Modifiers::SHIFT),
+                        visual_operator_leaf("uppercase", Operator::Uppercase),
+                    ),
+                    (
+                        translated_key("~"),
+                        visual_operator_leaf("toggle case", Operator::ToggleCase),
+                    ),
+                    (key("q"), visual_operator_leaf("format", Operator::Format)),
+                    (
+                        key("j").with_mods(Modifiers::SHIFT),
+                        visual_operator_leaf("join lines without spaces", Operator::Join {
+                            keep_whitespace: true,
+                        }),
+                    ),
+                ]))),
+            ),
+        ),
+        (
+            key("p"),
+            leaf("replace selection", |_, b, vim, _| {
+                if let Some(content) = vim.take_register_content() {
+                    let replaced = b.selected_texts();
+                    b.put_at_carets(&content, false);
+                    vim.registers
+                        .record_delete(None, RegisterContent::new(replaced, false));
+                }
+                vim.switch_mode(VimMode::Normal);
+            }),
+        ),
+        (
+            key("p").with_mods(Modifiers::SHIFT),
+            leaf("replace selection, keeping register", |_, b, vim, _| {
+                if let Some(content) = vim.take_register_content() {
+                    b.put_at_carets(&content, false);
+                }
+                vim.switch_mode(VimMode::Normal);
+            }),
+        ),
+        (
+            translated_key("\""),
+            KeymapNode::Submap("register".to_string(), Box::new(register_keymap())),
+        ),
+        (
+            key("s").with_mods(Modifiers::SHIFT),
+            KeymapNode::Submap(
+                "surround".to_string(),
+                Box::new(surround_pair_keymap(SurroundAction::Add(
+                    TargetSpec::Selection,
+                    None,
+                ))),
+            ),
+        ),
+    ]));
+    let text_objects = text_object_keymap().map(&|object| {
+        mapping(move |v, b, _, _| b.apply_buffer_op(&v.vp, BufferOp::SelectTextObject(object)))
+    });
+    visual_mode_movement
+        .merge(text_objects)
+        .merge(caret_keymap())
+        .merge(scroll_keymap())
+        .merge(selections_keymap())
+        .merge(keymap)
+}
+
+/// Keymap for managing multiple carets, shared by normal and visual mode
+fn caret_keymap() -> Keymap<MappedFn> {
+    Keymap::new_from_map(HashMap::from_iter([
+        (
+            key("r").with_mods(Modifiers::ALT),
+            leaf("next caret becomes primary", |v, b, vim, _| {
+                vim.change_carets_failable(v, b, BufferOp::RotatePrimaryCaret { backwards: false })
+            }),
+        ),
+        (
+            key("r").with_mods(Modifiers::ALT | Modifiers::SHIFT),
+            leaf("previous caret becomes primary", |v, b, vim, _| {
+                vim.change_carets_failable(v, b, BufferOp::RotatePrimaryCaret { backwards: true })
+            }),
+        ),
+        (
+            key("x").with_mods(Modifiers::ALT),
+            leaf("remove primary caret", |v, b, vim, _| {
+                vim.change_carets_failable(v, b, BufferOp::RemovePrimaryCaret)
+            }),
+        ),
+        (
+            key("n").with_mods(Modifiers::ALT | Modifiers::SHIFT),
+            leaf("remove newest caret", |v, b, vim, _| {
+                vim.change_carets_failable(v, b, BufferOp::RemoveLastAddedCaret)
+            }),
+        ),
+        (
+            key("d").with_mods(Modifiers::ALT),
+            leaf("select next occurrence", |v, b, vim, _| {
+                vim.select_occurrences(v, b, BufferOp::SelectNextOccurrence { skip: false })
+            }),
+        ),
+        (
+            key("d").with_mods(Modifiers::ALT | Modifiers::SHIFT),
+            leaf("skip to next occurrence", |v, b, vim, _| {
+                vim.select_occurrences(v, b, BufferOp::SelectNextOccurrence { skip: true })
+            }),
+        ),
+        (
+            key("a").with_mods(Modifiers::ALT),
+            leaf("select all occurrences", |v, b, vim, _| {
+                vim.select_occurrences(v, b, BufferOp::SelectAllOccurrences { in_viewport: false })
+            }),
+        ),
+        (
+            key("a").with_mods(Modifiers::ALT | Modifiers::SHIFT),
+            leaf("select occurrences in view", |v, b, vim, _| {
+                vim.select_occurrences(v, b, BufferOp::SelectAllOccurrences { in_viewport: true })
+            }),
+        ),
+    ]))
+}
+
+/// Keymap for splitting, filtering and merging the selections in visual mode, like in kakoune
+fn selections_keymap() -> Keymap<MappedFn> {
+    Keymap::new_from_map(HashMap::from_iter([
+        (
+            key("s"),
+            leaf("select matches", |_, b, vim, _| {
+                vim.start_selection_prompt(b, RegexSelectionOp::Select)
+            }),
+        ),
+        (
+            key("s").with_mods(Modifiers::ALT | Modifiers::SHIFT),
+            leaf("split at matches", |_, b, vim, _| {
+                vim.start_selection_prompt(b, RegexSelectionOp::Split)
+            }),
+        ),
+        (
+            key("k").with_mods(Modifiers::ALT),
+            leaf("keep matching selections", |_, b, vim, _| {
+                vim.start_selection_prompt(b, RegexSelectionOp::Filter { keep: true })
+            }),
+        ),
+        (
+            key("k").with_mods(Modifiers::ALT | Modifiers::SHIFT),
+            leaf("keep selections not matching", |_, b, vim, _| {
+                vim.start_selection_prompt(b, RegexSelectionOp::Filter { keep: false })
+            }),
+        ),
+        (
+            key("s").with_mods(Modifiers::ALT),
+            leaf("split into lines", |v, b, vim, _| {
+                vim.change_carets_failable(v, b, BufferOp::SplitLines)
+            }),
+        ),
+        (
+            key("m").with_mods(Modifiers::ALT),
+            leaf("merge selections", |v, b, vim, _| {
+                vim.change_carets_failable(v, b, BufferOp::MergeSelections)
+            }),
+        ),
+    ]))
+}
+
+/// Keymap of visual block mode, which extends the one of visual mode.
+/// `$` extends the block to the end of every line until the next motion that isn't vertical.
+fn visual_block_mode_keymap() -> Keymap<MappedFn> {
+    let block_movement = normal_mode_movement_key_motion_keymap().map(&|motion| {
+        mapping(move |v, b, vim, _| {
+            let count = vim.take_count();
+            vim.block_to_line_end = match motion {
+                Motion::EndOfLine => true,
+                Motion::Up | Motion::Down => vim.block_to_line_end,
+                _ => false,
+            };
+            b.apply_buffer_op(&v.vp, BufferOp::Selection(motion.with_count(count)))
+        })
+    });
+    let block_find = find_in_line_keymap(|v, b, vim, search| {
+        let count = vim.take_count();
+        vim.block_to_line_end = false;
+        b.apply_buffer_op(&v.vp, BufferOp::Selection(search.motion(count)))
+    });
+    let keymap = Keymap::new_from_map(HashMap::from_iter([
+        (
+            key("i").with_mods(Modifiers::SHIFT),
+            leaf("insert at left edge of block", |_, b, vim, _| {
+                vim.insert_at_block(b, BlockSplit::Starts)
+            }),
+        ),
+        (
+            key("a").with_mods(Modifiers::SHIFT),
+            leaf("append at right edge of block", |_, b, vim, _| {
+                vim.insert_at_block(b, BlockSplit::Ends)
+            }),
+        ),
+    ]));
+    visual_mode_keymap()
+        .merge(block_movement)
+        .merge(block_find)
+        .merge(keymap)
+}
+
+/// Mapping that asks the application to save the current document
+fn save_leaf() -> KeymapNode<MappedFn> {
+    leaf("save", |_, _, vim, _| {
+        vim.push_action(EditorAction::Write {
+            path: None,
+            force: false,
+        })
+    })
+}
+
+/// Keymap for the target of an operator, i.e. the motion after `d`.
+/// `line_key` applies the operator to whole lines when pressed, like the second `d` in `dd`.
+fn operator_keymap(operator: Operator, line_key: Combo) -> Keymap<MappedFn> {
+    let lines = Keymap::new_from_map(HashMap::from_iter([(
+        line_key,
+        leaf("lines", move |v, b, vim, _| {
+            let count = vim.take_count();
+            vim.apply_operator(v, b, operator, TargetSpec::Lines, count);
+        }),
+    )]));
+    let text_objects = text_object_keymap().map(&|object| {
+        mapping(move |v, b, vim, _| {
+            let count = vim.take_count();
+            vim.apply_operator(v, b, operator, TargetSpec::TextObject(object), count);
+        })
+    });
+    let marks = Keymap::new_from_map(HashMap::from_iter([
+        (
+            translated_key("'"),
+            KeymapNode::Submap(
+                "to line of mark".to_string(),
+                Box::new(operator_mark_keymap(operator, true)),
+            ),
+        ),
+        (
+            translated_key("`"),
+            KeymapNode::Submap(
+                "to mark".to_string(),
+                Box::new(operator_mark_keymap(operator, false)),
+            ),
+        ),
+    ]));
+    normal_mode_movement_key_motion_keymap()
+        .map(&|motion| {
+            mapping(move |v, b, vim, _| {
+                let count = vim.take_count();
+                vim.apply_operator(v, b, operator, TargetSpec::Motion(motion), count);
+            })
+        })
+        .merge(find_in_line_keymap(move |v, b, vim, search| {
+            let count = vim.take_count();
+            vim.apply_operator(v, b, operator, TargetSpec::FindInLine(search), count);
+        }))
+        .merge(text_objects)
+        .merge(marks)
+        .merge(lines)
+}
+
+/// Mapping that applies an operator to the current selections
+fn visual_operator_leaf(desc: &str, operator: Operator) -> KeymapNode<MappedFn> {
+    leaf(desc, move |v, b, vim, _| {
+        vim.apply_operator(v, b, operator, TargetSpec::Selection, None)
+    })
+}
+
+/// Keymap for the character to replace the target with after pressing `r`
+fn replace_char_keymap(target: TargetSpec) -> Keymap<MappedFn> {
+    let on_printable = leaf("replace with", move |v, b, vim, k| {
+        let mut chars = k.key.as_str().chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => {
+                let count = vim.take_count();
+                vim.apply_operator(v, b, Operator::ReplaceChars(c), target.clone(), count);
+            },
+            _ => vim.command_failed = true,
+        }
+    });
+    Keymap::new(HashMap::new(), Some(on_printable))
+}
+
+/// Keymap for in-line searches for the grapheme typed after `f`, `t`, `F` or `T`,
+/// and for repeating the most recent one with `;`, or in the opposite direction with `,`.
+/// The search is handed to `apply`, which moves or selects towards its match.
+fn find_in_line_keymap<F>(apply: F) -> Keymap<MappedFn>
+where
+    F: Fn(&mut View, &mut Buffer, &mut VimInterface, CharSearch) + Clone + Send + Sync + 'static,
+{
+    let search_keymap = |backwards: bool, till: bool| {
+        let apply = apply.clone();
+        let on_printable = leaf("find", move |v, b, vim, k| {
+            let search = CharSearch {
+                grapheme: k.key.as_str().to_string(),
+                backwards,
+                till,
+                repeated: false,
+            };
+            vim.last_char_search = Some(search.clone());
+            apply(v, b, vim, search);
+        });
+        Keymap::new(HashMap::new(), Some(on_printable))
+    };
+    let repeat = |desc: &str, reverse: bool| {
+        let apply = apply.clone();
+        leaf(desc, move |v, b, vim, _| {
+            match vim.last_char_search.as_ref().map(|x| x.repeat(reverse)) {
+                Some(search) => apply(v, b, vim, search),
+                None => vim.command_failed = true,
+            }
+        })
+    };
+    let submap = |desc: &str, backwards: bool, till: bool| {
+        KeymapNode::Submap(desc.to_string(), Box::new(search_keymap(backwards, till)))
+    };
+    Keymap::new_from_map(HashMap::from_iter([
+        (key("f"), submap("find in line", false, false)),
+        (key("t"), submap("till in line", false, true)),
+        (
+            key("f").with_mods(Modifiers::SHIFT),
+            submap("find in line backwards", true, false),
+        ),
+        (
+            key("t").with_mods(Modifiers::SHIFT),
+            submap("till in line backwards", true, true),
+        ),
+        (translated_key(";"), repeat("repeat find", false)),
+        (translated_key(","), repeat("repeat find backwards", true)),
+    ]))
+}
+
+/// Keymap for the pair to surround text with, or to replace the delimiters of an object with.
+/// `t` and `<` open a prompt for the name of a tag.
+fn surround_pair_keymap(action: SurroundAction) -> Keymap<MappedFn> {
+    let on_printable = leaf("surround with", move |v, b, vim, k| {
+        let action = match &action {
+            // The count typed before `ys` applies to its target
+            SurroundAction::Add(target, _) => SurroundAction::Add(target.clone(), vim.take_count()),
+            action => action.clone(),
+        };
+        let mut chars = k.key.as_str().chars();
+        match (chars.next(), chars.next()) {
+            (Some('t' | '<'), None) => vim.start_tag_prompt(action),
+            (Some(c), None) => vim.surround(v, b, action, &SurroundPair::for_char(c)),
+            _ => vim.command_failed = true,
+        }
+    });
+    Keymap::new(HashMap::new(), Some(on_printable))
+}
+
+/// Keymap for the target to surround after `ys`, with a submap for the pair after every target.
+/// `yss` surrounds the line of the caret.
+fn surround_target_keymap() -> Keymap<MappedFn> {
+    let lines = Keymap::new_from_map(HashMap::from_iter([(
+        key("s"),
+        KeymapNode::Leaf("line".to_string(), TargetSpec::Lines),
+    )]));
+    normal_mode_movement_key_motion_keymap()
+        .map(&TargetSpec::Motion)
+        .merge(text_object_keymap().map(&TargetSpec::TextObject))
+        .merge(lines)
+        .map_leaves(&|desc, target| {
+            let pairs = surround_pair_keymap(SurroundAction::Add(target, None));
+            KeymapNode::Submap(desc, Box::new(pairs))
+        })
+}
+
+/// Keymap of the surround commands following an operator key: `ys`, `cs` and `ds`
+fn surround_keymap(operator: Operator) -> Keymap<MappedFn> {
+    let node = match operator {
+        Operator::Yank => {
+            KeymapNode::Submap("surround".to_string(), Box::new(surround_target_keymap()))
+        },
+        Operator::Change => {
+            let objects = surround_object_keymap(&|object| {
+                let pairs = surround_pair_keymap(SurroundAction::Change(object));
+                KeymapNode::Submap("change to".to_string(), Box::new(pairs))
+            });
+            KeymapNode::Submap("change surrounding".to_string(), Box::new(objects))
+        },
+        _ => {
+            let objects = surround_object_keymap(&|object| {
+                leaf("delete surrounding", move |v, b, vim, _| {
+                    vim.delete_surround(v, b, object)
+                })
+            });
+            KeymapNode::Submap("delete surrounding".to_string(), Box::new(objects))
+        },
+    };
+    Keymap::new_from_map(HashMap::from_iter([(key("s"), node)]))
+}
+
+/// Keymap for the object whose delimiters are changed by `cs` or deleted by `ds`,
+/// given the node to use for each object
+fn surround_object_keymap(
+    node: &dyn Fn(TextObjectKind) -> KeymapNode<MappedFn>,
+) -> Keymap<MappedFn> {
+    let chars = "()b[]r{}<>at\"'`";
+    let mut map = HashMap::from_iter(chars.chars().filter_map(|c| {
+        let object = TextObjectKind::for_surround_char(c)?;
+        let combo = if c.is_alphabetic() {
+            key(&c.to_string())
+        } else {
+            translated_key(&c.to_string())
+        };
+        Some((combo, node(object)))
+    }));
+    map.insert(
+        key("b").with_mods(Modifiers::SHIFT),
+        node(TextObjectKind::Bracketed {
+            open: '{',
+            close: '}',
+        }),
+    );
+    Keymap::new_from_map(map)
+}
+
+/// Keymap for selecting a register after pressing `"`
+fn register_keymap() -> Keymap<MappedFn> {
+    let on_printable = leaf("select register", |_, _, vim, k| {
+        match k
+            .key
+            .as_str()
+            .chars()
+            .next()
+            .and_then(RegisterName::from_char)
+        {
+            Some(register) => vim.select_register(register),
+            None => tracing::info!("No register named {}", k.key),
+        }
+    });
+    Keymap::new(HashMap::new(), Some(on_printable))
+}
+
+/// Keymap for naming the mark to set at the primary caret after pressing `m`
+fn set_mark_keymap() -> Keymap<MappedFn> {
+    let on_printable = leaf("set mark", |_, b, vim, k| {
+        let offset = b.primary_caret().head;
+        match k.key.as_str().chars().next() {
+            Some(mark) if vim.set_mark(b, mark, offset) => {},
+            _ => vim.command_failed = true,
+        }
     });
-    let keymap = Keymap::new_from_map(HashMap::from_iter([
-        (
-            key("Escape"),
-            leaf("normal mode", |_, b, vim, _| {
-                vim.switch_mode(VimMode::Normal);
-                b.collapse_selections();
-            }),
-        ),
-        (
-            key("d"),
-            leaf("delete", |v, b, _, _| {
-                b.apply_buffer_op(&v.vp, BufferOp::DeleteSelected);
-            }),
-        ),
-        (
-            key("x"),
-            leaf("delete", |v, b, _, _| {
-                b.apply_buffer_op(&v.vp, BufferOp::DeleteSelected);
-            }),
-        ),
-    ]));
-    visual_mode_movement.merge(keymap)
+    Keymap::new(HashMap::new(), Some(on_printable))
+}
+
+/// Keymap for naming the mark to jump to after pressing `'` or `` ` ``,
+/// jumping to the start of its line if `linewise` is set
+fn jump_to_mark_keymap(linewise: bool) -> Keymap<MappedFn> {
+    let on_printable = leaf("jump to mark", move |v, b, vim, k| {
+        match k.key.as_str().chars().next() {
+            Some(mark) => vim.jump_to_mark(v, b, mark, linewise),
+            None => vim.command_failed = true,
+        }
+    });
+    Keymap::new(HashMap::new(), Some(on_printable))
+}
+
+/// Keymap for naming the mark an operator acts up to, i.e. the `a` in `d'a`
+fn operator_mark_keymap(operator: Operator, linewise: bool) -> Keymap<MappedFn> {
+    let on_printable = leaf("to mark", move |v, b, vim, k| {
+        let motion = k
+            .key
+            .as_str()
+            .chars()
+            .next()
+            .and_then(|mark| VimInterface::mark_motion(b, mark, linewise));
+        match motion {
+            Some(motion) => vim.apply_operator(v, b, operator, TargetSpec::Motion(motion), None),
+            None => vim.command_failed = true,
+        }
+    });
+    Keymap::new(HashMap::new(), Some(on_printable))
+}
+
+/// Keymap for selecting the register to record a macro into after pressing `q`
+fn record_macro_keymap() -> Keymap<MappedFn> {
+    let on_printable = leaf("record macro", |_, _, vim, k| {
+        match k
+            .key
+            .as_str()
+            .chars()
+            .next()
+            .and_then(RegisterName::from_char)
+        {
+            Some(register) => vim.start_macro_recording(register),
+            None => tracing::info!("No register named {}", k.key),
+        }
+    });
+    Keymap::new(HashMap::new(), Some(on_printable))
+}
+
+/// Keymap for selecting the register to play a macro from after pressing `@`.
+/// `@@` plays the most recently played macro again.
+fn play_macro_keymap() -> Keymap<MappedFn> {
+    let on_printable = leaf("play macro", |v, b, vim, k| {
+        let register = match k.key.as_str() {
+            "@" => vim.last_macro,
+            key => key.chars().next().and_then(RegisterName::from_char),
+        };
+        match register {
+            Some(register) => vim.play_macro(v, b, register),
+            None => vim.command_failed = true,
+        }
+    });
+    Keymap::new(HashMap::new(), Some(on_printable))
 }
 
 fn add_caret_keymap() -> Keymap<MappedFn> {
-    normal_mode_movement_key_motion_keymap().map(&|motion: Motion| {
-        mapping(move |v, b, _, _| b.apply_buffer_op(&v.vp, BufferOp::NewCaret(motion)))
-    })
+    let find = find_in_line_keymap(|v, b, vim, search| {
+        let count = vim.take_count();
+        b.apply_buffer_op(&v.vp, BufferOp::NewCaret(search.motion(count)))
+    });
+    normal_mode_movement_key_motion_keymap()
+        .map(&|motion: Motion| {
+            mapping(move |v, b, vim, _| {
+                let count = vim.take_count();
+                b.apply_buffer_op(&v.vp, BufferOp::NewCaret(motion.with_count(count)))
+            })
+        })
+        .merge(find)
 }
 
 fn normal_mode_movement_key_keymap() -> Keymap<MappedFn> {
-    normal_mode_movement_key_motion_keymap().map(&|motion: Motion| {
-        mapping(move |v, b, _, _| b.apply_buffer_op(&v.vp, BufferOp::Move(motion)))
-    })
+    normal_mode_movement_key_motion_keymap()
+        .map(&|motion: Motion| {
+            mapping(move |v, b, vim, _| {
+                let count = vim.take_count();
+                if let Motion::FirstLine
+                | Motion::LastLine
+                | Motion::TopOfViewport
+                | Motion::MiddleOfViewport
+                | Motion::BottomOfViewport
+                | Motion::MatchingBracket
+                | Motion::NextParagraph
+                | Motion::PrevParagraph
+                | Motion::NextSentence
+                | Motion::PrevSentence = motion
+                {
+                    v.record_jump(b);
+                }
+                // A motion that can't move any caret fails, i.e. to stop macros at the end of the buffer
+                vim.move_carets_failable(v, b, motion.with_count(count));
+            })
+        })
+        .merge(find_in_line_keymap(|v, b, vim, search| {
+            let count = vim.take_count();
+            vim.move_carets_failable(v, b, search.motion(count));
+        }))
 }
 
 fn movement_key_keymap() -> Keymap<MappedFn> {
@@ -318,10 +2397,257 @@ fn normal_mode_movement_key_motion_keymap() -> Keymap<Motion<'static>> {
             translated_key("$"),
             KeymapNode::Leaf("to end of line".to_string(), Motion::EndOfLine),
         ),
+        (
+            key("g").with_mods(Modifiers::SHIFT),
+            KeymapNode::Leaf("to last line".to_string(), Motion::LastLine),
+        ),
+        (
+            translated_key("%"),
+            KeymapNode::Leaf("to matching bracket".to_string(), Motion::MatchingBracket),
+        ),
+        (
+            key("h").with_mods(Modifiers::SHIFT),
+            KeymapNode::Leaf("to top of view".to_string(), Motion::TopOfViewport),
+        ),
+        (
+            key("m").with_mods(Modifiers::SHIFT),
+            KeymapNode::Leaf("to middle of view".to_string(), Motion::MiddleOfViewport),
+        ),
+        (
+            key("l").with_mods(Modifiers::SHIFT),
+            KeymapNode::Leaf("to bottom of view".to_string(), Motion::BottomOfViewport),
+        ),
+        (
+            key("w").with_mods(Modifiers::SHIFT),
+            KeymapNode::Leaf(
+                "to next WORD".to_string(),
+                Motion::NextBigWordBoundary(WordBoundaryType::Start),
+            ),
+        ),
+        (
+            key("b").with_mods(Modifiers::SHIFT),
+            KeymapNode::Leaf(
+                "to previous WORD".to_string(),
+                Motion::PrevBigWordBoundary(WordBoundaryType::Start),
+            ),
+        ),
+        (
+            key("e").with_mods(Modifiers::SHIFT),
+            KeymapNode::Leaf(
+                "to end of WORD".to_string(),
+                Motion::NextBigWordBoundary(WordBoundaryType::End),
+            ),
+        ),
+        (
+            key("g"),
+            KeymapNode::Submap(
+                "goto".to_string(),
+                Box::new(Keymap::new_from_map(HashMap::from_iter([
+                    (
+                        key("g"),
+                        KeymapNode::Leaf("to first line".to_string(), Motion::FirstLine),
+                    ),
+                    (
+                        key("e").with_mods(Modifiers::SHIFT),
+                        KeymapNode::Leaf(
+                            "to end of previous WORD".to_string(),
+                            Motion::PrevBigWordBoundary(WordBoundaryType::End),
+                        ),
+                    ),
+                ]))),
+            ),
+        ),
+        (
+            key("w").with_mods(Modifiers::ALT),
+            KeymapNode::Leaf(
+                "to next subword".to_string(),
+                Motion::NextSubwordBoundary(WordBoundaryType::Start),
+            ),
+        ),
+        (
+            key("b").with_mods(Modifiers::ALT),
+            KeymapNode::Leaf(
+                "to previous subword".to_string(),
+                Motion::PrevSubwordBoundary(WordBoundaryType::Start),
+            ),
+        ),
+        (
+            key("e").with_mods(Modifiers::ALT),
+            KeymapNode::Leaf(
+                "to end of subword".to_string(),
+                Motion::NextSubwordBoundary(WordBoundaryType::End),
+            ),
+        ),
+        (
+            translated_key("}"),
+            KeymapNode::Leaf("to next paragraph".to_string(), Motion::NextParagraph),
+        ),
+        (
+            translated_key("{"),
+            KeymapNode::Leaf("to previous paragraph".to_string(), Motion::PrevParagraph),
+        ),
+        (
+            translated_key(")"),
+            KeymapNode::Leaf("to next sentence".to_string(), Motion::NextSentence),
+        ),
+        (
+            translated_key("("),
+            KeymapNode::Leaf("to previous sentence".to_string(), Motion::PrevSentence),
+        ),
     ]));
     normal_mode_movement_binds.merge(movement_key_motion_keymap())
 }
 
+/// Keymap for scrolling the view, like `Ctrl-d` or `zz` in vim.
+///
+/// A count given to `Ctrl-d` and `Ctrl-u` replaces the half page they scroll by.
+fn scroll_keymap() -> Keymap<MappedFn> {
+    let scroll_to = |desc: &str, position| {
+        leaf(desc, move |v, b, _, _| {
+            let line = b.primary_caret_position().line;
+            v.vp = v.vp.with_line_at(line, position, SCROLL_OFF);
+        })
+    };
+    // Like in vim, paging keeps two lines of the previous page in view
+    let page = |v: &View| v.vp.height.saturating_sub(2).max(1);
+    Keymap::new_from_map(HashMap::from_iter([
+        (
+            key("e").with_mods(Modifiers::CTRL),
+            leaf("scroll down", |v, b, vim, _| {
+                let count = vim.take_count().unwrap_or(1);
+                vim.scroll(v, b, count as isize, false);
+            }),
+        ),
+        (
+            key("y").with_mods(Modifiers::CTRL),
+            leaf("scroll up", |v, b, vim, _| {
+                let count = vim.take_count().unwrap_or(1);
+                vim.scroll(v, b, -(count as isize), false);
+            }),
+        ),
+        (
+            key("d").with_mods(Modifiers::CTRL),
+            leaf("half a page down", |v, b, vim, _| {
+                let lines = vim.take_count().unwrap_or(v.vp.height / 2);
+                vim.scroll(v, b, lines as isize, true);
+            }),
+        ),
+        (
+            key("u").with_mods(Modifiers::CTRL),
+            leaf("half a page up", |v, b, vim, _| {
+                let lines = vim.take_count().unwrap_or(v.vp.height / 2);
+                vim.scroll(v, b, -(lines as isize), true);
+            }),
+        ),
+        (
+            key("f").with_mods(Modifiers::CTRL),
+            leaf("page down", move |v, b, vim, _| {
+                let count = vim.take_count().unwrap_or(1);
+                vim.scroll(v, b, (count * page(v)) as isize, false);
+            }),
+        ),
+        (
+            key("b").with_mods(Modifiers::CTRL),
+            leaf("page up", move |v, b, vim, _| {
+                let count = vim.take_count().unwrap_or(1);
+                vim.scroll(v, b, -((count * page(v)) as isize), false);
+            }),
+        ),
+        (
+            key("z"),
+            KeymapNode::Submap(
+                "scroll".to_string(),
+                Box::new(Keymap::new_from_map(HashMap::from_iter([
+                    (key("t"), scroll_to("line to top", ScrollPosition::Top)),
+                    (
+                        key("z"),
+                        scroll_to("line to center", ScrollPosition::Center),
+                    ),
+                    (
+                        key("b"),
+                        scroll_to("line to bottom", ScrollPosition::Bottom),
+                    ),
+                ]))),
+            ),
+        ),
+    ]))
+}
+
+/// Keymap for text objects, i.e. the `iw` in `diw` or `a(` in `va(`
+fn text_object_keymap() -> Keymap<TextObject> {
+    let objects = |inner: bool| {
+        let object =
+            |desc: &str, kind| KeymapNode::Leaf(desc.to_string(), TextObject { kind, inner });
+        let parens = TextObjectKind::Bracketed {
+            open: '(',
+            close: ')',
+        };
+        let brackets = TextObjectKind::Bracketed {
+            open: '[',
+            close: ']',
+        };
+        let braces = TextObjectKind::Bracketed {
+            open: '{',
+            close: '}',
+        };
+        let angle_brackets = TextObjectKind::Bracketed {
+            open: '<',
+            close: '>',
+        };
+        Keymap::new_from_map(HashMap::from_iter([
+            (key("w"), object("word", TextObjectKind::Word)),
+            (
+                key("w").with_mods(Modifiers::SHIFT),
+                object("WORD", TextObjectKind::BigWord),
+            ),
+            (key("p"), object("paragraph", TextObjectKind::Paragraph)),
+            (key("s"), object("sentence", TextObjectKind::Sentence)),
+            (
+                translated_key("\""),
+                object("double quotes", TextObjectKind::Quoted('"')),
+            ),
+            (
+                translated_key("'"),
+                object("single quotes", TextObjectKind::Quoted('\'')),
+            ),
+            (
+                translated_key("`"),
+                object("backticks", TextObjectKind::Quoted('`')),
+            ),
+            (key("t"), object("tag", TextObjectKind::Tag)),
+            (translated_key("("), object("parentheses", parens)),
+            (translated_key(")"), object("parentheses", parens)),
+            (key("b"), object("parentheses", parens)),
+            (translated_key("["), object("brackets", brackets)),
+            (translated_key("]"), object("brackets", brackets)),
+            (translated_key("{"), object("braces", braces)),
+            (translated_key("}"), object("braces", braces)),
+            (
+                key("b").with_mods(Modifiers::SHIFT),
+                object("braces", braces),
+            ),
+            (
+                translated_key("<"),
+                object("angle brackets", angle_brackets),
+            ),
+            (
+                translated_key(">"),
+                object("angle brackets", angle_brackets),
+            ),
+        ]))
+    };
+    Keymap::new_from_map(HashMap::from_iter([
+        (
+            key("i"),
+            KeymapNode::Submap("inner".to_string(), Box::new(objects(true))),
+        ),
+        (
+            key("a"),
+            KeymapNode::Submap("around".to_string(), Box::new(objects(false))),
+        ),
+    ]))
+}
+
 fn movement_key_motion_keymap() -> Keymap<Motion<'static>> {
     Keymap::new_from_map(HashMap::from_iter([
         (
@@ -372,15 +2698,817 @@ fn translated_key(k: &str) -> Combo {
     Combo::from(KeySpec::Str(Key(k.to_string())))
 }
 
-fn leaf<F: Fn(&View, &mut Buffer, &mut VimInterface, KeyInput) + Send + Sync + 'static>(
+fn leaf<F: Fn(&mut View, &mut Buffer, &mut VimInterface, KeyInput) + Send + Sync + 'static>(
     desc: &str,
     f: F,
 ) -> KeymapNode<MappedFn> {
     KeymapNode::Leaf(desc.to_string(), mapping(f))
 }
 
-fn mapping<F: Fn(&View, &mut Buffer, &mut VimInterface, KeyInput) + Send + Sync + 'static>(
+fn mapping<F: Fn(&mut View, &mut Buffer, &mut VimInterface, KeyInput) + Send + Sync + 'static>(
     f: F,
 ) -> MappedFn {
     Arc::new(Box::new(f))
 }
+
+#[cfg(test)]
+mod test {
+    use bazed_input_mapper::input_event::{Key, KeyInput, Modifiers, RawKey};
+
+    use super::{VimInterface, VimMode};
+    use crate::{
+        buffer::Buffer,
+        document::DocumentId,
+        register::{RegisterContent, RegisterName},
+        test_util,
+        user_buffer_op::{BufferOp, Motion},
+        view::{View, Viewport},
+    };
+
+    /// Feed the given keys into the vim interface, one key per element
+    fn type_keys(vim: &mut VimInterface, view: &mut View, buffer: &mut Buffer, keys: &[&str]) {
+        for key in keys {
+            let input = KeyInput {
+                modifiers: Modifiers::empty(),
+                key: Key(key.to_string()),
+                code: RawKey::from(*key),
+            };
+            vim.on_input(view, buffer, input);
+        }
+    }
+
+    /// Input of a key pressed together with the given modifiers, i.e. `Ctrl-v`
+    fn modified_key(modifiers: Modifiers, key: &str) -> KeyInput {
+        KeyInput {
+            modifiers,
+            key: Key(key.to_string()),
+            code: RawKey::from(key),
+        }
+    }
+
+    fn setup(text: &str) -> (VimInterface, View, Buffer) {
+        let view = View::new(DocumentId::gen(), Viewport::new_ginormeous());
+        (
+            VimInterface::new(),
+            view,
+            Buffer::new_from_string(text.to_string()),
+        )
+    }
+
+    #[test]
+    fn test_repeat_operator() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo bar baz");
+        type_keys(&mut vim, &mut v, &mut b, &["d", "w", "."]);
+        assert_eq!("baz", b.content_to_string());
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a\n  b\n");
+        type_keys(&mut vim, &mut v, &mut b, &["d", "d"]);
+        assert_eq!("  b\n", b.content_to_string());
+        assert_eq!(2, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_count_followed_by_digit_target() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a1b1c1d1e1fghijklmn");
+        type_keys(&mut vim, &mut v, &mut b, &["d", "2", "f", "1"]);
+        assert_eq!("c1d1e1fghijklmn", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["2", "r", "0"]);
+        assert_eq!("00d1e1fghijklmn", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["0", "1", "0", "l"]);
+        assert_eq!(10, b.primary_caret().head);
+
+        let (mut vim, mut v, mut b) = setup("x");
+        type_keys(&mut vim, &mut v, &mut b, &["\"", "a", "y", "l"]);
+        type_keys(&mut vim, &mut v, &mut b, &["2", "\"", "a", "p"]);
+        assert_eq!("xxx", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["\"", "a", "2", "p"]);
+        assert_eq!("xxxxx", b.content_to_string());
+    }
+
+    #[test]
+    fn test_counted_text_objects() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("one two three four");
+        type_keys(&mut vim, &mut v, &mut b, &["d", "2", "a", "w"]);
+        assert_eq!("three four", b.content_to_string());
+        let (mut vim, mut v, mut b) = setup("f(a, (b))");
+        type_keys(&mut vim, &mut v, &mut b, &[
+            "f", "b", "c", "2", "i", "(", "x",
+        ]);
+        assert_eq!("f(x)", b.content_to_string());
+    }
+
+    #[test]
+    fn test_repeat_count_overrides_original() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a b c d e f");
+        type_keys(&mut vim, &mut v, &mut b, &["2", "d", "w", "."]);
+        assert_eq!("e f", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["1", "."]);
+        assert_eq!("f", b.content_to_string());
+    }
+
+    #[test]
+    fn test_repeat_insert() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("");
+        type_keys(&mut vim, &mut v, &mut b, &["i", "a", "b", "Escape", "."]);
+        assert_eq!("abab", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["2", "."]);
+        assert_eq!("abababab", b.content_to_string());
+    }
+
+    #[test]
+    fn test_repeat_change_is_own_undo_group() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo foo foo");
+        type_keys(&mut vim, &mut v, &mut b, &[
+            "c", "w", "b", "a", "r", "Escape",
+        ]);
+        type_keys(&mut vim, &mut v, &mut b, &["w", ".", "w", "."]);
+        assert_eq!("bar bar bar", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["u"]);
+        assert_eq!("bar bar foo", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["u"]);
+        assert_eq!("bar foo foo", b.content_to_string());
+    }
+
+    #[test]
+    fn test_repeat_with_multiple_carets() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo\nfoo");
+        b.apply_buffer_op(&v.vp, BufferOp::NewCaret(Motion::Down));
+        type_keys(&mut vim, &mut v, &mut b, &["x", "."]);
+        assert_eq!("o\no", b.content_to_string());
+    }
+
+    #[test]
+    fn test_repeat_visual_operator() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("abcdef");
+        type_keys(&mut vim, &mut v, &mut b, &["v", "l", "l", "d"]);
+        assert_eq!("cdef", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["."]);
+        assert_eq!("ef", b.content_to_string());
+    }
+
+    #[test]
+    fn test_record_and_play_macro() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a b c d e");
+        type_keys(&mut vim, &mut v, &mut b, &["q", "a", "d", "w", "q"]);
+        assert_eq!("b c d e", b.content_to_string());
+        assert_eq!(
+            Some(&RegisterContent::new(vec!["dw".to_string()], false)),
+            vim.registers.get(RegisterName::from_char('a').unwrap())
+        );
+        type_keys(&mut vim, &mut v, &mut b, &["@", "a"]);
+        assert_eq!("c d e", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["@", "@"]);
+        assert_eq!("d e", b.content_to_string());
+    }
+
+    #[test]
+    fn test_macro_stops_at_failing_motion() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("ab\ncd\nef");
+        type_keys(&mut vim, &mut v, &mut b, &["q", "a", "x", "j", "q"]);
+        type_keys(&mut vim, &mut v, &mut b, &["5", "@", "a"]);
+        assert_eq!("b\nd\nf", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["u"]);
+        assert_eq!("b\ncd\nef", b.content_to_string());
+    }
+
+    #[test]
+    fn test_play_macro_edited_as_text() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo");
+        vim.registers.record_yank(
+            RegisterName::from_char('b'),
+            RegisterContent::new(vec!["ia<lt><Escape>".to_string()], false),
+        );
+        type_keys(&mut vim, &mut v, &mut b, &["@", "b"]);
+        assert_eq!("a<foo", b.content_to_string());
+        assert_eq!(VimMode::Normal, vim.mode);
+    }
+
+    #[test]
+    fn test_search() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo bar baz bar");
+        type_keys(&mut vim, &mut v, &mut b, &["/", "b", "a"]);
+        assert_eq!(4, b.primary_caret().head, "Search previews the first match");
+        assert_eq!(Some("/ba".to_string()), vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["r", "Enter"]);
+        assert_eq!(4, b.primary_caret().head);
+        assert_eq!(None, vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["n"]);
+        assert_eq!(12, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["N"]);
+        assert_eq!(4, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["?", "Enter"]);
+        assert_eq!(
+            12,
+            b.primary_caret().head,
+            "Empty pattern repeats the last search"
+        );
+    }
+
+    #[test]
+    fn test_cancel_search() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo bar");
+        type_keys(&mut vim, &mut v, &mut b, &["/", "b", "Escape"]);
+        assert_eq!(0, b.primary_caret().head);
+        assert_eq!(VimMode::Normal, vim.mode);
+    }
+
+    #[test]
+    fn test_search_word_at_caret() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo foobar bar foo");
+        type_keys(&mut vim, &mut v, &mut b, &["*"]);
+        assert_eq!(15, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["n"]);
+        assert_eq!(0, b.primary_caret().head, "Search wraps around");
+        type_keys(&mut vim, &mut v, &mut b, &["w", "w", "#"]);
+        assert_eq!(11, b.primary_caret().head);
+        let (mut vim, mut v, mut b) = setup("über x über\n");
+        type_keys(&mut vim, &mut v, &mut b, &["*"]);
+        assert_eq!(8, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_command_line() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("b\na\nc\n");
+        type_keys(&mut vim, &mut v, &mut b, &[":", "s", "o", "r", "t"]);
+        assert_eq!(VimMode::Command, vim.mode);
+        assert_eq!(Some(":sort".to_string()), vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["Enter"]);
+        assert_eq!("a\nb\nc\n", b.content_to_string());
+        assert_eq!(VimMode::Normal, vim.mode);
+        assert_eq!(None, vim.command_line());
+    }
+
+    #[test]
+    fn test_command_on_visual_selection() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("c\nb\na\nd\n");
+        type_keys(&mut vim, &mut v, &mut b, &["j", "v", "j", ":"]);
+        assert_eq!(Some(":'<,'>".to_string()), vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["s", "o", "r", "Enter"]);
+        assert_eq!("c\na\nb\nd\n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_command_completion() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("");
+        type_keys(&mut vim, &mut v, &mut b, &[
+            ":", "s", "e", "t", " ", "i", "Tab",
+        ]);
+        assert_eq!(Some(":set ignorecase".to_string()), vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["Tab"]);
+        assert_eq!(Some(":set incsearch".to_string()), vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["Tab"]);
+        assert_eq!(Some(":set ignorecase".to_string()), vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["!", "Enter"]);
+        assert!(!vim.options.ignorecase);
+    }
+
+    #[test]
+    fn test_command_error_message() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo");
+        type_keys(&mut vim, &mut v, &mut b, &[":", "f", "o", "o", "Enter"]);
+        assert_eq!(
+            Some("Not an editor command: foo".to_string()),
+            vim.command_line()
+        );
+        type_keys(&mut vim, &mut v, &mut b, &["l"]);
+        assert_eq!(None, vim.command_line());
+    }
+
+    #[test]
+    fn test_marks() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo\nbar\nbaz");
+        type_keys(&mut vim, &mut v, &mut b, &[
+            "j", "l", "m", "a", "/", "z", "Enter",
+        ]);
+        assert_eq!(10, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["'", "a"]);
+        assert_eq!(4, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["`", "a"]);
+        assert_eq!(5, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["`", "`"]);
+        assert_eq!(4, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["`", "`"]);
+        assert_eq!(5, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["k", "d", "'", "a"]);
+        assert_eq!("baz", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["'", "z"]);
+        assert_eq!(0, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_marks_on_deleted_lines() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a\nb\nc");
+        type_keys(&mut vim, &mut v, &mut b, &[
+            "j", "j", "m", "b", "k", "m", "a", "d", "d",
+        ]);
+        type_keys(&mut vim, &mut v, &mut b, &["'", "a"]);
+        assert!(vim.command_failed);
+        type_keys(&mut vim, &mut v, &mut b, &["'", "b"]);
+        assert!(!vim.command_failed);
+        assert_eq!(2, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["d", "d", "'", "b"]);
+        assert!(vim.command_failed);
+        assert_eq!("a", b.content_to_string());
+    }
+
+    #[test]
+    fn test_automatic_marks() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo bar");
+        type_keys(&mut vim, &mut v, &mut b, &["w", "i", "x", "Escape"]);
+        assert_eq!(Some(4), b.mark_offset('.'));
+        assert_eq!(Some(5), b.mark_offset('^'));
+        type_keys(&mut vim, &mut v, &mut b, &["0", "v", "l", "Escape"]);
+        assert_eq!(Some(0), b.mark_offset('<'));
+        assert_eq!(Some(1), b.mark_offset('>'));
+    }
+
+    #[test]
+    fn test_marks_in_command_range() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("c\nb\na\nd\n");
+        type_keys(&mut vim, &mut v, &mut b, &["m", "a", "j", "j"]);
+        type_keys(&mut vim, &mut v, &mut b, &[
+            ":", "'", "a", ",", ".", "s", "o", "r", "Enter",
+        ]);
+        assert_eq!("a\nb\nc\nd\n", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &[
+            ":", "4", "m", "a", " ", "b", "Enter",
+        ]);
+        type_keys(&mut vim, &mut v, &mut b, &["'", "b"]);
+        assert_eq!(6, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &[":", "'", "x", "d", "Enter"]);
+        assert_eq!(Some("Mark not set: x".to_string()), vim.command_line());
+    }
+
+    #[test]
+    fn test_jump_list() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a\nb\nc\nd\n");
+        let ctrl_o = KeyInput {
+            modifiers: Modifiers::CTRL,
+            key: Key("o".to_string()),
+            code: RawKey::from("o"),
+        };
+        type_keys(&mut vim, &mut v, &mut b, &[
+            "/", "c", "Enter", "/", "d", "Enter",
+        ]);
+        assert_eq!(6, b.primary_caret().head);
+        assert!(vim.on_input(&mut v, &mut b, ctrl_o.clone()));
+        assert_eq!(4, b.primary_caret().head);
+        assert!(vim.on_input(&mut v, &mut b, ctrl_o.clone()));
+        assert_eq!(0, b.primary_caret().head);
+        assert!(!vim.on_input(&mut v, &mut b, ctrl_o));
+        type_keys(&mut vim, &mut v, &mut b, &["2", "Tab"]);
+        assert_eq!(6, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_change_list() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo\nbar\nbaz\n");
+        type_keys(&mut vim, &mut v, &mut b, &["i", "x", "Escape", "j", "j"]);
+        let second_change = b.primary_caret().head;
+        type_keys(&mut vim, &mut v, &mut b, &[
+            "i", "y", "Escape", "k", "g", ";",
+        ]);
+        assert_eq!(second_change, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["g", ";"]);
+        assert_eq!(0, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["g", ","]);
+        assert_eq!(second_change, b.primary_caret().head);
+    }
+
+    #[test]
+    fn test_switch_visual_modes() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo");
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "V"));
+        assert_eq!(VimMode::VisualLine, vim.mode);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "v"));
+        assert_eq!(VimMode::VisualBlock, vim.mode);
+        type_keys(&mut vim, &mut v, &mut b, &["v"]);
+        assert_eq!(VimMode::Visual, vim.mode);
+        type_keys(&mut vim, &mut v, &mut b, &["v"]);
+        assert_eq!(VimMode::Normal, vim.mode);
+    }
+
+    #[test]
+    fn test_visual_line_mode() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a\nb\nc\nd\ne\n");
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "V"));
+        type_keys(&mut vim, &mut v, &mut b, &["j", "d"]);
+        assert_eq!("c\nd\ne\n", b.content_to_string());
+        assert_eq!(VimMode::Normal, vim.mode);
+        type_keys(&mut vim, &mut v, &mut b, &["."]);
+        assert_eq!("e\n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_visual_block_delete() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("abcd\nab\nabcd\n");
+        type_keys(&mut vim, &mut v, &mut b, &["l"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "v"));
+        type_keys(&mut vim, &mut v, &mut b, &["j", "j", "l", "l", "d"]);
+        assert_eq!("ad\na\nad\n", b.content_to_string());
+        assert_eq!(VimMode::Normal, vim.mode);
+        assert_eq!(1, b.all_carets().len());
+        assert_eq!(1, b.primary_caret().head);
+
+        let (mut vim, mut v, mut b) = setup("abcd\néé\nabcd");
+        type_keys(&mut vim, &mut v, &mut b, &["l"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "v"));
+        type_keys(&mut vim, &mut v, &mut b, &["j", "j", "l", "d"]);
+        assert_eq!("acd\né\nacd", b.content_to_string());
+    }
+
+    #[test]
+    fn test_visual_block_insert() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("abcd\na\nabcd");
+        type_keys(&mut vim, &mut v, &mut b, &["l", "l"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "v"));
+        type_keys(&mut vim, &mut v, &mut b, &["j", "j", "l"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "I"));
+        type_keys(&mut vim, &mut v, &mut b, &["x", "Escape"]);
+        // The short line in between doesn't reach the block
+        assert_eq!("abxcd\na\nabxcd", b.content_to_string());
+
+        let (mut vim, mut v, mut b) = setup("abcd\na\nabcd");
+        type_keys(&mut vim, &mut v, &mut b, &["l", "l"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "v"));
+        type_keys(&mut vim, &mut v, &mut b, &["j", "j", "l"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "A"));
+        type_keys(&mut vim, &mut v, &mut b, &["x", "Escape"]);
+        assert_eq!("abcxd\nax\nabcxd", b.content_to_string());
+
+        let (mut vim, mut v, mut b) = setup("abcd\néèé\nabcd");
+        type_keys(&mut vim, &mut v, &mut b, &["l", "l"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "v"));
+        type_keys(&mut vim, &mut v, &mut b, &["j", "j", "l"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "I"));
+        type_keys(&mut vim, &mut v, &mut b, &["x", "Escape"]);
+        assert_eq!("abxcd\néèxé\nabxcd", b.content_to_string());
+    }
+
+    #[test]
+    fn test_visual_block_to_line_end() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("ab\nabcd\nabc");
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "v"));
+        type_keys(&mut vim, &mut v, &mut b, &["$", "j", "j"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "A"));
+        type_keys(&mut vim, &mut v, &mut b, &["!", "Escape"]);
+        assert_eq!("ab!\nabcd!\nabc!", b.content_to_string());
+    }
+
+    #[test]
+    fn test_visual_block_change() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("abcd\nabcd");
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "v"));
+        type_keys(&mut vim, &mut v, &mut b, &[
+            "j", "l", "l", "c", "x", "Escape",
+        ]);
+        assert_eq!("xcd\nxcd", b.content_to_string());
+    }
+
+    #[test]
+    fn test_select_matches() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo bar foo");
+        type_keys(&mut vim, &mut v, &mut b, &["v", "$", "s", "f", "o"]);
+        assert_eq!(Some("select:fo".to_string()), vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["o", "Enter"]);
+        assert_eq!(VimMode::Visual, vim.mode);
+        assert_eq!(2, b.all_carets().len());
+        type_keys(&mut vim, &mut v, &mut b, &["d"]);
+        assert_eq!(" bar ", b.content_to_string());
+
+        let (mut vim, mut v, mut b) = setup("foo bar foo");
+        type_keys(&mut vim, &mut v, &mut b, &["v", "$", "s", "x", "Enter"]);
+        assert_eq!(1, b.all_carets().len());
+        assert_eq!(0..11, b.primary_caret().range());
+
+        let (mut vim, mut v, mut b) = setup("foo bar foo");
+        type_keys(&mut vim, &mut v, &mut b, &["v", "$"]);
+        let alt_shift_s = modified_key(Modifiers::ALT | Modifiers::SHIFT, "S");
+        vim.on_input(&mut v, &mut b, alt_shift_s);
+        type_keys(&mut vim, &mut v, &mut b, &[" "]);
+        assert_eq!(Some("split: ".to_string()), vim.command_line());
+        type_keys(&mut vim, &mut v, &mut b, &["Enter"]);
+        let ranges = b.all_carets().map(|x| x.range());
+        assert_eq!(
+            vec![0..3, 4..7, 8..11],
+            ranges.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_editing_commands() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo Bar\n  baz 0x0f -3");
+        type_keys(&mut vim, &mut v, &mut b, &["~", "~", "."]);
+        assert_eq!("FOO Bar\n  baz 0x0f -3", b.content_to_string());
+        assert_eq!(3, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "J"));
+        assert_eq!("FOO Bar baz 0x0f -3", b.content_to_string());
+        assert_eq!(7, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "a"));
+        assert_eq!("FOO Bar baz 0x10 -3", b.content_to_string());
+        assert_eq!(15, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["$", "h", "5"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::CTRL, "x"));
+        assert_eq!("FOO Bar baz 0x10 -8", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["0", "3", "r", "x"]);
+        assert_eq!("xxx Bar baz 0x10 -8", b.content_to_string());
+        assert_eq!(2, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["u"]);
+        assert_eq!("FOO Bar baz 0x10 -8", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["r", "x", "u", "u"]);
+        assert_eq!("FOO Bar baz 0x10 -3", b.content_to_string());
+
+        let (mut vim, mut v, mut b) = setup("a\n  b\nc");
+        type_keys(&mut vim, &mut v, &mut b, &["g"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "J"));
+        type_keys(&mut vim, &mut v, &mut b, &["j", "r", "x"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "V"));
+        type_keys(&mut vim, &mut v, &mut b, &["r", "y"]);
+        assert_eq!("a  b\ny", b.content_to_string());
+        assert_eq!(VimMode::Normal, vim.mode);
+
+        let (mut vim, mut v, mut b) = setup("abc\ndef");
+        type_keys(&mut vim, &mut v, &mut b, &["$", "r", "x"]);
+        assert_eq!("abx\ndef", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["$", "~"]);
+        assert_eq!("abX\ndef", b.content_to_string());
+        assert_eq!(0, b.primary_caret_position().line);
+        type_keys(&mut vim, &mut v, &mut b, &["0", "5", "r", "y"]);
+        assert!(vim.command_failed);
+        type_keys(&mut vim, &mut v, &mut b, &["$", "~", "0", "5", "~"]);
+        assert!(!vim.command_failed);
+        assert_eq!("ABX\ndef", b.content_to_string());
+        assert_eq!(0, b.primary_caret_position().line);
+        assert_eq!(3, b.primary_caret_position().col);
+    }
+
+    #[test]
+    fn test_surround() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo bar");
+        type_keys(&mut vim, &mut v, &mut b, &["y", "s", "i", "w", ")"]);
+        assert_eq!("(foo) bar", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["c", "s", "b", "]"]);
+        assert_eq!("[foo] bar", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &[
+            "c", "s", "]", "t", "e", "m", "Enter",
+        ]);
+        assert_eq!("<em>foo</em> bar", b.content_to_string());
+        assert_eq!(VimMode::Normal, vim.mode);
+        type_keys(&mut vim, &mut v, &mut b, &["d", "s", "t"]);
+        assert_eq!("foo bar", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["d", "s", "t"]);
+        assert!(vim.command_failed);
+        type_keys(&mut vim, &mut v, &mut b, &["y", "s", "s", "\""]);
+        assert_eq!("\"foo bar\"", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["u"]);
+        assert_eq!("foo bar", b.content_to_string());
+
+        type_keys(&mut vim, &mut v, &mut b, &["w", "v", "e"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "S"));
+        type_keys(&mut vim, &mut v, &mut b, &["<", "b", ">"]);
+        assert_eq!("foo <b>bar</b>", b.content_to_string());
+        assert_eq!(VimMode::Normal, vim.mode);
+    }
+
+    #[test]
+    fn test_select_next_occurrence() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo bar foo");
+        let alt_d = modified_key(Modifiers::ALT, "d");
+        vim.on_input(&mut v, &mut b, alt_d.clone());
+        assert_eq!(VimMode::Visual, vim.mode);
+        assert_eq!(0..3, b.primary_caret().range());
+        vim.on_input(&mut v, &mut b, alt_d.clone());
+        assert_eq!(8..11, b.primary_caret().range());
+        type_keys(&mut vim, &mut v, &mut b, &["c", "x", "Escape"]);
+        assert_eq!("x bar x", b.content_to_string());
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup(
+            "if (a[0]) {
+  b
+}
+",
+        );
+        type_keys(&mut vim, &mut v, &mut b, &["%"]);
+        assert_eq!(8, b.primary_caret().head);
+        assert_eq!(Some((8, 3)), b.matching_pair(&v.vp));
+        type_keys(&mut vim, &mut v, &mut b, &["%"]);
+        assert_eq!(3, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["$", "h"]);
+        assert_eq!(Some((10, 16)), b.matching_pair(&v.vp));
+        let first_line = Viewport {
+            first_line: 0,
+            height: 1,
+        };
+        assert_eq!(None, b.matching_pair(&first_line));
+        type_keys(&mut vim, &mut v, &mut b, &["0", "f", "("]);
+        type_keys(&mut vim, &mut v, &mut b, &["w", "%"]);
+        assert_eq!(7, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["j", "%"]);
+        assert!(vim.command_failed);
+        type_keys(&mut vim, &mut v, &mut b, &["d", "%"]);
+        assert_eq!("if (a[0]) {\n  b\n}\n", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["k", "$", "h", "d", "%"]);
+        assert_eq!("if (a[0]) \n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_find_in_line() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a,b;c,d\n");
+        type_keys(&mut vim, &mut v, &mut b, &["f", ";"]);
+        assert_eq!(3, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &[";"]);
+        assert!(vim.command_failed);
+        type_keys(&mut vim, &mut v, &mut b, &["f", ",", ","]);
+        assert_eq!(1, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &[";"]);
+        assert_eq!(5, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "T"));
+        type_keys(&mut vim, &mut v, &mut b, &["b"]);
+        assert_eq!(3, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["0", "t", ","]);
+        assert!(
+            vim.command_failed,
+            "till stays in front of an adjacent match"
+        );
+        type_keys(&mut vim, &mut v, &mut b, &[";"]);
+        assert_eq!(
+            4,
+            b.primary_caret().head,
+            "repeated till skips an adjacent match"
+        );
+        type_keys(&mut vim, &mut v, &mut b, &["0", "v", "2", "f", ",", "d"]);
+        assert_eq!(",d\n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_find_in_line_operator() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a👍b👍🏽c👍d");
+        type_keys(&mut vim, &mut v, &mut b, &["d", "f", "👍"]);
+        assert_eq!("b👍🏽c👍d", b.content_to_string());
+        type_keys(&mut vim, &mut v, &mut b, &["."]);
+        assert_eq!("d", b.content_to_string());
+        let (mut vim, mut v, mut b) = setup("foo(bar, baz)");
+        type_keys(&mut vim, &mut v, &mut b, &["$", "h", "d"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "F"));
+        type_keys(&mut vim, &mut v, &mut b, &["("]);
+        assert_eq!("foo)", b.content_to_string());
+
+        let (mut vim, mut v, mut b) = setup("a0b0c0d");
+        type_keys(&mut vim, &mut v, &mut b, &["d", "2", "t", "0"]);
+        assert_eq!("0c0d", b.content_to_string());
+        let (mut vim, mut v, mut b) = setup("a0b0c0d");
+        type_keys(&mut vim, &mut v, &mut b, &["2", "d", "t", "0"]);
+        assert_eq!("0c0d", b.content_to_string());
+    }
+
+    #[test]
+    fn test_word_motions() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo.bar bazQux_quux\n");
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "W"));
+        assert_eq!(8, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::ALT, "w"));
+        assert_eq!(11, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::ALT, "w"));
+        assert_eq!(15, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::ALT, "b"));
+        assert_eq!(11, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::ALT, "e"));
+        assert_eq!(14, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "B"));
+        assert_eq!(8, b.primary_caret().head);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "E"));
+        assert_eq!(19, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["g"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "E"));
+        assert_eq!(7, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["0", "c"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "W"));
+        type_keys(&mut vim, &mut v, &mut b, &["x", "Escape", "w", "c"]);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::ALT, "w"));
+        type_keys(&mut vim, &mut v, &mut b, &["y", "Escape"]);
+        assert_eq!("x yQux_quux\n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_paragraph_and_sentence_motions() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("foo\nbar\n\nNext one. Last one.\n");
+        type_keys(&mut vim, &mut v, &mut b, &["}"]);
+        assert_eq!(8, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &[")"]);
+        assert_eq!(9, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &[")"]);
+        assert_eq!(19, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["(", "("]);
+        assert_eq!(0, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["}", "}", "{"]);
+        assert_eq!(8, b.primary_caret().head);
+        type_keys(&mut vim, &mut v, &mut b, &["(", "d", "}"]);
+        assert_eq!("\n\nNext one. Last one.\n", b.content_to_string());
+    }
+
+    #[test]
+    fn test_line_jumps() {
+        test_util::setup_test();
+        let (mut vim, mut v, mut b) = setup("a\nb\nc\nd");
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "G"));
+        assert_eq!(3, b.primary_caret_position().line);
+        type_keys(&mut vim, &mut v, &mut b, &["g", "g"]);
+        assert_eq!(0, b.primary_caret_position().line);
+        type_keys(&mut vim, &mut v, &mut b, &["3", "g", "g"]);
+        assert_eq!(2, b.primary_caret_position().line);
+        type_keys(&mut vim, &mut v, &mut b, &["5", "0", "%"]);
+        assert_eq!(1, b.primary_caret_position().line);
+        type_keys(&mut vim, &mut v, &mut b, &["`", "`"]);
+        assert_eq!(2, b.primary_caret_position().line);
+        type_keys(&mut vim, &mut v, &mut b, &["d", "g", "g"]);
+        assert_eq!("d", b.content_to_string());
+    }
+
+    #[test]
+    fn test_scrolling() {
+        test_util::setup_test();
+        let text: String = (0..50).map(|x| format!("{x}\n")).collect();
+        let (mut vim, mut v, mut b) = setup(&text);
+        v.vp = Viewport::new(0, 10);
+        let ctrl = |key| modified_key(Modifiers::CTRL, key);
+        vim.on_input(&mut v, &mut b, ctrl("d"));
+        assert_eq!((5, 8), (v.vp.first_line, b.primary_caret_position().line));
+        vim.on_input(&mut v, &mut b, ctrl("u"));
+        assert_eq!((0, 3), (v.vp.first_line, b.primary_caret_position().line));
+        vim.on_input(&mut v, &mut b, ctrl("u"));
+        assert_eq!((0, 0), (v.vp.first_line, b.primary_caret_position().line));
+        assert!(!vim.command_failed);
+        vim.on_input(&mut v, &mut b, ctrl("u"));
+        assert!(vim.command_failed);
+        // The caret is moved along when it would leave the scroll-off
+        type_keys(&mut vim, &mut v, &mut b, &["2"]);
+        vim.on_input(&mut v, &mut b, ctrl("e"));
+        assert_eq!((2, 5), (v.vp.first_line, b.primary_caret_position().line));
+        vim.on_input(&mut v, &mut b, ctrl("y"));
+        assert_eq!((1, 5), (v.vp.first_line, b.primary_caret_position().line));
+        vim.on_input(&mut v, &mut b, ctrl("f"));
+        assert_eq!((9, 12), (v.vp.first_line, b.primary_caret_position().line));
+        vim.on_input(&mut v, &mut b, ctrl("b"));
+        assert_eq!((1, 7), (v.vp.first_line, b.primary_caret_position().line));
+        type_keys(&mut vim, &mut v, &mut b, &["z", "t"]);
+        assert_eq!(4, v.vp.first_line);
+        type_keys(&mut vim, &mut v, &mut b, &["z", "b"]);
+        assert_eq!(1, v.vp.first_line);
+        type_keys(&mut vim, &mut v, &mut b, &["z", "z"]);
+        assert_eq!(2, v.vp.first_line);
+        // Viewport motions stay within the scroll-off, such that they don't scroll
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "L"));
+        assert_eq!(8, b.primary_caret_position().line);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "H"));
+        assert_eq!(5, b.primary_caret_position().line);
+        vim.on_input(&mut v, &mut b, modified_key(Modifiers::SHIFT, "M"));
+        assert_eq!(6, b.primary_caret_position().line);
+        assert_eq!(2, v.vp.first_line);
+    }
+}
@@ -1,4 +1,7 @@
-//! Traverse a text, looking for word boundaries.
+//! Traverse a text, looking for word boundaries,
+//! as well as for the boundaries of larger units of text like sentences and paragraphs.
+
+use std::ops::Range;
 
 use unicode_general_category::GeneralCategory;
 use xi_rope::{interval::IntervalBounds, Cursor, Interval, Rope, RopeInfo};
@@ -11,9 +14,7 @@ pub(crate) fn find_word_boundaries(
     rope: &Rope,
     start_at: usize,
 ) -> impl Iterator<Item = (usize, WordBoundaryType)> + '_ {
-    WordBoundaries::from_iter(false, rope.iter_chunks(start_at..).flat_map(|c| c.chars()))
-        .map(move |(offset, t)| (offset + start_at, t))
-        .chain(std::iter::once((rope.len(), WordBoundaryType::Both)))
+    find_boundaries(rope, start_at, WordKind::Word)
 }
 
 /// Search backwards for any word boundaries in a rope, starting at a given offset.
@@ -24,7 +25,32 @@ pub(crate) fn find_word_boundaries_backwards(
     rope: &Rope,
     start_at: usize,
 ) -> impl Iterator<Item = (usize, WordBoundaryType)> + '_ {
-    WordBoundaries::from_iter(
+    find_boundaries_backwards(rope, start_at, WordKind::Word)
+}
+
+/// Search forwards for the boundaries of the given kind of word, like [find_word_boundaries].
+pub(crate) fn find_boundaries(
+    rope: &Rope,
+    start_at: usize,
+    kind: WordKind,
+) -> impl Iterator<Item = (usize, WordBoundaryType)> + '_ {
+    WordBoundaries::new(
+        kind,
+        false,
+        rope.iter_chunks(start_at..).flat_map(|c| c.chars()),
+    )
+    .map(move |(offset, t)| (offset + start_at, t))
+    .chain(std::iter::once((rope.len(), WordBoundaryType::Both)))
+}
+
+/// Search backwards for the boundaries of the given kind of word, like [find_word_boundaries_backwards].
+pub(crate) fn find_boundaries_backwards(
+    rope: &Rope,
+    start_at: usize,
+    kind: WordKind,
+) -> impl Iterator<Item = (usize, WordBoundaryType)> + '_ {
+    WordBoundaries::new(
+        kind,
         true,
         iter_rope_chunks_reverse(rope, ..start_at).flat_map(|c| c.chars().rev()),
     )
@@ -32,6 +58,184 @@ pub(crate) fn find_word_boundaries_backwards(
     .chain(std::iter::once((0, WordBoundaryType::Both)))
 }
 
+/// Search forwards for the ends of paragraphs, i.e. the start of every blank line that follows a non-blank one.
+/// Note that the line containing the offset itself is not considered.
+///
+/// Will always yield the end of the text.
+pub(crate) fn find_paragraph_boundaries(
+    rope: &Rope,
+    start_at: usize,
+) -> impl Iterator<Item = usize> + '_ {
+    let last_line = rope.line_of_offset(rope.len());
+    (rope.line_of_offset(start_at) + 1..=last_line)
+        .filter(|line| is_blank_line(rope, *line) && !is_blank_line(rope, line - 1))
+        .map(|line| rope.offset_of_line(line))
+        .chain(std::iter::once(rope.len()))
+}
+
+/// Search backwards for the starts of paragraphs, i.e. the start of every blank line that precedes a non-blank one.
+/// Note that the line containing the offset itself is not considered.
+///
+/// Will always yield the start of the text.
+pub(crate) fn find_paragraph_boundaries_backwards(
+    rope: &Rope,
+    start_at: usize,
+) -> impl Iterator<Item = usize> + '_ {
+    (0..rope.line_of_offset(start_at))
+        .rev()
+        .filter(|line| is_blank_line(rope, *line) && !is_blank_line(rope, line + 1))
+        .map(|line| rope.offset_of_line(line))
+        .chain(std::iter::once(0))
+}
+
+/// Search forwards for the starts of sentences, as split by [split_sentences] within each paragraph.
+/// Note that the location at the offset itself is not considered.
+///
+/// Will always yield the end of the text.
+pub(crate) fn find_sentence_starts(
+    rope: &Rope,
+    start_at: usize,
+) -> impl Iterator<Item = usize> + '_ {
+    paragraphs(rope, rope.line_of_offset(start_at), false)
+        .flat_map(|paragraph| sentence_starts(rope, paragraph))
+        .filter(move |offset| *offset > start_at)
+        .chain(std::iter::once(rope.len()))
+}
+
+/// Search backwards for the starts of sentences, as split by [split_sentences] within each paragraph.
+/// Note that the location at the offset itself is not considered.
+///
+/// Will always yield the start of the text.
+pub(crate) fn find_sentence_starts_backwards(
+    rope: &Rope,
+    start_at: usize,
+) -> impl Iterator<Item = usize> + '_ {
+    paragraphs(rope, rope.line_of_offset(start_at), true)
+        .flat_map(|paragraph| sentence_starts(rope, paragraph).into_iter().rev())
+        .filter(move |offset| *offset < start_at)
+        .chain(std::iter::once(0))
+}
+
+/// Offsets of the starts of the sentences within a paragraph
+fn sentence_starts(rope: &Rope, paragraph: Range<usize>) -> Vec<usize> {
+    let content = rope.slice_to_cow(paragraph.clone());
+    split_sentences(content.trim_end())
+        .into_iter()
+        .map(|sentence| paragraph.start + sentence.start)
+        .collect()
+}
+
+/// Iterate over the ranges of paragraphs, i.e. runs of non-blank lines,
+/// starting with the one containing the given line, or the next one if the line is blank.
+/// Goes towards the start of the text if `backwards` is set.
+fn paragraphs(
+    rope: &Rope,
+    line: usize,
+    backwards: bool,
+) -> impl Iterator<Item = Range<usize>> + '_ {
+    let last_line = rope.line_of_offset(rope.len());
+    let step = move |line: usize| {
+        if backwards {
+            line.checked_sub(1)
+        } else {
+            Some(line + 1).filter(|x| *x <= last_line)
+        }
+    };
+    let mut next_line = Some(line);
+    std::iter::from_fn(move || {
+        let mut line = next_line.take()?;
+        while is_blank_line(rope, line) {
+            line = step(line)?;
+        }
+        let (mut first, mut last) = (line, line);
+        while first > 0 && !is_blank_line(rope, first - 1) {
+            first -= 1;
+        }
+        while last < last_line && !is_blank_line(rope, last + 1) {
+            last += 1;
+        }
+        next_line = step(if backwards { first } else { last });
+        Some(rope.offset_of_line(first)..rope.offset_of_line(last + 1))
+    })
+}
+
+/// Check if a line contains only whitespace
+pub(crate) fn is_blank_line(rope: &Rope, line: usize) -> bool {
+    rope.slice_to_cow(rope.offset_of_line(line)..rope.offset_of_line(line + 1))
+        .trim()
+        .is_empty()
+}
+
+/// Split a text into the ranges of its sentences, excluding the whitespace between them.
+///
+/// A sentence ends at a `.`, `!` or `?` that is followed by whitespace,
+/// optionally with closing brackets and quotes in between.
+pub(crate) fn split_sentences(s: &str) -> Vec<Range<usize>> {
+    let chars = s.char_indices().collect::<Vec<_>>();
+    let mut sentences = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let (offset, c) = chars[i];
+        i += 1;
+        if start.is_none() {
+            if c.is_whitespace() {
+                continue;
+            }
+            start = Some(offset);
+        }
+        if matches!(c, '.' | '!' | '?') {
+            while matches!(chars.get(i), Some((_, ')' | ']' | '"' | '\''))) {
+                i += 1;
+            }
+            match chars.get(i) {
+                None => break,
+                Some((end, c)) if c.is_whitespace() => {
+                    sentences.extend(start.take().map(|start| start..*end));
+                },
+                Some(_) => {},
+            }
+        }
+    }
+    sentences.extend(start.map(|start| start..s.len()));
+    sentences
+}
+
+/// What counts as a word when looking for word boundaries
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub(crate) enum WordKind {
+    /// A run of word characters or of punctuation, like a `word` in vim
+    Word,
+    /// A run of non-whitespace characters, like a `WORD` in vim
+    BigWord,
+    /// Part of an identifier in camelCase or snake_case.
+    /// Underscores separate subwords like whitespace,
+    /// and a new subword starts at an uppercase letter that follows a lowercase letter or digit,
+    /// or that starts a capitalized word after an acronym, as in `HTTPServer`.
+    Subword,
+}
+
+impl WordKind {
+    fn category(&self, c: char) -> CharCategory {
+        match (self, CharCategory::of_char(c)) {
+            (WordKind::BigWord, CharCategory::Whitespace) => CharCategory::Whitespace,
+            (WordKind::BigWord, _) => CharCategory::Word,
+            (WordKind::Subword, _) if c == '_' => CharCategory::Whitespace,
+            (_, category) => category,
+        }
+    }
+
+    /// The boundary between two adjacent characters, if there is one
+    fn boundary_between(&self, a: char, b: char) -> Option<WordBoundaryType> {
+        let boundary = WordBoundaryType::between_types(self.category(a), self.category(b));
+        let case_change = (a.is_lowercase() || a.is_numeric()) && b.is_uppercase();
+        match boundary {
+            None if *self == WordKind::Subword && case_change => Some(WordBoundaryType::Both),
+            boundary => boundary,
+        }
+    }
+}
+
 /// Type of a word-boundary.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub(crate) enum WordBoundaryType {
@@ -63,10 +267,6 @@ impl WordBoundaryType {
             (Punctuation, Whitespace) => Some(End),
         }
     }
-    pub(crate) fn between(a: char, b: char) -> Option<WordBoundaryType> {
-        Self::between_types(CharCategory::of_char(a), CharCategory::of_char(b))
-    }
-
     /// Compare two word boundaries, checking if they match.
     /// If either boundary is `Other` this will always match
     pub(crate) fn matches(&self, other: &Self) -> bool {
@@ -121,17 +321,26 @@ impl CharCategory {
 /// This iterator will not emit an `End`-boundary at the end of the text.
 pub(crate) struct WordBoundaries<I> {
     iter: I,
+    kind: WordKind,
     prev: Option<char>,
-    /// The index of the cursor. `prev` is to the left of this (effectively at `current_offset - 1`)
+    /// The character before `prev`, needed to find subword boundaries after acronyms
+    before_prev: Option<char>,
+    /// The byte offset of the next character, with `prev` being directly to the left of it.
     current_offset: usize,
     /// when true, the previous character and current character will be swapped in boundary checks
     reversing: bool,
 }
 impl<I: Iterator<Item = char>> WordBoundaries<I> {
-    pub(crate) fn from_iter<It: IntoIterator<IntoIter = I>>(reversing: bool, iter: It) -> Self {
+    pub(crate) fn new<It: IntoIterator<IntoIter = I>>(
+        kind: WordKind,
+        reversing: bool,
+        iter: It,
+    ) -> Self {
         Self {
             iter: iter.into_iter(),
+            kind,
             prev: None,
+            before_prev: None,
             current_offset: 0,
             reversing,
         }
@@ -143,18 +352,38 @@ impl<I: Iterator<Item = char>> Iterator for WordBoundaries<I> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            self.current_offset += 1;
             let cur = self.iter.next()?;
-            let prev = self.prev;
+            let offset = self.current_offset;
+            self.current_offset += cur.len_utf8();
+            let (before_prev, prev) = (self.before_prev, self.prev);
+            self.before_prev = prev;
             self.prev = Some(cur);
-            if let Some(prev) = prev {
-                let boundary = if self.reversing {
-                    WordBoundaryType::between(cur, prev)
+            let Some(prev) = prev else {
+                continue;
+            };
+            let boundary = if self.reversing {
+                self.kind.boundary_between(cur, prev)
+            } else {
+                self.kind.boundary_between(prev, cur)
+            };
+            if let Some(boundary) = boundary {
+                return Some((offset, boundary));
+            }
+            // In `HTTPServer`, the subword `Server` starts before the `S`,
+            // which is only known once the lowercase letter after it has been seen.
+            if let (WordKind::Subword, Some(before_prev)) = (self.kind, before_prev) {
+                let (a, b, c) = if self.reversing {
+                    (cur, prev, before_prev)
                 } else {
-                    WordBoundaryType::between(prev, cur)
+                    (before_prev, prev, cur)
                 };
-                if let Some(boundary) = boundary {
-                    return Some((self.current_offset - 1, boundary));
+                if a.is_uppercase() && b.is_uppercase() && c.is_lowercase() {
+                    let boundary_offset = if self.reversing {
+                        offset
+                    } else {
+                        offset - prev.len_utf8()
+                    };
+                    return Some((boundary_offset, WordBoundaryType::Both));
                 }
             }
         }
@@ -210,7 +439,12 @@ mod test {
     use super::{iter_rope_chunks_reverse, WordBoundaries};
     use crate::{
         test_util,
-        word_boundary::{find_word_boundaries, find_word_boundaries_backwards, WordBoundaryType},
+        word_boundary::{
+            find_boundaries, find_boundaries_backwards, find_paragraph_boundaries,
+            find_paragraph_boundaries_backwards, find_sentence_starts,
+            find_sentence_starts_backwards, find_word_boundaries, find_word_boundaries_backwards,
+            WordBoundaryType, WordKind,
+        },
     };
 
     #[test]
@@ -218,7 +452,7 @@ mod test {
         test_util::setup_test();
         use WordBoundaryType::*;
         fn boundaries(s: &str) -> Vec<(usize, WordBoundaryType)> {
-            WordBoundaries::from_iter(false, s.chars()).collect()
+            WordBoundaries::new(WordKind::Word, false, s.chars()).collect()
         }
         let actual = boundaries("foo foo...");
         assert_eq!(vec![(3, End), (4, Start), (7, Both)], actual);
@@ -230,6 +464,9 @@ mod test {
         assert_eq!(vec![(3, Both), (4, Both)], actual);
         let actual = boundaries("foo___");
         assert_eq!(vec![(3, Both)], actual);
+
+        let actual = boundaries("ä ö");
+        assert_eq!(vec![(2, End), (3, Start)], actual, "Offsets are in bytes");
     }
 
     #[test]
@@ -252,6 +489,102 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_big_word_boundaries() {
+        test_util::setup_test();
+        use WordBoundaryType::*;
+        fn boundaries(s: &str) -> Vec<(usize, WordBoundaryType)> {
+            WordBoundaries::new(WordKind::BigWord, false, s.chars()).collect()
+        }
+        assert_eq!(vec![(7, End), (8, Start)], boundaries("foo.bar baz"));
+        assert_eq!(vec![(1, Start), (5, End)], boundaries(" (a)! "));
+        assert_eq!(
+            vec![(4, Start), (3, End), (0, Both)],
+            find_boundaries_backwards(&Rope::from("a.b c.d"), 6, WordKind::BigWord)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn test_subword_boundaries() {
+        test_util::setup_test();
+        use WordBoundaryType::*;
+        let text = Rope::from("fooBar_baz HTTPServer");
+        assert_eq!(
+            vec![
+                (3, Both),
+                (6, End),
+                (7, Start),
+                (10, End),
+                (11, Start),
+                (15, Both),
+                (21, Both)
+            ],
+            find_boundaries(&text, 0, WordKind::Subword).collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            vec![
+                (15, Both),
+                (11, Start),
+                (10, End),
+                (7, Start),
+                (6, End),
+                (3, Both),
+                (0, Both)
+            ],
+            find_boundaries_backwards(&text, 21, WordKind::Subword).collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            vec![(4, Both)],
+            WordBoundaries::new(WordKind::Subword, false, "get2Xml".chars()).collect::<Vec<_>>(),
+            "Digits are part of subwords"
+        );
+    }
+
+    #[test]
+    fn test_paragraph_boundaries() {
+        test_util::setup_test();
+        let text = Rope::from("a\nb\n\nc\n\n\nd");
+        assert_eq!(
+            vec![4, 7, 10],
+            find_paragraph_boundaries(&text, 0).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![7, 10],
+            find_paragraph_boundaries(&text, 4).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![8, 4, 0],
+            find_paragraph_boundaries_backwards(&text, 9).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_sentence_starts() {
+        test_util::setup_test();
+        let text = Rope::from("Hello there. How are you?\n\nFine!  Thanks.");
+        assert_eq!(
+            vec![13, 27, 34, 41],
+            find_sentence_starts(&text, 0).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![27, 34, 41],
+            find_sentence_starts(&text, 20).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![34, 27, 13, 0],
+            find_sentence_starts_backwards(&text, 41)
+                .take(4)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![13, 0],
+            find_sentence_starts_backwards(&text, 20)
+                .take(2)
+                .collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_reverse_chunks_iter() {
         test_util::setup_test();
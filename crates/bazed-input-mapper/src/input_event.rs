@@ -1,7 +1,11 @@
 //! Input events, as they are received from a frontend.
 
+use std::fmt::Write;
+
 use serde::{Deserialize, Serialize};
 
+use crate::key_combo::KeyInputParseError;
+
 /// A combination of held [Modifiers] and a [Key].
 // TODO figure out normalization: Do we get `Shift+a` or do we get `Key::Char('A')`?
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -183,9 +187,108 @@ impl From<&str> for RawKey {
     }
 }
 
+/// Write a sequence of key inputs in a textual notation, which can be read back with [parse_key_sequence].
+///
+/// Key strings without modifiers are written as they are, any other input is written as
+/// `<{modifiers}-{key}>` or `<{key}>`, i.e. `<C-r>` or `<Escape>`.
+/// The keys `<` and `>` are written as `lt` and `gt` within brackets, i.e. `<lt>`.
+pub fn format_key_sequence(inputs: &[KeyInput]) -> String {
+    let mut out = String::new();
+    for input in inputs {
+        let key = match input.key.as_str() {
+            "<" => "lt",
+            ">" => "gt",
+            key => key,
+        };
+        if input.modifiers.is_empty() && input.key.is_key_string() && key == input.key.as_str() {
+            out.push_str(key);
+        } else if input.modifiers.is_empty() {
+            _ = write!(out, "<{key}>");
+        } else {
+            _ = write!(out, "<{}-{key}>", input.modifiers);
+        }
+    }
+    out
+}
+
+/// Parse a sequence of key inputs written in the notation produced by [format_key_sequence].
+///
+/// As the notation does not contain raw key codes, these are derived from the keys (see [RawKey::from]).
+pub fn parse_key_sequence(s: &str) -> Result<Vec<KeyInput>, KeyInputParseError> {
+    let mut inputs = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let end = rest.find('>').ok_or(KeyInputParseError::UnclosedBracket)?;
+            inputs.push(parse_bracketed_key_input(&rest[1..end])?);
+            rest = &rest[end + 1..];
+        } else {
+            inputs.push(KeyInput {
+                modifiers: Modifiers::empty(),
+                key: Key(c.to_string()),
+                code: RawKey::from(c.to_string().as_str()),
+            });
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Ok(inputs)
+}
+
+/// Parse the inside of a `<...>` in a key sequence, i.e. `C-r`
+fn parse_bracketed_key_input(s: &str) -> Result<KeyInput, KeyInputParseError> {
+    let mut modifiers = Modifiers::empty();
+    let mut rest = s;
+    // Every modifier is a single character followed by a `-`, anything after the last one is the key
+    loop {
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(m), Some('-')) if rest.len() > m.len_utf8() + 1 => {
+                modifiers |= Modifiers::from_char(m)
+                    .ok_or_else(|| KeyInputParseError::InvalidModifier(m.to_string()))?;
+                rest = &rest[m.len_utf8() + 1..];
+            },
+            _ => break,
+        }
+    }
+    let key = match rest {
+        "" => return Err(KeyInputParseError::EmptyInput),
+        "lt" => "<",
+        "gt" => ">",
+        key => key,
+    };
+    Ok(KeyInput {
+        modifiers,
+        key: Key(key.to_string()),
+        code: RawKey::from(key),
+    })
+}
+
 #[cfg(test)]
 mod test {
-    use super::Key;
+    use super::{format_key_sequence, parse_key_sequence, Key, KeyInput, Modifiers, RawKey};
+
+    #[test]
+    fn test_key_sequence_roundtrip() {
+        let input = |modifiers, key: &str| KeyInput {
+            modifiers,
+            key: Key(key.to_string()),
+            code: RawKey::from(key),
+        };
+        let inputs = vec![
+            input(Modifiers::empty(), "d"),
+            input(Modifiers::empty(), "2"),
+            input(Modifiers::empty(), "<"),
+            input(Modifiers::empty(), "Escape"),
+            input(Modifiers::CTRL, "r"),
+            input(Modifiers::CTRL | Modifiers::SHIFT, "-"),
+            input(Modifiers::empty(), "ä"),
+        ];
+        let text = format_key_sequence(&inputs);
+        assert_eq!("d2<lt><Escape><C-r><C-S-->ä", text);
+        assert_eq!(inputs, parse_key_sequence(&text).unwrap());
+        assert!(parse_key_sequence("a<Escape").is_err());
+        assert!(parse_key_sequence("<X-a>").is_err());
+    }
 
     #[test]
     fn test_key_string() {
@@ -140,6 +140,8 @@ pub enum KeyInputParseError {
     InvalidModifier(String),
     #[error("Input was empty")]
     EmptyInput,
+    #[error("Missing closing `>`")]
+    UnclosedBracket,
 }
 
 #[cfg(test)]
@@ -45,6 +45,15 @@ impl<V> KeymapNode<V> {
         }
     }
 
+    /// recursively replace the leaves of this node with the nodes returned by a function,
+    /// which gets the description and the value of each leaf
+    pub fn map_leaves<O>(self, f: &dyn Fn(String, V) -> KeymapNode<O>) -> KeymapNode<O> {
+        match self {
+            KeymapNode::Submap(d, submap) => KeymapNode::Submap(d, Box::new(submap.map_leaves(f))),
+            KeymapNode::Leaf(d, v) => f(d, v),
+        }
+    }
+
     /// Merge two keymap-nodes together recursively.
     /// If one of them is a leaf, `other` takes precedence.
     pub fn merge(self, other: KeymapNode<V>) -> Self {
@@ -104,6 +113,21 @@ impl<V> Keymap<V> {
         }
     }
 
+    /// recursively replace the leaves of this node with the nodes returned by a function,
+    /// i.e. to continue a sequence of keys with a submap after each of them
+    pub fn map_leaves<O>(self, f: &dyn Fn(String, V) -> KeymapNode<O>) -> Keymap<O> {
+        let map = self
+            .map
+            .into_iter()
+            .map(|(k, v)| (k, v.map_leaves(f)))
+            .collect();
+        let on_any_printable = self.on_any_printable.map(|v| v.map_leaves(f));
+        Keymap {
+            map,
+            on_any_printable,
+        }
+    }
+
     /// Get the [KeymapNode] corresponding to the given input, if there is one
     pub fn node_at_input(&self, input: &KeyInput) -> Option<&KeymapNode<V>> {
         // This is surprisingly complex, as we have to check the powerset of all modifiers
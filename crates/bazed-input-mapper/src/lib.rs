@@ -85,6 +85,19 @@ impl<V> InputMapper<V> {
         }
     }
 
+    /// Check if there are inputs buffered, meaning that we're currently inside of a submap.
+    pub fn has_buffered_inputs(&self) -> bool {
+        !self.buffered_inputs.is_empty()
+    }
+
+    /// Get the [KeymapNode] the given input would lead to, without actually handling the input.
+    pub fn peek(&self, input: &KeyInput) -> Option<&KeymapNode<V>> {
+        let active_keymap = self.keymaps.get(self.stack.last()).unwrap();
+        let mut inputs = self.buffered_inputs.clone();
+        inputs.push(input.clone());
+        active_keymap.node_at_path(&inputs)
+    }
+
     /// Handle a single key input.
     ///
     /// Buffers inputs when the input leads us to a submap.
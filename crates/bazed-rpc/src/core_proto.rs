@@ -30,9 +30,46 @@ pub struct CoordinateRegion {
 pub struct ViewData {
     pub first_line: usize,
     pub text: Vec<String>,
-    /// caret positions are absolute
+    /// caret positions are absolute.
+    /// In visual block mode, these are the lines of the selected block instead, one region per line.
     pub carets: Vec<CoordinateRegion>,
+    /// Name of the current vim mode, i.e. `VisualLine`, in which selections span whole lines
     pub vim_mode: String,
+    /// Count typed so far for the next vim command, if any
+    pub pending_count: Option<usize>,
+    /// Matches of the current search within the viewport, to be highlighted.
+    /// The tail of each region is the start of the match, the head is its end.
+    pub search_matches: Vec<CoordinateRegion>,
+    /// The bracket under the primary caret and the bracket matching it, to be highlighted.
+    /// Empty if the primary caret isn't on a bracket that has a match.
+    pub matching_pair: Vec<Coordinate>,
+    /// Text of the command line, if it is shown,
+    /// i.e. the command or search pattern being typed, or a message such as an error
+    pub command_line: Option<String>,
+    /// Set if the document has changes that haven't been saved yet
+    pub dirty: bool,
+}
+
+/// The undo history of a document, which branches wherever an edit was made after undoing.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct UndoTree {
+    /// All states of the document, in the order they were created in.
+    /// The first one is the state before any edit was made.
+    pub states: Vec<UndoTreeState>,
+    /// Index of the state the document is currently in
+    pub current: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct UndoTreeState {
+    /// Index of the state this one was created from by editing, `None` for the first state
+    pub parent: Option<usize>,
+    /// Time the state was created at, in milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// Set if the document was saved in this state
+    pub saved: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -47,6 +84,31 @@ pub enum ToFrontend {
     /// Sent whenever anything in the view changed, i.e. the content,
     /// the viewport, or a caret position
     UpdateView { view_id: Uuid, view_data: ViewData },
+    /// Sent when a view was closed, i.e. by `:q`
+    CloseView { view_id: Uuid },
+    /// Sent after trying to save the document of a view
+    DocumentSaved {
+        view_id: Uuid,
+        path: PathBuf,
+        /// Description of the error that kept the document from being saved, if any
+        error: Option<String>,
+    },
+    /// Sent when another program changed the file of the document of a view,
+    /// and the change was merged with the unsaved changes of the document.
+    /// Changes to documents without unsaved changes are simply reloaded, and sent as an [ToFrontend::UpdateView].
+    FileChanged {
+        view_id: Uuid,
+        path: PathBuf,
+        /// Set if the change and the unsaved changes touched the same part of the document,
+        /// in which case both were kept next to each other
+        conflict: bool,
+    },
+    /// Response to [ToBackend::GetUndoTree]
+    UndoTree {
+        request_id: RequestId,
+        view_id: Uuid,
+        tree: UndoTree,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -74,4 +136,24 @@ pub enum ToBackend {
         view_id: Uuid,
         height: usize,
     },
+    /// Save the document shown in the view to its path
+    SaveDocument {
+        view_id: Uuid,
+        /// Overwrite the file even if another program changed it since it was loaded or saved
+        #[serde(default)]
+        force: bool,
+    },
+    /// Save the document shown in the view to a new path, which it keeps from then on once written
+    SaveAs {
+        view_id: Uuid,
+        path: PathBuf,
+        /// Overwrite the file at the path if it already exists
+        #[serde(default)]
+        force: bool,
+    },
+    /// Request the undo history of the document shown in the view, answered with a [ToFrontend::UndoTree]
+    GetUndoTree {
+        request_id: RequestId,
+        view_id: Uuid,
+    },
 }
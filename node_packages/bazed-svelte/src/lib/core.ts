@@ -13,6 +13,14 @@ export type State = {
       lines: string[]
       firstLine: number
       carets: CoordinateRegion[]
+      searchMatches: CoordinateRegion[]
+      /** the bracket under the primary caret and its matching bracket, if it has one */
+      matchingPair: Coordinate[]
+      commandLine: string | null
+      /** name of the current vim mode, i.e. `VisualLine`, in which selections span whole lines */
+      vimMode: string
+      /** set if the document has unsaved changes */
+      dirty: boolean
     }
   }
 }
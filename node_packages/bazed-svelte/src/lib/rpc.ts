@@ -17,6 +17,8 @@ export const initSession = async (): Promise<Session> => {
 export class Session {
   websocket: WebSocket
   state: State = {} as any
+  /** resolve functions of requests that are still waiting for their response, by request id */
+  pendingRequests: Map<Uuid, (response: any) => void> = new Map()
 
   constructor(websocket: WebSocket) {
     websocket.onmessage = (event) => this.onMessageReceived(JSON.parse(event.data))
@@ -69,6 +71,35 @@ export class Session {
     this.send({ method: "viewport_changed", params: { view_id, ...args } })
   }
 
+  /**
+   * save the document shown in a view to its path
+   * @param {boolean} force - overwrite the file even if another program changed it since it was loaded
+   */
+  saveDocument(view_id: string, force: boolean = false) {
+    this.send({ method: "save_document", params: { view_id, force } })
+  }
+
+  /**
+   * save the document shown in a view to a new path, which the document keeps from then on
+   * @param {string} path - path to save the document to
+   * @param {boolean} force - overwrite the file at the path if it already exists
+   */
+  saveAs(view_id: string, path: string, force: boolean = false) {
+    this.send({ method: "save_as", params: { view_id, path, force } })
+  }
+
+  /**
+   * request the undo history of the document shown in a view, i.e. to draw it as a tree
+   * @returns {Promise<UndoTree>} - the states of the history and the one the document is in
+   */
+  getUndoTree(view_id: string): Promise<UndoTree> {
+    const request_id = crypto.randomUUID()
+    return new Promise((resolve) => {
+      this.pendingRequests.set(request_id, resolve)
+      this.send({ method: "get_undo_tree", params: { request_id, view_id } })
+    })
+  }
+
   /**
    * handles all messages recieved by the frontend, sent by the backend via the established
    * websocket
@@ -81,6 +112,18 @@ export class Session {
       case "update_view":
         this.onUpdateView(message.params)
         break
+      case "close_view":
+        this.onCloseView(message.params)
+        break
+      case "document_saved":
+        this.onDocumentSaved(message.params)
+        break
+      case "file_changed":
+        this.onFileChanged(message.params)
+        break
+      case "undo_tree":
+        this.onResponse(message.params.request_id, message.params.tree)
+        break
       default:
         ensureExhaustive(message)
     }
@@ -94,6 +137,11 @@ export class Session {
         firstLine: params.view_data.first_line,
         lines: params.view_data.text,
         carets: params.view_data.carets,
+        searchMatches: params.view_data.search_matches,
+        matchingPair: params.view_data.matching_pair,
+        commandLine: params.view_data.command_line,
+        vimMode: params.view_data.vim_mode,
+        dirty: params.view_data.dirty,
       }
       return state
     })
@@ -111,11 +159,59 @@ export class Session {
           firstLine: params.view_data.first_line,
           lines: params.view_data.text,
           carets: params.view_data.carets,
+          searchMatches: params.view_data.search_matches,
+          matchingPair: params.view_data.matching_pair,
+          commandLine: params.view_data.command_line,
+          vimMode: params.view_data.vim_mode,
+          dirty: params.view_data.dirty,
         }
       }
       return state
     })
   }
+
+  /** expected behavior is for the frontend to stop displaying the view */
+  async onCloseView(params: CloseView["params"]) {
+    state.update((state) => {
+      delete state.views[params.view_id]
+      return state
+    })
+  }
+
+  /** the backend tried to save a document, remember its new path if that worked */
+  async onDocumentSaved(params: DocumentSaved["params"]) {
+    if (params.error !== null) {
+      log.error(`Failed to save ${params.path}: ${params.error}`)
+      return
+    }
+    state.update((state) => {
+      const view = state.views[params.view_id]
+      if (view) {
+        view.filePath = params.path
+      }
+      return state
+    })
+  }
+
+  /** another program changed the file of a view, the backend merged that with the unsaved changes */
+  async onFileChanged(params: FileChanged["params"]) {
+    if (params.conflict) {
+      log.warn(`${params.path} was changed on disk, conflicting with unsaved changes`)
+    } else {
+      log.info(`${params.path} was changed on disk, merged with unsaved changes`)
+    }
+  }
+
+  /** resolve the request the response belongs to */
+  onResponse(request_id: Uuid, response: any) {
+    const resolve = this.pendingRequests.get(request_id)
+    if (!resolve) {
+      console.error("Got response for unknown request id, ignoring...")
+      return
+    }
+    this.pendingRequests.delete(request_id)
+    resolve(response)
+  }
 }
 
 type Position = {
@@ -128,14 +224,32 @@ type Message<Method extends string, Params> = {
   params: Params
 }
 
-type ToFrontend = OpenView | UpdateView
+type ToFrontend =
+  | OpenView
+  | UpdateView
+  | CloseView
+  | DocumentSaved
+  | FileChanged
+  | UndoTreeResponse
 
-type ToBackend = ViewportChanged | KeyPressed | MouseInput | MouseScroll
+type ToBackend =
+  | ViewportChanged
+  | KeyPressed
+  | MouseInput
+  | MouseScroll
+  | SaveDocument
+  | SaveAs
+  | GetUndoTree
 type ViewData = {
   first_line: number
   text: string[]
   carets: CoordinateRegion[]
   vim_mode: string
+  pending_count: number | null
+  search_matches: CoordinateRegion[]
+  matching_pair: Coordinate[]
+  command_line: string | null
+  dirty: boolean
 }
 
 type OpenView = Message<
@@ -155,6 +269,52 @@ type UpdateView = Message<
   }
 >
 
+type CloseView = Message<
+  "close_view",
+  {
+    view_id: Uuid
+  }
+>
+
+type DocumentSaved = Message<
+  "document_saved",
+  {
+    view_id: Uuid
+    path: string
+    error: string | null
+  }
+>
+
+type FileChanged = Message<
+  "file_changed",
+  {
+    view_id: Uuid
+    path: string
+    conflict: boolean
+  }
+>
+
+/** undo history of a document, which branches wherever an edit was made after undoing */
+export type UndoTree = {
+  /** states of the document in the order they were created in, the first one being before any edit */
+  states: {
+    parent: number | null
+    /** milliseconds since the unix epoch */
+    timestamp: number
+    saved: boolean
+  }[]
+  current: number
+}
+
+type UndoTreeResponse = Message<
+  "undo_tree",
+  {
+    request_id: Uuid
+    view_id: Uuid
+    tree: UndoTree
+  }
+>
+
 type ViewportChanged = Message<
   "viewport_changed",
   {
@@ -180,6 +340,31 @@ type MouseInput = Message<
   }
 >
 
+type SaveDocument = Message<
+  "save_document",
+  {
+    view_id: Uuid
+    force: boolean
+  }
+>
+
+type SaveAs = Message<
+  "save_as",
+  {
+    view_id: Uuid
+    path: string
+    force: boolean
+  }
+>
+
+type GetUndoTree = Message<
+  "get_undo_tree",
+  {
+    request_id: Uuid
+    view_id: Uuid
+  }
+>
+
 type MouseScroll = Message<
   "mouse_scroll",
   {
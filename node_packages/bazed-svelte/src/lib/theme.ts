@@ -5,4 +5,6 @@ export type Theme = {
   editorFg: color
   gutterBg: color
   cursorColorPrimary: color
+  searchMatchBg: color
+  matchingPairBg: color
 }
@@ -67,4 +67,6 @@ export const theme: Theme = {
   editorBg: kanagawa.sumiInk1,
   gutterBg: kanagawa.sumiInk0,
   cursorColorPrimary: kanagawa.fujiWhite,
+  searchMatchBg: kanagawa.waveBlue2,
+  matchingPairBg: kanagawa.sumiInk4,
 }